@@ -27,6 +27,8 @@ pub struct Prompt {
     pub parallel_tool_calls: bool,
     /// Optional output schema used to build the `text.format` controls.
     pub output_schema: Option<Value>,
+    /// Sampling seed; only the Chat Completions API accepts one.
+    pub seed: Option<i64>,
 }
 
 /// Canonical input payload for the compaction endpoint.
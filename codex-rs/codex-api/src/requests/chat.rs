@@ -26,6 +26,7 @@ pub struct ChatRequestBuilder<'a> {
     tools: &'a [Value],
     conversation_id: Option<String>,
     session_source: Option<SessionSource>,
+    seed: Option<i64>,
 }
 
 impl<'a> ChatRequestBuilder<'a> {
@@ -42,6 +43,7 @@ impl<'a> ChatRequestBuilder<'a> {
             tools,
             conversation_id: None,
             session_source: None,
+            seed: None,
         }
     }
 
@@ -55,6 +57,11 @@ impl<'a> ChatRequestBuilder<'a> {
         self
     }
 
+    pub fn seed(mut self, seed: Option<i64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
     pub fn build(self, _provider: &Provider) -> Result<ChatRequest, ApiError> {
         let mut messages = Vec::<Value>::new();
         messages.push(json!({"role": "system", "content": self.instructions}));
@@ -291,12 +298,15 @@ impl<'a> ChatRequestBuilder<'a> {
             }
         }
 
-        let payload = json!({
+        let mut payload = json!({
             "model": self.model,
             "messages": messages,
             "stream": true,
             "tools": self.tools,
         });
+        if let (Some(seed), Some(obj)) = (self.seed, payload.as_object_mut()) {
+            obj.insert("seed".to_string(), json!(seed));
+        }
 
         let mut headers = build_conversation_headers(self.conversation_id);
         if let Some(subagent) = subagent_header(&self.session_source) {
@@ -407,6 +417,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn sends_seed_only_when_set() {
+        let prompt_input = vec![ResponseItem::Message {
+            id: None,
+            role: "user".to_string(),
+            content: vec![ContentItem::InputText {
+                text: "hi".to_string(),
+            }],
+        }];
+        let req = ChatRequestBuilder::new("gpt-test", "inst", &prompt_input, &[])
+            .seed(Some(42))
+            .build(&provider())
+            .expect("request");
+        assert_eq!(req.body["seed"], 42);
+
+        let req = ChatRequestBuilder::new("gpt-test", "inst", &prompt_input, &[])
+            .build(&provider())
+            .expect("request");
+        assert_eq!(req.body.get("seed"), None);
+    }
+
     #[test]
     fn groups_consecutive_tool_calls_into_a_single_assistant_message() {
         let prompt_input = vec![
@@ -312,6 +312,7 @@ async fn streaming_client_retries_on_transport_error() -> Result<()> {
         tools: Vec::<Value>::new(),
         parallel_tool_calls: false,
         output_schema: None,
+        seed: None,
     };
 
     let options = ResponsesOptions::default();
@@ -39,6 +39,9 @@ pub struct Prompt {
 
     /// Optional the output schema for the model's response.
     pub output_schema: Option<Value>,
+
+    /// Sampling seed, sent to providers on the Chat Completions API.
+    pub seed: Option<i64>,
 }
 
 impl Prompt {
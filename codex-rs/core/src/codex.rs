@@ -2538,6 +2538,7 @@ async fn run_model_turn(
         parallel_tool_calls: model_supports_parallel,
         base_instructions_override: turn_context.base_instructions.clone(),
         output_schema: turn_context.final_output_json_schema.clone(),
+        seed: None,
     };
 
     let mut retries = 0;
@@ -67,6 +67,9 @@ pub(crate) fn with_config_overrides(mut model: ModelInfo, config: &Config) -> Mo
     if let Some(supports_reasoning_summaries) = config.model_supports_reasoning_summaries {
         model.supports_reasoning_summaries = supports_reasoning_summaries;
     }
+    if let Some(supports_parallel_tool_calls) = config.model_supports_parallel_tool_calls {
+        model.supports_parallel_tool_calls = supports_parallel_tool_calls;
+    }
     if let Some(context_window) = config.model_context_window {
         model.context_window = Some(context_window);
     }
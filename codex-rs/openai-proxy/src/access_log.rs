@@ -0,0 +1,637 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::HeaderValue;
+use axum::http::Method;
+use axum::http::header::CONTENT_TYPE;
+use axum::middleware::Next;
+use axum::response::Response;
+use codex_core::protocol::SandboxPolicy;
+use codex_protocol::ThreadId;
+use codex_protocol::protocol::EventMsg;
+use codex_protocol::protocol::RateLimitSnapshot;
+use codex_protocol::protocol::TokenCountEvent;
+use codex_protocol::user_input::UserInput;
+use tokio_stream::StreamExt;
+use tracing::Instrument;
+use tracing::info;
+use tracing::info_span;
+use tracing::warn;
+
+use crate::AppState;
+use crate::audit_log::AuditEntry;
+use crate::audit_log::AuditLog;
+use crate::audit_log::TurnActivity;
+use crate::interceptors::Interceptors;
+use crate::ledger::Ledger;
+use crate::ledger::LedgerEntry;
+use crate::log_message;
+use crate::model_map::ModelMapping;
+use crate::now_ts;
+use crate::turn_diffs::TurnDiffs;
+use crate::turn_slots::QueueTicket;
+use crate::turn_webhook::TurnNotice;
+use crate::turn_webhook::TurnWebhook;
+use crate::upstream_rate_limits;
+use crate::usage::usage_owner;
+
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+const QUEUE_POSITION_HEADER: &str = "x-queue-position";
+const QUEUE_TIME_HEADER: &str = "x-queue-time-ms";
+
+/// Longest client-supplied `x-request-id` that is reused rather than replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Characters of a request's `user` field that are kept; OpenAI allows 256.
+const MAX_USER_LEN: usize = 256;
+
+/// What handlers learn about a request after routing, reported in its access
+/// log line.
+#[derive(Debug, Default)]
+struct AccessFields {
+    model: Option<String>,
+    /// The Codex model `model` runs, recorded in the ledger, audit log and
+    /// turn webhook.
+    codex_model: Option<String>,
+    /// End user named by the request's `user` field.
+    user: Option<String>,
+    conversation_id: Option<String>,
+    tokens: Option<i64>,
+    /// Turns started on behalf of the request.
+    turns: u64,
+    /// Response status, recorded in the ledger.
+    status: Option<u16>,
+    /// Set when the request waited for a turn slot.
+    queue: Option<QueueTicket>,
+    /// What its turns did; only collected for the audit log and turn
+    /// webhook.
+    activity: TurnActivity,
+    /// Id of the completion, response or message it was answered with.
+    response_id: Option<String>,
+    /// The provider's rate limits as last reported during its turns.
+    rate_limits: Option<RateLimitSnapshot>,
+    /// `danger-full-access` when one of its turns ran unsandboxed.
+    sandbox: Option<&'static str>,
+}
+
+/// Per-request id and access-log fields, inserted into the request extensions
+/// by [`access_log`].
+#[derive(Debug, Clone)]
+pub(crate) struct RequestContext {
+    request_id: Arc<str>,
+    fields: Arc<Mutex<AccessFields>>,
+    /// Set when the request is recorded in the ledger.
+    ledger: Option<Arc<LedgerRecord>>,
+    /// Set when the request is written to the audit log.
+    audit: Option<Arc<AuditRecord>>,
+    /// Set when the request's turns are notified to the turn webhook.
+    webhook: Option<Arc<WebhookRecord>>,
+    /// Set when embedders registered event interceptors.
+    interceptors: Option<Arc<Interceptors>>,
+    /// Set when the request's turn diffs are kept for the diff endpoint.
+    turn_diffs: Option<Arc<TurnDiffs>>,
+    /// Resolves the request's model to the Codex model it runs.
+    model_map: Option<Arc<ModelMapping>>,
+}
+
+impl RequestContext {
+    pub(crate) fn new(request_id: String) -> Self {
+        Self {
+            request_id: request_id.into(),
+            fields: Arc::new(Mutex::new(AccessFields::default())),
+            ledger: None,
+            audit: None,
+            webhook: None,
+            interceptors: None,
+            turn_diffs: None,
+            model_map: None,
+        }
+    }
+
+    /// Records the request in `ledger`, charged to `owner`, once the last
+    /// handle on the context is dropped.
+    pub(crate) fn with_ledger(
+        mut self,
+        ledger: Arc<Ledger>,
+        owner: String,
+        method: &str,
+        path: &str,
+    ) -> Self {
+        self.ledger = Some(Arc::new(LedgerRecord {
+            ledger,
+            request_id: self.request_id.to_string(),
+            owner,
+            method: method.to_string(),
+            path: path.to_string(),
+            at: now_ts(),
+            started: Instant::now(),
+            fields: self.fields.clone(),
+        }));
+        self
+    }
+
+    /// Also writes the request to `audit_log`, if there is one, once the
+    /// last handle on the context is dropped. Follows [`Self::with_ledger`],
+    /// whose owner, method and path it reuses.
+    pub(crate) fn with_audit_log(mut self, audit_log: Option<Arc<AuditLog>>) -> Self {
+        let (Some(log), Some(ledger)) = (audit_log, &self.ledger) else {
+            return self;
+        };
+        self.audit = Some(Arc::new(AuditRecord {
+            log,
+            request_id: self.request_id.to_string(),
+            owner: ledger.owner.clone(),
+            method: ledger.method.clone(),
+            path: ledger.path.clone(),
+            at: ledger.at,
+            started: ledger.started,
+            fields: self.fields.clone(),
+        }));
+        self
+    }
+
+    /// Notifies `webhook`, if there is one, once the request's turns have
+    /// ended; `background` for batch requests, which are always notified.
+    /// Follows [`Self::with_ledger`], whose path it reuses.
+    pub(crate) fn with_turn_webhook(
+        mut self,
+        webhook: Option<Arc<TurnWebhook>>,
+        background: bool,
+    ) -> Self {
+        if let Some(webhook) = webhook {
+            self.webhook = Some(Arc::new(WebhookRecord {
+                webhook,
+                request_id: self.request_id.to_string(),
+                path: self
+                    .ledger
+                    .as_ref()
+                    .map(|ledger| ledger.path.clone())
+                    .unwrap_or_default(),
+                started: Instant::now(),
+                background,
+                fields: self.fields.clone(),
+            }));
+        }
+        self
+    }
+
+    /// Runs `interceptors`' event hooks on the request's turn events.
+    pub(crate) fn with_interceptors(mut self, interceptors: &Arc<Interceptors>) -> Self {
+        if !interceptors.event.is_empty() {
+            self.interceptors = Some(interceptors.clone());
+        }
+        self
+    }
+
+    /// Keeps the diffs of the request's turns in `turn_diffs`, under its
+    /// conversation and response ids.
+    pub(crate) fn with_turn_diffs(mut self, turn_diffs: &Arc<TurnDiffs>) -> Self {
+        self.turn_diffs = Some(turn_diffs.clone());
+        self
+    }
+
+    /// Records the Codex model behind the request's model as `model_map`
+    /// resolves it.
+    pub(crate) fn with_model_map(mut self, model_map: &Arc<ModelMapping>) -> Self {
+        self.model_map = Some(model_map.clone());
+        self
+    }
+
+    pub(crate) fn set_status(&self, status: u16) {
+        if let Ok(mut fields) = self.fields.lock() {
+            fields.status = Some(status);
+        }
+    }
+
+    pub(crate) fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Who the request is charged to in the ledger; see [`usage_owner`].
+    pub(crate) fn owner(&self) -> Option<&str> {
+        self.ledger.as_ref().map(|record| record.owner.as_str())
+    }
+
+    pub(crate) fn set_model(&self, model: &str) {
+        let codex_model = match &self.model_map {
+            Some(model_map) => model_map.codex_model(model),
+            None => model.to_string(),
+        };
+        if let Ok(mut fields) = self.fields.lock() {
+            fields.model = Some(model.to_string());
+            fields.codex_model = Some(codex_model);
+        }
+    }
+
+    /// Attributes the request to the end user a shared deployment's client
+    /// named in the OpenAI `user` field, in the access log, the request span
+    /// and the ledger.
+    pub(crate) fn set_user(&self, user: Option<&str>) {
+        let Some(user) = user.filter(|user| !user.is_empty()) else {
+            return;
+        };
+        let user = user.chars().take(MAX_USER_LEN).collect::<String>();
+        tracing::Span::current().record("user", user.as_str());
+        if let Ok(mut fields) = self.fields.lock() {
+            fields.user = Some(user);
+        }
+    }
+
+    /// Records that a turn is starting on `thread_id`.
+    pub(crate) fn set_conversation_id(&self, thread_id: ThreadId) {
+        if let Ok(mut fields) = self.fields.lock() {
+            fields.conversation_id = Some(thread_id.to_string());
+            fields.turns += 1;
+        }
+    }
+
+    /// Records the id the request is answered with.
+    pub(crate) fn set_response_id(&self, id: &str) {
+        if let Ok(mut fields) = self.fields.lock() {
+            fields.response_id = Some(id.to_string());
+        }
+    }
+
+    pub(crate) fn set_queue_ticket(&self, ticket: QueueTicket) {
+        if let Ok(mut fields) = self.fields.lock() {
+            fields.queue = Some(ticket);
+        }
+    }
+
+    fn queue_ticket(&self) -> Option<QueueTicket> {
+        self.fields.lock().ok().and_then(|fields| fields.queue)
+    }
+
+    /// Tokens the request's turn has used so far.
+    pub(crate) fn tokens(&self) -> Option<i64> {
+        self.fields.lock().ok().and_then(|fields| fields.tokens)
+    }
+
+    /// Notes the input a turn is submitted with, for the audit log.
+    pub(crate) fn record_input(&self, items: &[UserInput]) {
+        if self.collects_activity()
+            && let Ok(mut fields) = self.fields.lock()
+        {
+            fields.activity.record_input(items);
+        }
+    }
+
+    /// Notes the sandbox a turn is submitted with. Turns without one are
+    /// always logged, and marked in the audit log.
+    pub(crate) fn record_sandbox(&self, policy: &SandboxPolicy) {
+        if !matches!(policy, SandboxPolicy::DangerFullAccess) {
+            return;
+        }
+        warn!(
+            "request {} runs a turn with danger-full-access",
+            self.request_id
+        );
+        log_message(
+            serde_json::json!({
+                "type": "danger_full_access_turn",
+                "request_id": &*self.request_id,
+            })
+            .to_string(),
+        );
+        if let Ok(mut fields) = self.fields.lock() {
+            fields.sandbox = Some("danger-full-access");
+        }
+    }
+
+    /// Passes a turn event through the event interceptors; `false` when one
+    /// dropped it.
+    pub(crate) fn intercept_event(&self, msg: &mut EventMsg) -> bool {
+        match &self.interceptors {
+            Some(interceptors) => interceptors.event(msg),
+            None => true,
+        }
+    }
+
+    /// Notes what a turn event did, for the audit log and turn webhook, the
+    /// rate limits the provider reported and the diff of the turn.
+    pub(crate) fn record_event(&self, msg: &EventMsg) {
+        if let EventMsg::TokenCount(TokenCountEvent {
+            rate_limits: Some(rate_limits),
+            ..
+        }) = msg
+            && let Ok(mut fields) = self.fields.lock()
+        {
+            fields.rate_limits = Some(rate_limits.clone());
+        }
+        if self.collects_activity()
+            && let Ok(mut fields) = self.fields.lock()
+        {
+            fields.activity.record_event(msg);
+        }
+        if let Some(turn_diffs) = &self.turn_diffs
+            && let Some((conversation_id, turn_id)) = self.turn_key()
+        {
+            turn_diffs.record_event(&conversation_id, &turn_id, msg);
+        }
+    }
+
+    /// The conversation and response ids a turn diff is kept under.
+    fn turn_key(&self) -> Option<(String, String)> {
+        let fields = self.fields.lock().ok()?;
+        Some((fields.conversation_id.clone()?, fields.response_id.clone()?))
+    }
+
+    fn rate_limits(&self) -> Option<RateLimitSnapshot> {
+        self.fields.lock().ok()?.rate_limits.clone()
+    }
+
+    fn collects_activity(&self) -> bool {
+        self.audit.is_some() || self.webhook.is_some()
+    }
+
+    /// Adds the tokens one model call used to the request's total.
+    pub(crate) fn add_tokens(&self, tokens: i64) {
+        if let Ok(mut fields) = self.fields.lock() {
+            *fields.tokens.get_or_insert(0) += tokens;
+        }
+    }
+}
+
+/// Writes the request's ledger row when the last handle on its context is
+/// dropped, i.e. once its response and every turn it started are done.
+#[derive(Debug)]
+struct LedgerRecord {
+    ledger: Arc<Ledger>,
+    request_id: String,
+    owner: String,
+    method: String,
+    path: String,
+    at: u64,
+    started: Instant,
+    fields: Arc<Mutex<AccessFields>>,
+}
+
+impl Drop for LedgerRecord {
+    fn drop(&mut self) {
+        let Ok(fields) = self.fields.lock() else {
+            return;
+        };
+        self.ledger.record(LedgerEntry {
+            at: self.at,
+            request_id: std::mem::take(&mut self.request_id),
+            owner: std::mem::take(&mut self.owner),
+            method: std::mem::take(&mut self.method),
+            path: std::mem::take(&mut self.path),
+            model: fields.codex_model.clone(),
+            user: fields.user.clone(),
+            conversation_id: fields.conversation_id.clone(),
+            status: fields.status,
+            latency_ms: self.started.elapsed().as_millis() as u64,
+            turns: fields.turns,
+            tokens: fields.tokens.unwrap_or(0),
+        });
+    }
+}
+
+/// Writes the request's audit log line when the last handle on its context
+/// is dropped, like [`LedgerRecord`].
+#[derive(Debug)]
+struct AuditRecord {
+    log: Arc<AuditLog>,
+    request_id: String,
+    owner: String,
+    method: String,
+    path: String,
+    at: u64,
+    started: Instant,
+    fields: Arc<Mutex<AccessFields>>,
+}
+
+impl Drop for AuditRecord {
+    fn drop(&mut self) {
+        let Ok(fields) = self.fields.lock() else {
+            return;
+        };
+        let entry = AuditEntry {
+            at: self.at,
+            request_id: std::mem::take(&mut self.request_id),
+            owner: std::mem::take(&mut self.owner),
+            method: std::mem::take(&mut self.method),
+            path: std::mem::take(&mut self.path),
+            model: fields.codex_model.clone(),
+            user: fields.user.clone(),
+            conversation_id: fields.conversation_id.clone(),
+            status: fields.status,
+            latency_ms: self.started.elapsed().as_millis() as u64,
+            outcome: "ok",
+            error: None,
+            prompt_sha256: None,
+            prompt: None,
+            commands: Vec::new(),
+            files: Vec::new(),
+            sandbox: fields.sandbox,
+        };
+        self.log
+            .write(entry.with_activity(&fields.activity, fields.turns));
+    }
+}
+
+/// Notifies the turn webhook when the last handle on the request's context
+/// is dropped, if it started a turn.
+#[derive(Debug)]
+struct WebhookRecord {
+    webhook: Arc<TurnWebhook>,
+    request_id: String,
+    path: String,
+    started: Instant,
+    background: bool,
+    fields: Arc<Mutex<AccessFields>>,
+}
+
+impl Drop for WebhookRecord {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        if !self.webhook.wants(elapsed, self.background) {
+            return;
+        }
+        let Ok(fields) = self.fields.lock() else {
+            return;
+        };
+        if fields.turns == 0 {
+            return;
+        }
+        let activity = &fields.activity;
+        self.webhook.send(TurnNotice {
+            event: TurnNotice::event(activity.outcome()),
+            request_id: std::mem::take(&mut self.request_id),
+            response_id: fields.response_id.clone(),
+            conversation_id: fields.conversation_id.clone(),
+            model: fields.codex_model.clone(),
+            user: fields.user.clone(),
+            path: std::mem::take(&mut self.path),
+            background: self.background,
+            duration_ms: elapsed.as_millis() as u64,
+            usage: activity.usage(),
+            summary: activity.answer().map(TurnNotice::summary),
+            error: activity.error().map(str::to_string),
+        });
+    }
+}
+
+/// Reuses the client's `x-request-id` when it is a short printable token and
+/// generates one otherwise.
+fn request_id(request: &Request) -> String {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("req_{}", uuid::Uuid::new_v4().simple()))
+}
+
+/// Writes the access log line when dropped, so streamed responses are logged
+/// once their body finishes (or the client goes away).
+struct AccessLogEntry {
+    context: RequestContext,
+    method: Method,
+    path: String,
+    status: u16,
+    started: Instant,
+}
+
+impl Drop for AccessLogEntry {
+    fn drop(&mut self) {
+        let (model, user, conversation_id, tokens, queue) = match self.context.fields.lock() {
+            Ok(fields) => (
+                fields.model.clone(),
+                fields.user.clone(),
+                fields.conversation_id.clone(),
+                fields.tokens,
+                fields.queue,
+            ),
+            Err(_) => (None, None, None, None, None),
+        };
+        let line = serde_json::json!({
+            "type": "access",
+            "request_id": self.context.request_id(),
+            "method": self.method.as_str(),
+            "path": self.path,
+            "model": model,
+            "user": user,
+            "conversation_id": conversation_id,
+            "status": self.status,
+            "duration_ms": self.started.elapsed().as_millis() as u64,
+            "tokens": tokens,
+            "queue_ms": queue.map(|ticket| ticket.waited.as_millis() as u64),
+        });
+        info!("{line}");
+    }
+}
+
+/// Middleware that tags every request with an `x-request-id`, echoes it in the
+/// response headers and emits one JSON access log line per request. Every
+/// request is also recorded in the ledger, charged to its owner.
+pub(crate) async fn access_log(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let context = RequestContext::new(request_id(&request))
+        .with_ledger(
+            state.ledger.clone(),
+            usage_owner(&state, request.headers()),
+            method.as_str(),
+            &path,
+        )
+        .with_audit_log(state.audit_log.clone())
+        .with_turn_webhook(state.turn_webhook.clone(), false)
+        .with_interceptors(&state.interceptors)
+        .with_turn_diffs(&state.turn_diffs)
+        .with_model_map(&state.model_map);
+    request.extensions_mut().insert(context.clone());
+    let span = info_span!(
+        "http.request",
+        method = %method,
+        path = %path,
+        request_id = %context.request_id(),
+        user = tracing::field::Empty,
+    );
+
+    let started = Instant::now();
+    let mut response = next.run(request).instrument(span).await;
+    if let Some(rate_limits) = context.rate_limits() {
+        response = upstream_rate_limits::apply(response, &rate_limits).await;
+    }
+    context.set_status(response.status().as_u16());
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(context.request_id()) {
+        headers.insert(REQUEST_ID_HEADER, value);
+    }
+    if let Some(ticket) = context.queue_ticket() {
+        headers.insert(QUEUE_POSITION_HEADER, HeaderValue::from(ticket.position));
+        headers.insert(
+            QUEUE_TIME_HEADER,
+            HeaderValue::from(ticket.waited.as_millis() as u64),
+        );
+    }
+
+    let entry = AccessLogEntry {
+        context,
+        method,
+        path,
+        status: response.status().as_u16(),
+        started,
+    };
+    // Keep the entry alive until an SSE body is done so the line carries the
+    // full duration and the tokens the turn used.
+    hold_until_body_ends(response, entry)
+}
+
+/// Drops `guard` once the client has received all of `response`: right away
+/// for buffered bodies, or when an SSE stream ends or is abandoned.
+pub(crate) fn hold_until_body_ends<T: Send + 'static>(response: Response, guard: T) -> Response {
+    if !is_event_stream(&response) {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _ = &guard;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+fn is_event_stream(response: &Response) -> bool {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn client_request_ids_are_reused_only_when_well_formed() {
+        let with_header = |value: &str| {
+            Request::builder()
+                .header(REQUEST_ID_HEADER, value)
+                .body(Body::empty())
+                .expect("request")
+        };
+
+        assert_eq!(request_id(&with_header("trace-123")), "trace-123");
+        assert!(request_id(&with_header("has space")).starts_with("req_"));
+        assert!(request_id(&with_header(&"x".repeat(200))).starts_with("req_"));
+        assert!(request_id(&Request::new(Body::empty())).starts_with("req_"));
+    }
+}
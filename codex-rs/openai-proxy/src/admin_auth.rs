@@ -0,0 +1,127 @@
+//! Who may call the operator endpoints, `/admin/*` and `/v1/sessions`: they
+//! list every conversation, abort turns and change process-wide settings.
+//! With `CODEX_OPENAI_PROXY_ADMIN_TOKEN` set they take that token as a
+//! bearer token; without one they are only served on a loopback address.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use tracing::warn;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::rate_limit::api_key;
+
+/// Whether the operator endpoints are served, and to whom.
+#[derive(Debug, Clone)]
+pub(crate) enum AdminAccess {
+    /// To anyone who reaches the proxy: no token is set and it listens on a
+    /// loopback address, or is mounted in an app that guards it.
+    Open,
+    /// To requests bearing this token.
+    Token(Arc<str>),
+    /// To no one: the proxy listens beyond this host and no token is set.
+    Closed,
+}
+
+impl AdminAccess {
+    /// The access `token` grants when the proxy listens on `listen_addr`;
+    /// `None` for an app the proxy is mounted in.
+    pub(crate) fn new(token: Option<String>, listen_addr: Option<SocketAddr>) -> Self {
+        match (token, listen_addr) {
+            (Some(token), _) => Self::Token(token.into()),
+            (None, Some(addr)) if !addr.ip().is_loopback() => {
+                warn!(
+                    "admin endpoints are disabled: {addr} is not a loopback address and CODEX_OPENAI_PROXY_ADMIN_TOKEN is unset"
+                );
+                Self::Closed
+            }
+            (None, _) => Self::Open,
+        }
+    }
+
+    fn admits(&self, request: &Request) -> bool {
+        match self {
+            Self::Open => true,
+            Self::Token(token) => api_key(request.headers())
+                .is_some_and(|key| same_token(key.as_bytes(), token.as_bytes())),
+            Self::Closed => false,
+        }
+    }
+}
+
+/// Whether `path` is an operator endpoint, which tenants' API keys do not
+/// route.
+pub(crate) fn is_admin_path(path: &str) -> bool {
+    path.starts_with("/admin/") || path == "/v1/sessions"
+}
+
+/// Middleware on the operator endpoints: 401 without the admin token, 403
+/// when they are closed.
+pub(crate) async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.admin_access.admits(&request) {
+        return next.run(request).await;
+    }
+    match state.admin_access {
+        AdminAccess::Closed => ProxyError::new(
+            StatusCode::FORBIDDEN,
+            "admin endpoints need CODEX_OPENAI_PROXY_ADMIN_TOKEN when the proxy listens beyond loopback",
+            "invalid_request_error",
+        )
+        .with_code("admin_token_required"),
+        _ => ProxyError::new(
+            StatusCode::UNAUTHORIZED,
+            "missing or wrong admin token",
+            "invalid_request_error",
+        )
+        .with_code("invalid_admin_token"),
+    }
+    .into_response()
+}
+
+/// Compares in time independent of where the tokens differ.
+fn same_token(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn only_loopback_listeners_serve_admin_without_a_token() {
+        let access = |token: Option<&str>, addr: Option<&str>| match AdminAccess::new(
+            token.map(str::to_string),
+            addr.map(|addr| addr.parse().expect("address")),
+        ) {
+            AdminAccess::Open => "open",
+            AdminAccess::Token(_) => "token",
+            AdminAccess::Closed => "closed",
+        };
+        assert_eq!(access(None, None), "open");
+        assert_eq!(access(None, Some("127.0.0.1:11435")), "open");
+        assert_eq!(access(None, Some("[::1]:11435")), "open");
+        assert_eq!(access(None, Some("0.0.0.0:11435")), "closed");
+        assert_eq!(access(Some("s3cret"), Some("0.0.0.0:11435")), "token");
+        assert_eq!(access(Some("s3cret"), None), "token");
+    }
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(same_token(b"s3cret", b"s3cret"));
+        assert!(!same_token(b"s3cret", b"s3creT"));
+        assert!(!same_token(b"s3cret", b"s3cret2"));
+        assert!(!same_token(b"", b"s3cret"));
+    }
+}
@@ -0,0 +1,883 @@
+use std::env;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::http::HeaderValue;
+use codex_core::ThreadManager;
+use codex_core::auth::AuthManager;
+use codex_core::config::Config;
+use codex_core::protocol::ReviewDecision;
+use codex_protocol::protocol::SessionSource;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::Registry;
+use tracing_subscriber::reload;
+
+use crate::AppState;
+use crate::DEFAULT_APPROVAL_WEBHOOK_TIMEOUT_SECS;
+use crate::DEFAULT_BATCH_CONCURRENCY;
+use crate::DEFAULT_IDEMPOTENCY_TTL_SECS;
+use crate::DEFAULT_LEDGER_RETENTION_DAYS;
+use crate::DEFAULT_MAX_REQUEST_BODY_BYTES;
+use crate::DEFAULT_MODERATION_TIMEOUT_SECS;
+use crate::DEFAULT_RESPONSE_STORE_TTL_SECS;
+use crate::DEFAULT_STREAM_BUFFER_CAPACITY;
+use crate::DEFAULT_THREAD_IDLE_TTL_MINS;
+use crate::DEFAULT_TURN_WEBHOOK_TIMEOUT_SECS;
+use crate::EventInterceptor;
+use crate::RequestInterceptor;
+use crate::ResponseInterceptor;
+use crate::admin_auth::AdminAccess;
+use crate::approvals;
+use crate::approvals::ApprovalRegistry;
+use crate::approvals::ApprovalWebhook;
+use crate::attachments::AttachmentStore;
+use crate::audit_log::AuditContent;
+use crate::audit_log::AuditLog;
+use crate::batches::BatchRegistry;
+use crate::completions::RunningCompletions;
+use crate::config_cache::ConfigCache;
+use crate::conversation_store::ConversationStore;
+use crate::files::FileStore;
+use crate::idempotency::IdempotencyStore;
+use crate::interceptors::Interceptors;
+use crate::known_models;
+use crate::ledger::Ledger;
+use crate::limits::Limits;
+use crate::limits::LiveLimit;
+use crate::mcp_servers::McpServerAllowlist;
+use crate::mock_backend;
+use crate::model_map::ModelMapping;
+use crate::moderation::Moderation;
+use crate::proxy_config;
+use crate::rate_limit::RateLimiter;
+use crate::redaction;
+use crate::redaction::Redactor;
+use crate::response_cache::ResponseCache;
+use crate::response_store::ResponseStore;
+use crate::sandbox_mode;
+use crate::sse_keep_alive::SseKeepAlive;
+use crate::stream_buffer;
+use crate::stream_buffer::BackpressurePolicy;
+use crate::stream_buffer::StreamBuffers;
+use crate::stream_granularity::StreamGranularity;
+use crate::threads::ThreadRegistry;
+use crate::turn_diffs::TurnDiffs;
+use crate::turn_slots::TurnSlots;
+use crate::turn_webhook::TurnWebhook;
+use crate::workspace::OutsideRootPolicy;
+use crate::workspace::WorkspacePolicy;
+
+/// Builds the [`AppState`] that [`crate::router`] serves, for running the
+/// proxy inside another axum app. Settings default to what the standalone
+/// binary uses when no `CODEX_OPENAI_PROXY_*` variable is set;
+/// [`AppStateBuilder::from_env`] reads those variables instead.
+///
+/// ```no_run
+/// # async fn example(config: codex_core::config::Config) -> anyhow::Result<()> {
+/// let state = codex_openai_proxy::AppStateBuilder::new(config)
+///     .max_concurrent_turns(Some(4))
+///     .build()?;
+/// let app = axum::Router::new().nest("/codex", codex_openai_proxy::router(state));
+/// # Ok(())
+/// # }
+/// ```
+pub struct AppStateBuilder {
+    config: Config,
+    config_overrides: Vec<(String, toml::Value)>,
+    auth_manager: Option<Arc<AuthManager>>,
+    thread_manager: Option<Arc<ThreadManager>>,
+    log_filter: Option<reload::Handle<EnvFilter, Registry>>,
+    thread_idle_ttl: Option<Duration>,
+    interrupt_previous_turn: bool,
+    approval_webhook: Option<(String, Duration, ReviewDecision)>,
+    allow_danger_full_access: bool,
+    admin_token: Option<String>,
+    listen_addr: Option<SocketAddr>,
+    workspace_roots: Vec<PathBuf>,
+    clamp_outside_workspaces: bool,
+    mcp_server_commands: Vec<String>,
+    mcp_server_urls: Vec<String>,
+    rate_limit_rpm: Option<u64>,
+    rate_limit_tpm: Option<u64>,
+    max_concurrent_turns: Option<usize>,
+    turn_queue_size: usize,
+    response_cache_ttl: Option<Duration>,
+    batch_concurrency: usize,
+    idempotency_ttl: Option<Duration>,
+    response_store_ttl: Option<Duration>,
+    ledger_path: Option<PathBuf>,
+    files_dir: Option<PathBuf>,
+    ledger_retention: Option<Duration>,
+    max_output_tokens: Option<u64>,
+    sse_keep_alive: SseKeepAlive,
+    stream_buffer_capacity: usize,
+    backpressure: BackpressurePolicy,
+    stream_granularity: StreamGranularity,
+    stream_min_interval: Option<Duration>,
+    max_request_body_bytes: Option<usize>,
+    cors_origins: Vec<String>,
+    audit_log: Option<(PathBuf, AuditContent)>,
+    redact_builtins: Vec<String>,
+    redact_patterns: Vec<String>,
+    redact_prompts: bool,
+    moderation_rules: Option<PathBuf>,
+    moderation_endpoint: Option<(String, Option<String>, Duration)>,
+    turn_webhook: Option<(String, Duration, Duration)>,
+    interceptors: Interceptors,
+    model_map: Arc<ModelMapping>,
+}
+
+impl AppStateBuilder {
+    /// Serves Codex with `config`, which also picks the Codex home that
+    /// logins, sessions and the request ledger live in.
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            config_overrides: Vec::new(),
+            auth_manager: None,
+            thread_manager: None,
+            log_filter: None,
+            thread_idle_ttl: Some(Duration::from_secs(DEFAULT_THREAD_IDLE_TTL_MINS * 60)),
+            interrupt_previous_turn: false,
+            approval_webhook: None,
+            allow_danger_full_access: false,
+            admin_token: None,
+            listen_addr: None,
+            workspace_roots: Vec::new(),
+            clamp_outside_workspaces: false,
+            mcp_server_commands: Vec::new(),
+            mcp_server_urls: Vec::new(),
+            rate_limit_rpm: None,
+            rate_limit_tpm: None,
+            max_concurrent_turns: None,
+            turn_queue_size: 0,
+            response_cache_ttl: None,
+            batch_concurrency: DEFAULT_BATCH_CONCURRENCY,
+            idempotency_ttl: Some(Duration::from_secs(DEFAULT_IDEMPOTENCY_TTL_SECS)),
+            response_store_ttl: Some(Duration::from_secs(DEFAULT_RESPONSE_STORE_TTL_SECS)),
+            ledger_path: None,
+            files_dir: None,
+            ledger_retention: Some(Duration::from_secs(DEFAULT_LEDGER_RETENTION_DAYS * 86_400)),
+            max_output_tokens: None,
+            sse_keep_alive: SseKeepAlive::default(),
+            stream_buffer_capacity: DEFAULT_STREAM_BUFFER_CAPACITY,
+            backpressure: BackpressurePolicy::default(),
+            stream_granularity: StreamGranularity::default(),
+            stream_min_interval: None,
+            max_request_body_bytes: Some(DEFAULT_MAX_REQUEST_BODY_BYTES),
+            cors_origins: Vec::new(),
+            audit_log: None,
+            redact_builtins: Vec::new(),
+            redact_patterns: Vec::new(),
+            redact_prompts: false,
+            moderation_rules: None,
+            moderation_endpoint: None,
+            turn_webhook: None,
+            interceptors: Interceptors::default(),
+            model_map: Arc::new(ModelMapping::default()),
+        }
+    }
+
+    /// Settings from the `CODEX_OPENAI_PROXY_*` environment variables, as the
+    /// standalone binary runs with; its `--config` file fills in unset ones.
+    pub fn from_env(config: Config) -> anyhow::Result<Self> {
+        let mut builder = Self::new(config);
+
+        // 0 disables idle eviction.
+        let idle_ttl_mins = match proxy_config::var("CODEX_OPENAI_PROXY_THREAD_IDLE_TTL_MINS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .context("parse CODEX_OPENAI_PROXY_THREAD_IDLE_TTL_MINS")?,
+            Err(_) => DEFAULT_THREAD_IDLE_TTL_MINS,
+        };
+        builder.thread_idle_ttl =
+            (idle_ttl_mins > 0).then(|| Duration::from_secs(idle_ttl_mins * 60));
+        builder.interrupt_previous_turn =
+            proxy_config::var("CODEX_OPENAI_PROXY_INTERRUPT_PREVIOUS_TURN")
+                .map(|value| matches!(value.as_str(), "1" | "true"))
+                .unwrap_or(false);
+        builder.allow_danger_full_access =
+            proxy_config::var("CODEX_OPENAI_PROXY_ALLOW_DANGER_FULL_ACCESS")
+                .map(|value| matches!(value.as_str(), "1" | "true"))
+                .unwrap_or(false);
+        // Bearer token `/admin/*` and `/v1/sessions` require.
+        builder.admin_token = proxy_config::var("CODEX_OPENAI_PROXY_ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+
+        if let Ok(url) = proxy_config::var("CODEX_OPENAI_PROXY_APPROVAL_WEBHOOK_URL") {
+            let timeout_secs =
+                match proxy_config::var("CODEX_OPENAI_PROXY_APPROVAL_WEBHOOK_TIMEOUT_SECS") {
+                    Ok(value) => value
+                        .parse::<u64>()
+                        .context("parse CODEX_OPENAI_PROXY_APPROVAL_WEBHOOK_TIMEOUT_SECS")?,
+                    Err(_) => DEFAULT_APPROVAL_WEBHOOK_TIMEOUT_SECS,
+                };
+            // Applied when the webhook fails or times out; deny unless told otherwise.
+            let fallback = match proxy_config::var("CODEX_OPENAI_PROXY_APPROVAL_WEBHOOK_FALLBACK") {
+                Ok(value) => approvals::parse_webhook_fallback(&value).with_context(|| {
+                    format!(
+                        "CODEX_OPENAI_PROXY_APPROVAL_WEBHOOK_FALLBACK must be deny or approve, got {value:?}"
+                    )
+                })?,
+                Err(_) => ReviewDecision::Denied,
+            };
+            builder.approval_webhook = Some((url, Duration::from_secs(timeout_secs), fallback));
+        }
+
+        // Roots that requests may choose a `cwd` under, separated like PATH.
+        builder.workspace_roots = proxy_config::var_os("CODEX_OPENAI_PROXY_WORKSPACE_ROOTS")
+            .map(|roots| env::split_paths(&roots).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if let Ok(value) = proxy_config::var("CODEX_OPENAI_PROXY_WORKSPACE_OUTSIDE") {
+            let outside = OutsideRootPolicy::parse(&value).with_context(|| {
+                format!(
+                    "CODEX_OPENAI_PROXY_WORKSPACE_OUTSIDE must be reject or clamp, got {value:?}"
+                )
+            })?;
+            builder.clamp_outside_workspaces = matches!(outside, OutsideRootPolicy::Clamp);
+        }
+        // Tool servers requests may bring: comma-separated commands for stdio
+        // servers and URL prefixes for HTTP ones. Unset allows none.
+        let list = |name: &str| {
+            proxy_config::var(name)
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(str::to_string)
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        };
+        builder.mcp_server_commands = list("CODEX_OPENAI_PROXY_MCP_SERVER_COMMANDS");
+        builder.mcp_server_urls = list("CODEX_OPENAI_PROXY_MCP_SERVER_URLS");
+
+        // Rate limits, turn slots, the output cap and the body limit, which a
+        // reload can change.
+        let limits = Limits::from_env()?;
+        builder.rate_limit_rpm = limits.rate_limit_rpm;
+        builder.rate_limit_tpm = limits.rate_limit_tpm;
+        builder.max_concurrent_turns = limits.max_concurrent_turns;
+        builder.turn_queue_size = limits.turn_queue_size;
+        builder.max_output_tokens = limits.max_output_tokens;
+        builder.max_request_body_bytes = limits.max_request_body_bytes;
+        // Caches non-streaming responses to identical new-conversation
+        // requests; 0 (the default) disables the cache.
+        builder.response_cache_ttl =
+            env_limit("CODEX_OPENAI_PROXY_RESPONSE_CACHE_TTL_SECS")?.map(Duration::from_secs);
+        // How many batch requests run at once across all batches.
+        builder.batch_concurrency = match proxy_config::var("CODEX_OPENAI_PROXY_BATCH_CONCURRENCY")
+        {
+            Ok(value) => value
+                .parse::<usize>()
+                .context("parse CODEX_OPENAI_PROXY_BATCH_CONCURRENCY")?,
+            Err(_) => DEFAULT_BATCH_CONCURRENCY,
+        };
+        // How long responses are replayed for a repeated `Idempotency-Key`; 0
+        // disables idempotency keys.
+        if proxy_config::var_os("CODEX_OPENAI_PROXY_IDEMPOTENCY_TTL_SECS").is_some() {
+            builder.idempotency_ttl =
+                env_limit("CODEX_OPENAI_PROXY_IDEMPOTENCY_TTL_SECS")?.map(Duration::from_secs);
+        }
+        // How long Responses API objects can be fetched by id; 0 disables
+        // `GET /v1/responses/{id}`.
+        if proxy_config::var_os("CODEX_OPENAI_PROXY_RESPONSE_STORE_TTL_SECS").is_some() {
+            builder.response_store_ttl =
+                env_limit("CODEX_OPENAI_PROXY_RESPONSE_STORE_TTL_SECS")?.map(Duration::from_secs);
+        }
+        // SQLite file every request is recorded in (`:memory:` keeps it in
+        // memory); rows older than the retention are deleted, 0 keeps them all.
+        builder.ledger_path =
+            proxy_config::var_os("CODEX_OPENAI_PROXY_LEDGER_PATH").map(PathBuf::from);
+        if proxy_config::var_os("CODEX_OPENAI_PROXY_LEDGER_RETENTION_DAYS").is_some() {
+            builder.ledger_retention = env_limit("CODEX_OPENAI_PROXY_LEDGER_RETENTION_DAYS")?
+                .map(|days| Duration::from_secs(days * 86_400));
+        }
+        // Directory `POST /v1/files` uploads are kept in.
+        builder.files_dir = proxy_config::var_os("CODEX_OPENAI_PROXY_FILES_DIR").map(PathBuf::from);
+        // Keep-alives on idle streams, as empty comments or `ping` events.
+        builder.sse_keep_alive = SseKeepAlive::from_env()?;
+        // Events buffered per stream, and what a full buffer does with text
+        // deltas: wait for the client, merge them, or drop them.
+        (builder.stream_buffer_capacity, builder.backpressure) =
+            stream_buffer::settings_from_env()?;
+        // Where text deltas end: wherever tokens do, or on word or
+        // sentence boundaries.
+        builder.stream_granularity = StreamGranularity::from_env()?;
+        // Minimum spacing of text deltas; 0 sends each as it arrives.
+        builder.stream_min_interval = stream_buffer::min_interval_from_env()?;
+        // JSONL file every request is audited in, and whether it keeps prompt
+        // text (`full`) or only its hash (`hash`, the default).
+        if let Some(path) = proxy_config::var_os("CODEX_OPENAI_PROXY_AUDIT_LOG") {
+            let content = match proxy_config::var("CODEX_OPENAI_PROXY_AUDIT_LOG_CONTENT") {
+                Ok(value) => AuditContent::parse(&value).with_context(|| {
+                    format!(
+                        "CODEX_OPENAI_PROXY_AUDIT_LOG_CONTENT must be hash or full, got {value:?}"
+                    )
+                })?,
+                Err(_) => AuditContent::default(),
+            };
+            builder.audit_log = Some((PathBuf::from(path), content));
+        }
+        // Built-in PII patterns (comma-separated, or `all`) and extra regexes
+        // (one per line) scrubbed from logs, and from prompts if asked to.
+        if let Ok(builtins) = proxy_config::var("CODEX_OPENAI_PROXY_REDACT") {
+            builder.redact_builtins = builtins
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Ok(patterns) = proxy_config::var("CODEX_OPENAI_PROXY_REDACT_PATTERNS") {
+            builder.redact_patterns = patterns
+                .lines()
+                .filter(|pattern| !pattern.trim().is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        builder.redact_prompts = proxy_config::var("CODEX_OPENAI_PROXY_REDACT_PROMPTS")
+            .map(|value| matches!(value.as_str(), "1" | "true"))
+            .unwrap_or(false);
+        // Input is checked against a file of regexes and/or an OpenAI-style
+        // moderation endpoint before a turn is submitted.
+        builder.moderation_rules =
+            proxy_config::var_os("CODEX_OPENAI_PROXY_MODERATION_RULES").map(PathBuf::from);
+        if let Ok(url) = proxy_config::var("CODEX_OPENAI_PROXY_MODERATION_URL") {
+            let timeout_secs = match proxy_config::var("CODEX_OPENAI_PROXY_MODERATION_TIMEOUT_SECS")
+            {
+                Ok(value) => value
+                    .parse::<u64>()
+                    .context("parse CODEX_OPENAI_PROXY_MODERATION_TIMEOUT_SECS")?,
+                Err(_) => DEFAULT_MODERATION_TIMEOUT_SECS,
+            };
+            let api_key = proxy_config::var("CODEX_OPENAI_PROXY_MODERATION_API_KEY").ok();
+            builder.moderation_endpoint = Some((url, api_key, Duration::from_secs(timeout_secs)));
+        }
+        // Notified when turns end: every batch turn, and interactive ones
+        // that ran for at least the minimum (0 for all of them).
+        if let Ok(url) = proxy_config::var("CODEX_OPENAI_PROXY_TURN_WEBHOOK_URL") {
+            let secs = |name: &str, default: u64| match proxy_config::var(name) {
+                Ok(value) => value
+                    .parse::<u64>()
+                    .with_context(|| format!("parse {name}")),
+                Err(_) => Ok(default),
+            };
+            let timeout_secs = secs(
+                "CODEX_OPENAI_PROXY_TURN_WEBHOOK_TIMEOUT_SECS",
+                DEFAULT_TURN_WEBHOOK_TIMEOUT_SECS,
+            )?;
+            let min_secs = secs("CODEX_OPENAI_PROXY_TURN_WEBHOOK_MIN_SECS", 0)?;
+            builder.turn_webhook = Some((
+                url,
+                Duration::from_secs(timeout_secs),
+                Duration::from_secs(min_secs),
+            ));
+        }
+        // Comma-separated origins browsers may call from; unset allows any.
+        if let Ok(origins) = proxy_config::var("CODEX_OPENAI_PROXY_CORS_ORIGINS") {
+            builder.cors_origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        Ok(builder)
+    }
+
+    /// `-c`-style overrides applied to every config the proxy loads, on top
+    /// of the Codex home's `config.toml`.
+    pub fn config_overrides(mut self, overrides: Vec<(String, toml::Value)>) -> Self {
+        self.config_overrides = overrides;
+        self
+    }
+
+    /// Sends every model request to a local fake that answers with scripted
+    /// deltas, tool calls and errors instead of a real provider, for testing
+    /// clients without credentials or network access. Replaces any
+    /// [`AppStateBuilder::config_overrides`].
+    pub async fn mock_backend(self) -> anyhow::Result<Self> {
+        let addr = mock_backend::spawn().await?;
+        Ok(self.config_overrides(mock_backend::config_overrides(addr)))
+    }
+
+    /// Maps client-facing model names to Codex models with the file at
+    /// `path` (see `CODEX_OPENAI_PROXY_MODEL_MAP`), re-read on
+    /// `POST /admin/config/reload`; without one every name runs as given.
+    /// Fails when the file points at a model Codex cannot run, which is
+    /// checked with the thread manager, so set a custom one first.
+    pub async fn model_map(mut self, path: &Path) -> anyhow::Result<Self> {
+        let thread_manager = match &self.thread_manager {
+            Some(thread_manager) => thread_manager.clone(),
+            None => {
+                let auth_manager = self
+                    .auth_manager
+                    .get_or_insert_with(|| default_auth_manager(&self.config))
+                    .clone();
+                let thread_manager = default_thread_manager(&self.config, auth_manager);
+                self.thread_manager = Some(thread_manager.clone());
+                thread_manager
+            }
+        };
+        let known = known_models(&thread_manager, &self.config).await;
+        let model_map = ModelMapping::load(path, &|model| known.contains(model))?;
+        Ok(self.model_mapping(Arc::new(model_map)))
+    }
+
+    /// A model map the caller already loaded.
+    pub(crate) fn model_mapping(mut self, model_map: Arc<ModelMapping>) -> Self {
+        self.model_map = model_map;
+        self
+    }
+
+    /// Defaults to a manager for the config's Codex home.
+    pub fn auth_manager(mut self, auth_manager: Arc<AuthManager>) -> Self {
+        self.auth_manager = Some(auth_manager);
+        self
+    }
+
+    /// Defaults to a manager for the config's Codex home.
+    pub fn thread_manager(mut self, thread_manager: Arc<ThreadManager>) -> Self {
+        self.thread_manager = Some(thread_manager);
+        self
+    }
+
+    /// The filter `PUT /admin/log-level` swaps; without it that endpoint
+    /// fails, since the host application owns logging.
+    pub fn log_filter(mut self, log_filter: reload::Handle<EnvFilter, Registry>) -> Self {
+        self.log_filter = Some(log_filter);
+        self
+    }
+
+    /// How long a conversation may sit idle before its thread is shut down;
+    /// `None` keeps threads until they are deleted.
+    pub fn thread_idle_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.thread_idle_ttl = ttl;
+        self
+    }
+
+    /// Interrupt a running turn when another request arrives on the same
+    /// conversation instead of waiting for it.
+    pub fn interrupt_previous_turn(mut self, interrupt: bool) -> Self {
+        self.interrupt_previous_turn = interrupt;
+        self
+    }
+
+    /// Lets requests ask for `sandbox_mode: "danger-full-access"`, running
+    /// their turns with no sandbox. Only for trusted automation; every such
+    /// request is logged and marked in the audit log.
+    pub fn allow_danger_full_access(mut self, allow: bool) -> Self {
+        self.allow_danger_full_access = allow;
+        self
+    }
+
+    /// Requires `token` as the bearer token of `/admin/*` and `/v1/sessions`.
+    pub fn admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_token = token;
+        self
+    }
+
+    /// The address the proxy is served on. Without an admin token, the
+    /// admin endpoints answer 403 unless it is a loopback address; left
+    /// unset, as when mounted in another app, they are open.
+    pub fn listen_addr(mut self, addr: SocketAddr) -> Self {
+        self.listen_addr = Some(addr);
+        self
+    }
+
+    /// POSTs approval requests to `url`; `fallback` applies when it fails or
+    /// does not answer within `timeout`.
+    pub fn approval_webhook(
+        mut self,
+        url: impl Into<String>,
+        timeout: Duration,
+        fallback: ReviewDecision,
+    ) -> Self {
+        self.approval_webhook = Some((url.into(), timeout, fallback));
+        self
+    }
+
+    /// Directories requests may pick a `cwd` under. A `cwd` outside them is
+    /// rejected, or replaced by the default workspace with `clamp_outside`.
+    pub fn workspace_roots(mut self, roots: Vec<PathBuf>, clamp_outside: bool) -> Self {
+        self.workspace_roots = roots;
+        self.clamp_outside_workspaces = clamp_outside;
+        self
+    }
+
+    /// Tool servers requests may add to their conversation through
+    /// `mcp_servers`: stdio servers started with one of `commands`, and HTTP
+    /// servers whose URL starts with one of `url_prefixes` (end them with
+    /// `/` so a prefix cannot match another host).
+    pub fn mcp_server_allowlist(
+        mut self,
+        commands: Vec<String>,
+        url_prefixes: Vec<String>,
+    ) -> Self {
+        self.mcp_server_commands = commands;
+        self.mcp_server_urls = url_prefixes;
+        self
+    }
+
+    /// Per-client requests and tokens per minute.
+    pub fn rate_limits(mut self, rpm: Option<u64>, tpm: Option<u64>) -> Self {
+        self.rate_limit_rpm = rpm;
+        self.rate_limit_tpm = tpm;
+        self
+    }
+
+    /// How many turns may run at once; `None` is unlimited.
+    pub fn max_concurrent_turns(mut self, max: Option<usize>) -> Self {
+        self.max_concurrent_turns = max;
+        self
+    }
+
+    /// With a turn limit, how many requests may wait for a slot.
+    pub fn turn_queue_size(mut self, size: usize) -> Self {
+        self.turn_queue_size = size;
+        self
+    }
+
+    /// Caches non-streaming responses to identical new-conversation requests.
+    pub fn response_cache_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.response_cache_ttl = ttl;
+        self
+    }
+
+    /// How many batch requests run at once across all batches.
+    pub fn batch_concurrency(mut self, concurrency: usize) -> Self {
+        self.batch_concurrency = concurrency;
+        self
+    }
+
+    /// How long responses are replayed for a repeated `Idempotency-Key`;
+    /// `None` ignores the header.
+    pub fn idempotency_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.idempotency_ttl = ttl;
+        self
+    }
+
+    /// How long Responses API objects can be fetched with
+    /// `GET /v1/responses/{id}`; `None` keeps none.
+    pub fn response_store_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.response_store_ttl = ttl;
+        self
+    }
+
+    /// The SQLite request ledger (`:memory:` keeps it in memory) and how long
+    /// rows are kept; `None` keeps them all. Defaults to
+    /// `$CODEX_HOME/openai-proxy/ledger.sqlite`.
+    pub fn ledger(mut self, path: PathBuf, retention: Option<Duration>) -> Self {
+        self.ledger_path = Some(path);
+        self.ledger_retention = retention;
+        self
+    }
+
+    /// The directory `POST /v1/files` uploads are kept in, one subdirectory
+    /// per conversation. Defaults to `$CODEX_HOME/openai-proxy/files`.
+    pub fn files_dir(mut self, dir: PathBuf) -> Self {
+        self.files_dir = Some(dir);
+        self
+    }
+
+    /// The most output tokens any answer may have; requests can only ask for
+    /// fewer. Longer answers are cut off with `finish_reason: "length"`.
+    pub fn max_output_tokens(mut self, max: Option<u64>) -> Self {
+        self.max_output_tokens = max;
+        self
+    }
+
+    /// How often idle streams get a keep-alive (`None` sends none) and
+    /// whether it is an `event: ping` rather than an empty `:` comment, for
+    /// intermediaries and SSE parsers that ignore comments.
+    pub fn sse_keep_alive(mut self, interval: Option<Duration>, ping: bool) -> Self {
+        self.sse_keep_alive = SseKeepAlive::new(interval, ping);
+        self
+    }
+
+    /// How many events each stream buffers for its client, and what happens
+    /// to text deltas once a slow client lets the buffer fill up.
+    pub fn stream_buffer(mut self, capacity: usize, backpressure: BackpressurePolicy) -> Self {
+        self.stream_buffer_capacity = capacity;
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Re-chunks text deltas to end on word or sentence boundaries, unless
+    /// a request sets its own `stream_granularity`.
+    pub fn stream_granularity(mut self, granularity: StreamGranularity) -> Self {
+        self.stream_granularity = granularity;
+        self
+    }
+
+    /// Holds back text deltas arriving sooner than this after the previous
+    /// one and sends them merged, unless a request sets its own
+    /// `stream_min_interval_ms`. `None` sends every delta as it arrives.
+    pub fn stream_min_interval(mut self, min_interval: Option<Duration>) -> Self {
+        self.stream_min_interval = min_interval;
+        self
+    }
+
+    /// The largest request body accepted outside `/v1/batches`; bigger ones
+    /// get a 413 `request_too_large` error. `None` lifts the limit.
+    pub fn max_request_body_bytes(mut self, max: Option<usize>) -> Self {
+        self.max_request_body_bytes = max;
+        self
+    }
+
+    /// Appends a JSON line per request to `path`: its prompt's SHA-256, the
+    /// model, commands run, files changed and outcome. The prompt text itself
+    /// is only written with `keep_prompts`.
+    pub fn audit_log(mut self, path: PathBuf, keep_prompts: bool) -> Self {
+        let content = if keep_prompts {
+            AuditContent::Full
+        } else {
+            AuditContent::Hash
+        };
+        self.audit_log = Some((path, content));
+        self
+    }
+
+    /// Redacts PII from the `/logs` stream and the audit log: the built-in
+    /// `email`, `api_key` and `credit_card` patterns named in `builtins` (or
+    /// `all` of them) and the regexes in `patterns`. With `scrub_prompts`,
+    /// prompts are redacted before they reach the model too.
+    pub fn redaction(
+        mut self,
+        builtins: Vec<String>,
+        patterns: Vec<String>,
+        scrub_prompts: bool,
+    ) -> Self {
+        self.redact_builtins = builtins;
+        self.redact_patterns = patterns;
+        self.redact_prompts = scrub_prompts;
+        self
+    }
+
+    /// Rejects input matching any regex in `path` (one per line, `#` for
+    /// comments) with a 400 `content_policy` error before a turn starts.
+    pub fn moderation_rules(mut self, path: PathBuf) -> Self {
+        self.moderation_rules = Some(path);
+        self
+    }
+
+    /// Sends input to an OpenAI-style moderation endpoint before a turn
+    /// starts and rejects what it flags. Requests fail with a 502 when the
+    /// endpoint cannot be reached within `timeout`.
+    pub fn moderation_endpoint(
+        mut self,
+        url: impl Into<String>,
+        api_key: Option<String>,
+        timeout: Duration,
+    ) -> Self {
+        self.moderation_endpoint = Some((url.into(), api_key, timeout));
+        self
+    }
+
+    /// POSTs a JSON notification to `url` when a request's turns end, with
+    /// its response id, usage and a summary of the answer. Batch turns are
+    /// always notified, others only when they took at least `min_duration`.
+    pub fn turn_webhook(
+        mut self,
+        url: impl Into<String>,
+        timeout: Duration,
+        min_duration: Duration,
+    ) -> Self {
+        self.turn_webhook = Some((url.into(), timeout, min_duration));
+        self
+    }
+
+    /// Runs `interceptor` on every request before it is routed; it can
+    /// change headers, rewrite the JSON body or answer the request itself.
+    pub fn request_interceptor(mut self, interceptor: impl RequestInterceptor) -> Self {
+        self.interceptors.request.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Runs `interceptor` on the head of every response before it is sent.
+    pub fn response_interceptor(mut self, interceptor: impl ResponseInterceptor) -> Self {
+        self.interceptors.response.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Runs `interceptor` on every Codex event of every turn before the
+    /// handlers translate it; it can rewrite or drop the event.
+    pub fn event_interceptor(mut self, interceptor: impl EventInterceptor) -> Self {
+        self.interceptors.event.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Origins browsers may call the proxy from, e.g.
+    /// `https://app.example.com`; empty (the default) allows any.
+    pub fn cors_origins(mut self, origins: Vec<String>) -> Self {
+        self.cors_origins = origins;
+        self
+    }
+
+    /// Opens the ledger and starts the idle-thread reaper, so this must run
+    /// inside a Tokio runtime.
+    pub fn build(self) -> anyhow::Result<AppState> {
+        let config = self.config;
+        let auth_manager = self
+            .auth_manager
+            .unwrap_or_else(|| default_auth_manager(&config));
+        let thread_manager = self
+            .thread_manager
+            .unwrap_or_else(|| default_thread_manager(&config, auth_manager.clone()));
+        // Not installed in any subscriber unless the caller passed one in.
+        let log_filter = self
+            .log_filter
+            .unwrap_or_else(|| reload::Layer::new(EnvFilter::from_default_env()).1);
+
+        let threads = Arc::new(ThreadRegistry::new(
+            thread_manager.clone(),
+            auth_manager.clone(),
+            ConversationStore::load_from_home(&config.codex_home),
+            AttachmentStore::for_home(&config.codex_home),
+            self.thread_idle_ttl,
+            self.interrupt_previous_turn,
+        ));
+        threads.spawn_reaper();
+        let approval_webhook = self
+            .approval_webhook
+            .map(|(url, timeout, fallback)| ApprovalWebhook::new(url, timeout, fallback));
+        let outside = if self.clamp_outside_workspaces {
+            OutsideRootPolicy::Clamp
+        } else {
+            OutsideRootPolicy::Reject
+        };
+        let workspaces = WorkspacePolicy::new(self.workspace_roots, outside)?;
+        let ledger_path = self
+            .ledger_path
+            .unwrap_or_else(|| config.codex_home.join("openai-proxy").join("ledger.sqlite"));
+        let ledger = Ledger::open(&ledger_path, self.ledger_retention)?;
+        let files_dir = self
+            .files_dir
+            .unwrap_or_else(|| config.codex_home.join("openai-proxy").join("files"));
+        let redactor = Redactor::new(
+            &self.redact_builtins,
+            &self.redact_patterns,
+            self.redact_prompts,
+        )?
+        .map(Arc::new);
+        if let Some(redactor) = &redactor {
+            redaction::redact_logs_with(redactor.clone());
+        }
+        let audit_log = match &self.audit_log {
+            Some((path, content)) => {
+                Some(Arc::new(AuditLog::open(path, *content, redactor.clone())?))
+            }
+            None => None,
+        };
+        if self.allow_danger_full_access {
+            sandbox_mode::warn_full_access_allowed(audit_log.is_some());
+        }
+        let moderation =
+            Moderation::new(self.moderation_rules.as_deref(), self.moderation_endpoint)?
+                .map(Arc::new);
+        let cors_origins = self
+            .cors_origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin)
+                    .with_context(|| format!("invalid CORS origin {origin:?}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(AppState {
+            thread_manager,
+            auth_manager,
+            configs: Arc::new(
+                ConfigCache::for_codex_home(config.codex_home.clone())
+                    .with_base_overrides(self.config_overrides),
+            ),
+            threads,
+            approvals: Arc::new(ApprovalRegistry::new(approval_webhook)),
+            allow_danger_full_access: self.allow_danger_full_access,
+            admin_access: AdminAccess::new(self.admin_token, self.listen_addr),
+            completions: Arc::new(RunningCompletions::default()),
+            workspaces: Arc::new(workspaces),
+            mcp_servers: Arc::new(McpServerAllowlist::new(
+                self.mcp_server_commands,
+                self.mcp_server_urls,
+            )),
+            model_map: self.model_map,
+            files: Arc::new(FileStore::new(files_dir)),
+            turn_diffs: Arc::new(TurnDiffs::default()),
+            batches: Arc::new(BatchRegistry::new(self.batch_concurrency)),
+            http_client: reqwest::Client::new(),
+            turn_slots: Arc::new(TurnSlots::new(
+                self.max_concurrent_turns.filter(|max| *max > 0),
+                self.turn_queue_size,
+            )),
+            response_cache: self
+                .response_cache_ttl
+                .map(|ttl| Arc::new(ResponseCache::new(ttl))),
+            idempotency: self
+                .idempotency_ttl
+                .map(|ttl| Arc::new(IdempotencyStore::new(ttl))),
+            response_store: self
+                .response_store_ttl
+                .map(|ttl| Arc::new(ResponseStore::new(ttl))),
+            rate_limiter: Arc::new(RateLimiter::new(self.rate_limit_rpm, self.rate_limit_tpm)),
+            log_filter,
+            tenant: None,
+            ledger: Arc::new(ledger),
+            audit_log,
+            redactor,
+            moderation,
+            turn_webhook: self.turn_webhook.map(|(url, timeout, min_duration)| {
+                Arc::new(TurnWebhook::new(url, timeout, min_duration))
+            }),
+            interceptors: Arc::new(self.interceptors),
+            max_output_tokens: Arc::new(LiveLimit::new(self.max_output_tokens)),
+            sse_keep_alive: self.sse_keep_alive,
+            stream_buffers: Arc::new(
+                StreamBuffers::new(self.stream_buffer_capacity, self.backpressure)
+                    .with_granularity(self.stream_granularity)
+                    .with_min_interval(self.stream_min_interval),
+            ),
+            max_request_body_bytes: Arc::new(LiveLimit::new(
+                self.max_request_body_bytes.map(|n| n as u64),
+            )),
+            cors_origins,
+            tenants: Arc::default(),
+        })
+    }
+}
+
+fn default_auth_manager(config: &Config) -> Arc<AuthManager> {
+    Arc::new(AuthManager::new(
+        config.codex_home.clone(),
+        false,
+        config.cli_auth_credentials_store_mode,
+    ))
+}
+
+fn default_thread_manager(config: &Config, auth_manager: Arc<AuthManager>) -> Arc<ThreadManager> {
+    Arc::new(ThreadManager::new(
+        config.codex_home.clone(),
+        auth_manager,
+        SessionSource::Exec,
+    ))
+}
+
+/// A non-negative number from `name`, where unset or 0 means none.
+pub(crate) fn env_limit(name: &str) -> anyhow::Result<Option<u64>> {
+    match proxy_config::var(name) {
+        Ok(value) => {
+            let limit = value
+                .parse::<u64>()
+                .with_context(|| format!("parse {name}"))?;
+            Ok((limit > 0).then_some(limit))
+        }
+        Err(_) => Ok(None),
+    }
+}
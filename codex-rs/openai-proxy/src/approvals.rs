@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::Json;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use codex_core::CodexThread;
+use codex_core::protocol::AskForApproval;
+use codex_core::protocol::Op;
+use codex_core::protocol::ReviewDecision;
+use codex_protocol::ThreadId;
+use codex_protocol::protocol::EventMsg;
+use serde::Deserialize;
+use tracing::info;
+use tracing::warn;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::admin::redact_url;
+use crate::codex_events::CodexEvent;
+use crate::conversations::parse_conversation_id;
+use crate::json_response;
+use crate::log_message;
+
+/// Resolves a request's `approval_policy`. `on-request` is only accepted when
+/// someone can answer: the client over a stream, or the approval webhook.
+pub(crate) fn approval_policy(
+    requested: Option<&str>,
+    answerable: bool,
+) -> Result<AskForApproval, ProxyError> {
+    match requested {
+        None | Some("never") => Ok(AskForApproval::Never),
+        Some("on-request") if answerable => Ok(AskForApproval::OnRequest),
+        Some("on-request") => Err(ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            "approval_policy \"on-request\" requires stream: true or an approval webhook",
+            "invalid_request_error",
+        )),
+        Some(other) => Err(ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            format!("unsupported approval_policy {other:?}; expected \"never\" or \"on-request\""),
+            "invalid_request_error",
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ApprovalKind {
+    Exec,
+    Patch,
+}
+
+/// An approval Codex is blocked on, keyed by the tool call id it was raised for.
+#[derive(Debug, Clone)]
+struct PendingApproval {
+    thread_id: ThreadId,
+    /// Codex resolves approvals by the id of the turn that raised them.
+    turn_id: String,
+    kind: ApprovalKind,
+}
+
+/// Posts approval requests to an external service and applies its decision,
+/// for automation that cannot answer over a stream.
+#[derive(Clone)]
+pub(crate) struct ApprovalWebhook {
+    client: reqwest::Client,
+    url: String,
+    timeout: Duration,
+    /// Applied when the webhook fails, times out or returns garbage.
+    fallback: ReviewDecision,
+}
+
+impl ApprovalWebhook {
+    pub(crate) fn new(url: String, timeout: Duration, fallback: ReviewDecision) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            timeout,
+            fallback,
+        }
+    }
+
+    async fn decide(&self, payload: &serde_json::Value) -> ReviewDecision {
+        let response = self
+            .client
+            .post(&self.url)
+            .timeout(self.timeout)
+            .json(payload)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        let decision = match response {
+            Ok(response) => response
+                .json::<ApprovalDecisionRequest>()
+                .await
+                .map(|body| body.decision),
+            Err(e) => Err(e),
+        };
+        decision.unwrap_or_else(|e| {
+            warn!(
+                "approval webhook failed, falling back to {}: {e}",
+                self.fallback
+            );
+            self.fallback.clone()
+        })
+    }
+}
+
+/// Parses the fallback decision for the approval webhook: `deny` or `approve`.
+pub(crate) fn parse_webhook_fallback(value: &str) -> Option<ReviewDecision> {
+    match value {
+        "deny" => Some(ReviewDecision::Denied),
+        "approve" => Some(ReviewDecision::Approved),
+        _ => None,
+    }
+}
+
+/// Approvals raised by running turns that are waiting for a decision, either
+/// from the client or from the approval webhook.
+pub(crate) struct ApprovalRegistry {
+    pending: Mutex<HashMap<String, PendingApproval>>,
+    webhook: Option<ApprovalWebhook>,
+}
+
+impl ApprovalRegistry {
+    pub(crate) fn new(webhook: Option<ApprovalWebhook>) -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            webhook,
+        }
+    }
+
+    /// An empty registry for a tenant, answered by the same webhook.
+    pub(crate) fn for_tenant(&self) -> Self {
+        Self::new(self.webhook.clone())
+    }
+
+    pub(crate) fn has_webhook(&self) -> bool {
+        self.webhook.is_some()
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        match &self.webhook {
+            Some(webhook) => serde_json::json!({
+                "webhook_url": redact_url(&webhook.url),
+                "webhook_timeout_secs": webhook.timeout.as_secs(),
+                "webhook_fallback": webhook.fallback,
+            }),
+            None => serde_json::json!({ "webhook_url": null }),
+        }
+    }
+
+    /// Handles the approval carried by `msg`, if any. With a webhook
+    /// configured the decision is fetched and submitted right away; otherwise
+    /// the approval is recorded and the event to stream to the client is
+    /// returned.
+    ///
+    /// A turn running under `never` has no one to ask, so an approval it
+    /// raises anyway is denied on the spot and a `codex.warning` event is
+    /// returned instead; leaving it unanswered would stall the turn.
+    pub(crate) async fn handle(
+        &self,
+        thread: &Arc<CodexThread>,
+        thread_id: ThreadId,
+        turn_id: &str,
+        policy: AskForApproval,
+        msg: &EventMsg,
+    ) -> Option<CodexEvent> {
+        let (approval_id, kind, request) = describe(thread_id, msg)?;
+        if policy == AskForApproval::Never {
+            warn!("denying approval {approval_id} raised under approval_policy never");
+            deny(
+                thread,
+                thread_id,
+                turn_id,
+                kind,
+                &approval_id,
+                "never_policy",
+            )
+            .await;
+            return Some(auto_denied_warning(thread_id, &approval_id, request));
+        }
+        let Some(webhook) = &self.webhook else {
+            if let Ok(mut pending) = self.pending.lock() {
+                pending.insert(
+                    approval_id,
+                    PendingApproval {
+                        thread_id,
+                        turn_id: turn_id.to_string(),
+                        kind,
+                    },
+                );
+            }
+            return Some(request);
+        };
+
+        let decision = webhook.decide(&request.into_event()).await;
+        info!("approval webhook decided {decision} for {approval_id}");
+        log_message(
+            serde_json::json!({
+                "type": "approval_decision",
+                "source": "webhook",
+                "conversation_id": thread_id.to_string(),
+                "approval_id": approval_id,
+                "decision": decision,
+            })
+            .to_string(),
+        );
+        if let Err(e) = thread
+            .submit(decision_op(kind, turn_id.to_string(), decision))
+            .await
+        {
+            warn!("failed to submit webhook approval for {approval_id}: {e}");
+        }
+        None
+    }
+
+    fn take(&self, thread_id: ThreadId, approval_id: &str) -> Option<PendingApproval> {
+        let mut pending = self.pending.lock().ok()?;
+        match pending.get(approval_id) {
+            Some(approval) if approval.thread_id == thread_id => pending.remove(approval_id),
+            _ => None,
+        }
+    }
+
+    /// Submits the client's answer to a pending approval on `thread`.
+    pub(crate) async fn decide(
+        &self,
+        thread: &CodexThread,
+        thread_id: ThreadId,
+        approval_id: &str,
+        decision: ReviewDecision,
+    ) -> Result<(), ProxyError> {
+        let Some(approval) = self.take(thread_id, approval_id) else {
+            return Err(ProxyError::new(
+                StatusCode::NOT_FOUND,
+                format!("no pending approval {approval_id} in conversation {thread_id}"),
+                "invalid_request_error",
+            )
+            .with_code("approval_not_found"));
+        };
+        let op = decision_op(approval.kind, approval.turn_id, decision.clone());
+        thread
+            .submit(op)
+            .await
+            .map_err(|e| ProxyError::internal(format!("failed to submit approval: {e}")))?;
+        log_message(
+            serde_json::json!({
+                "type": "approval_decision",
+                "conversation_id": thread_id.to_string(),
+                "approval_id": approval_id,
+                "decision": decision,
+            })
+            .to_string(),
+        );
+        Ok(())
+    }
+
+    /// Denies the approval carried by `msg`, if any, on behalf of a dry run,
+    /// which must not write anything or leave its sandbox.
+    pub(crate) async fn deny_dry_run(
+        &self,
+        thread: &Arc<CodexThread>,
+        thread_id: ThreadId,
+        turn_id: &str,
+        msg: &EventMsg,
+    ) {
+        if let Some((approval_id, kind, _)) = describe(thread_id, msg) {
+            deny(thread, thread_id, turn_id, kind, &approval_id, "dry_run").await;
+        }
+    }
+
+    /// Drops approvals left unanswered when their turn ends.
+    pub(crate) fn clear_turn(&self, turn_id: &str) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.retain(|_, approval| approval.turn_id != turn_id);
+        }
+    }
+}
+
+/// Extracts the approval id, kind and client-facing event from an approval
+/// request event.
+fn describe(thread_id: ThreadId, msg: &EventMsg) -> Option<(String, ApprovalKind, CodexEvent)> {
+    let described = match msg {
+        EventMsg::ExecApprovalRequest(ev) => (
+            ev.call_id.clone(),
+            ApprovalKind::Exec,
+            CodexEvent {
+                kind: "exec_approval_request",
+                fields: serde_json::json!({
+                    "approval_id": ev.call_id,
+                    "conversation_id": thread_id.to_string(),
+                    "command": ev.command,
+                    "cwd": ev.cwd,
+                    "reason": ev.reason,
+                }),
+            },
+        ),
+        EventMsg::ApplyPatchApprovalRequest(ev) => (
+            ev.call_id.clone(),
+            ApprovalKind::Patch,
+            CodexEvent {
+                kind: "apply_patch_approval_request",
+                fields: serde_json::json!({
+                    "approval_id": ev.call_id,
+                    "conversation_id": thread_id.to_string(),
+                    "changes": ev.changes,
+                    "reason": ev.reason,
+                    "grant_root": ev.grant_root,
+                }),
+            },
+        ),
+        _ => return None,
+    };
+    Some(described)
+}
+
+/// The `codex.warning` event reporting an approval denied because the turn
+/// runs under `never`.
+fn auto_denied_warning(thread_id: ThreadId, approval_id: &str, request: CodexEvent) -> CodexEvent {
+    CodexEvent {
+        kind: "codex.warning",
+        fields: serde_json::json!({
+            "code": "approval_auto_denied",
+            "message": format!(
+                "Codex requested approval {approval_id} although approval_policy is \"never\"; \
+                 it was denied"
+            ),
+            "conversation_id": thread_id.to_string(),
+            "approval_id": approval_id,
+            "request": request.into_event(),
+        }),
+    }
+}
+
+/// Denies an approval no one is asked about; `source` says why in the log.
+async fn deny(
+    thread: &CodexThread,
+    thread_id: ThreadId,
+    turn_id: &str,
+    kind: ApprovalKind,
+    approval_id: &str,
+    source: &str,
+) {
+    log_message(
+        serde_json::json!({
+            "type": "approval_decision",
+            "source": source,
+            "conversation_id": thread_id.to_string(),
+            "approval_id": approval_id,
+            "decision": ReviewDecision::Denied,
+        })
+        .to_string(),
+    );
+    if let Err(e) = thread
+        .submit(decision_op(
+            kind,
+            turn_id.to_string(),
+            ReviewDecision::Denied,
+        ))
+        .await
+    {
+        warn!("failed to deny approval {approval_id}: {e}");
+    }
+}
+
+fn decision_op(kind: ApprovalKind, turn_id: String, decision: ReviewDecision) -> Op {
+    match kind {
+        ApprovalKind::Exec => Op::ExecApproval {
+            id: turn_id,
+            decision,
+        },
+        ApprovalKind::Patch => Op::PatchApproval {
+            id: turn_id,
+            decision,
+        },
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ApprovalDecisionRequest {
+    decision: ReviewDecision,
+}
+
+/// `POST /v1/conversations/{id}/approvals/{approval_id}`: answers an approval
+/// request streamed earlier in the conversation.
+pub(crate) async fn handle_approval_decision(
+    State(state): State<AppState>,
+    Path((id, approval_id)): Path<(String, String)>,
+    Json(body): Json<ApprovalDecisionRequest>,
+) -> Response {
+    let thread_id = match parse_conversation_id(&id) {
+        Ok(thread_id) => thread_id,
+        Err(e) => return e.into_response(),
+    };
+    let thread = match state.threads.get(thread_id) {
+        Ok(thread) => thread,
+        Err(e) => return e.into_response(),
+    };
+    let decision = body.decision;
+    if let Err(e) = state
+        .approvals
+        .decide(&thread, thread_id, &approval_id, decision.clone())
+        .await
+    {
+        return e.into_response();
+    }
+
+    json_response(
+        StatusCode::OK,
+        serde_json::json!({
+            "id": approval_id,
+            "object": "conversation.approval",
+            "conversation_id": id,
+            "decision": decision,
+        })
+        .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn on_request_requires_streaming() {
+        assert_eq!(
+            approval_policy(None, false).ok(),
+            Some(AskForApproval::Never)
+        );
+        assert_eq!(
+            approval_policy(Some("on-request"), true).ok(),
+            Some(AskForApproval::OnRequest)
+        );
+        assert_eq!(
+            approval_policy(Some("on-request"), false)
+                .err()
+                .map(|e| e.status),
+            Some(StatusCode::BAD_REQUEST)
+        );
+        assert!(approval_policy(Some("on-failure"), true).is_err());
+    }
+
+    #[test]
+    fn webhook_fallback_accepts_deny_or_approve() {
+        assert_eq!(parse_webhook_fallback("deny"), Some(ReviewDecision::Denied));
+        assert_eq!(
+            parse_webhook_fallback("approve"),
+            Some(ReviewDecision::Approved)
+        );
+        assert_eq!(parse_webhook_fallback("abort"), None);
+    }
+
+    #[test]
+    fn auto_denied_warning_carries_the_request() {
+        let thread_id = ThreadId::new();
+        let msg = EventMsg::ExecApprovalRequest(
+            serde_json::from_value(serde_json::json!({
+                "call_id": "call-1",
+                "command": ["rm", "-rf", "build"],
+                "cwd": "/tmp",
+                "parsed_cmd": [],
+            }))
+            .expect("exec approval request"),
+        );
+        let (approval_id, kind, request) = describe(thread_id, &msg).expect("approval");
+        assert_eq!(kind, ApprovalKind::Exec);
+
+        let warning = auto_denied_warning(thread_id, &approval_id, request).into_event();
+        assert_eq!(warning["type"], "codex.warning");
+        assert_eq!(warning["code"], "approval_auto_denied");
+        assert_eq!(warning["approval_id"], "call-1");
+        assert_eq!(warning["request"]["type"], "exec_approval_request");
+        assert_eq!(warning["request"]["command"][0], "rm");
+    }
+}
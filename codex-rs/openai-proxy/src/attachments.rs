@@ -0,0 +1,318 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use axum::http::StatusCode;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use codex_protocol::ThreadId;
+
+use crate::ProxyError;
+use crate::files::FileStore;
+
+/// Largest attachment the proxy will write to disk.
+pub(crate) const MAX_ATTACHMENT_BYTES: usize = 50 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AttachmentSource {
+    /// Inline contents: a base64 data URL or bare base64.
+    Data(String),
+    /// A file uploaded with `POST /v1/files`.
+    FileId(String),
+}
+
+/// A file content part from a chat (`{"type":"file"}`) or Responses
+/// (`{"type":"input_file"}`) request, or a Messages document block
+/// (`{"type":"document"}`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Attachment {
+    filename: Option<String>,
+    source: AttachmentSource,
+}
+
+/// Collects file parts, including parts nested in a message's `content`.
+pub(crate) fn collect_attachments(value: &serde_json::Value) -> Vec<Attachment> {
+    let mut attachments = Vec::new();
+    let serde_json::Value::Array(items) = value else {
+        return attachments;
+    };
+    for item in items {
+        let file = match item.get("type").and_then(|t| t.as_str()) {
+            // Chat nests the fields under `file`; Responses puts them inline.
+            Some("file") => item.get("file"),
+            Some("input_file") => Some(item),
+            Some("document") => {
+                attachments.extend(anthropic_document(item));
+                continue;
+            }
+            _ => {
+                if let Some(content) = item.get("content") {
+                    attachments.extend(collect_attachments(content));
+                }
+                continue;
+            }
+        };
+        let Some(file) = file else {
+            continue;
+        };
+        let field = |name: &str| file.get(name).and_then(|v| v.as_str()).map(str::to_string);
+        let source = match (field("file_data"), field("file_id")) {
+            (Some(data), _) => AttachmentSource::Data(data),
+            (None, Some(file_id)) => AttachmentSource::FileId(file_id),
+            (None, None) => continue,
+        };
+        attachments.push(Attachment {
+            filename: field("filename"),
+            source,
+        });
+    }
+    attachments
+}
+
+/// A Messages document block with a `base64` or `file` source.
+fn anthropic_document(block: &serde_json::Value) -> Option<Attachment> {
+    let source = block.get("source")?;
+    let field = |name: &str| {
+        source
+            .get(name)
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+    };
+    let source = match source.get("type").and_then(|t| t.as_str())? {
+        "base64" => AttachmentSource::Data(field("data")?),
+        "file" => AttachmentSource::FileId(field("file_id")?),
+        _ => return None,
+    };
+    Some(Attachment {
+        filename: block
+            .get("title")
+            .and_then(|t| t.as_str())
+            .map(str::to_string),
+        source,
+    })
+}
+
+/// An attachment checked before its turn starts: an upload's path, or
+/// decoded contents still to be written.
+#[derive(Debug)]
+pub(crate) enum PreparedAttachment {
+    Upload(PathBuf),
+    Inline { name: String, contents: Vec<u8> },
+}
+
+/// Decodes inline attachments and looks up uploads, so bad input is
+/// rejected before a thread is started for it.
+pub(crate) fn prepare(
+    attachments: Vec<Attachment>,
+    files: &FileStore,
+) -> Result<Vec<PreparedAttachment>, ProxyError> {
+    attachments
+        .into_iter()
+        .map(|attachment| match &attachment.source {
+            AttachmentSource::Data(data) => Ok(PreparedAttachment::Inline {
+                name: sanitize_filename(attachment.filename.as_deref()),
+                contents: decode_file_data(data)?,
+            }),
+            AttachmentSource::FileId(file_id) => files
+                .path(file_id)
+                .map(PreparedAttachment::Upload)
+                .ok_or_else(|| {
+                    ProxyError::new(
+                        StatusCode::BAD_REQUEST,
+                        format!("file {file_id} not found"),
+                        "invalid_request_error",
+                    )
+                    .with_code("file_not_found")
+                }),
+        })
+        .collect()
+}
+
+/// Where inline attachments are kept:
+/// `<root>/<conversation id>/<uuid>-<filename>`, removed with the
+/// conversation.
+pub(crate) struct AttachmentStore {
+    root: PathBuf,
+}
+
+impl AttachmentStore {
+    pub(crate) fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// The store kept under `codex_home`.
+    pub(crate) fn for_home(codex_home: &Path) -> Self {
+        Self::new(codex_home.join("openai-proxy").join("attachments"))
+    }
+
+    /// Writes each inline attachment under `thread_id`'s directory and
+    /// returns the paths of all of them; uploads are read where they were
+    /// stored.
+    pub(crate) fn materialize(
+        &self,
+        thread_id: ThreadId,
+        attachments: Vec<PreparedAttachment>,
+    ) -> Result<Vec<PathBuf>, ProxyError> {
+        let dir = self.root.join(thread_id.to_string());
+        let mut paths = Vec::with_capacity(attachments.len());
+        for attachment in attachments {
+            let (name, contents) = match attachment {
+                PreparedAttachment::Upload(path) => {
+                    paths.push(path);
+                    continue;
+                }
+                PreparedAttachment::Inline { name, contents } => (name, contents),
+            };
+            std::fs::create_dir_all(&dir).map_err(|e| {
+                ProxyError::internal(format!("failed to create {}: {e}", dir.display()))
+            })?;
+            let path = dir.join(format!("{}-{name}", uuid::Uuid::new_v4()));
+            std::fs::write(&path, contents).map_err(|e| {
+                ProxyError::internal(format!("failed to write {}: {e}", path.display()))
+            })?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// Deletes the attachments of a conversation that is going away.
+    pub(crate) async fn remove_conversation(&self, thread_id: ThreadId) {
+        let dir = self.root.join(thread_id.to_string());
+        match tokio::fs::remove_dir_all(&dir).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("failed to remove {}: {e}", dir.display()),
+        }
+    }
+}
+
+/// Appends the attachment paths to the turn text so the model knows to read
+/// them.
+pub(crate) fn with_attachment_note(text: String, paths: &[PathBuf]) -> String {
+    if paths.is_empty() {
+        return text;
+    }
+    let listing = paths
+        .iter()
+        .map(|path| format!("- {}", path.display()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("{text}\n\nAttached files:\n{listing}")
+}
+
+fn decode_file_data(data: &str) -> Result<Vec<u8>, ProxyError> {
+    let encoded = match data.split_once(";base64,") {
+        Some((header, encoded)) if header.starts_with("data:") => encoded,
+        _ => data,
+    };
+    let contents = BASE64_STANDARD.decode(encoded).map_err(|e| {
+        ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            format!("invalid base64 file_data: {e}"),
+            "invalid_request_error",
+        )
+    })?;
+    if contents.len() > MAX_ATTACHMENT_BYTES {
+        return Err(ProxyError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("attachment exceeds {MAX_ATTACHMENT_BYTES} bytes"),
+            "invalid_request_error",
+        ));
+    }
+    Ok(contents)
+}
+
+/// Keeps only the final path component and replaces anything unusual, so
+/// client-supplied names cannot escape the attachments directory.
+pub(crate) fn sanitize_filename(filename: Option<&str>) -> String {
+    let name = filename
+        .and_then(|f| Path::new(f).file_name())
+        .and_then(|f| f.to_str())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if name.trim_matches('.').is_empty() {
+        "attachment".to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn file_parts_are_collected_from_every_shape() {
+        let chat = serde_json::json!([
+            {"type": "text", "text": "analyze"},
+            {"type": "file", "file": {"filename": "a.csv", "file_data": "YSxi"}},
+        ]);
+        let responses = serde_json::json!([{
+            "role": "user",
+            "content": [{"type": "input_file", "file_id": "file-1"}],
+        }]);
+
+        assert_eq!(
+            collect_attachments(&chat),
+            vec![Attachment {
+                filename: Some("a.csv".to_string()),
+                source: AttachmentSource::Data("YSxi".to_string()),
+            }]
+        );
+        assert_eq!(
+            collect_attachments(&responses),
+            vec![Attachment {
+                filename: None,
+                source: AttachmentSource::FileId("file-1".to_string()),
+            }]
+        );
+
+        let messages = serde_json::json!([{
+            "role": "user",
+            "content": [
+                {"type": "document", "title": "a.pdf", "source": {"type": "base64", "media_type": "application/pdf", "data": "JVBE"}},
+                {"type": "document", "source": {"type": "url", "url": "https://example.com/a.pdf"}},
+            ],
+        }]);
+        assert_eq!(
+            collect_attachments(&messages),
+            vec![Attachment {
+                filename: Some("a.pdf".to_string()),
+                source: AttachmentSource::Data("JVBE".to_string()),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn attachments_are_kept_per_conversation() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = AttachmentStore::new(root.path().join("attachments"));
+        let prepared = prepare(
+            vec![Attachment {
+                filename: Some("../../etc/data.csv".to_string()),
+                source: AttachmentSource::Data("data:text/csv;base64,YSxi".to_string()),
+            }],
+            &FileStore::new(root.path().join("files")),
+        )
+        .expect("prepare");
+        let thread_id = ThreadId::new();
+        let paths = store.materialize(thread_id, prepared).expect("materialize");
+
+        let dir = root.path().join("attachments").join(thread_id.to_string());
+        assert_eq!(paths.len(), 1);
+        assert!(paths[0].starts_with(&dir));
+        assert!(paths[0].to_string_lossy().ends_with("-data.csv"));
+        assert_eq!(std::fs::read_to_string(&paths[0]).expect("read"), "a,b");
+
+        store.remove_conversation(thread_id).await;
+        assert!(!dir.exists());
+    }
+}
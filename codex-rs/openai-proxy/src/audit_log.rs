@@ -0,0 +1,361 @@
+//! The audit log: an append-only JSONL file with one line per request — who
+//! sent it, a hash of its prompt, the model, the commands Codex ran, the
+//! files it changed and how it ended. Prompts themselves are only written
+//! when configured to be.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use codex_protocol::protocol::EventMsg;
+use codex_protocol::protocol::TokenUsage;
+use codex_protocol::user_input::UserInput;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use tracing::info;
+use tracing::warn;
+
+use crate::codex_events;
+use crate::codex_events::ChangedFile;
+use crate::redaction::Redactor;
+
+/// How much of each prompt the log keeps.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AuditContent {
+    /// Only a SHA-256 of the prompt.
+    #[default]
+    Hash,
+    /// The hash and the prompt text.
+    Full,
+}
+
+impl AuditContent {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "hash" => Some(Self::Hash),
+            "full" => Some(Self::Full),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Hash => "hash",
+            Self::Full => "full",
+        }
+    }
+}
+
+/// A command Codex ran for the request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct AuditCommand {
+    command: Vec<String>,
+    cwd: PathBuf,
+    exit_code: i32,
+}
+
+/// Tokens the turns a request started used.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct TurnUsage {
+    pub(crate) input_tokens: i64,
+    pub(crate) output_tokens: i64,
+    pub(crate) total_tokens: i64,
+}
+
+impl std::ops::AddAssign for TurnUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+impl TurnUsage {
+    /// Adds what one model call used.
+    pub(crate) fn add(&mut self, usage: &TokenUsage) {
+        self.input_tokens += usage.input_tokens;
+        self.output_tokens += usage.output_tokens;
+        self.total_tokens += usage.total_tokens;
+    }
+}
+
+/// What the turns a request started did, collected while they run.
+#[derive(Debug, Default)]
+pub(crate) struct TurnActivity {
+    prompt: Option<String>,
+    commands: Vec<AuditCommand>,
+    files: Vec<ChangedFile>,
+    /// How the last turn ended: `completed`, `failed` or `aborted`.
+    outcome: Option<&'static str>,
+    error: Option<String>,
+    /// The last turn's final answer.
+    answer: Option<String>,
+    usage: TurnUsage,
+}
+
+impl TurnActivity {
+    /// Notes the text a turn was submitted with.
+    pub(crate) fn record_input(&mut self, items: &[UserInput]) {
+        let text = items
+            .iter()
+            .filter_map(|item| match item {
+                UserInput::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        match &mut self.prompt {
+            Some(prompt) => {
+                prompt.push('\n');
+                prompt.push_str(&text);
+            }
+            None => self.prompt = Some(text),
+        }
+    }
+
+    /// Notes commands run, files patched and how the turn ended.
+    pub(crate) fn record_event(&mut self, msg: &EventMsg) {
+        match msg {
+            EventMsg::ExecCommandEnd(ev) => self.commands.push(AuditCommand {
+                command: ev.command.clone(),
+                cwd: ev.cwd.clone(),
+                exit_code: ev.exit_code,
+            }),
+            EventMsg::PatchApplyEnd(patch) if patch.success => {
+                codex_events::record_changed_files(&mut self.files, &patch.changes);
+            }
+            EventMsg::TurnComplete(done) => {
+                self.outcome = Some("completed");
+                self.answer = done.last_agent_message.clone();
+            }
+            EventMsg::TokenCount(count) => {
+                if let Some(info) = &count.info {
+                    self.usage.add(&info.last_token_usage);
+                }
+            }
+            EventMsg::TurnAborted(_) => self.outcome = Some("aborted"),
+            EventMsg::Error(err) => {
+                self.outcome = Some("failed");
+                self.error = Some(err.message.clone());
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn outcome(&self) -> Option<&'static str> {
+        self.outcome
+    }
+
+    pub(crate) fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub(crate) fn answer(&self) -> Option<&str> {
+        self.answer.as_deref()
+    }
+
+    pub(crate) fn usage(&self) -> TurnUsage {
+        self.usage
+    }
+}
+
+/// One line of the audit log.
+#[derive(Debug, Serialize)]
+pub(crate) struct AuditEntry {
+    /// Unix seconds when the request arrived.
+    pub(crate) at: u64,
+    pub(crate) request_id: String,
+    pub(crate) owner: String,
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) model: Option<String>,
+    pub(crate) user: Option<String>,
+    pub(crate) conversation_id: Option<String>,
+    pub(crate) status: Option<u16>,
+    pub(crate) latency_ms: u64,
+    /// `completed`, `failed` or `aborted` for requests that ran a turn,
+    /// `rejected` for error responses and `incomplete` when the client left
+    /// before the turn ended; `ok` otherwise.
+    pub(crate) outcome: &'static str,
+    pub(crate) error: Option<String>,
+    pub(crate) prompt_sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) prompt: Option<String>,
+    pub(crate) commands: Vec<AuditCommand>,
+    pub(crate) files: Vec<ChangedFile>,
+    /// `danger-full-access` when a turn ran unsandboxed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) sandbox: Option<&'static str>,
+}
+
+impl AuditEntry {
+    /// Fills in what the request's turns did.
+    pub(crate) fn with_activity(mut self, activity: &TurnActivity, turns: u64) -> Self {
+        self.outcome = match activity.outcome {
+            Some(outcome) => outcome,
+            None if self.status.is_some_and(|status| status >= 400) => "rejected",
+            None if turns > 0 => "incomplete",
+            None => "ok",
+        };
+        self.error = activity.error.clone();
+        self.prompt_sha256 = activity
+            .prompt
+            .as_ref()
+            .map(|prompt| format!("{:x}", Sha256::digest(prompt.as_bytes())));
+        self.prompt = activity.prompt.clone();
+        self.commands = activity.commands.clone();
+        self.files = activity.files.clone();
+        self
+    }
+}
+
+/// The open audit log file.
+#[derive(Debug)]
+pub(crate) struct AuditLog {
+    path: PathBuf,
+    content: AuditContent,
+    /// Scrubs prompts, commands and errors before they are written.
+    redactor: Option<Arc<Redactor>>,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Opens `path` for appending, creating it and its directory if needed.
+    pub(crate) fn open(
+        path: &Path,
+        content: AuditContent,
+        redactor: Option<Arc<Redactor>>,
+    ) -> anyhow::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("create audit log directory {}", parent.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open audit log {}", path.display()))?;
+        info!("audit log at {}", path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            content,
+            redactor,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends `entry` as one line, dropping the prompt text unless the log
+    /// keeps it and redacting what is left.
+    pub(crate) fn write(&self, mut entry: AuditEntry) {
+        if self.content == AuditContent::Hash {
+            entry.prompt = None;
+        }
+        if let Some(redactor) = &self.redactor {
+            let redact = |text: &mut String| {
+                if let Cow::Owned(redacted) = redactor.redact(text) {
+                    *text = redacted;
+                }
+            };
+            entry.prompt.iter_mut().for_each(redact);
+            entry.error.iter_mut().for_each(redact);
+            for command in &mut entry.commands {
+                command.command.iter_mut().for_each(redact);
+            }
+        }
+        let mut line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("failed to serialize audit entry: {e}");
+                return;
+            }
+        };
+        line.push('\n');
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            warn!("failed to write audit log {}: {e}", self.path.display());
+        }
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "path": self.path,
+            "content": self.content.as_str(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codex_protocol::protocol::ErrorEvent;
+    use pretty_assertions::assert_eq;
+
+    fn entry(status: u16) -> AuditEntry {
+        AuditEntry {
+            at: 1,
+            request_id: "req_1".to_string(),
+            owner: "anonymous".to_string(),
+            method: "POST".to_string(),
+            path: "/v1/chat/completions".to_string(),
+            model: Some("gpt-5".to_string()),
+            user: None,
+            conversation_id: None,
+            status: Some(status),
+            latency_ms: 5,
+            outcome: "ok",
+            error: None,
+            prompt_sha256: None,
+            prompt: None,
+            commands: Vec::new(),
+            files: Vec::new(),
+            sandbox: None,
+        }
+    }
+
+    #[test]
+    fn entries_are_appended_with_prompts_redacted() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("audit").join("audit.jsonl");
+        let log = AuditLog::open(&path, AuditContent::Hash, None).expect("open");
+
+        let mut activity = TurnActivity::default();
+        activity.record_input(&[UserInput::Text {
+            text: "hello".to_string(),
+        }]);
+        activity.record_event(&EventMsg::Error(ErrorEvent {
+            message: "boom".to_string(),
+            codex_error_info: None,
+        }));
+        log.write(entry(200).with_activity(&activity, 1));
+        log.write(entry(400).with_activity(&TurnActivity::default(), 0));
+
+        let lines = std::fs::read_to_string(&path).expect("read");
+        let lines = lines
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).expect("json"))
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["outcome"], "failed");
+        assert_eq!(lines[0]["error"], "boom");
+        assert_eq!(
+            lines[0]["prompt_sha256"],
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(lines[0].get("prompt"), None);
+        assert_eq!(lines[1]["outcome"], "rejected");
+        assert_eq!(lines[1]["prompt_sha256"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,89 @@
+use axum::Extension;
+use axum::Json;
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use serde::Deserialize;
+
+use crate::AppState;
+use crate::ChatCompletionRequest;
+use crate::ProxyError;
+use crate::access_log::RequestContext;
+use crate::handle_chat_completions;
+use crate::log_message;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct AzureParams {
+    /// Required by Azure clients but irrelevant here; only logged.
+    #[serde(rename = "api-version")]
+    api_version: Option<String>,
+}
+
+/// `POST /openai/deployments/{deployment}/chat/completions?api-version=...`:
+/// the Azure OpenAI URL shape. The deployment name stands in for `model`
+/// (and goes through the model map like any other model name); the request
+/// body is otherwise a regular chat completion request.
+pub(crate) async fn handle_azure_chat_completions(
+    State(state): State<AppState>,
+    Extension(context): Extension<RequestContext>,
+    Path(deployment): Path<String>,
+    Query(params): Query<AzureParams>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Response {
+    log_message(
+        serde_json::json!({
+            "type": "azure_request",
+            "deployment": deployment,
+            "api_version": params.api_version,
+        })
+        .to_string(),
+    );
+    let body = match deployment_request(&deployment, body) {
+        Ok(body) => body,
+        Err(e) => return e.into_response(),
+    };
+    handle_chat_completions(State(state), Extension(context), headers, Json(body)).await
+}
+
+/// Parses an Azure chat body, whose model is given by the deployment path
+/// segment rather than a `model` field.
+fn deployment_request(
+    deployment: &str,
+    mut body: serde_json::Value,
+) -> Result<ChatCompletionRequest, ProxyError> {
+    let invalid = |message: String| {
+        ProxyError::new(StatusCode::BAD_REQUEST, message, "invalid_request_error")
+    };
+    let Some(fields) = body.as_object_mut() else {
+        return Err(invalid("request body must be a JSON object".to_string()));
+    };
+    fields.insert(
+        "model".to_string(),
+        serde_json::Value::String(deployment.to_string()),
+    );
+    serde_json::from_value(body).map_err(|e| invalid(format!("invalid request body: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn deployment_name_becomes_the_model() {
+        let body = serde_json::json!({
+            "model": "ignored",
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream": true,
+        });
+        let request = deployment_request("2.5-tpg", body).expect("valid body");
+        assert_eq!(request.model, "2.5-tpg");
+        assert!(request.stream);
+        assert!(deployment_request("2.5-tpg", serde_json::json!([])).is_err());
+    }
+}
@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::info;
+
+use crate::AppState;
+use crate::ChatCompletionRequest;
+use crate::ProxyError;
+use crate::access_log::RequestContext;
+use crate::handle_once;
+use crate::json_response;
+use crate::log_message;
+use crate::now_ts;
+use crate::responses;
+use crate::usage::usage_owner;
+
+/// Endpoints a batch may target, as in the OpenAI Batch API.
+const BATCH_ENDPOINTS: &[&str] = &["/v1/chat/completions", "/v1/responses"];
+
+/// Most requests accepted in one batch.
+const MAX_BATCH_REQUESTS: usize = 50_000;
+
+/// Largest batch input accepted, as in the OpenAI Batch API.
+pub(crate) const MAX_BATCH_INPUT_BYTES: usize = 200 * 1024 * 1024;
+
+/// Largest response body recorded for one batch request.
+const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Batch turns queue behind interactive requests (priority 0 by default).
+const BATCH_PRIORITY: i32 = -1;
+
+/// One line of a batch input file.
+#[derive(Debug, Deserialize)]
+struct BatchRequest {
+    custom_id: String,
+    #[serde(default = "default_method")]
+    method: String,
+    url: String,
+    body: serde_json::Value,
+}
+
+fn default_method() -> String {
+    "POST".to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum BatchStatus {
+    InProgress,
+    Cancelling,
+    Cancelled,
+    Completed,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+struct RequestCounts {
+    total: usize,
+    completed: usize,
+    failed: usize,
+}
+
+/// A batch as reported by the API; mirrors the OpenAI batch object.
+#[derive(Debug, Clone, Serialize)]
+struct Batch {
+    id: String,
+    object: &'static str,
+    endpoint: String,
+    status: BatchStatus,
+    created_at: u64,
+    in_progress_at: u64,
+    completed_at: Option<u64>,
+    cancelled_at: Option<u64>,
+    request_counts: RequestCounts,
+    /// Path of the JSONL results, available while and after the batch runs.
+    output_url: String,
+}
+
+struct BatchEntry {
+    batch: Batch,
+    /// One JSONL line per finished request, in completion order.
+    output: Vec<String>,
+    cancel: Arc<AtomicBool>,
+}
+
+/// In-memory batches submitted through `POST /v1/batches`. Requests from all
+/// batches share one concurrency limit so offline work cannot crowd out
+/// interactive traffic.
+pub(crate) struct BatchRegistry {
+    concurrency: usize,
+    permits: Arc<Semaphore>,
+    batches: Mutex<HashMap<String, BatchEntry>>,
+}
+
+impl BatchRegistry {
+    pub(crate) fn new(concurrency: usize) -> Self {
+        let concurrency = concurrency.max(1);
+        Self {
+            concurrency,
+            permits: Arc::new(Semaphore::new(concurrency)),
+            batches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "concurrency": self.concurrency,
+            "max_requests": MAX_BATCH_REQUESTS,
+        })
+    }
+
+    fn get(&self, id: &str) -> Result<Batch, ProxyError> {
+        self.batches
+            .lock()
+            .ok()
+            .and_then(|batches| batches.get(id).map(|entry| entry.batch.clone()))
+            .ok_or_else(|| not_found(id))
+    }
+
+    fn record(&self, id: &str, line: String, succeeded: bool) {
+        if let Ok(mut batches) = self.batches.lock()
+            && let Some(entry) = batches.get_mut(id)
+        {
+            entry.output.push(line);
+            if succeeded {
+                entry.batch.request_counts.completed += 1;
+            } else {
+                entry.batch.request_counts.failed += 1;
+            }
+        }
+    }
+
+    fn finish(&self, id: &str) {
+        if let Ok(mut batches) = self.batches.lock()
+            && let Some(entry) = batches.get_mut(id)
+        {
+            let now = now_ts();
+            if entry.batch.status == BatchStatus::Cancelling {
+                entry.batch.status = BatchStatus::Cancelled;
+                entry.batch.cancelled_at = Some(now);
+            } else {
+                entry.batch.status = BatchStatus::Completed;
+                entry.batch.completed_at = Some(now);
+            }
+            info!(
+                "batch {id} finished: {} completed, {} failed",
+                entry.batch.request_counts.completed, entry.batch.request_counts.failed
+            );
+        }
+    }
+}
+
+/// Parses and validates a JSONL batch: every line must target the same
+/// supported endpoint with a unique `custom_id`.
+fn parse_batch(input: &str) -> Result<(String, Vec<BatchRequest>), String> {
+    let mut requests = Vec::new();
+    let mut seen = HashSet::new();
+    for (index, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_no = index + 1;
+        let request = serde_json::from_str::<BatchRequest>(line)
+            .map_err(|e| format!("line {line_no}: {e}"))?;
+        if !request.method.eq_ignore_ascii_case("POST") {
+            return Err(format!("line {line_no}: method must be POST"));
+        }
+        if !BATCH_ENDPOINTS.contains(&request.url.as_str()) {
+            return Err(format!(
+                "line {line_no}: unsupported url {}; supported: {}",
+                request.url,
+                BATCH_ENDPOINTS.join(", ")
+            ));
+        }
+        if let Some(first) = requests.first().map(|r: &BatchRequest| r.url.as_str())
+            && first != request.url
+        {
+            return Err(format!(
+                "line {line_no}: all requests in a batch must use the same url ({first})"
+            ));
+        }
+        if !seen.insert(request.custom_id.clone()) {
+            return Err(format!(
+                "line {line_no}: duplicate custom_id {}",
+                request.custom_id
+            ));
+        }
+        requests.push(request);
+        if requests.len() > MAX_BATCH_REQUESTS {
+            return Err(format!(
+                "a batch may contain at most {MAX_BATCH_REQUESTS} requests"
+            ));
+        }
+    }
+    match requests.first() {
+        Some(first) => Ok((first.url.clone(), requests)),
+        None => Err("the batch contains no requests".to_string()),
+    }
+}
+
+/// `POST /v1/batches`: accepts a JSONL batch (one OpenAI batch request per
+/// line) and runs it in the background.
+pub(crate) async fn handle_create_batch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let (endpoint, requests) = match parse_batch(&body) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            return ProxyError::new(StatusCode::BAD_REQUEST, message, "invalid_request_error")
+                .with_code("invalid_batch")
+                .into_response();
+        }
+    };
+
+    let id = format!("batch_{}", uuid::Uuid::new_v4().simple());
+    let now = now_ts();
+    let batch = Batch {
+        id: id.clone(),
+        object: "batch",
+        endpoint,
+        status: BatchStatus::InProgress,
+        created_at: now,
+        in_progress_at: now,
+        completed_at: None,
+        cancelled_at: None,
+        request_counts: RequestCounts {
+            total: requests.len(),
+            ..RequestCounts::default()
+        },
+        output_url: format!("/v1/batches/{id}/output"),
+    };
+    let cancel = Arc::new(AtomicBool::new(false));
+    if let Ok(mut batches) = state.batches.batches.lock() {
+        batches.insert(
+            id.clone(),
+            BatchEntry {
+                batch: batch.clone(),
+                output: Vec::new(),
+                cancel: cancel.clone(),
+            },
+        );
+    }
+    log_message(
+        serde_json::json!({
+            "type": "batch_created",
+            "batch_id": id,
+            "requests": requests.len(),
+        })
+        .to_string(),
+    );
+    let owner = usage_owner(&state, &headers);
+    tokio::spawn(run_batch(state, id, requests, cancel, owner));
+
+    json_response(
+        StatusCode::OK,
+        serde_json::to_string(&batch).unwrap_or_else(|_| "{}".to_string()),
+    )
+}
+
+async fn run_batch(
+    state: AppState,
+    id: String,
+    requests: Vec<BatchRequest>,
+    cancel: Arc<AtomicBool>,
+    owner: String,
+) {
+    let mut running = JoinSet::new();
+    for request in requests {
+        let Ok(permit) = state.batches.permits.clone().acquire_owned().await else {
+            break;
+        };
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        let state = state.clone();
+        let id = id.clone();
+        let owner = owner.clone();
+        running.spawn(async move {
+            let _permit = permit;
+            let (line, succeeded) = execute(&state, request, owner).await;
+            state.batches.record(&id, line, succeeded);
+        });
+    }
+    while running.join_next().await.is_some() {}
+    state.batches.finish(&id);
+}
+
+/// Runs one batch request through the non-streaming handler for its endpoint
+/// and renders the output line. It is recorded in the ledger under `owner`,
+/// the batch's creator.
+async fn execute(state: &AppState, request: BatchRequest, owner: String) -> (String, bool) {
+    let request_id = format!("req_{}", uuid::Uuid::new_v4().simple());
+    let context = RequestContext::new(request_id.clone())
+        .with_ledger(state.ledger.clone(), owner, "POST", &request.url)
+        .with_audit_log(state.audit_log.clone())
+        .with_turn_webhook(state.turn_webhook.clone(), true)
+        .with_interceptors(&state.interceptors)
+        .with_turn_diffs(&state.turn_diffs)
+        .with_model_map(&state.model_map);
+    let response = match request.url.as_str() {
+        "/v1/chat/completions" => {
+            match serde_json::from_value::<ChatCompletionRequest>(request.body) {
+                Ok(body) => {
+                    context.set_model(&body.model);
+                    context.set_user(body.user.as_deref());
+                    handle_once(state.clone(), context.clone(), BATCH_PRIORITY, body).await
+                }
+                Err(e) => invalid_body(e),
+            }
+        }
+        _ => match serde_json::from_value::<responses::ResponsesRequest>(request.body) {
+            Ok(body) => {
+                let context = context.clone();
+                context.set_user(body.user.as_deref());
+                responses::handle_responses_once(state.clone(), context, BATCH_PRIORITY, body).await
+            }
+            Err(e) => invalid_body(e),
+        },
+    };
+
+    let status = response.status();
+    context.set_status(status.as_u16());
+    let body = match axum::body::to_bytes(response.into_body(), MAX_RESPONSE_BYTES).await {
+        Ok(bytes) => serde_json::from_slice::<serde_json::Value>(&bytes)
+            .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(&bytes).into())),
+        Err(e) => serde_json::json!({ "error": { "message": e.to_string() } }),
+    };
+    let line = serde_json::json!({
+        "id": format!("batch_req_{}", uuid::Uuid::new_v4().simple()),
+        "custom_id": request.custom_id,
+        "response": {
+            "status_code": status.as_u16(),
+            "request_id": request_id,
+            "body": body,
+        },
+        "error": null,
+    });
+    (line.to_string(), status.is_success())
+}
+
+fn invalid_body(error: serde_json::Error) -> Response {
+    ProxyError::new(
+        StatusCode::BAD_REQUEST,
+        format!("invalid request body: {error}"),
+        "invalid_request_error",
+    )
+    .into_response()
+}
+
+fn not_found(id: &str) -> ProxyError {
+    ProxyError::new(
+        StatusCode::NOT_FOUND,
+        format!("batch {id} not found"),
+        "invalid_request_error",
+    )
+    .with_code("batch_not_found")
+}
+
+/// `GET /v1/batches`: lists batches, newest first.
+pub(crate) async fn handle_list_batches(State(state): State<AppState>) -> Response {
+    let mut batches = state
+        .batches
+        .batches
+        .lock()
+        .map(|batches| {
+            batches
+                .values()
+                .map(|entry| entry.batch.clone())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    batches.sort_by_key(|batch| std::cmp::Reverse(batch.created_at));
+    json_response(
+        StatusCode::OK,
+        serde_json::json!({ "object": "list", "data": batches }).to_string(),
+    )
+}
+
+/// `GET /v1/batches/{id}`: the batch's status and request counts.
+pub(crate) async fn handle_get_batch(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    match state.batches.get(&id) {
+        Ok(batch) => json_response(
+            StatusCode::OK,
+            serde_json::to_string(&batch).unwrap_or_else(|_| "{}".to_string()),
+        ),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// `POST /v1/batches/{id}/cancel`: stops starting new requests; requests
+/// already running finish and stay in the output.
+pub(crate) async fn handle_cancel_batch(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let batch = state.batches.batches.lock().ok().and_then(|mut batches| {
+        let entry = batches.get_mut(&id)?;
+        if entry.batch.status == BatchStatus::InProgress {
+            entry.batch.status = BatchStatus::Cancelling;
+            entry.cancel.store(true, Ordering::Relaxed);
+        }
+        Some(entry.batch.clone())
+    });
+    match batch {
+        Some(batch) => json_response(
+            StatusCode::OK,
+            serde_json::to_string(&batch).unwrap_or_else(|_| "{}".to_string()),
+        ),
+        None => not_found(&id).into_response(),
+    }
+}
+
+/// `GET /v1/batches/{id}/output`: the results so far as JSONL.
+pub(crate) async fn handle_batch_output(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let output = state.batches.batches.lock().ok().and_then(|batches| {
+        batches.get(&id).map(|entry| {
+            let mut output = entry.output.join("\n");
+            if !output.is_empty() {
+                output.push('\n');
+            }
+            output
+        })
+    });
+    match output {
+        Some(output) => ([(CONTENT_TYPE, "application/jsonl")], output).into_response(),
+        None => not_found(&id).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn line(custom_id: &str, url: &str) -> String {
+        serde_json::json!({
+            "custom_id": custom_id,
+            "method": "POST",
+            "url": url,
+            "body": { "model": "2.5-tpg", "messages": [] },
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn batches_need_one_endpoint_and_unique_ids() {
+        let valid = format!(
+            "{}\n\n{}\n",
+            line("a", "/v1/chat/completions"),
+            line("b", "/v1/chat/completions")
+        );
+        let (endpoint, requests) = parse_batch(&valid).expect("valid batch");
+        assert_eq!(endpoint, "/v1/chat/completions");
+        assert_eq!(requests.len(), 2);
+
+        let duplicate = format!(
+            "{}\n{}",
+            line("a", "/v1/responses"),
+            line("a", "/v1/responses")
+        );
+        assert_eq!(
+            parse_batch(&duplicate).err(),
+            Some("line 2: duplicate custom_id a".to_string())
+        );
+        let mixed = format!(
+            "{}\n{}",
+            line("a", "/v1/responses"),
+            line("b", "/v1/chat/completions")
+        );
+        assert!(parse_batch(&mixed).is_err());
+        assert!(parse_batch(&line("a", "/v1/embeddings")).is_err());
+        assert!(parse_batch("").is_err());
+    }
+}
@@ -0,0 +1,69 @@
+//! Request body size limit, so a giant base64 payload is turned away before
+//! it is buffered in memory.
+
+use std::sync::Arc;
+
+use axum::Router;
+use axum::body::Body;
+use axum::extract::DefaultBodyLimit;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+
+use crate::ProxyError;
+use crate::limits::LiveLimit;
+
+/// Rejects bodies over `max_bytes` (unset for no limit) with a 413
+/// `request_too_large` error. The limit is read per request, so a reload
+/// changes it for the next one.
+pub(crate) fn limit<S>(router: Router<S>, max_bytes: Arc<LiveLimit>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router
+        .layer(DefaultBodyLimit::disable())
+        .layer(axum::middleware::from_fn_with_state(max_bytes, enforce))
+}
+
+/// Turns away bodies declared too large up front and buffers the rest up to
+/// the limit, as the handlers buffer them anyway.
+async fn enforce(
+    State(max_bytes): State<Arc<LiveLimit>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(max_bytes) = max_bytes.get() else {
+        return next.run(request).await;
+    };
+    let max_bytes = usize::try_from(max_bytes).unwrap_or(usize::MAX);
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if declared.is_some_and(|declared| declared > max_bytes) {
+        return too_large(max_bytes);
+    }
+    let (parts, body) = request.into_parts();
+    match axum::body::to_bytes(body, max_bytes).await {
+        Ok(bytes) => {
+            next.run(Request::from_parts(parts, Body::from(bytes)))
+                .await
+        }
+        Err(_) => too_large(max_bytes),
+    }
+}
+
+fn too_large(max_bytes: usize) -> Response {
+    ProxyError::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("Request body is larger than the {max_bytes} bytes allowed."),
+        "invalid_request_error",
+    )
+    .with_code("request_too_large")
+    .into_response()
+}
@@ -0,0 +1,279 @@
+//! Per-provider circuit breakers for forward mode. A provider failing most
+//! of its recent requests is taken out of rotation for a while: requests
+//! fail over to other targets, or fail fast with a 503 and `Retry-After`,
+//! instead of waiting on a provider that is down. One probe request is let
+//! through once the break is over; its outcome closes or reopens the circuit.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::proxy_config;
+
+/// Outcomes of a provider's most recent requests that the failure rate is
+/// computed over.
+const WINDOW: usize = 20;
+
+const DEFAULT_MIN_REQUESTS: usize = 5;
+const DEFAULT_FAILURE_RATE_PERCENT: u64 = 50;
+const DEFAULT_OPEN_SECS: u64 = 30;
+
+/// When a circuit opens and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BreakerSettings {
+    /// Requests in the window before the circuit may open; 0 disables the
+    /// breakers.
+    pub(crate) min_requests: usize,
+    /// Share of failed requests in the window that opens the circuit.
+    pub(crate) failure_rate_percent: u64,
+    /// How long an open circuit fails requests before letting a probe through.
+    pub(crate) open_for: Duration,
+}
+
+impl Default for BreakerSettings {
+    fn default() -> Self {
+        Self {
+            min_requests: DEFAULT_MIN_REQUESTS,
+            failure_rate_percent: DEFAULT_FAILURE_RATE_PERCENT,
+            open_for: Duration::from_secs(DEFAULT_OPEN_SECS),
+        }
+    }
+}
+
+impl BreakerSettings {
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        let mut settings = Self::default();
+        let parse = |name: &str| -> anyhow::Result<Option<u64>> {
+            match proxy_config::var(name) {
+                Ok(value) => Ok(Some(
+                    value
+                        .parse::<u64>()
+                        .with_context(|| format!("parse {name}"))?,
+                )),
+                Err(_) => Ok(None),
+            }
+        };
+        if let Some(min_requests) = parse("CODEX_OPENAI_PROXY_CIRCUIT_MIN_REQUESTS")? {
+            settings.min_requests = min_requests as usize;
+        }
+        if let Some(percent) = parse("CODEX_OPENAI_PROXY_CIRCUIT_FAILURE_RATE_PERCENT")? {
+            anyhow::ensure!(
+                (1..=100).contains(&percent),
+                "CODEX_OPENAI_PROXY_CIRCUIT_FAILURE_RATE_PERCENT must be between 1 and 100"
+            );
+            settings.failure_rate_percent = percent;
+        }
+        if let Some(secs) = parse("CODEX_OPENAI_PROXY_CIRCUIT_OPEN_SECS")? {
+            settings.open_for = Duration::from_secs(secs);
+        }
+        Ok(settings)
+    }
+}
+
+/// A provider's circuit as reported by `GET /status`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct CircuitStatus {
+    /// `closed`, `open` or `half_open`.
+    pub(crate) state: &'static str,
+    /// Seconds until an open circuit lets a probe through.
+    pub(crate) retry_after_secs: Option<u64>,
+    pub(crate) recent_requests: usize,
+    pub(crate) recent_failures: usize,
+}
+
+/// Where a provider's circuit stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Requests go through.
+    Closed,
+    /// Requests fail fast until `until`.
+    Open { until: Instant },
+    /// One probe request is in flight since `since`; the rest fail fast.
+    HalfOpen { since: Instant },
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: State,
+    /// `true` for each failed request among the most recent ones.
+    outcomes: VecDeque<bool>,
+}
+
+impl Breaker {
+    fn new() -> Self {
+        Self {
+            state: State::Closed,
+            outcomes: VecDeque::with_capacity(WINDOW),
+        }
+    }
+
+    fn failures(&self) -> usize {
+        self.outcomes.iter().filter(|failed| **failed).count()
+    }
+}
+
+/// The circuit breakers of every provider forward mode sends requests to.
+#[derive(Debug)]
+pub(crate) struct CircuitBreakers {
+    settings: BreakerSettings,
+    breakers: Mutex<HashMap<String, Breaker>>,
+}
+
+impl CircuitBreakers {
+    pub(crate) fn new(settings: BreakerSettings) -> Self {
+        Self {
+            settings,
+            breakers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `Err` with the time until the circuit lets a request through again
+    /// when `provider`'s is open; otherwise the request may go ahead, as the
+    /// probe if the circuit was waiting for one.
+    pub(crate) fn check(&self, provider: &str, now: Instant) -> Result<(), Duration> {
+        if self.settings.min_requests == 0 {
+            return Ok(());
+        }
+        let Ok(mut breakers) = self.breakers.lock() else {
+            return Ok(());
+        };
+        let Some(breaker) = breakers.get_mut(provider) else {
+            return Ok(());
+        };
+        match breaker.state {
+            State::Closed => Ok(()),
+            State::Open { until } if now < until => Err(until - now),
+            // A probe that never reported back (its client went away) is
+            // replaced after another break.
+            State::HalfOpen { since } if now < since + self.settings.open_for => {
+                Err(since + self.settings.open_for - now)
+            }
+            State::Open { .. } | State::HalfOpen { .. } => {
+                breaker.state = State::HalfOpen { since: now };
+                Ok(())
+            }
+        }
+    }
+
+    /// Records the outcome of a request to `provider`; `failed` for failures
+    /// that say the provider is unwell, not for the client's own mistakes.
+    pub(crate) fn record(&self, provider: &str, failed: bool, now: Instant) {
+        if self.settings.min_requests == 0 {
+            return;
+        }
+        let Ok(mut breakers) = self.breakers.lock() else {
+            return;
+        };
+        let breaker = breakers
+            .entry(provider.to_string())
+            .or_insert_with(Breaker::new);
+        if let State::HalfOpen { .. } = breaker.state {
+            breaker.outcomes.clear();
+            breaker.state = if failed {
+                State::Open {
+                    until: now + self.settings.open_for,
+                }
+            } else {
+                State::Closed
+            };
+            return;
+        }
+        if breaker.outcomes.len() == WINDOW {
+            breaker.outcomes.pop_front();
+        }
+        breaker.outcomes.push_back(failed);
+        let requests = breaker.outcomes.len();
+        if breaker.state == State::Closed
+            && requests >= self.settings.min_requests
+            && (breaker.failures() as u64) * 100
+                >= self.settings.failure_rate_percent * requests as u64
+        {
+            warn!(
+                "{provider} failed {} of {requests} recent requests, opening its circuit for {:?}",
+                breaker.failures(),
+                self.settings.open_for
+            );
+            breaker.state = State::Open {
+                until: now + self.settings.open_for,
+            };
+        }
+    }
+
+    /// `provider`'s circuit; closed for providers that have not been used.
+    pub(crate) fn status(&self, provider: &str, now: Instant) -> CircuitStatus {
+        let closed = CircuitStatus {
+            state: "closed",
+            retry_after_secs: None,
+            recent_requests: 0,
+            recent_failures: 0,
+        };
+        let Ok(breakers) = self.breakers.lock() else {
+            return closed;
+        };
+        let Some(breaker) = breakers.get(provider) else {
+            return closed;
+        };
+        let (state, retry_after_secs) = match breaker.state {
+            State::Closed => ("closed", None),
+            State::Open { until } if now < until => {
+                ("open", Some((until - now).as_secs_f64().ceil() as u64))
+            }
+            State::Open { .. } | State::HalfOpen { .. } => ("half_open", None),
+        };
+        CircuitStatus {
+            state,
+            retry_after_secs,
+            recent_requests: breaker.outcomes.len(),
+            recent_failures: breaker.failures(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn circuits_open_on_failures_and_close_after_a_good_probe() {
+        let breakers = CircuitBreakers::new(BreakerSettings {
+            min_requests: 4,
+            failure_rate_percent: 50,
+            open_for: Duration::from_secs(30),
+        });
+        let start = Instant::now();
+
+        breakers.record("openai", false, start);
+        breakers.record("openai", true, start);
+        breakers.record("openai", false, start);
+        assert_eq!(breakers.check("openai", start), Ok(()));
+        breakers.record("openai", true, start);
+        assert_eq!(
+            breakers.check("openai", start + Duration::from_secs(10)),
+            Err(Duration::from_secs(20))
+        );
+        let status = breakers.status("openai", start + Duration::from_secs(10));
+        assert_eq!((status.state, status.retry_after_secs), ("open", Some(20)));
+        assert_eq!(breakers.check("azure", start), Ok(()));
+
+        // After the break one probe goes through while the rest wait.
+        let later = start + Duration::from_secs(30);
+        assert_eq!(breakers.check("openai", later), Ok(()));
+        assert!(breakers.check("openai", later).is_err());
+        breakers.record("openai", true, later);
+        assert!(breakers.check("openai", later).is_err());
+
+        let probe = later + Duration::from_secs(30);
+        assert_eq!(breakers.check("openai", probe), Ok(()));
+        breakers.record("openai", false, probe);
+        assert_eq!(breakers.check("openai", probe), Ok(()));
+        assert_eq!(breakers.status("openai", probe).state, "closed");
+        assert_eq!(breakers.status("azure", probe).recent_requests, 0);
+    }
+}
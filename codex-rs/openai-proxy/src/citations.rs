@@ -0,0 +1,194 @@
+//! URL citations for answers that drew on a web search. Codex's output text
+//! carries no annotations, so once a turn searched the web the proxy cites
+//! the links the answer itself contains: markdown links and bare `http(s)`
+//! URLs.
+
+use codex_protocol::models::ResponseItem;
+use serde_json::Value;
+use serde_json::json;
+
+/// Characters that end a bare URL, besides whitespace. `)` and `]` close
+/// markdown links around one.
+const URL_TERMINATORS: &[char] = &['<', '>', '"', '`', ')', ']'];
+
+/// Trailing characters that end the sentence rather than the URL.
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', '\''];
+
+/// One link in the answer text. Indices count characters, as OpenAI's do.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct UrlCitation {
+    pub(crate) url: String,
+    pub(crate) title: String,
+    pub(crate) start_index: usize,
+    pub(crate) end_index: usize,
+}
+
+impl UrlCitation {
+    /// A Responses `output_text` annotation.
+    fn responses_annotation(&self) -> Value {
+        json!({
+            "type": "url_citation",
+            "url": self.url,
+            "title": self.title,
+            "start_index": self.start_index,
+            "end_index": self.end_index,
+        })
+    }
+
+    /// A chat `message.annotations` entry.
+    fn chat_annotation(&self) -> Value {
+        json!({
+            "type": "url_citation",
+            "url_citation": {
+                "url": self.url,
+                "title": self.title,
+                "start_index": self.start_index,
+                "end_index": self.end_index,
+            },
+        })
+    }
+}
+
+/// Whether the turn searched the web, and so whether its answer is cited.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Citations {
+    searched: bool,
+}
+
+impl Citations {
+    pub(crate) fn from_items(items: &[ResponseItem]) -> Self {
+        let mut citations = Self::default();
+        for item in items {
+            citations.record(item);
+        }
+        citations
+    }
+
+    pub(crate) fn record(&mut self, item: &ResponseItem) {
+        if matches!(item, ResponseItem::WebSearchCall { .. }) {
+            self.searched = true;
+        }
+    }
+
+    /// The links in `text`; `None` when the turn did not search.
+    pub(crate) fn cite(&self, text: &str) -> Option<Vec<UrlCitation>> {
+        self.searched.then(|| find_links(text))
+    }
+
+    /// Adds `annotations` to the `output_text` parts of a rendered message.
+    pub(crate) fn annotate_item(&self, item: &mut Value) {
+        let Some(parts) = item.get_mut("content").and_then(Value::as_array_mut) else {
+            return;
+        };
+        for part in parts {
+            if part.get("type").and_then(Value::as_str) != Some("output_text") {
+                continue;
+            }
+            let text = part.get("text").and_then(Value::as_str).unwrap_or_default();
+            let Some(citations) = self.cite(text) else {
+                return;
+            };
+            let annotations = citations
+                .iter()
+                .map(UrlCitation::responses_annotation)
+                .collect();
+            part["annotations"] = Value::Array(annotations);
+        }
+    }
+
+    /// The chat `message.annotations` extension for `text`.
+    pub(crate) fn chat_annotations(&self, text: &str) -> Option<Vec<Value>> {
+        let citations = self.cite(text)?;
+        Some(citations.iter().map(UrlCitation::chat_annotation).collect())
+    }
+}
+
+/// Markdown links to and bare occurrences of `http(s)` URLs, in order.
+fn find_links(text: &str) -> Vec<UrlCitation> {
+    let mut citations = Vec::new();
+    let mut from = 0;
+    while let Some(found) = next_url_start(&text[from..]) {
+        let url_start = from + found;
+        let rest = &text[url_start..];
+        let url_len = rest
+            .find(|c: char| c.is_whitespace() || URL_TERMINATORS.contains(&c))
+            .unwrap_or(rest.len());
+        let url = rest[..url_len].trim_end_matches(TRAILING_PUNCTUATION);
+        let url_end = url_start + url.len();
+        from = url_end.max(url_start + 1);
+
+        let (start, end, title) = match markdown_title(text, url_start, url_end) {
+            Some((start, title)) => (start, url_end + 1, title.to_string()),
+            None => (url_start, url_end, url.to_string()),
+        };
+        citations.push(UrlCitation {
+            url: url.to_string(),
+            title,
+            start_index: text[..start].chars().count(),
+            end_index: text[..end].chars().count(),
+        });
+    }
+    citations
+}
+
+fn next_url_start(text: &str) -> Option<usize> {
+    match (text.find("https://"), text.find("http://")) {
+        (Some(https), Some(http)) => Some(https.min(http)),
+        (https, http) => https.or(http),
+    }
+}
+
+/// The start and link text of the `[title](url)` around the URL at
+/// `url_start..url_end`, if it is the target of one.
+fn markdown_title(text: &str, url_start: usize, url_end: usize) -> Option<(usize, &str)> {
+    let before = text[..url_start].strip_suffix("](")?;
+    if !text[url_end..].starts_with(')') {
+        return None;
+    }
+    let open = before.rfind('[')?;
+    let title = &before[open + 1..];
+    if title.is_empty() || title.contains(['\n', ']']) {
+        return None;
+    }
+    Some((open, title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codex_protocol::models::WebSearchAction;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn links_are_cited_once_the_turn_searched() {
+        let text = "Façade: see [the docs](https://docs.rs/serde). Or https://serde.rs, or not.";
+        let mut citations = Citations::default();
+        assert_eq!(citations.cite(text), None);
+
+        citations.record(&ResponseItem::WebSearchCall {
+            id: None,
+            status: Some("completed".to_string()),
+            action: WebSearchAction::Search {
+                query: Some("serde".to_string()),
+            },
+        });
+        assert_eq!(
+            citations.cite(text),
+            Some(vec![
+                UrlCitation {
+                    url: "https://docs.rs/serde".to_string(),
+                    title: "the docs".to_string(),
+                    start_index: 12,
+                    end_index: 45,
+                },
+                UrlCitation {
+                    url: "https://serde.rs".to_string(),
+                    title: "https://serde.rs".to_string(),
+                    start_index: 50,
+                    end_index: 66,
+                },
+            ])
+        );
+        assert_eq!(citations.cite("No links here."), Some(Vec::new()));
+    }
+}
@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use codex_protocol::items::AgentMessageContent;
+use codex_protocol::items::TurnItem;
+use codex_protocol::protocol::EventMsg;
+use codex_protocol::protocol::FileChange;
+use codex_protocol::protocol::McpInvocation;
+use codex_protocol::protocol::TurnAbortReason;
+use serde::Serialize;
+
+/// Longest command output forwarded in an exec end event.
+pub(crate) const MAX_EXEC_OUTPUT_BYTES: usize = 4096;
+
+/// How Codex names MCP tools to the model: `mcp__<server>__<tool>`.
+const MCP_TOOL_PREFIX: &str = "mcp__";
+
+/// A Codex event forwarded to streaming clients as a custom SSE event.
+#[derive(Debug)]
+pub(crate) struct CodexEvent {
+    pub(crate) kind: &'static str,
+    pub(crate) fields: serde_json::Value,
+}
+
+impl CodexEvent {
+    /// The event as a standalone payload whose `type` names the SSE event.
+    pub(crate) fn into_event(self) -> serde_json::Value {
+        let mut event = serde_json::json!({ "type": self.kind });
+        if let (Some(event), serde_json::Value::Object(fields)) =
+            (event.as_object_mut(), self.fields)
+        {
+            event.extend(fields);
+        }
+        event
+    }
+}
+
+/// Why an aborted turn stopped, as reported to clients: `interrupted`,
+/// `replaced` or `review_ended`.
+pub(crate) fn abort_reason(reason: &TurnAbortReason) -> &'static str {
+    match reason {
+        TurnAbortReason::Interrupted => "interrupted",
+        TurnAbortReason::Replaced => "replaced",
+        TurnAbortReason::ReviewEnded => "review_ended",
+    }
+}
+
+/// Command execution progress, so clients can show what Codex is running
+/// instead of dead air while a tool runs.
+pub(crate) fn exec_event(msg: &EventMsg) -> Option<CodexEvent> {
+    match msg {
+        EventMsg::ExecCommandBegin(ev) => Some(CodexEvent {
+            kind: "codex.exec_command.begin",
+            fields: serde_json::json!({
+                "call_id": ev.call_id,
+                "command": ev.command,
+                "cwd": ev.cwd,
+            }),
+        }),
+        EventMsg::ExecCommandEnd(ev) => {
+            let output = if ev.aggregated_output.is_empty() {
+                &ev.formatted_output
+            } else {
+                &ev.aggregated_output
+            };
+            Some(CodexEvent {
+                kind: "codex.exec_command.end",
+                fields: serde_json::json!({
+                    "call_id": ev.call_id,
+                    "command": ev.command,
+                    "exit_code": ev.exit_code,
+                    "duration_ms": ev.duration.as_millis() as u64,
+                    "output": truncate_output(output),
+                    "output_truncated": output.len() > MAX_EXEC_OUTPUT_BYTES,
+                }),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// The assistant text of one turn, each piece passed on exactly once.
+///
+/// Codex reports a message's text as deltas, again as the completed message,
+/// once more as legacy copies of both, and the last message a final time on
+/// `TurnComplete`. Only the item deltas are used, plus completed messages
+/// that were never streamed; `last_agent_message` only when nothing else
+/// arrived.
+#[derive(Debug, Default)]
+pub(crate) struct AgentText {
+    /// The message being streamed: its item id and the text so far.
+    streaming: Option<(String, String)>,
+    /// The message text was last delivered for.
+    last_item: Option<String>,
+}
+
+impl AgentText {
+    /// The new assistant text `msg` carries, if any. Text of a message
+    /// after an earlier one starts on a new paragraph.
+    pub(crate) fn text(&mut self, msg: &EventMsg) -> Option<String> {
+        match msg {
+            EventMsg::AgentMessageContentDelta(delta) => {
+                match &mut self.streaming {
+                    Some((id, text)) if *id == delta.item_id => text.push_str(&delta.delta),
+                    _ => self.streaming = Some((delta.item_id.clone(), delta.delta.clone())),
+                }
+                self.deliver(&delta.item_id, &delta.delta)
+            }
+            EventMsg::ItemCompleted(completed) => {
+                let TurnItem::AgentMessage(message) = &completed.item else {
+                    return None;
+                };
+                let text = message
+                    .content
+                    .iter()
+                    .map(|AgentMessageContent::Text { text }| text.as_str())
+                    .collect::<String>();
+                // Providers without item ids get a fresh one per event, so
+                // the streamed text is matched too.
+                if self
+                    .streaming
+                    .take()
+                    .is_some_and(|(id, streamed)| id == message.id || streamed == text)
+                {
+                    return None;
+                }
+                self.deliver(&message.id, &text)
+            }
+            EventMsg::TurnComplete(done) if self.last_item.is_none() => {
+                self.deliver("", done.last_agent_message.as_deref()?)
+            }
+            _ => None,
+        }
+    }
+
+    fn deliver(&mut self, item_id: &str, text: &str) -> Option<String> {
+        if text.is_empty() {
+            return None;
+        }
+        let separator = match &self.last_item {
+            Some(last) if last != item_id => "\n\n",
+            _ => "",
+        };
+        self.last_item = Some(item_id.to_string());
+        Some(format!("{separator}{text}"))
+    }
+}
+
+/// A file touched by a successful patch, as summarized in final responses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ChangedFile {
+    pub(crate) path: String,
+    /// `add`, `delete` or `update`.
+    pub(crate) kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) move_path: Option<String>,
+}
+
+/// Files touched by `changes`, sorted by path.
+pub(crate) fn changed_files(changes: &HashMap<PathBuf, FileChange>) -> Vec<ChangedFile> {
+    let mut files = changes
+        .iter()
+        .map(|(path, change)| {
+            let (kind, move_path) = match change {
+                FileChange::Add { .. } => ("add", None),
+                FileChange::Delete { .. } => ("delete", None),
+                FileChange::Update { move_path, .. } => ("update", move_path.as_ref()),
+            };
+            ChangedFile {
+                path: path.display().to_string(),
+                kind,
+                move_path: move_path.map(|p| p.display().to_string()),
+            }
+        })
+        .collect::<Vec<_>>();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
+
+/// Folds `changes` into `files`, keeping one entry per path.
+pub(crate) fn record_changed_files(
+    files: &mut Vec<ChangedFile>,
+    changes: &HashMap<PathBuf, FileChange>,
+) {
+    for file in changed_files(changes) {
+        files.retain(|existing| existing.path != file.path);
+        files.push(file);
+    }
+}
+
+/// A git-style unified diff covering every file in `changes`.
+pub(crate) fn unified_diff(changes: &HashMap<PathBuf, FileChange>) -> String {
+    let mut paths = changes.keys().collect::<Vec<_>>();
+    paths.sort();
+    let mut diff = String::new();
+    for path in paths {
+        let path_str = path.display();
+        match &changes[path] {
+            FileChange::Add { content } => {
+                let lines = content.lines().collect::<Vec<_>>();
+                diff.push_str(&format!(
+                    "--- /dev/null\n+++ b/{path_str}\n@@ -0,0 +1,{} @@\n",
+                    lines.len()
+                ));
+                for line in lines {
+                    diff.push_str(&format!("+{line}\n"));
+                }
+            }
+            FileChange::Delete { content } => {
+                let lines = content.lines().collect::<Vec<_>>();
+                diff.push_str(&format!(
+                    "--- a/{path_str}\n+++ /dev/null\n@@ -1,{} +0,0 @@\n",
+                    lines.len()
+                ));
+                for line in lines {
+                    diff.push_str(&format!("-{line}\n"));
+                }
+            }
+            FileChange::Update {
+                unified_diff,
+                move_path,
+            } => {
+                let new_path = move_path.as_deref().unwrap_or(path).display();
+                diff.push_str(&format!("--- a/{path_str}\n+++ b/{new_path}\n"));
+                diff.push_str(unified_diff);
+                if !unified_diff.ends_with('\n') {
+                    diff.push('\n');
+                }
+            }
+        }
+    }
+    diff
+}
+
+/// The outcome of an `apply_patch`, with the files it touched and their diff.
+pub(crate) fn patch_event(msg: &EventMsg) -> Option<CodexEvent> {
+    let EventMsg::PatchApplyEnd(ev) = msg else {
+        return None;
+    };
+    Some(CodexEvent {
+        kind: "codex.patch_apply",
+        fields: serde_json::json!({
+            "call_id": ev.call_id,
+            "success": ev.success,
+            "files": changed_files(&ev.changes),
+            "unified_diff": unified_diff(&ev.changes),
+        }),
+    })
+}
+
+/// A plan update, so agent UIs can render step-by-step progress. Codex
+/// sends the whole plan each time, which replaces the one before.
+pub(crate) fn plan_event(msg: &EventMsg) -> Option<CodexEvent> {
+    let EventMsg::PlanUpdate(update) = msg else {
+        return None;
+    };
+    Some(CodexEvent {
+        kind: "codex.plan.delta",
+        fields: serde_json::json!({
+            "explanation": update.explanation,
+            "plan": update.plan,
+        }),
+    })
+}
+
+/// Whether the model's tool `name` is one Codex serves from an MCP server.
+/// Such calls are reported from their begin and end events instead, which
+/// name the server.
+pub(crate) fn is_mcp_tool(name: &str) -> bool {
+    name.starts_with(MCP_TOOL_PREFIX)
+}
+
+/// An MCP call's arguments as a JSON string, as function calls carry them.
+pub(crate) fn mcp_arguments(invocation: &McpInvocation) -> String {
+    invocation
+        .arguments
+        .as_ref()
+        .map_or_else(|| "{}".to_string(), ToString::to_string)
+}
+
+/// An MCP tool call as a Responses `mcp_call` output item: without output
+/// when it begins, and with the tool's text output or error once it ends.
+pub(crate) fn mcp_call_item(msg: &EventMsg) -> Option<serde_json::Value> {
+    let (call_id, invocation, result) = match msg {
+        EventMsg::McpToolCallBegin(ev) => (&ev.call_id, &ev.invocation, None),
+        EventMsg::McpToolCallEnd(ev) => (&ev.call_id, &ev.invocation, Some(&ev.result)),
+        _ => return None,
+    };
+    let (output, error) = match result {
+        None => (None, None),
+        Some(Ok(result)) => {
+            let texts = result
+                .content
+                .iter()
+                .filter_map(|block| serde_json::to_value(block).ok())
+                .filter_map(|block| block.get("text")?.as_str().map(str::to_string))
+                .collect::<Vec<_>>();
+            let text = if texts.is_empty() {
+                serde_json::to_string(&result.content).unwrap_or_default()
+            } else {
+                texts.join("\n")
+            };
+            if result.is_error == Some(true) {
+                (None, Some(text))
+            } else {
+                (Some(text), None)
+            }
+        }
+        Some(Err(e)) => (None, Some(e.clone())),
+    };
+    Some(serde_json::json!({
+        "type": "mcp_call",
+        "id": call_id,
+        "server_label": invocation.server,
+        "name": invocation.tool,
+        "arguments": mcp_arguments(invocation),
+        "output": output,
+        "error": error,
+    }))
+}
+
+/// Keeps the first [`MAX_EXEC_OUTPUT_BYTES`] of `output`, cut on a char
+/// boundary.
+fn truncate_output(output: &str) -> &str {
+    if output.len() <= MAX_EXEC_OUTPUT_BYTES {
+        return output;
+    }
+    let mut end = MAX_EXEC_OUTPUT_BYTES;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    &output[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codex_protocol::ThreadId;
+    use codex_protocol::items::AgentMessageItem;
+    use codex_protocol::protocol::AgentMessageContentDeltaEvent;
+    use codex_protocol::protocol::AgentMessageDeltaEvent;
+    use codex_protocol::protocol::AgentMessageEvent;
+    use codex_protocol::protocol::ItemCompletedEvent;
+    use codex_protocol::protocol::TurnCompleteEvent;
+    use pretty_assertions::assert_eq;
+
+    fn delta(item_id: &str, delta: &str) -> EventMsg {
+        EventMsg::AgentMessageContentDelta(AgentMessageContentDeltaEvent {
+            thread_id: "thread".to_string(),
+            turn_id: "turn".to_string(),
+            item_id: item_id.to_string(),
+            delta: delta.to_string(),
+        })
+    }
+
+    fn completed(item_id: &str, text: &str) -> EventMsg {
+        EventMsg::ItemCompleted(ItemCompletedEvent {
+            thread_id: ThreadId::new(),
+            turn_id: "turn".to_string(),
+            item: TurnItem::AgentMessage(AgentMessageItem {
+                id: item_id.to_string(),
+                content: vec![AgentMessageContent::Text {
+                    text: text.to_string(),
+                }],
+            }),
+        })
+    }
+
+    #[test]
+    fn agent_text_is_delivered_once() {
+        let mut agent_text = AgentText::default();
+        let events = [
+            delta("msg_1", "Looking"),
+            EventMsg::AgentMessageDelta(AgentMessageDeltaEvent {
+                delta: "Looking".to_string(),
+            }),
+            delta("msg_1", " now."),
+            completed("msg_1", "Looking now."),
+            EventMsg::AgentMessage(AgentMessageEvent {
+                message: "Looking now.".to_string(),
+            }),
+            // Completed under a different id than its deltas.
+            delta("msg_2", "Done."),
+            completed("msg_3", "Done."),
+            completed("msg_4", "Unstreamed."),
+            EventMsg::TurnComplete(TurnCompleteEvent {
+                last_agent_message: Some("Unstreamed.".to_string()),
+            }),
+        ];
+        let text = events
+            .iter()
+            .filter_map(|event| agent_text.text(event))
+            .collect::<String>();
+        assert_eq!(text, "Looking now.\n\nDone.\n\nUnstreamed.");
+
+        let mut agent_text = AgentText::default();
+        let done = EventMsg::TurnComplete(TurnCompleteEvent {
+            last_agent_message: Some("Only this.".to_string()),
+        });
+        assert_eq!(agent_text.text(&done).as_deref(), Some("Only this."));
+    }
+
+    #[test]
+    fn long_output_is_truncated_on_a_char_boundary() {
+        assert_eq!(truncate_output("ok"), "ok");
+
+        let output = format!("{}é", "a".repeat(MAX_EXEC_OUTPUT_BYTES - 1));
+        let truncated = truncate_output(&output);
+        assert_eq!(truncated.len(), MAX_EXEC_OUTPUT_BYTES - 1);
+        assert!(output.starts_with(truncated));
+    }
+
+    #[test]
+    fn patch_diff_lists_every_file() {
+        let changes = HashMap::from([
+            (
+                PathBuf::from("new.txt"),
+                FileChange::Add {
+                    content: "hello\n".to_string(),
+                },
+            ),
+            (
+                PathBuf::from("old.txt"),
+                FileChange::Update {
+                    unified_diff: "@@ -1 +1 @@\n-a\n+b\n".to_string(),
+                    move_path: Some(PathBuf::from("moved.txt")),
+                },
+            ),
+        ]);
+
+        assert_eq!(
+            unified_diff(&changes),
+            "--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,1 @@\n+hello\n\
+             --- a/old.txt\n+++ b/moved.txt\n@@ -1 +1 @@\n-a\n+b\n"
+        );
+
+        let mut files = Vec::new();
+        record_changed_files(&mut files, &changes);
+        record_changed_files(&mut files, &changes);
+        assert_eq!(
+            files,
+            vec![
+                ChangedFile {
+                    path: "new.txt".to_string(),
+                    kind: "add",
+                    move_path: None,
+                },
+                ChangedFile {
+                    path: "old.txt".to_string(),
+                    kind: "update",
+                    move_path: Some("moved.txt".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn mcp_calls_become_mcp_call_items() {
+        let end = serde_json::from_value::<EventMsg>(serde_json::json!({
+            "type": "mcp_tool_call_end",
+            "call_id": "call-1",
+            "invocation": {
+                "server": "docs",
+                "tool": "search",
+                "arguments": { "query": "rust" },
+            },
+            "duration": { "secs": 1, "nanos": 0 },
+            "result": { "Ok": { "content": [{ "type": "text", "text": "found it" }] } },
+        }))
+        .expect("mcp end event");
+        assert_eq!(
+            mcp_call_item(&end),
+            Some(serde_json::json!({
+                "type": "mcp_call",
+                "id": "call-1",
+                "server_label": "docs",
+                "name": "search",
+                "arguments": "{\"query\":\"rust\"}",
+                "output": "found it",
+                "error": null,
+            }))
+        );
+        assert!(is_mcp_tool("mcp__docs__search"));
+        assert!(!is_mcp_tool("shell"));
+    }
+}
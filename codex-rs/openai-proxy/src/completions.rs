@@ -0,0 +1,147 @@
+//! Cancelling streamed chat completions by id, for clients that cannot just
+//! close the connection (e.g. behind a buffering proxy).
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use codex_protocol::ThreadId;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::json_response;
+use crate::log_message;
+
+struct RunningCompletion {
+    thread_id: ThreadId,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Chat completion streams whose turn is still running, by completion id.
+#[derive(Default)]
+pub(crate) struct RunningCompletions {
+    running: Mutex<HashMap<String, RunningCompletion>>,
+}
+
+impl RunningCompletions {
+    /// Makes `id` cancellable until the returned guard drops.
+    pub(crate) fn track(self: &Arc<Self>, id: &str, thread_id: ThreadId) -> CompletionGuard {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        if let Ok(mut running) = self.running.lock() {
+            running.insert(
+                id.to_string(),
+                RunningCompletion {
+                    thread_id,
+                    cancelled: cancelled.clone(),
+                },
+            );
+        }
+        CompletionGuard {
+            completions: self.clone(),
+            id: id.to_string(),
+            cancelled,
+        }
+    }
+
+    /// Marks `id` cancelled and returns the thread its turn runs on.
+    fn cancel(&self, id: &str) -> Option<ThreadId> {
+        let running = self.running.lock().ok()?;
+        let completion = running.get(id)?;
+        completion.cancelled.store(true, Ordering::Relaxed);
+        Some(completion.thread_id)
+    }
+}
+
+/// Keeps a completion cancellable while its turn runs.
+pub(crate) struct CompletionGuard {
+    completions: Arc<RunningCompletions>,
+    id: String,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CompletionGuard {
+    /// Whether a cancel arrived; the turn may not have been submitted yet
+    /// when it did, so the interrupt has to be sent again.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for CompletionGuard {
+    fn drop(&mut self) {
+        if let Ok(mut running) = self.completions.running.lock() {
+            running.remove(&self.id);
+        }
+    }
+}
+
+/// `POST /v1/chat/completions/{id}/cancel`: interrupts the turn behind a
+/// streamed chat completion. Its stream ends with finish reason `stop` and
+/// `abort_reason` `interrupted`.
+pub(crate) async fn handle_cancel_completion(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let Some(thread_id) = state.completions.cancel(&id) else {
+        return ProxyError::new(
+            StatusCode::NOT_FOUND,
+            format!("No running chat completion found with id '{id}'."),
+            "invalid_request_error",
+        )
+        .with_code("completion_not_found")
+        .into_response();
+    };
+    // An idle thread means the turn has not started yet; it is interrupted
+    // right after it is submitted.
+    if let Err(e) = state.threads.abort_turn(thread_id).await {
+        return e.into_response();
+    }
+
+    log_message(
+        serde_json::json!({
+            "type": "turn_aborted",
+            "source": "cancel",
+            "completion_id": id,
+            "conversation_id": thread_id.to_string(),
+        })
+        .to_string(),
+    );
+
+    json_response(
+        StatusCode::OK,
+        serde_json::json!({
+            "id": id,
+            "object": "chat.completion",
+            "cancelled": true,
+        })
+        .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn completions_are_cancellable_until_their_guard_drops() {
+        let completions = Arc::new(RunningCompletions::default());
+        let thread_id = ThreadId::new();
+        let guard = completions.track("chatcmpl-1", thread_id);
+
+        assert_eq!(completions.cancel("chatcmpl-2"), None);
+        assert!(!guard.is_cancelled());
+        assert_eq!(completions.cancel("chatcmpl-1"), Some(thread_id));
+        assert!(guard.is_cancelled());
+
+        drop(guard);
+        assert_eq!(completions.cancel("chatcmpl-1"), None);
+    }
+}
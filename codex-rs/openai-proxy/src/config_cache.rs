@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use codex_core::config::Config;
+use codex_core::config::ConfigBuilder;
+use tracing::info;
+
+/// Loaded Codex configs keyed by the CLI overrides they were loaded with, so
+/// requests stop re-reading config files from disk. Edits to those files take
+/// effect after [`ConfigCache::reload`] (`POST /admin/config/reload`).
+#[derive(Default)]
+pub(crate) struct ConfigCache {
+    /// Defaults to the proxy's own Codex home (`CODEX_HOME`).
+    codex_home: Option<PathBuf>,
+    /// Applied under every load's own overrides, e.g. the mock backend's
+    /// provider.
+    base_overrides: Vec<(String, toml::Value)>,
+    entries: Mutex<HashMap<String, Config>>,
+}
+
+impl ConfigCache {
+    /// A cache for configs loaded from `codex_home` instead of the default.
+    pub(crate) fn for_codex_home(codex_home: PathBuf) -> Self {
+        Self {
+            codex_home: Some(codex_home),
+            ..Self::default()
+        }
+    }
+
+    /// A cache for `codex_home` with the same base overrides as this one.
+    pub(crate) fn for_codex_home_like(&self, codex_home: PathBuf) -> Self {
+        Self::for_codex_home(codex_home).with_base_overrides(self.base_overrides.clone())
+    }
+
+    /// Applies `overrides` to every config this cache loads.
+    pub(crate) fn with_base_overrides(mut self, overrides: Vec<(String, toml::Value)>) -> Self {
+        self.base_overrides = overrides;
+        self
+    }
+
+    fn builder(&self) -> ConfigBuilder {
+        match &self.codex_home {
+            Some(codex_home) => ConfigBuilder::default().codex_home(codex_home.clone()),
+            None => ConfigBuilder::default(),
+        }
+    }
+
+    /// Returns the config for `overrides`, loading it on first use.
+    pub(crate) async fn load(
+        &self,
+        overrides: Vec<(String, toml::Value)>,
+    ) -> std::io::Result<Config> {
+        let key = cache_key(&overrides);
+        if let Some(config) = self
+            .entries
+            .lock()
+            .ok()
+            .and_then(|entries| entries.get(&key).cloned())
+        {
+            return Ok(config);
+        }
+
+        let overrides = self
+            .base_overrides
+            .iter()
+            .cloned()
+            .chain(overrides)
+            .collect();
+        let config = self.builder().cli_overrides(overrides).build().await?;
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(key, config.clone());
+        }
+        Ok(config)
+    }
+
+    /// Checks that the config on disk still loads, then drops every cached
+    /// config so the next requests pick up the changes. On error the cache is
+    /// left untouched. Returns how many configs were dropped.
+    pub(crate) async fn reload(&self) -> std::io::Result<usize> {
+        self.builder()
+            .cli_overrides(self.base_overrides.clone())
+            .build()
+            .await?;
+        let dropped = self
+            .entries
+            .lock()
+            .map(|mut entries| entries.drain().count())
+            .unwrap_or(0);
+        info!("config cache cleared ({dropped} configs)");
+        Ok(dropped)
+    }
+}
+
+fn cache_key(overrides: &[(String, toml::Value)]) -> String {
+    overrides
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn override_sets_get_distinct_keys() {
+        let model = |name: &str| vec![("model".to_string(), toml::Value::String(name.to_string()))];
+        assert_eq!(cache_key(&model("gpt-5.2")), cache_key(&model("gpt-5.2")));
+        assert_ne!(
+            cache_key(&model("gpt-5.2")),
+            cache_key(&model("gpt-5.2-codex"))
+        );
+    }
+}
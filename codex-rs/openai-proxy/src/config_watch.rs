@@ -0,0 +1,154 @@
+//! Hot reload: the Codex config, the model map, `proxy.toml` and the tenants
+//! file are re-read on SIGHUP and whenever one of them changes on disk, the
+//! same as `POST /admin/config/reload`. Threads already running keep the
+//! config they were created with, so in-flight streams are not interrupted.
+
+use std::future::Future;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use codex_core::config::CONFIG_TOML_FILE;
+use tracing::info;
+use tracing::warn;
+
+use crate::AppState;
+use crate::admin::reload_config;
+use crate::proxy_config;
+
+/// How often the watched files are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The modification time and length of each watched file, `None` while it
+/// does not exist.
+#[derive(Debug)]
+struct FileStamps {
+    files: Vec<(PathBuf, Option<(SystemTime, u64)>)>,
+}
+
+impl FileStamps {
+    fn new(paths: Vec<PathBuf>) -> Self {
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let stamp = stamp(&path);
+                (path, stamp)
+            })
+            .collect();
+        Self { files }
+    }
+
+    /// Whether any file changed, was created or was removed since the last
+    /// check.
+    fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, last) in &mut self.files {
+            let current = stamp(path);
+            if current != *last {
+                *last = current;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Reloads the config of `state` on SIGHUP and when its files change.
+pub(crate) async fn spawn(state: AppState) {
+    let mut paths = Vec::new();
+    match state.configs.load(Vec::new()).await {
+        Ok(config) => paths.push(config.codex_home.join(CONFIG_TOML_FILE)),
+        Err(e) => warn!("not watching the Codex config: {e}"),
+    }
+    paths.extend(state.model_map.path());
+    paths.extend(proxy_config::path());
+    paths.extend(
+        state
+            .tenants
+            .get()
+            .map(|tenants| tenants.path().to_path_buf()),
+    );
+    watch(paths, move |trigger| {
+        let state = state.clone();
+        async move {
+            match reload_config(&state, trigger).await {
+                Ok(()) => info!("config reloaded ({trigger})"),
+                Err(e) => warn!("config reload ({trigger}) failed: {}", e.message),
+            }
+        }
+    });
+}
+
+/// Calls `reload` with what triggered it on SIGHUP and whenever one of
+/// `paths` changes.
+pub(crate) fn watch<F, Fut>(paths: Vec<PathBuf>, reload: F)
+where
+    F: Fn(&'static str) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    for path in &paths {
+        info!("watching {} for changes", path.display());
+    }
+
+    #[cfg(unix)]
+    {
+        let reload = reload.clone();
+        tokio::spawn(async move {
+            use tokio::signal::unix::SignalKind;
+            use tokio::signal::unix::signal;
+
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(e) => {
+                    warn!("cannot listen for SIGHUP: {e}");
+                    return;
+                }
+            };
+            while hangups.recv().await.is_some() {
+                reload("sighup").await;
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        let mut stamps = FileStamps::new(paths);
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if stamps.changed() {
+                reload("file_change").await;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_to_watched_files_are_noticed() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("models.toml");
+        let mut stamps = FileStamps::new(vec![path.clone()]);
+        assert!(!stamps.changed());
+
+        std::fs::write(&path, "a = 1\n").expect("write");
+        assert!(stamps.changed());
+        assert!(!stamps.changed());
+
+        std::fs::write(&path, "a = 1\nb = 2\n").expect("write");
+        assert!(stamps.changed());
+
+        std::fs::remove_file(&path).expect("remove");
+        assert!(stamps.changed());
+        assert!(!stamps.changed());
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::warn;
+
+/// What the proxy needs to bring a conversation back after a restart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StoredConversation {
+    pub(crate) rollout_path: PathBuf,
+    pub(crate) model: String,
+    /// The `parallel_tool_calls` the conversation was started with; later
+    /// turns may not change it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) parallel_tool_calls: Option<bool>,
+}
+
+/// On-disk `conversation_id` → rollout mapping, rewritten on every change so
+/// threads can be lazily resumed from the Codex sessions directory after the
+/// proxy is redeployed.
+pub(crate) struct ConversationStore {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, StoredConversation>>,
+}
+
+impl ConversationStore {
+    /// Loads the mapping kept under `codex_home`.
+    pub(crate) fn load_from_home(codex_home: &Path) -> Self {
+        Self::load(codex_home.join("openai-proxy").join("conversations.json"))
+    }
+
+    /// Loads the mapping at `path`. A missing or unreadable file starts empty.
+    pub(crate) fn load(path: PathBuf) -> Self {
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!(
+                    "ignoring corrupt conversation store {}: {e}",
+                    path.display()
+                );
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                warn!("failed to read conversation store {}: {e}", path.display());
+                HashMap::new()
+            }
+        };
+        Self {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub(crate) fn get(&self, conversation_id: &str) -> Option<StoredConversation> {
+        self.entries
+            .lock()
+            .ok()
+            .and_then(|entries| entries.get(conversation_id).cloned())
+    }
+
+    pub(crate) fn record(&self, conversation_id: String, conversation: StoredConversation) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if entries.get(&conversation_id) == Some(&conversation) {
+            return;
+        }
+        entries.insert(conversation_id, conversation);
+        self.persist(&entries);
+    }
+
+    /// Forgets `conversation_id`. Returns `false` if it was not stored.
+    pub(crate) fn remove(&self, conversation_id: &str) -> bool {
+        let Ok(mut entries) = self.entries.lock() else {
+            return false;
+        };
+        if entries.remove(conversation_id).is_none() {
+            return false;
+        }
+        self.persist(&entries);
+        true
+    }
+
+    fn persist(&self, entries: &HashMap<String, StoredConversation>) {
+        if let Err(e) = write_atomically(&self.path, entries) {
+            warn!(
+                "failed to write conversation store {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+fn write_atomically(path: &Path, entries: &HashMap<String, StoredConversation>) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let contents = serde_json::to_vec_pretty(entries)?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn mapping_survives_reload() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("openai-proxy").join("conversations.json");
+        let conversation = StoredConversation {
+            rollout_path: PathBuf::from("/tmp/rollout.jsonl"),
+            model: "gpt-5.2".to_string(),
+            parallel_tool_calls: Some(true),
+        };
+
+        let store = ConversationStore::load(path.clone());
+        store.record("a".to_string(), conversation.clone());
+        store.record("b".to_string(), conversation.clone());
+        assert!(store.remove("b"));
+        assert!(!store.remove("b"));
+
+        let reloaded = ConversationStore::load(path);
+        assert_eq!(reloaded.get("a"), Some(conversation));
+        assert_eq!(reloaded.get("b"), None);
+    }
+}
@@ -0,0 +1,334 @@
+use std::path::PathBuf;
+
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use bytes::Bytes;
+use codex_core::ARCHIVED_SESSIONS_SUBDIR;
+use codex_core::SESSIONS_SUBDIR;
+use codex_protocol::ThreadId;
+use codex_protocol::protocol::InitialHistory;
+use codex_protocol::protocol::RolloutItem;
+use codex_protocol::protocol::RolloutLine;
+use serde::Deserialize;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::json_response;
+use crate::log_message;
+use crate::now_ts;
+use crate::thread_config;
+
+pub(crate) fn parse_conversation_id(id: &str) -> Result<ThreadId, ProxyError> {
+    ThreadId::from_string(id).map_err(|e| {
+        ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            format!("invalid conversation_id: {e}"),
+            "invalid_request_error",
+        )
+    })
+}
+
+/// `DELETE /v1/conversations/{id}`: shuts the thread down and frees it.
+pub(crate) async fn handle_delete_conversation(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let thread_id = match parse_conversation_id(&id) {
+        Ok(thread_id) => thread_id,
+        Err(e) => return e.into_response(),
+    };
+
+    if !state.threads.close(thread_id).await {
+        // Reuse the registry's lookup error so expired ids are reported as such.
+        return match state.threads.get(thread_id) {
+            Ok(_) => ProxyError::internal(format!("failed to close conversation {id}")),
+            Err(e) => e,
+        }
+        .into_response();
+    }
+    state.files.remove_conversation(thread_id).await;
+    state.turn_diffs.remove_conversation(&thread_id.to_string());
+
+    log_message(
+        serde_json::json!({
+            "type": "conversation_deleted",
+            "conversation_id": id,
+        })
+        .to_string(),
+    );
+
+    json_response(
+        StatusCode::OK,
+        serde_json::json!({
+            "id": id,
+            "object": "conversation.deleted",
+            "deleted": true,
+        })
+        .to_string(),
+    )
+}
+
+/// `POST /v1/conversations/{id}/fork`: starts a new conversation from a copy
+/// of `id`'s history, leaving the original untouched.
+pub(crate) async fn handle_fork_conversation(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let thread_id = match parse_conversation_id(&id) {
+        Ok(thread_id) => thread_id,
+        Err(e) => return e.into_response(),
+    };
+    let source = match state.threads.stored(thread_id) {
+        Ok(source) => source,
+        Err(e) => return e.into_response(),
+    };
+    let mut config = match thread_config(&state, source.model.clone(), Vec::new()).await {
+        Ok(config) => config,
+        Err(e) => return e.into_response(),
+    };
+    config.model_supports_parallel_tool_calls = source.parallel_tool_calls;
+
+    let forked = match state
+        .thread_manager
+        .fork_thread(usize::MAX, config, source.rollout_path)
+        .await
+    {
+        Ok(forked) => forked,
+        Err(e) => {
+            return ProxyError::internal(format!("failed to fork conversation {id}: {e}"))
+                .into_response();
+        }
+    };
+    state.threads.insert(
+        forked.thread_id,
+        forked.thread.clone(),
+        source.model,
+        source.parallel_tool_calls,
+    );
+
+    let forked_id = forked.thread_id.to_string();
+    log_message(
+        serde_json::json!({
+            "type": "conversation_forked",
+            "conversation_id": forked_id,
+            "forked_from": id,
+        })
+        .to_string(),
+    );
+
+    json_response(
+        StatusCode::OK,
+        serde_json::json!({
+            "id": forked_id,
+            "object": "conversation",
+            "created_at": now_ts(),
+            "forked_from": id,
+        })
+        .to_string(),
+    )
+}
+
+/// `POST /v1/conversations/import` JSON body. A raw rollout can be posted
+/// instead, as `application/x-ndjson` or any other non-JSON content type.
+#[derive(Debug, Default, Deserialize)]
+struct ImportRequest {
+    /// A rollout under the Codex home's `sessions` or `archived_sessions`
+    /// directory; relative paths are taken from `sessions`.
+    #[serde(default)]
+    rollout_path: Option<String>,
+    /// A rollout uploaded through `POST /v1/files`.
+    #[serde(default)]
+    file_id: Option<String>,
+    /// The model the conversation continues with; defaults to the one the
+    /// session last ran with.
+    #[serde(default)]
+    model: Option<String>,
+}
+
+fn invalid_rollout(message: String) -> ProxyError {
+    ProxyError::new(StatusCode::BAD_REQUEST, message, "invalid_request_error")
+        .with_code("invalid_rollout")
+}
+
+/// `POST /v1/conversations/import`: starts a conversation from the history
+/// of a Codex session rollout, e.g. one recorded by the CLI, so it can be
+/// continued through the proxy. The rollout itself is left untouched.
+pub(crate) async fn handle_import_conversation(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let is_json = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let (request, rollout) = if is_json {
+        match serde_json::from_slice::<ImportRequest>(&body) {
+            Ok(request) => (request, None),
+            Err(e) => {
+                return ProxyError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("invalid import request: {e}"),
+                    "invalid_request_error",
+                )
+                .into_response();
+            }
+        }
+    } else {
+        (
+            ImportRequest::default(),
+            Some(String::from_utf8_lossy(&body).into_owned()),
+        )
+    };
+
+    let defaults = match state.configs.load(Vec::new()).await {
+        Ok(config) => config,
+        Err(e) => return ProxyError::internal(e.to_string()).into_response(),
+    };
+    let source = match (rollout, &request.rollout_path, &request.file_id) {
+        (Some(rollout), None, None) => Ok(rollout),
+        (None, Some(path), None) => match session_rollout(&defaults.codex_home, path) {
+            Ok(path) => read_rollout(&path).await,
+            Err(e) => Err(e),
+        },
+        (None, None, Some(id)) => match state.files.path(id) {
+            Some(path) => read_rollout(&path).await,
+            None => Err(invalid_rollout(format!("No such file: {id}"))),
+        },
+        _ => Err(invalid_rollout(
+            "name exactly one of rollout_path and file_id, or post the rollout itself".to_string(),
+        )),
+    };
+    let items = match source.and_then(|text| rollout_items(&text)) {
+        Ok(items) => items,
+        Err(e) => return e.into_response(),
+    };
+
+    let imported_from = items.iter().find_map(|item| match item {
+        RolloutItem::SessionMeta(line) => Some(line.meta.id.to_string()),
+        _ => None,
+    });
+    let requested_model = match request.model.as_deref() {
+        Some(model) => match state.model_map.resolve(model) {
+            Ok(model) => Some(model),
+            Err(e) => return e.into_response(),
+        },
+        None => None,
+    };
+    let Some(model) = requested_model
+        .or_else(|| last_model(&items))
+        .or_else(|| defaults.model.clone())
+    else {
+        return invalid_rollout("the rollout names no model; pass one as model".to_string())
+            .into_response();
+    };
+    let config = match thread_config(&state, model.clone(), Vec::new()).await {
+        Ok(config) => config,
+        Err(e) => return e.into_response(),
+    };
+    let imported = match state
+        .thread_manager
+        .resume_thread_with_history(
+            config,
+            InitialHistory::Forked(items),
+            state.auth_manager.clone(),
+        )
+        .await
+    {
+        Ok(imported) => imported,
+        Err(e) => {
+            return ProxyError::internal(format!("failed to import conversation: {e}"))
+                .into_response();
+        }
+    };
+    state.threads.insert(
+        imported.thread_id,
+        imported.thread.clone(),
+        model.clone(),
+        None,
+    );
+
+    let id = imported.thread_id.to_string();
+    log_message(
+        serde_json::json!({
+            "type": "conversation_imported",
+            "conversation_id": id,
+            "imported_from": imported_from,
+        })
+        .to_string(),
+    );
+
+    json_response(
+        StatusCode::OK,
+        serde_json::json!({
+            "id": id,
+            "object": "conversation",
+            "created_at": now_ts(),
+            "model": model,
+            "imported_from": imported_from,
+        })
+        .to_string(),
+    )
+}
+
+/// Resolves `requested` to a rollout inside the Codex home's session
+/// directories; rollouts anywhere else are not the proxy's to read.
+fn session_rollout(codex_home: &std::path::Path, requested: &str) -> Result<PathBuf, ProxyError> {
+    let sessions = codex_home.join(SESSIONS_SUBDIR);
+    let path = sessions
+        .join(requested)
+        .canonicalize()
+        .map_err(|e| invalid_rollout(format!("rollout {requested} is not accessible: {e}")))?;
+    let allowed = [SESSIONS_SUBDIR, ARCHIVED_SESSIONS_SUBDIR]
+        .iter()
+        .filter_map(|dir| codex_home.join(dir).canonicalize().ok())
+        .any(|dir| path.starts_with(dir));
+    if !allowed {
+        return Err(ProxyError::new(
+            StatusCode::FORBIDDEN,
+            format!("rollout {requested} is outside the Codex sessions directory"),
+            "permission_error",
+        )
+        .with_code("rollout_path_not_allowed"));
+    }
+    Ok(path)
+}
+
+async fn read_rollout(path: &std::path::Path) -> Result<String, ProxyError> {
+    tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| invalid_rollout(format!("failed to read {}: {e}", path.display())))
+}
+
+/// The items of a rollout, skipping lines that are not rollout items as
+/// Codex does when it resumes a session.
+fn rollout_items(text: &str) -> Result<Vec<RolloutItem>, ProxyError> {
+    let items = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str::<RolloutLine>(line).ok())
+        .map(|line| line.item)
+        .collect::<Vec<_>>();
+    if !items
+        .iter()
+        .any(|item| matches!(item, RolloutItem::ResponseItem(_)))
+    {
+        return Err(invalid_rollout(
+            "the rollout holds no conversation history".to_string(),
+        ));
+    }
+    Ok(items)
+}
+
+/// The model the session's last turn ran with.
+fn last_model(items: &[RolloutItem]) -> Option<String> {
+    items.iter().rev().find_map(|item| match item {
+        RolloutItem::TurnContext(context) => Some(context.model.clone()),
+        _ => None,
+    })
+}
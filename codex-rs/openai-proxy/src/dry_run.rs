@@ -0,0 +1,114 @@
+//! `dry_run: true`: the turn runs read-only, and every patch Codex would
+//! apply is declined and handed back to the client instead, so CI can
+//! review the patches and apply them out-of-band.
+//!
+//! A read-only turn asks for approval before each patch, with the patch
+//! attached; a dry run answers every such request with a denial and keeps
+//! the patch. Approvals to run commands outside the sandbox are denied too.
+
+use codex_core::protocol::AskForApproval;
+use codex_core::protocol::SandboxPolicy;
+use codex_protocol::protocol::EventMsg;
+use serde::Serialize;
+
+use crate::codex_events;
+use crate::codex_events::ChangedFile;
+use crate::codex_events::CodexEvent;
+
+/// The approval and sandbox policies a turn runs under: the request's, or
+/// for a dry run read-only with every write raised as an approval.
+pub(crate) fn policies(
+    dry_run: bool,
+    approval_policy: AskForApproval,
+    sandbox_policy: SandboxPolicy,
+) -> (AskForApproval, SandboxPolicy) {
+    if dry_run {
+        (AskForApproval::OnRequest, SandboxPolicy::ReadOnly)
+    } else {
+        (approval_policy, sandbox_policy)
+    }
+}
+
+/// A patch Codex proposed during a dry run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ProposedPatch {
+    /// The tool call that proposed it.
+    call_id: String,
+    files: Vec<ChangedFile>,
+    /// The patch as a git-style diff, ready for `git apply`.
+    unified_diff: String,
+}
+
+impl ProposedPatch {
+    /// The patch an approval request carries, if it is a patch approval.
+    pub(crate) fn from_event(msg: &EventMsg) -> Option<Self> {
+        let EventMsg::ApplyPatchApprovalRequest(ev) = msg else {
+            return None;
+        };
+        Some(Self {
+            call_id: ev.call_id.clone(),
+            files: codex_events::changed_files(&ev.changes),
+            unified_diff: codex_events::unified_diff(&ev.changes),
+        })
+    }
+
+    /// The patch as a Responses `proposed_patch` output item.
+    pub(crate) fn output_item(&self) -> serde_json::Value {
+        let mut item = serde_json::json!({ "type": "proposed_patch" });
+        if let (Some(item), Ok(serde_json::Value::Object(fields))) =
+            (item.as_object_mut(), serde_json::to_value(self))
+        {
+            item.extend(fields);
+        }
+        item
+    }
+
+    /// The patch as a `codex.proposed_patch` event for streamed chat
+    /// completions.
+    pub(crate) fn event(&self) -> CodexEvent {
+        CodexEvent {
+            kind: "codex.proposed_patch",
+            fields: serde_json::to_value(self).unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codex_protocol::protocol::ApplyPatchApprovalRequestEvent;
+    use codex_protocol::protocol::FileChange;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    #[test]
+    fn patch_approvals_become_proposed_patches() {
+        let msg = EventMsg::ApplyPatchApprovalRequest(ApplyPatchApprovalRequestEvent {
+            call_id: "call-1".to_string(),
+            turn_id: String::new(),
+            changes: HashMap::from([(
+                PathBuf::from("hello.txt"),
+                FileChange::Add {
+                    content: "hi\n".to_string(),
+                },
+            )]),
+            reason: None,
+            grant_root: None,
+        });
+        let patch = ProposedPatch::from_event(&msg).expect("proposed patch");
+        assert_eq!(
+            patch.output_item(),
+            serde_json::json!({
+                "type": "proposed_patch",
+                "call_id": "call-1",
+                "files": [{ "path": "hello.txt", "kind": "add" }],
+                "unified_diff": "--- /dev/null\n+++ b/hello.txt\n@@ -0,0 +1,1 @@\n+hi\n",
+            })
+        );
+        assert_eq!(
+            policies(true, AskForApproval::Never, SandboxPolicy::DangerFullAccess),
+            (AskForApproval::OnRequest, SandboxPolicy::ReadOnly)
+        );
+    }
+}
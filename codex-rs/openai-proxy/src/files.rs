@@ -0,0 +1,317 @@
+//! `POST /v1/files` and `GET /v1/files/{id}/content`: uploads clients can
+//! hand Codex to work on, such as code archives or data files. Each upload
+//! is kept on disk under its conversation's directory (or a shared one for
+//! uploads without a conversation), where turns can read it; requests then
+//! refer to it as a file part with its `file_id`.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use axum::body::Body;
+use axum::extract::Multipart;
+use axum::extract::State;
+use axum::extract::multipart::Field;
+use axum::extract::multipart::MultipartError;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use codex_protocol::ThreadId;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::attachments::sanitize_filename;
+use crate::conversations::parse_conversation_id;
+use crate::json_response;
+use crate::log_message;
+use crate::now_ts;
+
+/// Largest upload accepted, as in the OpenAI Files API.
+pub(crate) const MAX_FILE_BYTES: usize = 512 * 1024 * 1024;
+
+/// Directory for uploads made without a `conversation_id`.
+const SHARED_DIR: &str = "shared";
+
+/// Directory uploads are written to while they are received.
+const INCOMING_DIR: &str = ".incoming";
+
+/// Where uploads are kept: `<root>/<conversation id>/<file id>/<filename>`.
+pub(crate) struct FileStore {
+    root: PathBuf,
+    /// Every upload's path by file id.
+    index: Mutex<HashMap<String, PathBuf>>,
+}
+
+impl FileStore {
+    /// A store at `root`, indexing the uploads already there.
+    pub(crate) fn new(root: PathBuf) -> Self {
+        let index = root
+            .read_dir()
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .filter(|conversation| conversation.file_name() != INCOMING_DIR)
+            .filter_map(|conversation| conversation.path().read_dir().ok())
+            .flatten()
+            .filter_map(Result::ok)
+            .filter_map(|upload| {
+                let id = upload.file_name().into_string().ok()?;
+                Some((id, single_file(&upload.path())?))
+            })
+            .collect();
+        Self {
+            root,
+            index: Mutex::new(index),
+        }
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "dir": self.root,
+            "max_file_bytes": MAX_FILE_BYTES,
+        })
+    }
+
+    /// Streams the contents of `field` to disk under a new file id and
+    /// returns the id, the partial path it was written to and its size.
+    async fn receive(&self, field: &mut Field<'_>) -> Result<(String, PathBuf, u64), ProxyError> {
+        let id = format!("file-{}", uuid::Uuid::new_v4().simple());
+        let dir = self.root.join(INCOMING_DIR);
+        tokio::fs::create_dir_all(&dir).await.map_err(|e| {
+            ProxyError::internal(format!("failed to create {}: {e}", dir.display()))
+        })?;
+        let path = dir.join(&id);
+        let written = write_field(field, &path).await;
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&path).await;
+        }
+        Ok((id, path.clone(), written?))
+    }
+
+    /// Moves a received upload into its conversation's directory.
+    async fn keep(
+        &self,
+        id: &str,
+        received: &Path,
+        conversation: Option<ThreadId>,
+        filename: &str,
+    ) -> Result<(), ProxyError> {
+        let conversation_dir =
+            conversation.map_or_else(|| SHARED_DIR.to_string(), |id| id.to_string());
+        let dir = self.root.join(conversation_dir).join(id);
+        tokio::fs::create_dir_all(&dir).await.map_err(|e| {
+            ProxyError::internal(format!("failed to create {}: {e}", dir.display()))
+        })?;
+        let path = dir.join(filename);
+        tokio::fs::rename(received, &path).await.map_err(|e| {
+            ProxyError::internal(format!("failed to write {}: {e}", path.display()))
+        })?;
+        if let Ok(mut index) = self.index.lock() {
+            index.insert(id.to_string(), path);
+        }
+        Ok(())
+    }
+
+    /// The path of upload `id`, if there is one.
+    pub(crate) fn path(&self, id: &str) -> Option<PathBuf> {
+        self.index.lock().ok()?.get(id).cloned()
+    }
+
+    /// Deletes the uploads of a conversation that is going away.
+    pub(crate) async fn remove_conversation(&self, thread_id: ThreadId) {
+        let dir = self.root.join(thread_id.to_string());
+        if let Ok(mut index) = self.index.lock() {
+            index.retain(|_, path| !path.starts_with(&dir));
+        }
+        match tokio::fs::remove_dir_all(&dir).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("failed to remove {}: {e}", dir.display()),
+        }
+    }
+}
+
+/// Writes `field` to `path` chunk by chunk, returning its size.
+async fn write_field(field: &mut Field<'_>, path: &Path) -> Result<u64, ProxyError> {
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|e| ProxyError::internal(format!("failed to create {}: {e}", path.display())))?;
+    let mut bytes = 0;
+    while let Some(chunk) = field.chunk().await.map_err(invalid_form)? {
+        file.write_all(&chunk).await.map_err(|e| {
+            ProxyError::internal(format!("failed to write {}: {e}", path.display()))
+        })?;
+        bytes += chunk.len() as u64;
+    }
+    file.flush()
+        .await
+        .map_err(|e| ProxyError::internal(format!("failed to write {}: {e}", path.display())))?;
+    Ok(bytes)
+}
+
+/// The one file in an upload's directory.
+fn single_file(dir: &Path) -> Option<PathBuf> {
+    dir.read_dir()
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find(|path| path.is_file())
+}
+
+fn file_not_found(id: &str) -> ProxyError {
+    ProxyError::new(
+        StatusCode::NOT_FOUND,
+        format!("No such file: {id}"),
+        "invalid_request_error",
+    )
+    .with_code("file_not_found")
+}
+
+fn invalid_form(error: MultipartError) -> ProxyError {
+    ProxyError::new(
+        error.status(),
+        format!("invalid multipart/form-data body: {}", error.body_text()),
+        "invalid_request_error",
+    )
+}
+
+/// `POST /v1/files`: a `multipart/form-data` upload with a `file` part and
+/// optional `purpose` and `conversation_id` fields.
+pub(crate) async fn handle_upload_file(
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> Response {
+    let mut received = None;
+    let result = upload(&state, multipart, &mut received).await;
+    if result.is_err()
+        && let Some(path) = received
+    {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    match result {
+        Ok(file) => json_response(StatusCode::OK, file.to_string()),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Reads the upload's fields, streaming its file part to disk (noted in
+/// `received` until it is kept), and returns the file object.
+async fn upload(
+    state: &AppState,
+    mut multipart: Multipart,
+    received: &mut Option<PathBuf>,
+) -> Result<serde_json::Value, ProxyError> {
+    let mut file = None;
+    let mut conversation_id = None;
+    let mut purpose = None;
+    while let Some(mut field) = multipart.next_field().await.map_err(invalid_form)? {
+        match field.name() {
+            Some("file") if file.is_none() => {
+                let filename = sanitize_filename(field.file_name());
+                let (id, path, bytes) = state.files.receive(&mut field).await?;
+                *received = Some(path);
+                file = Some((id, filename, bytes));
+            }
+            Some("conversation_id") => {
+                conversation_id = Some(field.text().await.map_err(invalid_form)?);
+            }
+            Some("purpose") => purpose = Some(field.text().await.map_err(invalid_form)?),
+            _ => {}
+        }
+    }
+    let Some((id, filename, bytes)) = file else {
+        return Err(ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            "missing the file part of the upload",
+            "invalid_request_error",
+        ));
+    };
+    let conversation = match conversation_id.as_deref().map(str::trim) {
+        Some(id) if !id.is_empty() => {
+            let thread_id = parse_conversation_id(id)?;
+            state.threads.stored(thread_id)?;
+            Some(thread_id)
+        }
+        _ => None,
+    };
+    if let Some(path) = received.take() {
+        state
+            .files
+            .keep(&id, &path, conversation, &filename)
+            .await
+            .inspect_err(|_| *received = Some(path))?;
+    }
+    let purpose = purpose
+        .map(|purpose| purpose.trim().to_string())
+        .filter(|purpose| !purpose.is_empty())
+        .unwrap_or_else(|| "user_data".to_string());
+
+    log_message(
+        serde_json::json!({
+            "type": "file_uploaded",
+            "file_id": id,
+            "bytes": bytes,
+            "conversation_id": conversation.map(|id| id.to_string()),
+        })
+        .to_string(),
+    );
+    Ok(serde_json::json!({
+        "id": id,
+        "object": "file",
+        "bytes": bytes,
+        "created_at": now_ts(),
+        "filename": filename,
+        "purpose": purpose,
+        "status": "processed",
+        "conversation_id": conversation.map(|id| id.to_string()),
+    }))
+}
+
+/// `GET /v1/files/{id}/content`: the uploaded bytes.
+pub(crate) async fn handle_file_content(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> Response {
+    let Some(path) = state.files.path(&id) else {
+        return file_not_found(&id).into_response();
+    };
+    match tokio::fs::File::open(&path).await {
+        Ok(file) => (
+            [(CONTENT_TYPE, "application/octet-stream")],
+            Body::from_stream(ReaderStream::new(file)),
+        )
+            .into_response(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => file_not_found(&id).into_response(),
+        Err(e) => {
+            ProxyError::internal(format!("failed to read {}: {e}", path.display())).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn uploads_on_disk_are_indexed_until_their_conversation_goes() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let thread_id = ThreadId::new();
+        let dir = root.path().join(thread_id.to_string()).join("file-abc123");
+        std::fs::create_dir_all(&dir).expect("create upload dir");
+        std::fs::write(dir.join("notes.txt"), "remember the milk").expect("write upload");
+
+        let store = FileStore::new(root.path().to_path_buf());
+        assert_eq!(store.path("file-abc123"), Some(dir.join("notes.txt")));
+        assert_eq!(store.path("file-0000"), None);
+
+        store.remove_conversation(thread_id).await;
+        assert_eq!(store.path("file-abc123"), None);
+        assert!(!dir.exists());
+    }
+}
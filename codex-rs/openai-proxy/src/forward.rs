@@ -0,0 +1,1036 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use axum::Extension;
+use axum::Router;
+use axum::extract::State;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::response::sse::Event;
+use axum::response::sse::Sse;
+use axum::routing::get;
+use axum::routing::post;
+use codex_app_server_protocol::AuthMode;
+use codex_core::ModelClient;
+use codex_core::ModelProviderInfo;
+use codex_core::Prompt;
+use codex_core::ResponseEvent;
+use codex_core::ResponseStream;
+use codex_core::auth::load_auth_dot_json;
+use codex_core::config::Config;
+use codex_core::error::CodexErr;
+use codex_otel::OtelManager;
+use codex_protocol::ThreadId;
+use codex_protocol::config_types::ReasoningSummary;
+use codex_protocol::models::ContentItem;
+use codex_protocol::models::ResponseItem;
+use codex_protocol::protocol::RateLimitSnapshot;
+use codex_protocol::protocol::SessionSource;
+use futures::Stream;
+use serde::Deserialize;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+use tower_http::services::ServeDir;
+use tracing::info;
+use tracing::warn;
+
+use crate::AppState;
+use crate::ChatChoice;
+use crate::ChatCompletionRequest;
+use crate::ChatCompletionResponse;
+use crate::ChatMessageResponse;
+use crate::ChunkMeta;
+use crate::ProxyError;
+use crate::ToolCallIndices;
+use crate::access_log;
+use crate::access_log::RequestContext;
+use crate::audit_log::TurnUsage;
+use crate::body_limit;
+use crate::circuit_breaker::BreakerSettings;
+use crate::circuit_breaker::CircuitBreakers;
+use crate::cors_layer;
+use crate::error_chunk;
+use crate::health;
+use crate::json_response;
+use crate::map_tool_call;
+use crate::merged_text_from_request;
+use crate::model_list;
+use crate::model_map::ModelMapping;
+use crate::moderation;
+use crate::now_ts;
+use crate::output_cap;
+use crate::output_cap::OutputCap;
+use crate::proxy_config;
+use crate::rate_limit;
+use crate::redaction;
+use crate::role_chunk;
+use crate::stream_buffer::DeltaShaping;
+use crate::stream_buffer::StreamSender;
+use crate::stream_chunk;
+use crate::stream_chunk_with_finish;
+use crate::system_fingerprint;
+use crate::upstream_errors;
+use crate::upstream_rate_limits;
+use crate::upstream_retry;
+use crate::upstream_retry::RetryPolicy;
+use crate::upstream_status::ProviderStats;
+use crate::usage_chunk;
+
+/// Set on responses to name the `provider/model` that served the request,
+/// or each that served one of its candidates.
+const SERVED_BY_HEADER: &str = "x-codex-served-by";
+
+/// Most candidates one request may ask for with `n`.
+const MAX_CANDIDATES: usize = 8;
+
+/// A provider's events, starting with any read while waiting for output.
+type EventStream = Pin<Box<dyn Stream<Item = Result<ResponseEvent, CodexErr>> + Send>>;
+
+/// State of the forward-mode proxy, which sends each request straight to the
+/// model provider instead of running a Codex turn.
+#[derive(Clone)]
+struct ForwardState {
+    config: Arc<Config>,
+    /// What forward mode shares with agent mode: the login, the model map,
+    /// the limits, stream settings and the probes and middleware built on
+    /// them.
+    app: AppState,
+    routes: Arc<RoutingTable>,
+    /// Retries of each target on transient failures.
+    retry: RetryPolicy,
+    /// Providers failing most of their requests are skipped for a while.
+    breakers: Arc<CircuitBreakers>,
+    /// Recent latency and failures per provider, for `GET /status`.
+    stats: Arc<ProviderStats>,
+}
+
+/// A provider from `model_providers` in the Codex config and the model to
+/// ask it for.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RouteTarget {
+    provider: String,
+    model: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Route {
+    model: String,
+    targets: Vec<RouteTarget>,
+}
+
+/// The `CODEX_OPENAI_PROXY_ROUTES` file, e.g.
+///
+/// ```toml
+/// [[route]]
+/// model = "gpt-5.2-codex"
+/// targets = [
+///     { provider = "openai", model = "gpt-5.2-codex" },
+///     { provider = "azure", model = "gpt-5.2-codex" },
+/// ]
+/// ```
+///
+/// Targets are tried in order; a target answering with a 5xx or 429 hands the
+/// request to the next one. Models without a route go to the configured
+/// provider as before.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoutingTable {
+    #[serde(default, rename = "route")]
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    fn load(path: &Path, config: &Config) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("read routes file {}", path.display()))?;
+        Self::parse(&contents, config)
+            .with_context(|| format!("parse routes file {}", path.display()))
+    }
+
+    fn parse(contents: &str, config: &Config) -> anyhow::Result<Self> {
+        let table: Self = toml::from_str(contents)?;
+        let mut seen = HashSet::new();
+        for route in &table.routes {
+            if !seen.insert(route.model.as_str()) {
+                anyhow::bail!("model {:?} is routed twice", route.model);
+            }
+            if route.targets.is_empty() {
+                anyhow::bail!("route for {:?} has no targets", route.model);
+            }
+            for target in &route.targets {
+                if !config.model_providers.contains_key(&target.provider) {
+                    anyhow::bail!(
+                        "route for {:?} uses unknown provider {:?}",
+                        route.model,
+                        target.provider
+                    );
+                }
+            }
+        }
+        Ok(table)
+    }
+
+    /// The targets for `model`, or the configured provider with the model
+    /// `model_map` maps it to when it has no route.
+    fn targets(
+        &self,
+        config: &Config,
+        model_map: &ModelMapping,
+        model: &str,
+    ) -> Result<Vec<RouteTarget>, ProxyError> {
+        match self.routes.iter().find(|route| route.model == model) {
+            Some(route) => Ok(route.targets.clone()),
+            None => Ok(vec![RouteTarget {
+                provider: config.model_provider_id.clone(),
+                model: model_map.resolve(model)?,
+            }]),
+        }
+    }
+
+    /// Every provider requests may be sent to.
+    fn providers(&self, config: &Config) -> BTreeSet<String> {
+        self.routes
+            .iter()
+            .flat_map(|route| &route.targets)
+            .map(|target| target.provider.clone())
+            .chain([config.model_provider_id.clone()])
+            .collect()
+    }
+}
+
+/// The forward-mode router: chat completions, the model list and provider
+/// status.
+pub(crate) fn router(config: Config, app: AppState, static_dir: &Path) -> anyhow::Result<Router> {
+    let routes = match proxy_config::var_os("CODEX_OPENAI_PROXY_ROUTES") {
+        Some(path) => RoutingTable::load(Path::new(&path), &config)?,
+        None => RoutingTable::default(),
+    };
+    info!("Routing {} model(s) with failover", routes.routes.len());
+    let retry = RetryPolicy::from_env()?;
+    info!(
+        "Trying each target up to {} time(s) on transient failures",
+        retry.max_attempts
+    );
+    let breakers = BreakerSettings::from_env()?;
+
+    let state = ForwardState {
+        config: Arc::new(config),
+        app: app.clone(),
+        routes: Arc::new(routes),
+        retry,
+        breakers: Arc::new(CircuitBreakers::new(breakers)),
+        stats: Arc::new(ProviderStats::default()),
+    };
+    let cors = cors_layer(&app);
+    // Requests that reach a provider, subject to rate limiting.
+    let chat_routes = Router::new()
+        .route("/v1/chat/completions", post(handle_chat_completions))
+        .route("/chat/completions", post(handle_chat_completions))
+        .route_layer(axum::middleware::from_fn_with_state(
+            app.clone(),
+            rate_limit::rate_limit,
+        ));
+    let routes = Router::new()
+        .merge(chat_routes)
+        .route("/v1/models", get(handle_models))
+        .route("/models", get(handle_models))
+        .route("/status", get(handle_status))
+        // Probes
+        .route("/healthz", get(health::handle_healthz))
+        .route(
+            "/readyz",
+            get(health::handle_readyz).with_state(app.clone()),
+        )
+        // Bundled chat UI
+        .route(
+            "/",
+            get(|| async { axum::response::Redirect::temporary("/static/chat.html") }),
+        )
+        .nest_service("/static", ServeDir::new(static_dir));
+    Ok(
+        body_limit::limit(routes, state.app.max_request_body_bytes.clone())
+            .with_state(state)
+            .layer(cors)
+            .layer(axum::middleware::from_fn_with_state(
+                app,
+                access_log::access_log,
+            )),
+    )
+}
+
+async fn handle_models(State(state): State<ForwardState>) -> Response {
+    model_list(
+        &state.app.thread_manager,
+        &state.config,
+        &state.app.model_map,
+        state.app.max_output_tokens.get(),
+    )
+    .await
+}
+
+/// `GET /status`: each provider's health, recent latency and circuit, and
+/// when the login was last refreshed. Always 200; `/readyz` is for load
+/// balancers.
+async fn handle_status(State(state): State<ForwardState>) -> Response {
+    let now = Instant::now();
+    let providers: BTreeMap<_, _> = state
+        .routes
+        .providers(&state.config)
+        .into_iter()
+        .map(|provider| {
+            let status = state.stats.status(&provider, &state.breakers, now);
+            (provider, status)
+        })
+        .collect();
+    let down = providers
+        .values()
+        .filter(|status| status.health == "down")
+        .count();
+    let overall = match down {
+        0 => "ok",
+        down if down == providers.len() => "unavailable",
+        _ => "degraded",
+    };
+    let auth_mode = state
+        .app
+        .auth_manager
+        .get_auth_mode()
+        .map(|mode| match mode {
+            AuthMode::ApiKey => "api_key",
+            AuthMode::ChatGPT => "chatgpt",
+        });
+    // The auth manager refreshes tokens without saying when, so the time is
+    // read back from the stored credentials.
+    let last_refresh_at = load_auth_dot_json(
+        &state.config.codex_home,
+        state.config.cli_auth_credentials_store_mode,
+    )
+    .ok()
+    .flatten()
+    .and_then(|auth| auth.last_refresh)
+    .map(|at| at.timestamp());
+    json_response(
+        StatusCode::OK,
+        serde_json::json!({
+            "status": overall,
+            "providers": providers,
+            "auth": {
+                "mode": auth_mode,
+                "last_refresh_at": last_refresh_at,
+            },
+        })
+        .to_string(),
+    )
+}
+
+async fn handle_chat_completions(
+    State(state): State<ForwardState>,
+    Extension(context): Extension<RequestContext>,
+    body: axum::Json<ChatCompletionRequest>,
+) -> Response {
+    info!(
+        "Forwarding chat completion: model={}, stream={}",
+        body.model, body.stream
+    );
+    context.set_model(&body.model);
+    context.set_user(body.user.as_deref());
+    let candidates = body.candidates();
+    if !(1..=MAX_CANDIDATES).contains(&candidates) {
+        return ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            format!("n must be between 1 and {MAX_CANDIDATES}"),
+            "invalid_request_error",
+        )
+        .into_response();
+    }
+    let shaping = match DeltaShaping::from_request(
+        body.stream_granularity.as_deref(),
+        body.stream_min_interval_ms,
+    ) {
+        Ok(shaping) => shaping,
+        Err(e) => return e.into_response(),
+    };
+    let Some(merged_text) = merged_text_from_request(&body) else {
+        return ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            "no user content found",
+            "invalid_request_error",
+        )
+        .into_response();
+    };
+    if let Err(e) = moderation::check(&state.app, &merged_text).await {
+        return e.into_response();
+    }
+    // No tools: the provider's answer goes back to the client as is.
+    let mut prompt = Prompt::default();
+    prompt.seed = body.seed;
+    prompt.input = vec![ResponseItem::Message {
+        id: None,
+        role: "user".to_string(),
+        content: vec![ContentItem::InputText {
+            text: redaction::scrub_prompt(state.app.redactor.as_deref(), &merged_text),
+        }],
+    }];
+    // Each candidate is its own request, so each may fail over on its own.
+    let started = futures::future::join_all(
+        (0..candidates).map(|_| start_stream(&state, &body.model, &prompt)),
+    )
+    .await;
+    let mut streams = Vec::with_capacity(candidates);
+    let mut served_by = Vec::<RouteTarget>::new();
+    let mut rate_limits = None;
+    for started in started {
+        let (stream, target, limits) = match started {
+            Ok(started) => started,
+            Err(e) => return e.into_response(),
+        };
+        streams.push(stream);
+        if !served_by.contains(&target) {
+            served_by.push(target);
+        }
+        rate_limits = limits.or(rate_limits);
+    }
+    let max_output_tokens =
+        output_cap::effective(state.app.max_output_tokens.get(), body.max_output_tokens());
+    let response = if body.stream {
+        stream_response(streams, &body, max_output_tokens, shaping, &state, context)
+    } else {
+        let codex_model = state.app.model_map.codex_model(&body.model);
+        collect_response(
+            streams,
+            body.0.model,
+            &codex_model,
+            body.0.seed,
+            max_output_tokens,
+            &context,
+        )
+        .await
+    };
+    let mut response = with_served_by(response, &served_by);
+    if let Some(rate_limits) = &rate_limits {
+        upstream_rate_limits::set_headers(response.headers_mut(), rate_limits);
+    }
+    response
+}
+
+/// Reads every candidate to its end, concurrently, and answers with one
+/// choice per candidate and their usage summed, which is also recorded in
+/// `context`.
+async fn collect_response(
+    streams: Vec<EventStream>,
+    model: String,
+    codex_model: &str,
+    seed: Option<i64>,
+    max_output_tokens: Option<u64>,
+    context: &RequestContext,
+) -> Response {
+    let candidates = streams
+        .into_iter()
+        .enumerate()
+        .map(|(index, stream)| collect_choice(index, stream, OutputCap::new(max_output_tokens)));
+    let collected = match futures::future::try_join_all(candidates).await {
+        Ok(collected) => collected,
+        Err(e) => return e.into_response(),
+    };
+    let mut usage = TurnUsage::default();
+    let choices = collected
+        .into_iter()
+        .map(|(choice, choice_usage)| {
+            usage += choice_usage;
+            choice
+        })
+        .collect();
+    context.add_tokens(usage.total_tokens);
+    let resp = ChatCompletionResponse {
+        id: format!("chatcmpl-codex-{}", uuid::Uuid::new_v4()),
+        object: "chat.completion".to_string(),
+        created: now_ts(),
+        system_fingerprint: system_fingerprint(codex_model),
+        model,
+        choices,
+        usage: usage.into(),
+        changed_files: Vec::new(),
+        proposed_patches: Vec::new(),
+        plan: None,
+        seed,
+    };
+    let body = serde_json::to_string(&resp).unwrap_or_else(|_| "{}".to_string());
+    json_response(StatusCode::OK, body)
+}
+
+/// Reads one candidate as choice `index`. Stops reading (and so drops the
+/// upstream request) once `output_cap` is reached.
+async fn collect_choice(
+    index: usize,
+    mut stream: EventStream,
+    mut output_cap: OutputCap,
+) -> Result<(ChatChoice, TurnUsage), ProxyError> {
+    let mut final_text = String::new();
+    let mut tool_calls = Vec::new();
+    let mut usage = TurnUsage::default();
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(ResponseEvent::OutputItemDone(item)) => tool_calls.extend(map_tool_call(&item)),
+            Ok(ResponseEvent::OutputTextDelta(delta)) => {
+                final_text.push_str(output_cap.take(&delta));
+                if output_cap.reached() {
+                    break;
+                }
+            }
+            Ok(ResponseEvent::Completed {
+                token_usage: Some(token_usage),
+                ..
+            }) => usage.add(&token_usage),
+            Ok(_) => {}
+            Err(e) => return Err(upstream_error(&e)),
+        }
+    }
+
+    let finish_reason = if output_cap.reached() {
+        "length"
+    } else if tool_calls.is_empty() {
+        "stop"
+    } else {
+        "tool_calls"
+    };
+    let choice = ChatChoice {
+        index,
+        message: ChatMessageResponse {
+            role: "assistant".to_string(),
+            content: final_text,
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            annotations: None,
+        },
+        finish_reason: finish_reason.to_string(),
+        abort_reason: None,
+    };
+    Ok((choice, usage))
+}
+
+/// Streams every candidate's chunks as they arrive, each under its own
+/// choice index. A failing candidate ends the whole stream with its error.
+fn stream_response(
+    streams: Vec<EventStream>,
+    body: &ChatCompletionRequest,
+    max_output_tokens: Option<u64>,
+    shaping: DeltaShaping,
+    state: &ForwardState,
+    context: RequestContext,
+) -> Response {
+    let (tx, rx) = state.app.stream_buffers.channel();
+    let rx = state.app.stream_buffers.shape(rx, shaping);
+    let codex_model = state.app.model_map.codex_model(&body.model);
+    let meta = ChunkMeta::new(&body.model, &codex_model, body.seed);
+    let include_usage = body.include_usage();
+    tokio::spawn(async move {
+        let candidates = streams.into_iter().enumerate().map(|(index, stream)| {
+            stream_candidate(index, stream, OutputCap::new(max_output_tokens), &meta, &tx)
+        });
+        match futures::future::try_join_all(candidates).await {
+            Ok(usages) => {
+                let mut usage = TurnUsage::default();
+                for candidate_usage in usages {
+                    usage += candidate_usage;
+                }
+                context.add_tokens(usage.total_tokens);
+                if include_usage {
+                    let _ = tx.send(usage_chunk(usage, &meta).to_string()).await;
+                }
+            }
+            Err(e) => {
+                let _ = tx.send(error_chunk(&e, &meta).to_string()).await;
+            }
+        }
+        let _ = tx.send("[DONE]".to_string()).await;
+    });
+
+    let events = ReceiverStream::new(rx)
+        .map(|data| Ok::<Event, std::convert::Infallible>(Event::default().data(data)));
+    state
+        .app
+        .sse_keep_alive
+        .apply(Sse::new(events))
+        .into_response()
+}
+
+/// Streams one candidate as choice `index`, from its role chunk to its
+/// finish chunk, and returns the tokens it used. Stops reading once
+/// `output_cap` is reached or the client has gone away.
+async fn stream_candidate(
+    index: usize,
+    mut stream: EventStream,
+    mut output_cap: OutputCap,
+    meta: &ChunkMeta,
+    tx: &StreamSender<String>,
+) -> Result<TurnUsage, ProxyError> {
+    let send = |chunk: serde_json::Value| tx.send(with_choice_index(chunk, index).to_string());
+    let mut usage = TurnUsage::default();
+    if send(role_chunk(meta)).await.is_err() {
+        return Ok(usage);
+    }
+    let mut has_tool_calls = false;
+    let mut tool_call_indices = ToolCallIndices::default();
+    while let Some(event) = stream.next().await {
+        let chunk = match event {
+            Ok(ResponseEvent::OutputItemDone(item)) => match map_tool_call(&item) {
+                Some(tool_call) => {
+                    has_tool_calls = true;
+                    let call_index = tool_call_indices.index(&tool_call.id);
+                    stream_chunk(None, Some((call_index, tool_call)), false, meta)
+                }
+                None => continue,
+            },
+            Ok(ResponseEvent::OutputTextDelta(delta)) => {
+                let text = output_cap.take(&delta);
+                if output_cap.reached() {
+                    if !text.is_empty() {
+                        let _ = send(stream_chunk(Some(text), None, false, meta)).await;
+                    }
+                    break;
+                }
+                stream_chunk(Some(text), None, false, meta)
+            }
+            Ok(ResponseEvent::Completed {
+                token_usage: Some(token_usage),
+                ..
+            }) => {
+                usage.add(&token_usage);
+                continue;
+            }
+            Ok(_) => continue,
+            Err(e) => return Err(upstream_error(&e)),
+        };
+        if send(chunk).await.is_err() {
+            return Ok(usage);
+        }
+    }
+    let finish_reason = if output_cap.reached() {
+        "length"
+    } else if has_tool_calls {
+        "tool_calls"
+    } else {
+        "stop"
+    };
+    let _ = send(stream_chunk_with_finish(None, None, finish_reason, meta)).await;
+    Ok(usage)
+}
+
+/// Points a chunk's choice at candidate `index`.
+fn with_choice_index(mut chunk: serde_json::Value, index: usize) -> serde_json::Value {
+    if let Some(choice) = chunk["choices"].get_mut(0) {
+        choice["index"] = index.into();
+    }
+    chunk
+}
+
+/// Opens a stream for `model` on the first of its route targets that accepts
+/// the request, retrying a target on transient failures and failing over to
+/// the next one on a 5xx or 429. Both only happen before the provider sent
+/// any output; after that the target is committed. Targets whose provider's
+/// circuit is open are skipped, and a 503 says when to come back if that
+/// leaves none. Returns the rate limits the provider reported, if any.
+async fn start_stream(
+    state: &ForwardState,
+    model: &str,
+    prompt: &Prompt,
+) -> Result<(EventStream, RouteTarget, Option<RateLimitSnapshot>), ProxyError> {
+    let targets = state
+        .routes
+        .targets(&state.config, &state.app.model_map, model)?;
+    let last = targets.len().saturating_sub(1);
+    // The soonest any skipped provider takes requests again.
+    let mut reopens_in: Option<Duration> = None;
+    for (index, target) in targets.into_iter().enumerate() {
+        if let Err(wait) = state.breakers.check(&target.provider, Instant::now()) {
+            warn!(
+                "{}/{} skipped for {model}: its provider's circuit is open",
+                target.provider, target.model
+            );
+            reopens_in = Some(reopens_in.map_or(wait, |soonest| soonest.min(wait)));
+            continue;
+        }
+        let model_info = state
+            .app
+            .thread_manager
+            .get_models_manager()
+            .construct_model_info(&target.model, &state.config)
+            .await;
+        let provider: ModelProviderInfo = match state.config.model_providers.get(&target.provider) {
+            Some(provider) => provider.clone(),
+            None => state.config.model_provider.clone(),
+        };
+        let conversation_id = ThreadId::new();
+        let otel_manager = OtelManager::new(
+            conversation_id,
+            &target.model,
+            &model_info.slug,
+            None,
+            None,
+            state.app.auth_manager.get_auth_mode(),
+            false,
+            "codex-openai-proxy".to_string(),
+            SessionSource::Exec,
+        );
+        let model_client = ModelClient::new(
+            state.config.clone(),
+            Some(state.app.auth_manager.clone()),
+            model_info,
+            otel_manager,
+            provider,
+            None,
+            ReasoningSummary::Detailed,
+            conversation_id,
+            SessionSource::Exec,
+        );
+
+        let mut attempt = 1;
+        let opened = loop {
+            let started = Instant::now();
+            let opened = match model_client.stream(prompt).await {
+                Ok(stream) => first_output(stream).await,
+                Err(e) => Err(e),
+            };
+            let failed = matches!(&opened, Err(e) if upstream_retry::is_transient(e));
+            state
+                .breakers
+                .record(&target.provider, failed, Instant::now());
+            match &opened {
+                Ok(_) => state
+                    .stats
+                    .record_success(&target.provider, started.elapsed()),
+                Err(e) if failed => state.stats.record_failure(&target.provider, &e.to_string()),
+                Err(_) => {}
+            }
+            match opened {
+                Err(e) if state.retry.should_retry(attempt, &e) => {
+                    let delay = state.retry.delay(attempt);
+                    warn!(
+                        "{}/{} failed for {model} on attempt {attempt}, retrying in {delay:?}: {e}",
+                        target.provider, target.model
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                opened => break opened,
+            }
+        };
+        match opened {
+            Ok((stream, rate_limits)) => {
+                info!(
+                    "{model} served by {}/{} (target {}, attempt {attempt})",
+                    target.provider,
+                    target.model,
+                    index + 1
+                );
+                return Ok((stream, target, rate_limits));
+            }
+            Err(e) if index < last && should_fail_over(&e) => {
+                warn!(
+                    "{}/{} failed for {model}, failing over: {e}",
+                    target.provider, target.model
+                );
+            }
+            Err(e) => return Err(upstream_error(&e)),
+        }
+    }
+    match reopens_in {
+        Some(wait) => Err(ProxyError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("No provider for {model} is available right now; try again later."),
+            "server_error",
+        )
+        .with_code("provider_unavailable")
+        .with_retry_after(wait.as_secs().max(1))),
+        None => Err(ProxyError::internal(format!(
+            "no route targets for {model}"
+        ))),
+    }
+}
+
+/// Reads `stream` up to its first output — text, an output item or the end
+/// of the response — so failures before it can still be retried. The events
+/// read are replayed ahead of the rest; the provider's rate limits among
+/// them are returned too.
+async fn first_output(
+    mut stream: ResponseStream,
+) -> Result<(EventStream, Option<RateLimitSnapshot>), CodexErr> {
+    let mut read = Vec::new();
+    let mut rate_limits = None;
+    while let Some(event) = stream.next().await {
+        let event = event?;
+        if let ResponseEvent::RateLimits(snapshot) = &event {
+            rate_limits = Some(snapshot.clone());
+        }
+        let is_output = matches!(
+            event,
+            ResponseEvent::OutputTextDelta(_)
+                | ResponseEvent::OutputItemDone(_)
+                | ResponseEvent::Completed { .. }
+        );
+        read.push(Ok(event));
+        if is_output {
+            break;
+        }
+    }
+    let stream: EventStream = Box::pin(futures::stream::iter(read).chain(stream));
+    Ok((stream, rate_limits))
+}
+
+/// Server errors and rate limits are the target's problem, so another target
+/// may still serve the request.
+fn should_fail_over(err: &CodexErr) -> bool {
+    match err {
+        CodexErr::InternalServerError
+        | CodexErr::UsageLimitReached(_)
+        | CodexErr::QuotaExceeded => true,
+        _ => err
+            .http_status_code_value()
+            .is_some_and(|code| code == 429 || (500..600).contains(&code)),
+    }
+}
+
+/// The provider's error, classified like a failed turn's, else with its
+/// HTTP status when it had one.
+fn upstream_error(err: &CodexErr) -> ProxyError {
+    upstream_errors::classify(&err.to_error_event(None)).unwrap_or_else(|| {
+        let status = err
+            .http_status_code_value()
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .unwrap_or(StatusCode::BAD_GATEWAY);
+        ProxyError::new(status, err.to_string(), "api_error")
+    })
+}
+
+fn with_served_by(mut response: Response, targets: &[RouteTarget]) -> Response {
+    let served_by = targets
+        .iter()
+        .map(|target| format!("{}/{}", target.provider, target.model))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if let Ok(value) = HeaderValue::from_str(&served_by) {
+        response.headers_mut().insert(SERVED_BY_HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use codex_core::config::ConfigBuilder;
+    use codex_protocol::protocol::TokenUsage;
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+    use tower::ServiceExt;
+
+    use crate::AppStateBuilder;
+
+    /// The forward router over a fresh Codex home, forwarding to the mock
+    /// backend, with `configure` applied to the shared state.
+    async fn forward_app(
+        codex_home: &Path,
+        configure: impl FnOnce(AppStateBuilder) -> AppStateBuilder,
+    ) -> Router {
+        let backend = crate::mock_backend::spawn()
+            .await
+            .expect("start mock backend");
+        let config = ConfigBuilder::default()
+            .codex_home(codex_home.to_path_buf())
+            .cli_overrides(crate::mock_backend::config_overrides(backend))
+            .build()
+            .await
+            .expect("load config");
+        let state =
+            configure(AppStateBuilder::new(config.clone()).ledger(PathBuf::from(":memory:"), None))
+                .build()
+                .expect("build state");
+        router(config, state, codex_home).expect("forward router")
+    }
+
+    /// Answers a non-streaming chat completion of `content`.
+    async fn chat(app: Router, content: &str) -> Response {
+        let request = Request::post("/v1/chat/completions")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                serde_json::json!({
+                    "model": "gpt-5.2",
+                    "messages": [{"role": "user", "content": content}],
+                })
+                .to_string(),
+            ))
+            .expect("request");
+        app.oneshot(request).await.expect("response")
+    }
+
+    async fn json_body(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        serde_json::from_slice(&body).expect("json body")
+    }
+
+    fn candidate(text: &str, total_tokens: i64) -> EventStream {
+        let events = vec![
+            Ok(ResponseEvent::OutputTextDelta(text.to_string())),
+            Ok(ResponseEvent::Completed {
+                response_id: "resp_1".to_string(),
+                token_usage: Some(TokenUsage {
+                    total_tokens,
+                    ..Default::default()
+                }),
+            }),
+        ];
+        Box::pin(futures::stream::iter(events))
+    }
+
+    #[tokio::test]
+    async fn candidates_become_choices_with_usage_summed() {
+        let response = collect_response(
+            vec![candidate("first", 3), candidate("second", 4)],
+            "gpt-test".to_string(),
+            "gpt-test",
+            None,
+            None,
+            &RequestContext::new("req_test".to_string()),
+        )
+        .await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+        let choices = body["choices"]
+            .as_array()
+            .expect("choices")
+            .iter()
+            .map(|choice| {
+                (
+                    choice["index"].clone(),
+                    choice["message"]["content"].clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            choices,
+            vec![(0.into(), "first".into()), (1.into(), "second".into()),]
+        );
+        assert_eq!(body["usage"]["total_tokens"], 7);
+    }
+
+    #[tokio::test]
+    async fn forward_mode_serves_the_probes() {
+        let codex_home = tempfile::tempdir().expect("temp codex home");
+        let app = forward_app(codex_home.path(), |builder| builder).await;
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).expect("request");
+
+        let health = app.clone().oneshot(get("/healthz")).await.expect("healthz");
+        assert_eq!(health.status(), StatusCode::OK);
+        assert_eq!(json_body(health).await["status"], "ok");
+
+        // Whether it is ready depends on the login, but the checks answer.
+        let ready = app.oneshot(get("/readyz")).await.expect("readyz");
+        assert!(json_body(ready).await["checks"]["auth"].is_object());
+    }
+
+    #[tokio::test]
+    async fn forwarded_prompts_are_redacted() {
+        let codex_home = tempfile::tempdir().expect("temp codex home");
+        let app = forward_app(codex_home.path(), |builder| {
+            builder.redaction(vec!["email".to_string()], Vec::new(), true)
+        })
+        .await;
+        let response = chat(app, "[mock:echo] write to ana@example.com").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let answer = json_body(response).await["choices"][0]["message"]["content"]
+            .as_str()
+            .expect("answer")
+            .to_string();
+        assert!(answer.contains("write to [REDACTED:email]"), "{answer}");
+        assert!(!answer.contains("ana@example.com"));
+    }
+
+    #[tokio::test]
+    async fn forwarded_requests_get_a_request_id() {
+        let codex_home = tempfile::tempdir().expect("temp codex home");
+        let app = forward_app(codex_home.path(), |builder| builder).await;
+        let response = chat(app, "hello").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let request_id = response
+            .headers()
+            .get(access_log::REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .expect("request id");
+        assert!(!request_id.is_empty());
+    }
+
+    #[tokio::test]
+    async fn forwarded_requests_are_rate_limited() {
+        let codex_home = tempfile::tempdir().expect("temp codex home");
+        let app = forward_app(codex_home.path(), |builder| {
+            builder.rate_limits(Some(1), None)
+        })
+        .await;
+        assert_eq!(chat(app.clone(), "hello").await.status(), StatusCode::OK);
+
+        let response = chat(app, "hello again").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            json_body(response).await["error"]["code"],
+            "rate_limit_exceeded"
+        );
+    }
+
+    #[tokio::test]
+    async fn forward_mode_allows_only_the_configured_origins() {
+        let codex_home = tempfile::tempdir().expect("temp codex home");
+        let app = forward_app(codex_home.path(), |builder| {
+            builder.cors_origins(vec!["https://app.example.com".to_string()])
+        })
+        .await;
+        let allowed_origin = |origin: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::get("/healthz")
+                    .header("origin", origin)
+                    .body(Body::empty())
+                    .expect("request");
+                let response = app.oneshot(request).await.expect("response");
+                response
+                    .headers()
+                    .get("access-control-allow-origin")
+                    .map(|value| value.to_str().expect("header").to_string())
+            }
+        };
+        assert_eq!(
+            allowed_origin("https://app.example.com").await.as_deref(),
+            Some("https://app.example.com")
+        );
+        assert_eq!(allowed_origin("https://evil.example").await, None);
+    }
+
+    #[tokio::test]
+    async fn forwarded_input_is_moderated() {
+        let codex_home = tempfile::tempdir().expect("temp codex home");
+        let rules = codex_home.path().join("moderation.txt");
+        std::fs::write(&rules, "(?i)\\bforbidden\\b\n").expect("write rules");
+        let app = forward_app(codex_home.path(), |builder| builder.moderation_rules(rules)).await;
+
+        let response = chat(app.clone(), "something Forbidden").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["error"]["code"], "content_policy");
+
+        let response = chat(app, "an allowed question").await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Response;
+use codex_app_server_protocol::AuthMode;
+use codex_core::config::Config;
+use serde::Serialize;
+
+use crate::AppState;
+use crate::json_response;
+
+/// How long `/readyz` waits for the model provider to answer.
+const UPSTREAM_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of one readiness check.
+#[derive(Debug, Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Self {
+            ok: true,
+            detail: None,
+        }
+    }
+
+    fn failed(detail: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            detail: Some(detail.into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Checks {
+    config: Check,
+    auth: Check,
+    upstream: Check,
+}
+
+/// `GET /healthz`: the process is up and serving requests.
+pub(crate) async fn handle_healthz() -> Response {
+    json_response(
+        StatusCode::OK,
+        serde_json::json!({ "status": "ok" }).to_string(),
+    )
+}
+
+/// `GET /readyz`: the Codex config loads, the auth token is usable and the
+/// model provider is reachable. Answers 503 otherwise so a load balancer stops
+/// routing to an instance whose login has expired.
+pub(crate) async fn handle_readyz(State(state): State<AppState>) -> Response {
+    let checks = match Config::load_with_cli_overrides(vec![]).await {
+        Ok(config) => {
+            let auth = check_auth(&state, &config).await;
+            let upstream = check_upstream(&state, &config).await;
+            Checks {
+                config: Check::ok(),
+                auth,
+                upstream,
+            }
+        }
+        Err(e) => Checks {
+            config: Check::failed(format!("failed to load config: {e}")),
+            auth: Check::failed("skipped: config did not load"),
+            upstream: Check::failed("skipped: config did not load"),
+        },
+    };
+
+    let ready = checks.config.ok && checks.auth.ok && checks.upstream.ok;
+    let (status, label) = if ready {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+    json_response(
+        status,
+        serde_json::json!({
+            "status": label,
+            "checks": checks,
+        })
+        .to_string(),
+    )
+}
+
+/// Fetching the auth refreshes stale ChatGPT tokens, so a token that can no
+/// longer be refreshed shows up here.
+async fn check_auth(state: &AppState, config: &Config) -> Check {
+    if !config.model_provider.requires_openai_auth {
+        return Check::ok();
+    }
+    let Some(auth) = state.auth_manager.auth().await else {
+        return Check::failed("not logged in; run `codex login`");
+    };
+    match auth.get_token() {
+        Ok(token) if !token.is_empty() => Check::ok(),
+        Ok(_) => Check::failed("auth token is empty"),
+        Err(e) => Check::failed(format!("auth token unavailable: {e}")),
+    }
+}
+
+/// Any HTTP response counts as reachable; only connection failures and
+/// timeouts fail the check.
+async fn check_upstream(state: &AppState, config: &Config) -> Check {
+    let base_url = match &config.model_provider.base_url {
+        Some(base_url) => base_url.clone(),
+        None if state.auth_manager.get_auth_mode() == Some(AuthMode::ChatGPT) => {
+            "https://chatgpt.com/backend-api/codex".to_string()
+        }
+        None => "https://api.openai.com/v1".to_string(),
+    };
+    match state
+        .http_client
+        .get(&base_url)
+        .timeout(UPSTREAM_PROBE_TIMEOUT)
+        .send()
+        .await
+    {
+        Ok(_) => Check::ok(),
+        Err(e) => Check::failed(format!("{base_url} is unreachable: {e}")),
+    }
+}
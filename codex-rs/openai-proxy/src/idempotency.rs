@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use axum::body::Body;
+use axum::body::Bytes;
+use axum::extract::ConnectInfo;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::Method;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use sha2::Digest;
+use sha2::Sha256;
+use tokio_stream::StreamExt;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::batches::MAX_BATCH_INPUT_BYTES;
+use crate::rate_limit::client_key;
+
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Set on responses replayed from an earlier request with the same key.
+const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+const MAX_KEY_LEN: usize = 255;
+
+/// Keys beyond this many are pruned, expired ones first.
+const MAX_ENTRIES: usize = 10_000;
+
+/// Responses larger than this are passed through without being stored.
+const MAX_STORED_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// Stored responses take up to this much in all; past it the oldest are
+/// evicted, expired ones first.
+const MAX_STORED_BYTES: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl StoredResponse {
+    /// Roughly the memory the response takes while stored.
+    fn size(&self) -> usize {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        self.body.len() + headers
+    }
+
+    fn replay(self) -> Response {
+        let mut response = Response::new(Body::from(self.body));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+        response
+            .headers_mut()
+            .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+        response
+    }
+}
+
+#[derive(Debug)]
+enum Slot {
+    /// The first request with the key is still running.
+    InFlight { fingerprint: String },
+    Done {
+        fingerprint: String,
+        response: StoredResponse,
+        stored_at: Instant,
+    },
+}
+
+impl Slot {
+    fn fingerprint(&self) -> &str {
+        match self {
+            Slot::InFlight { fingerprint } | Slot::Done { fingerprint, .. } => fingerprint,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Slot::InFlight { .. } => 0,
+            Slot::Done { response, .. } => response.size(),
+        }
+    }
+}
+
+/// The slots by key, and how many bytes their responses take.
+#[derive(Debug, Default)]
+struct Slots {
+    by_key: HashMap<String, Slot>,
+    stored_bytes: usize,
+}
+
+impl Slots {
+    fn insert(&mut self, key: String, slot: Slot) {
+        self.stored_bytes += slot.size();
+        if let Some(old) = self.by_key.insert(key, slot) {
+            self.stored_bytes -= old.size();
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Slot> {
+        let slot = self.by_key.remove(key)?;
+        self.stored_bytes -= slot.size();
+        Some(slot)
+    }
+
+    /// The key of the response stored longest ago.
+    fn oldest_done(&self) -> Option<String> {
+        self.by_key
+            .iter()
+            .filter_map(|(key, slot)| match slot {
+                Slot::Done { stored_at, .. } => Some((key, *stored_at)),
+                Slot::InFlight { .. } => None,
+            })
+            .min_by_key(|(_, stored_at)| *stored_at)
+            .map(|(key, _)| key.clone())
+    }
+}
+
+/// What to do with a request carrying an idempotency key.
+#[derive(Debug)]
+enum Begin {
+    /// First use of the key: run the request and record its response.
+    Proceed,
+    Replay(StoredResponse),
+    InFlight,
+    /// The key was used for a different request.
+    Mismatch,
+}
+
+/// Responses recorded per idempotency key so client retries after a timeout
+/// replay the first response instead of running another turn.
+pub(crate) struct IdempotencyStore {
+    ttl: Duration,
+    max_stored_bytes: usize,
+    entries: Mutex<Slots>,
+}
+
+impl IdempotencyStore {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self::with_budget(ttl, MAX_STORED_BYTES)
+    }
+
+    fn with_budget(ttl: Duration, max_stored_bytes: usize) -> Self {
+        Self {
+            ttl,
+            max_stored_bytes,
+            entries: Mutex::new(Slots::default()),
+        }
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "ttl_secs": self.ttl.as_secs(),
+            "max_keys": MAX_ENTRIES,
+            "max_stored_bytes": self.max_stored_bytes,
+        })
+    }
+
+    fn begin(&self, key: &str, fingerprint: &str) -> Begin {
+        let Ok(mut entries) = self.entries.lock() else {
+            return Begin::Proceed;
+        };
+        let expired = matches!(
+            entries.by_key.get(key),
+            Some(Slot::Done { stored_at, .. }) if stored_at.elapsed() >= self.ttl
+        );
+        if expired {
+            entries.remove(key);
+        }
+        match entries.by_key.get(key) {
+            Some(slot) if slot.fingerprint() != fingerprint => Begin::Mismatch,
+            Some(Slot::InFlight { .. }) => Begin::InFlight,
+            Some(Slot::Done { response, .. }) => Begin::Replay(response.clone()),
+            None => {
+                if entries.by_key.len() >= MAX_ENTRIES {
+                    self.prune(&mut entries, 0);
+                }
+                entries.insert(
+                    key.to_string(),
+                    Slot::InFlight {
+                        fingerprint: fingerprint.to_string(),
+                    },
+                );
+                Begin::Proceed
+            }
+        }
+    }
+
+    /// Drops expired responses, then the oldest until there is room for
+    /// another key and `incoming` more bytes.
+    fn prune(&self, entries: &mut Slots, incoming: usize) {
+        let expired = entries
+            .by_key
+            .iter()
+            .filter(|(_, slot)| {
+                matches!(slot, Slot::Done { stored_at, .. } if stored_at.elapsed() >= self.ttl)
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in expired {
+            entries.remove(&key);
+        }
+        while entries.by_key.len() >= MAX_ENTRIES
+            || entries.stored_bytes + incoming > self.max_stored_bytes
+        {
+            let Some(oldest) = entries.oldest_done() else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+    }
+
+    fn complete(&self, key: &str, response: StoredResponse) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        let Some(slot) = entries.remove(key) else {
+            return;
+        };
+        // Too large to keep even alone: a retry runs again.
+        if response.size() > self.max_stored_bytes {
+            return;
+        }
+        if entries.stored_bytes + response.size() > self.max_stored_bytes {
+            self.prune(&mut entries, response.size());
+        }
+        entries.insert(
+            key.to_string(),
+            Slot::Done {
+                fingerprint: slot.fingerprint().to_string(),
+                response,
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Forgets a key whose request failed or whose response was not fully
+    /// delivered, so a retry runs again.
+    fn abandon(&self, key: &str) {
+        if let Ok(mut entries) = self.entries.lock()
+            && matches!(entries.by_key.get(key), Some(Slot::InFlight { .. }))
+        {
+            entries.remove(key);
+        }
+    }
+}
+
+/// Collects a response body as it streams to the client and stores it under
+/// the key once the body has been sent in full.
+struct Recording {
+    store: Arc<IdempotencyStore>,
+    key: String,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    complete: bool,
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        if self.complete && self.body.len() <= MAX_STORED_BODY_BYTES {
+            self.store.complete(
+                &self.key,
+                StoredResponse {
+                    status: self.status,
+                    headers: std::mem::take(&mut self.headers),
+                    body: Bytes::from(std::mem::take(&mut self.body)),
+                },
+            );
+        } else {
+            self.store.abandon(&self.key);
+        }
+    }
+}
+
+/// Failed turns and overload responses are worth retrying, so they are not
+/// replayed.
+fn is_replayable(status: StatusCode) -> bool {
+    !status.is_server_error() && status != StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Identifies the request a key was first used for.
+fn fingerprint(method: &Method, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str().as_bytes());
+    hasher.update([0]);
+    hasher.update(path.as_bytes());
+    hasher.update([0]);
+    hasher.update(body);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Middleware honoring `Idempotency-Key` on POST requests. Keys are scoped to
+/// the client (API key or IP). A retry replays the recorded response, a retry
+/// while the first request runs gets a 409, and reusing a key for a different
+/// request gets a 422.
+pub(crate) async fn idempotency(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(store) = state.idempotency.clone() else {
+        return next.run(request).await;
+    };
+    let key = request
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LEN)
+        .map(str::to_string);
+    let Some(key) = key.filter(|_| request.method() == Method::POST) else {
+        return next.run(request).await;
+    };
+
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0);
+    let key = format!("{}\n{key}", client_key(request.headers(), peer));
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_BATCH_INPUT_BYTES).await {
+        Ok(body) => body,
+        Err(e) => {
+            return ProxyError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("failed to read request body: {e}"),
+                "invalid_request_error",
+            )
+            .into_response();
+        }
+    };
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or_default();
+    let fingerprint = fingerprint(&parts.method, path, &body);
+
+    match store.begin(&key, &fingerprint) {
+        Begin::Proceed => {}
+        Begin::Replay(stored) => return stored.replay(),
+        Begin::InFlight => {
+            return ProxyError::new(
+                StatusCode::CONFLICT,
+                "a request with this Idempotency-Key is still being processed",
+                "invalid_request_error",
+            )
+            .with_code("idempotency_key_in_use")
+            .into_response();
+        }
+        Begin::Mismatch => {
+            return ProxyError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "this Idempotency-Key was already used for a different request",
+                "invalid_request_error",
+            )
+            .with_code("idempotency_key_reused")
+            .into_response();
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if !is_replayable(response.status()) {
+        store.abandon(&key);
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let recording = Recording {
+        store,
+        key,
+        status: parts.status,
+        headers: parts.headers.clone(),
+        body: Vec::new(),
+        complete: false,
+    };
+    let body = futures::stream::unfold(
+        (body.into_data_stream(), Some(recording)),
+        |(mut stream, mut recording)| async move {
+            match stream.next().await {
+                Some(Ok(chunk)) => {
+                    if let Some(recording) = recording.as_mut()
+                        && recording.body.len() <= MAX_STORED_BODY_BYTES
+                    {
+                        recording.body.extend_from_slice(&chunk);
+                    }
+                    Some((Ok(chunk), (stream, recording)))
+                }
+                Some(Err(e)) => {
+                    // A broken body is not replayed; dropping the recording
+                    // abandons the key.
+                    recording = None;
+                    Some((Err(e), (stream, recording)))
+                }
+                None => {
+                    if let Some(recording) = recording.as_mut() {
+                        recording.complete = true;
+                    }
+                    None
+                }
+            }
+        },
+    );
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn stored(body: &'static str) -> StoredResponse {
+        StoredResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(body.as_bytes()),
+        }
+    }
+
+    #[test]
+    fn keys_replay_only_the_request_they_were_first_used_for() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        let first = fingerprint(&Method::POST, "/v1/chat/completions", b"{}");
+        let other = fingerprint(&Method::POST, "/v1/responses", b"{}");
+
+        assert!(matches!(store.begin("k", &first), Begin::Proceed));
+        assert!(matches!(store.begin("k", &first), Begin::InFlight));
+        store.complete("k", stored("done"));
+        match store.begin("k", &first) {
+            Begin::Replay(response) => assert_eq!(response.body, Bytes::from_static(b"done")),
+            other => panic!("expected a replay, got {other:?}"),
+        }
+        assert!(matches!(store.begin("k", &other), Begin::Mismatch));
+
+        assert!(matches!(store.begin("retry", &first), Begin::Proceed));
+        store.abandon("retry");
+        assert!(matches!(store.begin("retry", &first), Begin::Proceed));
+    }
+
+    #[test]
+    fn the_oldest_responses_are_evicted_past_the_byte_budget() {
+        let store = IdempotencyStore::with_budget(Duration::from_secs(60), 10);
+        let fingerprint = fingerprint(&Method::POST, "/v1/chat/completions", b"{}");
+        let replays = |key: &str| matches!(store.begin(key, &fingerprint), Begin::Replay(_));
+
+        for (key, body) in [("a", "1234"), ("b", "5678"), ("c", "90")] {
+            assert!(matches!(store.begin(key, &fingerprint), Begin::Proceed));
+            store.complete(key, stored(body));
+        }
+        assert!(replays("a") && replays("b") && replays("c"));
+
+        // Four more bytes only fit once "a" goes.
+        assert!(matches!(store.begin("d", &fingerprint), Begin::Proceed));
+        store.complete("d", stored("abcd"));
+        assert!(matches!(store.begin("a", &fingerprint), Begin::Proceed));
+        assert!(replays("b") && replays("c") && replays("d"));
+        assert_eq!(store.entries.lock().expect("entries").stored_bytes, 10);
+
+        // Nothing is evicted for a response that could never fit.
+        assert!(matches!(store.begin("e", &fingerprint), Begin::Proceed));
+        store.complete("e", stored("too large to keep"));
+        assert!(matches!(store.begin("e", &fingerprint), Begin::Proceed));
+        assert!(replays("b") && replays("c") && replays("d"));
+    }
+
+    #[test]
+    fn failures_are_not_replayed() {
+        assert!(is_replayable(StatusCode::OK));
+        assert!(is_replayable(StatusCode::BAD_REQUEST));
+        assert!(!is_replayable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_replayable(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+}
@@ -0,0 +1,212 @@
+use axum::http::StatusCode;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use codex_protocol::user_input::UserInput;
+
+use crate::ProxyError;
+
+/// Largest image the proxy will download or accept inline.
+pub(crate) const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// Collects image URLs from chat content parts (`{"type":"image_url"}`),
+/// Responses input items (`{"type":"input_image"}`) and Anthropic image blocks
+/// (`{"type":"image"}`), including parts nested in a message's `content` array.
+pub(crate) fn collect_image_urls(value: &serde_json::Value) -> Vec<String> {
+    let mut urls = Vec::new();
+    let serde_json::Value::Array(items) = value else {
+        return urls;
+    };
+    for item in items {
+        match item.get("type").and_then(|t| t.as_str()) {
+            Some("image_url") | Some("input_image") => {
+                let url = match item.get("image_url") {
+                    Some(serde_json::Value::String(url)) => Some(url.as_str()),
+                    Some(image_url) => image_url.get("url").and_then(|u| u.as_str()),
+                    None => None,
+                };
+                if let Some(url) = url {
+                    urls.push(url.to_string());
+                }
+            }
+            Some("image") => {
+                if let Some(url) = item.get("source").and_then(anthropic_image_url) {
+                    urls.push(url);
+                }
+            }
+            _ => {
+                if let Some(content) = item.get("content") {
+                    urls.extend(collect_image_urls(content));
+                }
+            }
+        }
+    }
+    urls
+}
+
+/// Converts an Anthropic image `source` (`base64` or `url`) into a URL.
+fn anthropic_image_url(source: &serde_json::Value) -> Option<String> {
+    match source.get("type").and_then(|t| t.as_str())? {
+        "base64" => {
+            let media_type = source.get("media_type").and_then(|m| m.as_str())?;
+            let data = source.get("data").and_then(|d| d.as_str())?;
+            Some(format!("data:{media_type};base64,{data}"))
+        }
+        "url" => source
+            .get("url")
+            .and_then(|u| u.as_str())
+            .map(str::to_string),
+        _ => None,
+    }
+}
+
+/// Turns image URLs into Codex inputs: data URLs are validated and passed
+/// through, `http(s)` URLs are downloaded and inlined as data URLs.
+pub(crate) async fn image_inputs(
+    client: &reqwest::Client,
+    urls: Vec<String>,
+) -> Result<Vec<UserInput>, ProxyError> {
+    let mut inputs = Vec::with_capacity(urls.len());
+    for url in urls {
+        let image_url = if url.starts_with("data:") {
+            validate_data_url(&url)?;
+            url
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            download_image(client, &url).await?
+        } else {
+            return Err(invalid_image(format!(
+                "unsupported image_url {url:?}; expected a data: or http(s) URL"
+            )));
+        };
+        inputs.push(UserInput::Image { image_url });
+    }
+    Ok(inputs)
+}
+
+fn validate_data_url(url: &str) -> Result<(), ProxyError> {
+    let Some((header, data)) = url.split_once(',') else {
+        return Err(invalid_image("malformed data URL".to_string()));
+    };
+    let Some(media_type) = header
+        .strip_prefix("data:")
+        .and_then(|h| h.strip_suffix(";base64"))
+    else {
+        return Err(invalid_image(
+            "image data URLs must be base64-encoded".to_string(),
+        ));
+    };
+    if !media_type.starts_with("image/") {
+        return Err(invalid_image(format!(
+            "unsupported image media type {media_type:?}"
+        )));
+    }
+    let decoded = BASE64_STANDARD
+        .decode(data)
+        .map_err(|e| invalid_image(format!("invalid base64 image data: {e}")))?;
+    if decoded.len() > MAX_IMAGE_BYTES {
+        return Err(invalid_image(format!(
+            "image exceeds {MAX_IMAGE_BYTES} bytes"
+        )));
+    }
+    Ok(())
+}
+
+async fn download_image(client: &reqwest::Client, url: &str) -> Result<String, ProxyError> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| invalid_image(format!("failed to download image {url}: {e}")))?;
+    let media_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(str::trim)
+        .unwrap_or_default()
+        .to_string();
+    if !media_type.starts_with("image/") {
+        return Err(invalid_image(format!(
+            "{url} is not an image (content-type {media_type:?})"
+        )));
+    }
+    if response
+        .content_length()
+        .is_some_and(|len| len > MAX_IMAGE_BYTES as u64)
+    {
+        return Err(invalid_image(format!(
+            "image {url} exceeds {MAX_IMAGE_BYTES} bytes"
+        )));
+    }
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| invalid_image(format!("failed to download image {url}: {e}")))?;
+    if bytes.len() > MAX_IMAGE_BYTES {
+        return Err(invalid_image(format!(
+            "image {url} exceeds {MAX_IMAGE_BYTES} bytes"
+        )));
+    }
+    Ok(format!(
+        "data:{media_type};base64,{}",
+        BASE64_STANDARD.encode(&bytes)
+    ))
+}
+
+fn invalid_image(message: String) -> ProxyError {
+    ProxyError::new(StatusCode::BAD_REQUEST, message, "invalid_request_error")
+        .with_code("invalid_image")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn image_urls_are_found_in_chat_responses_and_anthropic_shapes() {
+        let chat = serde_json::json!([
+            {"type": "text", "text": "what is this?"},
+            {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}},
+        ]);
+        let responses = serde_json::json!([{
+            "role": "user",
+            "content": [
+                {"type": "input_text", "text": "and this?"},
+                {"type": "input_image", "image_url": "data:image/png;base64,AAAA"},
+            ],
+        }]);
+
+        assert_eq!(
+            collect_image_urls(&chat),
+            vec!["https://example.com/a.png".to_string()]
+        );
+        assert_eq!(
+            collect_image_urls(&responses),
+            vec!["data:image/png;base64,AAAA".to_string()]
+        );
+
+        let anthropic = serde_json::json!([{
+            "role": "user",
+            "content": [
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "AAAA"}},
+                {"type": "image", "source": {"type": "url", "url": "https://example.com/b.png"}},
+            ],
+        }]);
+        assert_eq!(
+            collect_image_urls(&anthropic),
+            vec![
+                "data:image/png;base64,AAAA".to_string(),
+                "https://example.com/b.png".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn data_urls_must_be_base64_images() {
+        assert!(validate_data_url("data:image/png;base64,AAAA").is_ok());
+        assert!(validate_data_url("data:text/plain;base64,AAAA").is_err());
+        assert!(validate_data_url("data:image/png,raw").is_err());
+        assert!(validate_data_url("data:image/png;base64,@@@").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Hooks for embedders: code registered on [`AppStateBuilder`] that sees
+//! every request before it is routed, every response before it is sent and
+//! every Codex event before the handlers translate it, so headers can be
+//! added, prompts rewritten or events filtered without forking the handlers.
+//!
+//! [`AppStateBuilder`]: crate::AppStateBuilder
+
+use std::fmt;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::HeaderValue;
+use axum::http::Method;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::http::header;
+use axum::http::request;
+use axum::http::response;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use codex_protocol::protocol::EventMsg;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::batches;
+
+/// Sees each request before it is routed.
+pub trait RequestInterceptor: Send + Sync + 'static {
+    /// Called with the request's head and, for JSON bodies, the parsed body;
+    /// changes to either are what the handler sees. Returning a response
+    /// answers the request with it instead.
+    fn on_request(
+        &self,
+        parts: &mut request::Parts,
+        body: Option<&mut serde_json::Value>,
+    ) -> Result<(), Response>;
+}
+
+/// Sees each response before it is sent.
+pub trait ResponseInterceptor: Send + Sync + 'static {
+    /// Called with the response's head, e.g. to add headers. Streamed bodies
+    /// are already under way, so only the head can change.
+    fn on_response(&self, method: &Method, uri: &Uri, parts: &mut response::Parts);
+}
+
+/// Sees each Codex event of a turn before the handler translates it.
+pub trait EventInterceptor: Send + Sync + 'static {
+    /// Called with each event, which may be rewritten in place; returning
+    /// `false` drops it. Events that end the turn are kept regardless.
+    fn on_event(&self, event: &mut EventMsg) -> bool;
+}
+
+/// The interceptors registered on a state, run in registration order.
+#[derive(Default)]
+pub(crate) struct Interceptors {
+    pub(crate) request: Vec<Arc<dyn RequestInterceptor>>,
+    pub(crate) response: Vec<Arc<dyn ResponseInterceptor>>,
+    pub(crate) event: Vec<Arc<dyn EventInterceptor>>,
+}
+
+impl fmt::Debug for Interceptors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interceptors")
+            .field("request", &self.request.len())
+            .field("response", &self.response.len())
+            .field("event", &self.event.len())
+            .finish()
+    }
+}
+
+impl Interceptors {
+    /// Runs the event interceptors on `event`; `false` if one dropped it.
+    pub(crate) fn event(&self, event: &mut EventMsg) -> bool {
+        let keep = self
+            .event
+            .iter()
+            .all(|interceptor| interceptor.on_event(event));
+        keep || matches!(
+            event,
+            EventMsg::TurnComplete(_) | EventMsg::TurnAborted(_) | EventMsg::Error(_)
+        )
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "request": self.request.len(),
+            "response": self.response.len(),
+            "event": self.event.len(),
+        })
+    }
+}
+
+/// Middleware running the request and response interceptors.
+pub(crate) async fn intercept(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let interceptors = state.interceptors.clone();
+    if interceptors.request.is_empty() && interceptors.response.is_empty() {
+        return next.run(request).await;
+    }
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let request = if interceptors.request.is_empty() {
+        request
+    } else {
+        // Batch inputs are the largest bodies any route accepts.
+        let limit = state
+            .max_request_body_bytes
+            .get()
+            .map(|max| (max as usize).max(batches::MAX_BATCH_INPUT_BYTES))
+            .unwrap_or(usize::MAX);
+        match intercept_request(&interceptors, request, limit).await {
+            Ok(request) => request,
+            Err(response) => return response,
+        }
+    };
+
+    let response = next.run(request).await;
+    if interceptors.response.is_empty() {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    for interceptor in &interceptors.response {
+        interceptor.on_response(&method, &uri, &mut parts);
+    }
+    Response::from_parts(parts, body)
+}
+
+async fn intercept_request(
+    interceptors: &Interceptors,
+    request: Request,
+    limit: usize,
+) -> Result<Request, Response> {
+    let (mut parts, body) = request.into_parts();
+    let is_json = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_json {
+        for interceptor in &interceptors.request {
+            interceptor.on_request(&mut parts, None)?;
+        }
+        return Ok(Request::from_parts(parts, body));
+    }
+
+    let bytes = axum::body::to_bytes(body, limit).await.map_err(|_| {
+        ProxyError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Request body is larger than the {limit} bytes allowed."),
+            "invalid_request_error",
+        )
+        .with_code("request_too_large")
+        .into_response()
+    })?;
+    // Malformed JSON is left for the handler to reject.
+    let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(&bytes) else {
+        for interceptor in &interceptors.request {
+            interceptor.on_request(&mut parts, None)?;
+        }
+        return Ok(Request::from_parts(parts, Body::from(bytes)));
+    };
+    for interceptor in &interceptors.request {
+        interceptor.on_request(&mut parts, Some(&mut json))?;
+    }
+    let bytes = serde_json::to_vec(&json).map_err(|e| {
+        ProxyError::internal(format!("failed to serialize the request body: {e}")).into_response()
+    })?;
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+    Ok(Request::from_parts(parts, Body::from(bytes)))
+}
@@ -0,0 +1,543 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::Context;
+use rusqlite::Connection;
+use rusqlite::params;
+use serde::Serialize;
+use tracing::info;
+use tracing::warn;
+
+use crate::ProxyError;
+use crate::now_ts;
+
+/// How often rows older than the retention period are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Most rows `GET /admin/requests` returns at once.
+const MAX_LIST_LIMIT: u32 = 1_000;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS requests (
+        id INTEGER PRIMARY KEY,
+        at INTEGER NOT NULL,
+        request_id TEXT NOT NULL,
+        owner TEXT NOT NULL,
+        method TEXT NOT NULL,
+        path TEXT NOT NULL,
+        model TEXT,
+        end_user TEXT,
+        conversation_id TEXT,
+        status INTEGER,
+        latency_ms INTEGER NOT NULL,
+        turns INTEGER NOT NULL,
+        tokens INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS requests_at ON requests (at);
+";
+
+/// Columns added to `requests` since it was first created, with their types;
+/// added to older ledgers when they are opened.
+const ADDED_COLUMNS: &[(&str, &str)] = &[("end_user", "TEXT")];
+
+/// One request (or WebSocket turn, or batch item) as recorded in the ledger.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct LedgerEntry {
+    /// Unix seconds when the request arrived.
+    pub(crate) at: u64,
+    pub(crate) request_id: String,
+    /// Tenant or API key fingerprint; see [`crate::usage::usage_owner`].
+    pub(crate) owner: String,
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) model: Option<String>,
+    /// The request's OpenAI `user` field.
+    pub(crate) user: Option<String>,
+    pub(crate) conversation_id: Option<String>,
+    pub(crate) status: Option<u16>,
+    pub(crate) latency_ms: u64,
+    pub(crate) turns: u64,
+    pub(crate) tokens: i64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct Totals {
+    turns: u64,
+    tokens: i64,
+}
+
+impl Totals {
+    fn add(&mut self, other: Totals) {
+        self.turns += other.turns;
+        self.tokens += other.tokens;
+    }
+}
+
+/// Turns and tokens charged to one tenant (or API key) in a usage report.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct OwnerUsage {
+    tenant: String,
+    #[serde(flatten)]
+    totals: Totals,
+    models: Vec<ModelUsage>,
+    /// Breakdown by the end users requests named; requests without a `user`
+    /// are left out.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    users: Vec<UserUsage>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ModelUsage {
+    model: String,
+    #[serde(flatten)]
+    totals: Totals,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct UserUsage {
+    user: String,
+    #[serde(flatten)]
+    totals: Totals,
+}
+
+/// Which rows `GET /admin/requests` lists, newest first.
+#[derive(Debug, Default)]
+pub(crate) struct RequestFilter {
+    pub(crate) from: u64,
+    pub(crate) to: u64,
+    pub(crate) owner: Option<String>,
+    pub(crate) user: Option<String>,
+    pub(crate) conversation_id: Option<String>,
+    pub(crate) limit: u32,
+}
+
+/// Every request the proxy served, persisted in SQLite so usage reports and
+/// the admin endpoints survive restarts. Rows are written by a background
+/// thread and deleted once older than the retention period.
+#[derive(Debug)]
+pub(crate) struct Ledger {
+    path: PathBuf,
+    retention: Option<Duration>,
+    conn: Arc<Mutex<Connection>>,
+    tx: mpsc::Sender<LedgerEntry>,
+}
+
+impl Ledger {
+    /// Opens (or creates) the ledger at `path`; `:memory:` keeps it in memory.
+    /// `retention` of `None` keeps rows forever.
+    pub(crate) fn open(path: &Path, retention: Option<Duration>) -> anyhow::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("create ledger directory {}", parent.display()))?;
+        }
+        let conn =
+            Connection::open(path).with_context(|| format!("open ledger {}", path.display()))?;
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .context("enable ledger WAL")?;
+        conn.execute_batch(SCHEMA).context("create ledger schema")?;
+        add_missing_columns(&conn).context("migrate ledger schema")?;
+        let conn = Arc::new(Mutex::new(conn));
+
+        let (tx, rx) = mpsc::channel();
+        let writer = Arc::clone(&conn);
+        std::thread::Builder::new()
+            .name("ledger-writer".to_string())
+            .spawn(move || write_entries(&writer, &rx, retention))
+            .context("spawn ledger writer")?;
+        info!("request ledger at {}", path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            retention,
+            conn,
+            tx,
+        })
+    }
+
+    /// Queues `entry` to be written; never blocks the caller on disk.
+    pub(crate) fn record(&self, entry: LedgerEntry) {
+        let _ = self.tx.send(entry);
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "path": self.path,
+            "retention_days": self.retention.map(|retention| retention.as_secs() / 86_400),
+        })
+    }
+
+    /// Turns and tokens per owner and model for requests that arrived between
+    /// `from` (inclusive) and `to` (exclusive), optionally for one owner.
+    pub(crate) async fn usage(
+        &self,
+        from: u64,
+        to: u64,
+        owner: Option<String>,
+    ) -> Result<Vec<OwnerUsage>, ProxyError> {
+        self.query(move |conn| usage(conn, from, to, owner.as_deref()))
+            .await
+    }
+
+    pub(crate) async fn requests(
+        &self,
+        filter: RequestFilter,
+    ) -> Result<Vec<LedgerEntry>, ProxyError> {
+        self.query(move |conn| requests(conn, &filter)).await
+    }
+
+    async fn query<T, F>(&self, read: F) -> Result<T, ProxyError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || {
+            let conn = conn
+                .lock()
+                .map_err(|_| ProxyError::internal("ledger lock poisoned"))?;
+            read(&conn).map_err(|e| ProxyError::internal(format!("ledger query failed: {e}")))
+        })
+        .await
+        .map_err(|e| ProxyError::internal(format!("ledger query failed: {e}")))?
+    }
+}
+
+/// Body of the writer thread: inserts queued entries and prunes expired rows
+/// until the ledger is dropped.
+fn write_entries(
+    conn: &Mutex<Connection>,
+    rx: &mpsc::Receiver<LedgerEntry>,
+    retention: Option<Duration>,
+) {
+    let prune = |conn: &Mutex<Connection>| {
+        let Some(retention) = retention else {
+            return;
+        };
+        let cutoff = now_ts().saturating_sub(retention.as_secs());
+        if let Ok(conn) = conn.lock() {
+            match conn.execute("DELETE FROM requests WHERE at < ?1", params![cutoff]) {
+                Ok(0) => {}
+                Ok(deleted) => info!("pruned {deleted} ledger rows older than {cutoff}"),
+                Err(e) => warn!("failed to prune ledger: {e}"),
+            }
+        }
+    };
+
+    prune(conn);
+    let mut next_prune = std::time::Instant::now() + PRUNE_INTERVAL;
+    loop {
+        match rx.recv_timeout(PRUNE_INTERVAL) {
+            Ok(entry) => {
+                if let Ok(conn) = conn.lock()
+                    && let Err(e) = insert(&conn, &entry)
+                {
+                    warn!(
+                        "failed to record request {} in ledger: {e}",
+                        entry.request_id
+                    );
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+        if std::time::Instant::now() >= next_prune {
+            prune(conn);
+            next_prune = std::time::Instant::now() + PRUNE_INTERVAL;
+        }
+    }
+}
+
+fn add_missing_columns(conn: &Connection) -> rusqlite::Result<()> {
+    let existing = conn
+        .prepare("SELECT name FROM pragma_table_info('requests')")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<HashSet<_>>>()?;
+    for (name, kind) in ADDED_COLUMNS {
+        if !existing.contains(*name) {
+            conn.execute(
+                &format!("ALTER TABLE requests ADD COLUMN {name} {kind}"),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn insert(conn: &Connection, entry: &LedgerEntry) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO requests (at, request_id, owner, method, path, model, end_user,
+             conversation_id, status, latency_ms, turns, tokens)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![
+            entry.at,
+            entry.request_id,
+            entry.owner,
+            entry.method,
+            entry.path,
+            entry.model,
+            entry.user,
+            entry.conversation_id,
+            entry.status,
+            entry.latency_ms,
+            entry.turns,
+            entry.tokens,
+        ],
+    )?;
+    Ok(())
+}
+
+fn usage(
+    conn: &Connection,
+    from: u64,
+    to: u64,
+    owner: Option<&str>,
+) -> rusqlite::Result<Vec<OwnerUsage>> {
+    let mut statement = conn.prepare(
+        "SELECT owner, COALESCE(model, ''), end_user, SUM(turns), SUM(tokens) FROM requests
+         WHERE at >= ?1 AND at < ?2 AND turns > 0 AND (?3 IS NULL OR owner = ?3)
+         GROUP BY owner, model, end_user",
+    )?;
+    let rows = statement.query_map(params![from, to, owner], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, Option<String>>(2)?,
+            Totals {
+                turns: row.get(3)?,
+                tokens: row.get(4)?,
+            },
+        ))
+    })?;
+
+    /// Per-model and per-user totals of one owner.
+    type Breakdown = (BTreeMap<String, Totals>, BTreeMap<String, Totals>);
+    let mut owners = BTreeMap::<String, Breakdown>::new();
+    for row in rows {
+        let (owner, model, user, totals) = row?;
+        let (models, users) = owners.entry(owner).or_default();
+        models.entry(model).or_default().add(totals);
+        if let Some(user) = user {
+            users.entry(user).or_default().add(totals);
+        }
+    }
+    Ok(owners
+        .into_iter()
+        .map(|(tenant, (models, users))| {
+            let mut total = Totals::default();
+            let models = models
+                .into_iter()
+                .map(|(model, totals)| {
+                    total.add(totals);
+                    ModelUsage { model, totals }
+                })
+                .collect();
+            let users = users
+                .into_iter()
+                .map(|(user, totals)| UserUsage { user, totals })
+                .collect();
+            OwnerUsage {
+                tenant,
+                totals: total,
+                models,
+                users,
+            }
+        })
+        .collect())
+}
+
+fn requests(conn: &Connection, filter: &RequestFilter) -> rusqlite::Result<Vec<LedgerEntry>> {
+    let mut statement = conn.prepare(
+        "SELECT at, request_id, owner, method, path, model, end_user, conversation_id,
+             status, latency_ms, turns, tokens
+         FROM requests
+         WHERE at >= ?1 AND at < ?2 AND (?3 IS NULL OR owner = ?3)
+             AND (?4 IS NULL OR conversation_id = ?4) AND (?5 IS NULL OR end_user = ?5)
+         ORDER BY at DESC, id DESC
+         LIMIT ?6",
+    )?;
+    let rows = statement.query_map(
+        params![
+            filter.from,
+            filter.to,
+            filter.owner,
+            filter.conversation_id,
+            filter.user,
+            filter.limit.min(MAX_LIST_LIMIT),
+        ],
+        |row| {
+            Ok(LedgerEntry {
+                at: row.get(0)?,
+                request_id: row.get(1)?,
+                owner: row.get(2)?,
+                method: row.get(3)?,
+                path: row.get(4)?,
+                model: row.get(5)?,
+                user: row.get(6)?,
+                conversation_id: row.get(7)?,
+                status: row.get(8)?,
+                latency_ms: row.get(9)?,
+                turns: row.get(10)?,
+                tokens: row.get(11)?,
+            })
+        },
+    )?;
+    rows.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn entry(at: u64, owner: &str, model: Option<&str>, turns: u64, tokens: i64) -> LedgerEntry {
+        LedgerEntry {
+            at,
+            request_id: format!("req_{at}"),
+            owner: owner.to_string(),
+            method: "POST".to_string(),
+            path: "/v1/chat/completions".to_string(),
+            model: model.map(str::to_string),
+            user: None,
+            conversation_id: Some(format!("conv-{owner}")),
+            status: Some(200),
+            latency_ms: 10,
+            turns,
+            tokens,
+        }
+    }
+
+    #[test]
+    fn usage_and_requests_are_read_back_per_owner_and_range() {
+        let conn = Connection::open_in_memory().expect("open");
+        conn.execute_batch(SCHEMA).expect("schema");
+        let for_user = |mut entry: LedgerEntry, user: &str| {
+            entry.user = Some(user.to_string());
+            entry
+        };
+        for row in [
+            for_user(entry(1_000, "alice", Some("gpt-5.2"), 1, 100), "u1"),
+            for_user(entry(1_010, "alice", Some("gpt-5.2-codex"), 1, 50), "u2"),
+            entry(1_015, "alice", None, 0, 0),
+            entry(1_020, "bob", Some("gpt-5.2"), 1, 10),
+            entry(5_000, "alice", Some("gpt-5.2"), 1, 1_000),
+        ] {
+            insert(&conn, &row).expect("insert");
+        }
+        let turn = |tokens| Totals { turns: 1, tokens };
+
+        assert_eq!(
+            usage(&conn, 0, 2_000, None).expect("usage"),
+            vec![
+                OwnerUsage {
+                    tenant: "alice".to_string(),
+                    totals: Totals {
+                        turns: 2,
+                        tokens: 150
+                    },
+                    models: vec![
+                        ModelUsage {
+                            model: "gpt-5.2".to_string(),
+                            totals: turn(100),
+                        },
+                        ModelUsage {
+                            model: "gpt-5.2-codex".to_string(),
+                            totals: turn(50),
+                        },
+                    ],
+                    users: vec![
+                        UserUsage {
+                            user: "u1".to_string(),
+                            totals: turn(100),
+                        },
+                        UserUsage {
+                            user: "u2".to_string(),
+                            totals: turn(50),
+                        },
+                    ],
+                },
+                OwnerUsage {
+                    tenant: "bob".to_string(),
+                    totals: turn(10),
+                    models: vec![ModelUsage {
+                        model: "gpt-5.2".to_string(),
+                        totals: turn(10),
+                    }],
+                    users: Vec::new(),
+                },
+            ]
+        );
+        let alice_later = usage(&conn, 2_000, 6_000, Some("alice")).expect("usage");
+        assert_eq!(alice_later.len(), 1);
+        assert_eq!(alice_later[0].totals, turn(1_000));
+
+        let listed = requests(
+            &conn,
+            &RequestFilter {
+                from: 0,
+                to: 6_000,
+                owner: Some("alice".to_string()),
+                user: None,
+                conversation_id: None,
+                limit: 2,
+            },
+        )
+        .expect("requests");
+        assert_eq!(
+            listed,
+            vec![
+                entry(5_000, "alice", Some("gpt-5.2"), 1, 1_000),
+                entry(1_015, "alice", None, 0, 0),
+            ]
+        );
+
+        let by_user = requests(
+            &conn,
+            &RequestFilter {
+                from: 0,
+                to: 6_000,
+                user: Some("u2".to_string()),
+                limit: 10,
+                ..Default::default()
+            },
+        )
+        .expect("requests");
+        assert_eq!(by_user.len(), 1);
+        assert_eq!(by_user[0].request_id, "req_1010");
+    }
+
+    #[test]
+    fn older_ledgers_gain_new_columns() {
+        let conn = Connection::open_in_memory().expect("open");
+        conn.execute_batch(
+            "CREATE TABLE requests (
+                id INTEGER PRIMARY KEY, at INTEGER NOT NULL, request_id TEXT NOT NULL,
+                owner TEXT NOT NULL, method TEXT NOT NULL, path TEXT NOT NULL, model TEXT,
+                conversation_id TEXT, status INTEGER, latency_ms INTEGER NOT NULL,
+                turns INTEGER NOT NULL, tokens INTEGER NOT NULL
+            );",
+        )
+        .expect("old schema");
+        conn.execute_batch(SCHEMA).expect("schema");
+        add_missing_columns(&conn).expect("migrate");
+        add_missing_columns(&conn).expect("migrate again");
+
+        let mut row = entry(1_000, "alice", Some("gpt-5.2"), 1, 100);
+        row.user = Some("u1".to_string());
+        insert(&conn, &row).expect("insert");
+        let filter = RequestFilter {
+            to: 2_000,
+            limit: 1,
+            ..Default::default()
+        };
+        assert_eq!(requests(&conn, &filter).expect("requests"), vec![row]);
+    }
+}
@@ -0,0 +1,2467 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Context;
+use axum::Extension;
+use axum::Router;
+use axum::extract::DefaultBodyLimit;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::header::RETRY_AFTER;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::response::sse::Event;
+use axum::response::sse::Sse;
+use axum::routing::delete;
+use axum::routing::get;
+use axum::routing::post;
+use axum::routing::put;
+use axum::serve::ListenerExt;
+use clap::Parser;
+use clap::ValueEnum;
+use codex_core::CodexThread;
+use codex_core::ThreadManager;
+use codex_core::auth::AuthManager;
+use codex_core::config::Config;
+use codex_core::protocol::Op;
+use codex_protocol::ThreadId;
+use codex_protocol::models::LocalShellAction;
+use codex_protocol::models::ResponseItem;
+use codex_protocol::openai_models::ModelInfo;
+use codex_protocol::openai_models::ReasoningEffort;
+use codex_protocol::plan_tool::UpdatePlanArgs;
+use codex_protocol::protocol::EventMsg;
+use codex_protocol::protocol::McpToolCallBeginEvent;
+use codex_protocol::protocol::SessionSource;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use tokio::sync::Mutex;
+use tokio::sync::broadcast;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tower_http::cors::AllowOrigin;
+use tower_http::cors::Any;
+use tower_http::cors::CorsLayer;
+use tower_http::services::ServeDir;
+use tracing::Instrument;
+use tracing::info;
+use tracing::warn;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::Layer;
+use tracing_subscriber::Registry;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::reload;
+use tracing_subscriber::util::SubscriberInitExt;
+
+mod access_log;
+mod admin;
+mod admin_auth;
+mod app_state;
+mod approvals;
+mod attachments;
+mod audit_log;
+mod azure;
+mod batches;
+mod body_limit;
+mod circuit_breaker;
+mod citations;
+mod codex_events;
+mod completions;
+mod config_cache;
+mod config_watch;
+mod conversation_store;
+mod conversations;
+mod dry_run;
+mod files;
+mod forward;
+mod health;
+mod idempotency;
+mod images;
+mod interceptors;
+mod ledger;
+mod limits;
+mod mcp;
+mod mcp_servers;
+mod messages;
+mod mock_backend;
+mod model_map;
+mod moderation;
+mod output_cap;
+mod proxy_config;
+mod rate_limit;
+mod recordings;
+mod redaction;
+mod response_cache;
+mod response_store;
+mod responses;
+mod sandbox_mode;
+mod sessions;
+mod sse_keep_alive;
+mod stream_buffer;
+mod stream_granularity;
+mod structured_output;
+mod tenants;
+mod threads;
+mod tls;
+mod turn_diffs;
+mod turn_setup;
+mod turn_slots;
+mod turn_webhook;
+mod upstream_errors;
+mod upstream_rate_limits;
+mod upstream_retry;
+mod upstream_status;
+mod usage;
+mod workspace;
+mod ws;
+
+use access_log::RequestContext;
+use admin_auth::AdminAccess;
+pub use app_state::AppStateBuilder;
+use approvals::ApprovalRegistry;
+use audit_log::AuditLog;
+use audit_log::TurnUsage;
+use batches::BatchRegistry;
+use citations::Citations;
+use codex_events::AgentText;
+use codex_events::ChangedFile;
+use completions::RunningCompletions;
+use config_cache::ConfigCache;
+use dry_run::ProposedPatch;
+use files::FileStore;
+use idempotency::IdempotencyStore;
+pub use interceptors::EventInterceptor;
+use interceptors::Interceptors;
+pub use interceptors::RequestInterceptor;
+pub use interceptors::ResponseInterceptor;
+use ledger::Ledger;
+use limits::LiveLimit;
+use mcp_servers::McpServerAllowlist;
+use mcp_servers::McpServerRequest;
+use model_map::ModelMapping;
+use moderation::Moderation;
+use output_cap::OutputCap;
+use proxy_config::ProxyConfigFile;
+use rate_limit::RateLimiter;
+use redaction::Redactor;
+use response_cache::ResponseCache;
+use response_cache::with_cache_status;
+use response_store::ResponseStore;
+use sse_keep_alive::SseKeepAlive;
+pub use stream_buffer::BackpressurePolicy;
+use stream_buffer::DeltaShaping;
+use stream_buffer::StreamBuffers;
+use stream_buffer::StreamSender;
+pub use stream_granularity::StreamGranularity;
+use structured_output::OutputSchema;
+use tenants::TenantRouter;
+use threads::ThreadRegistry;
+use turn_diffs::TurnDiffs;
+use turn_setup::TurnOptions;
+use turn_slots::TurnSlots;
+use turn_webhook::TurnWebhook;
+use workspace::WorkspacePolicy;
+
+/// Default idle TTL after which a conversation's thread is closed.
+const DEFAULT_THREAD_IDLE_TTL_MINS: u64 = 60;
+
+/// Default time to wait for the approval webhook before applying the fallback.
+const DEFAULT_APPROVAL_WEBHOOK_TIMEOUT_SECS: u64 = 30;
+
+/// Default time to wait for the moderation endpoint before rejecting the request.
+const DEFAULT_MODERATION_TIMEOUT_SECS: u64 = 10;
+
+/// Default time to wait for the turn webhook to accept a notification.
+const DEFAULT_TURN_WEBHOOK_TIMEOUT_SECS: u64 = 10;
+
+/// Batch requests run at once unless `CODEX_OPENAI_PROXY_BATCH_CONCURRENCY` says otherwise.
+const DEFAULT_BATCH_CONCURRENCY: usize = 2;
+
+/// Idempotency keys are remembered for a day unless
+/// `CODEX_OPENAI_PROXY_IDEMPOTENCY_TTL_SECS` says otherwise.
+const DEFAULT_IDEMPOTENCY_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Responses API objects stay retrievable for a day unless
+/// `CODEX_OPENAI_PROXY_RESPONSE_STORE_TTL_SECS` says otherwise.
+const DEFAULT_RESPONSE_STORE_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Ledger rows are kept this long unless
+/// `CODEX_OPENAI_PROXY_LEDGER_RETENTION_DAYS` says otherwise.
+const DEFAULT_LEDGER_RETENTION_DAYS: u64 = 90;
+
+/// Idle SSE streams get a keep-alive this often unless
+/// `CODEX_OPENAI_PROXY_SSE_KEEP_ALIVE_SECS` says otherwise.
+const DEFAULT_SSE_KEEP_ALIVE_SECS: u64 = 15;
+
+/// Events a stream buffers for a slow client unless
+/// `CODEX_OPENAI_PROXY_STREAM_BUFFER_CAPACITY` says otherwise.
+const DEFAULT_STREAM_BUFFER_CAPACITY: usize = 16;
+
+/// Request bodies are limited to 10 MiB unless
+/// `CODEX_OPENAI_PROXY_MAX_REQUEST_BODY_BYTES` says otherwise.
+const DEFAULT_MAX_REQUEST_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+// Global log broadcast channel
+static LOG_CHANNEL: once_cell::sync::Lazy<broadcast::Sender<String>> =
+    once_cell::sync::Lazy::new(|| {
+        let (tx, _rx) = broadcast::channel(1000);
+        tx
+    });
+
+/// Everything the proxy's handlers share; built with [`AppStateBuilder`].
+#[derive(Clone)]
+pub struct AppState {
+    thread_manager: Arc<ThreadManager>,
+    auth_manager: Arc<AuthManager>,
+    /// Loaded configs per override set; see `POST /admin/config/reload`.
+    configs: Arc<ConfigCache>,
+    threads: Arc<ThreadRegistry>,
+    approvals: Arc<ApprovalRegistry>,
+    /// Streamed chat completions that can still be cancelled.
+    completions: Arc<RunningCompletions>,
+    workspaces: Arc<WorkspacePolicy>,
+    /// Tool servers requests may bring; see [`crate::mcp_servers`].
+    mcp_servers: Arc<McpServerAllowlist>,
+    /// Client-facing model names to Codex models; see [`crate::model_map`].
+    model_map: Arc<ModelMapping>,
+    files: Arc<FileStore>,
+    /// Diffs of recent turns; see `GET /v1/conversations/{id}/turns/{turn_id}/diff`.
+    turn_diffs: Arc<TurnDiffs>,
+    batches: Arc<BatchRegistry>,
+    http_client: reqwest::Client,
+    turn_slots: Arc<TurnSlots>,
+    /// `None` unless response caching is enabled.
+    response_cache: Option<Arc<ResponseCache>>,
+    /// `None` when `Idempotency-Key` support is disabled.
+    idempotency: Option<Arc<IdempotencyStore>>,
+    /// `None` when Responses API objects are not kept for retrieval.
+    response_store: Option<Arc<ResponseStore>>,
+    /// Shared with the tenants that set no limits of their own.
+    rate_limiter: Arc<RateLimiter>,
+    /// Swaps the log filter at runtime; see `PUT /admin/log-level`.
+    log_filter: reload::Handle<EnvFilter, Registry>,
+    /// The tenant this state serves; `None` for the proxy's own Codex home.
+    tenant: Option<Arc<str>>,
+    /// Every request served; shared by every tenant.
+    ledger: Arc<Ledger>,
+    /// Whether requests may run turns with `danger-full-access`.
+    allow_danger_full_access: bool,
+    /// Who may call `/admin/*` and `/v1/sessions`.
+    admin_access: AdminAccess,
+    /// `None` when no audit log is written; shared by every tenant.
+    audit_log: Option<Arc<AuditLog>>,
+    /// `None` when nothing is redacted.
+    redactor: Option<Arc<Redactor>>,
+    /// `None` when input is not moderated.
+    moderation: Option<Arc<Moderation>>,
+    /// `None` when turn completions are not notified; shared by every tenant.
+    turn_webhook: Option<Arc<TurnWebhook>>,
+    /// Embedders' request, response and event hooks.
+    interceptors: Arc<Interceptors>,
+    /// Proxy-wide cap on output tokens; unset leaves answers uncapped.
+    max_output_tokens: Arc<LiveLimit>,
+    /// What idle chat, Responses and Messages streams send.
+    sse_keep_alive: SseKeepAlive,
+    /// Buffers between turns and their SSE responses.
+    stream_buffers: Arc<StreamBuffers>,
+    /// Largest request body accepted outside batches; unset for no limit.
+    max_request_body_bytes: Arc<LiveLimit>,
+    /// Origins browsers may call the proxy from; empty allows any.
+    cors_origins: Arc<[HeaderValue]>,
+    /// The per-tenant routers in front of this state, reloaded along with
+    /// it; set once they are built.
+    tenants: Arc<OnceLock<Arc<TenantRouter>>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    role: String,
+    content: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionRequest {
+    model: String,
+    #[serde(default)]
+    messages: Option<Vec<ChatMessage>>,
+    #[serde(default)]
+    stream: bool,
+    /// `include_usage` adds a chunk with the turn's token usage before
+    /// `[DONE]`, as OpenAI does.
+    #[serde(default)]
+    stream_options: Option<StreamOptions>,
+    /// Spaces text deltas at least this many milliseconds apart, merging
+    /// those that arrive sooner; overrides
+    /// `CODEX_OPENAI_PROXY_STREAM_MIN_INTERVAL_MS`, and 0 turns it off.
+    #[serde(default)]
+    stream_min_interval_ms: Option<u64>,
+    /// `word` or `sentence` re-chunks text deltas to end on those
+    /// boundaries; overrides `CODEX_OPENAI_PROXY_STREAM_GRANULARITY`.
+    #[serde(default)]
+    stream_granularity: Option<String>,
+    #[serde(default)]
+    conversation_id: Option<String>,
+    /// Interrupts a turn already running on `conversation_id` instead of
+    /// queueing behind it; overrides `CODEX_OPENAI_PROXY_INTERRUPT_PREVIOUS_TURN`.
+    #[serde(default)]
+    interrupt_previous_turn: Option<bool>,
+    /// `never` (default) or `on-request`; the latter streams approval
+    /// requests that are answered via the approvals endpoint.
+    #[serde(default)]
+    approval_policy: Option<String>,
+    /// `read-only` (default) or, when the proxy allows it,
+    /// `danger-full-access`; see [`crate::sandbox_mode`].
+    #[serde(default)]
+    sandbox_mode: Option<String>,
+    /// Runs the turn read-only and returns the patches Codex would apply
+    /// instead; see [`crate::dry_run`].
+    #[serde(default)]
+    dry_run: bool,
+    /// Directory to run the turn in; must be inside a configured workspace root.
+    #[serde(default, alias = "workspace")]
+    cwd: Option<String>,
+    /// Forwarded to Codex as the turn's reasoning effort.
+    #[serde(default)]
+    reasoning_effort: Option<ReasoningEffort>,
+    /// Stream reasoning summaries as `reasoning_content` deltas (DeepSeek-style).
+    #[serde(default)]
+    include_reasoning: bool,
+    /// Cuts the answer off with `finish_reason: "length"`; preferred over
+    /// the deprecated `max_tokens`.
+    #[serde(default)]
+    max_completion_tokens: Option<u64>,
+    #[serde(default)]
+    max_tokens: Option<u64>,
+    /// The client's end user, recorded for usage attribution.
+    #[serde(default)]
+    user: Option<String>,
+    /// Lets the model issue several tool calls at once. Fixed when the
+    /// conversation's thread is started; unset keeps the model's default, and
+    /// a later turn asking for a different value is rejected.
+    #[serde(default)]
+    parallel_tool_calls: Option<bool>,
+    /// A `json_schema` format the answer is asked for and validated against;
+    /// see [`crate::structured_output`].
+    #[serde(default)]
+    response_format: Option<serde_json::Value>,
+    /// Sent on to Chat Completions providers in forward mode and echoed in
+    /// the response. Codex turns take no seed, so agent mode rejects it.
+    #[serde(default)]
+    seed: Option<i64>,
+    /// How many independent answers to sample, as separate choices; only
+    /// forward mode runs more than one. `candidates` is accepted as well.
+    #[serde(default, alias = "candidates")]
+    n: Option<usize>,
+    /// Replaces Codex's built-in system prompt for the thread this request
+    /// starts or resumes; see [`crate::BASE_INSTRUCTIONS_HEADER`].
+    #[serde(default)]
+    base_instructions: Option<String>,
+    /// The Codex config profile the thread this request starts or resumes
+    /// runs with; see [`crate::PROFILE_HEADER`].
+    #[serde(default)]
+    profile: Option<String>,
+    /// Tool servers added to the thread this request starts; see
+    /// [`crate::mcp_servers`].
+    #[serde(default)]
+    mcp_servers: Vec<McpServerRequest>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StreamOptions {
+    #[serde(default)]
+    include_usage: bool,
+}
+
+impl ChatCompletionRequest {
+    fn max_output_tokens(&self) -> Option<u64> {
+        self.max_completion_tokens.or(self.max_tokens)
+    }
+
+    fn candidates(&self) -> usize {
+        self.n.unwrap_or(1)
+    }
+
+    fn include_usage(&self) -> bool {
+        self.stream_options
+            .as_ref()
+            .is_some_and(|options| options.include_usage)
+    }
+
+    /// Config overrides for a thread started by this request.
+    fn thread_overrides(&self) -> Vec<(String, toml::Value)> {
+        self.parallel_tool_calls
+            .map(|parallel| {
+                (
+                    PARALLEL_TOOL_CALLS_OVERRIDE.to_string(),
+                    toml::Value::Boolean(parallel),
+                )
+            })
+            .into_iter()
+            .chain(profile_override(self.profile.as_deref()))
+            .collect()
+    }
+
+    /// What this request asks of its turn, given its merged `text`.
+    fn turn_options(
+        &self,
+        text: String,
+        answerable: bool,
+        priority: i32,
+        output_schema: Option<&OutputSchema>,
+    ) -> TurnOptions {
+        TurnOptions {
+            model: self.model.clone(),
+            text,
+            conversation_id: self.conversation_id.clone(),
+            thread_overrides: self.thread_overrides(),
+            base_instructions: self.base_instructions.clone(),
+            mcp_servers: self.mcp_servers.clone(),
+            user: self.user.clone(),
+            approval_policy: self.approval_policy.clone(),
+            answerable,
+            sandbox_mode: self.sandbox_mode.clone(),
+            dry_run: self.dry_run,
+            cwd: self.cwd.clone(),
+            effort: self.reasoning_effort,
+            output_schema: output_schema.map(OutputSchema::schema),
+            image_urls: image_urls_from_request(self),
+            attachments: attachments_from_request(self),
+            priority,
+            interrupt_previous_turn: self.interrupt_previous_turn,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChatCompletionResponse {
+    id: String,
+    object: String,
+    created: u64,
+    model: String,
+    system_fingerprint: String,
+    choices: Vec<ChatChoice>,
+    usage: Usage,
+    /// Files Codex modified during the turn.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    changed_files: Vec<ChangedFile>,
+    /// Patches a `dry_run` turn would have applied.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    proposed_patches: Vec<ProposedPatch>,
+    /// The plan as Codex last updated it during the turn.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<UpdatePlanArgs>,
+    /// The request's `seed`, echoed back when forward mode sent it on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Usage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+    total_tokens: u32,
+}
+
+impl From<TurnUsage> for Usage {
+    fn from(usage: TurnUsage) -> Self {
+        let tokens = |n: i64| u32::try_from(n).unwrap_or(0);
+        Self {
+            prompt_tokens: tokens(usage.input_tokens),
+            completion_tokens: tokens(usage.output_tokens),
+            total_tokens: tokens(usage.total_tokens),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChatChoice {
+    index: usize,
+    message: ChatMessageResponse,
+    finish_reason: String,
+    /// Proxy extension: why the turn was aborted (`interrupted`, `replaced`
+    /// or `review_ended`), as by `POST /v1/chat/completions/{id}/cancel`.
+    /// `finish_reason` is then `stop`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    abort_reason: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatMessageResponse {
+    role: String,
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<ToolCall>>,
+    /// `url_citation`s for the links in `content`, when Codex searched the web.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotations: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+struct ToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    function: ToolFunction,
+    /// The MCP server Codex called the tool on; `function.name` is then the
+    /// server's own name for the tool.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+struct ToolFunction {
+    name: String,
+    arguments: String,
+}
+
+/// How requests are served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProxyMode {
+    /// Run each request as a Codex turn, with tools, approvals and sessions.
+    Agent,
+    /// Send each request straight to the model provider, without tools.
+    Forward,
+}
+
+/// Where model requests go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// The model provider from the Codex config.
+    Codex,
+    /// A local fake answering with scripted deltas, tool calls and errors;
+    /// needs no credentials or network.
+    Mock,
+    /// The Codex model provider, with every model stream also written to
+    /// `--recordings`.
+    Record,
+    /// The streams in `--recordings` played back in order instead of asking
+    /// a model.
+    Replay,
+}
+
+/// Command-line options; everything else is configured through
+/// `CODEX_OPENAI_PROXY_*` environment variables.
+#[derive(Debug, Parser)]
+#[command(name = "codex-openai-proxy")]
+pub struct Cli {
+    #[arg(long, value_enum, default_value_t = ProxyMode::Agent)]
+    mode: ProxyMode,
+    #[arg(long, value_enum, default_value_t = Backend::Codex)]
+    backend: Backend,
+    /// Directory `--backend record` writes model streams to and
+    /// `--backend replay` reads them from.
+    #[arg(long, default_value = "recordings")]
+    recordings: PathBuf,
+    /// PEM certificate chain to serve HTTPS with.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key matching --tls-cert.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Address to listen on, over `CODEX_OPENAI_PROXY_ADDR`.
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+    /// A `proxy.toml` whose settings apply where no flag or
+    /// `CODEX_OPENAI_PROXY_*` variable sets them.
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
+
+/// Runs the proxy until the server stops.
+pub async fn run_main(cli: Cli) -> anyhow::Result<()> {
+    let proxy_file = match &cli.config {
+        Some(path) => proxy_config::load(path)?,
+        None => ProxyConfigFile::default(),
+    };
+    // The file's sandbox defaults go under the backend's overrides.
+    let mut config_overrides = proxy_file.config_overrides();
+    config_overrides.extend(match cli.backend {
+        Backend::Codex => Vec::new(),
+        Backend::Mock => mock_backend::config_overrides(mock_backend::spawn().await?),
+        Backend::Record => {
+            let config = Config::load_with_cli_overrides(Vec::new())
+                .await
+                .context("load config")?;
+            let auth_mode = AuthManager::new(
+                config.codex_home.clone(),
+                false,
+                config.cli_auth_credentials_store_mode,
+            )
+            .get_auth_mode();
+            recordings::record(&config, auth_mode, &cli.recordings).await?
+        }
+        Backend::Replay => recordings::replay(&cli.recordings).await?,
+    });
+    let config = Config::load_with_cli_overrides(config_overrides.clone())
+        .await
+        .context("load config")?;
+
+    // Spans are exported over OTLP when the Codex config enables an `[otel]`
+    // trace exporter; the provider must stay alive for the whole process.
+    let otel = codex_core::otel_init::build_provider(
+        &config,
+        env!("CARGO_PKG_VERSION"),
+        Some("codex-openai-proxy"),
+        false,
+    )
+    .map_err(|e| anyhow::anyhow!("create otel exporter: {e}"))?;
+    let (log_filter, log_filter_handle) = reload::Layer::new(EnvFilter::from_default_env());
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_target(false)
+        .with_filter(log_filter);
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(otel.as_ref().and_then(|o| o.tracing_layer()))
+        .with(otel.as_ref().and_then(|o| o.logger_layer()))
+        .init();
+
+    let auth_manager = Arc::new(AuthManager::new(
+        config.codex_home.clone(),
+        false,
+        config.cli_auth_credentials_store_mode,
+    ));
+
+    let thread_manager = Arc::new(ThreadManager::new(
+        config.codex_home.clone(),
+        auth_manager.clone(),
+        SessionSource::Exec,
+    ));
+
+    // Client-facing model names to Codex models; re-read on SIGHUP, when the
+    // file changes and on `POST /admin/config/reload`.
+    let model_map = match proxy_config::var_os("CODEX_OPENAI_PROXY_MODEL_MAP") {
+        Some(path) => {
+            let known = known_models(&thread_manager, &config).await;
+            ModelMapping::load(Path::new(&path), &|model| known.contains(model))?
+        }
+        None => ModelMapping::default(),
+    };
+    let model_map = Arc::new(model_map);
+
+    match cli.backend {
+        Backend::Codex => {}
+        Backend::Mock => warn!("model requests are answered by the scripted mock backend"),
+        Backend::Record => info!("recording model streams to {}", cli.recordings.display()),
+        Backend::Replay => {
+            warn!(
+                "model requests are answered from {}",
+                cli.recordings.display()
+            );
+        }
+    }
+
+    let static_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("static");
+    info!("Static files directory: {:?}", static_dir);
+
+    let addr: SocketAddr = match cli.listen {
+        Some(addr) => addr,
+        None => proxy_config::var("CODEX_OPENAI_PROXY_ADDR")
+            .unwrap_or_else(|_| "127.0.0.1:11435".to_string())
+            .parse()
+            .context("parse CODEX_OPENAI_PROXY_ADDR")?,
+    };
+
+    // Forward mode shares the login, model map, limits and probes.
+    let state = AppStateBuilder::from_env(config.clone())?
+        .listen_addr(addr)
+        .config_overrides(config_overrides)
+        .auth_manager(auth_manager)
+        .thread_manager(thread_manager)
+        .log_filter(log_filter_handle)
+        .model_mapping(model_map)
+        .build()?;
+    let router = match cli.mode {
+        ProxyMode::Agent => agent_router(state.clone(), &static_dir).await?,
+        ProxyMode::Forward => forward::router(config, state.clone(), &static_dir)?,
+    };
+    config_watch::spawn(state).await;
+
+    let tls_files = match (&cli.tls_cert, &cli.tls_key) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        _ => proxy_file
+            .tls
+            .cert
+            .as_ref()
+            .zip(proxy_file.tls.key.as_ref()),
+    };
+    let tls_config = match tls_files {
+        Some((cert, key)) => Some(tls::load_server_config(cert, key)?),
+        None => None,
+    };
+    let scheme = if tls_config.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+
+    info!(
+        "codex-openai-proxy ({:?} mode) listening on {scheme}://{addr}",
+        cli.mode
+    );
+    info!("Web logs available at {scheme}://{addr}/logs");
+
+    // Send initial log message
+    log_message(
+        serde_json::json!({
+            "type": "info",
+            "message": format!("Proxy started in {:?} mode on {}", cli.mode, addr)
+        })
+        .to_string(),
+    );
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .context("bind listener")?;
+    let app = router.into_make_service_with_connect_info::<SocketAddr>();
+    match tls_config {
+        Some(tls_config) => {
+            // `tap_io` lets the TLS listener provide `ConnectInfo<SocketAddr>`
+            // like the plain TCP one.
+            let listener = tls::TlsListener::new(listener, tls_config)
+                .context("start TLS listener")?
+                .tap_io(|_| {});
+            axum::serve(listener, app).await
+        }
+        None => axum::serve(listener, app).await,
+    }
+    .context("run server")?;
+
+    Ok(())
+}
+
+/// The agent-mode router for `state`, behind per-tenant routers when
+/// `CODEX_OPENAI_PROXY_TENANTS` is set.
+async fn agent_router(state: AppState, static_dir: &Path) -> anyhow::Result<Router> {
+    let router = build_router(state.clone(), static_dir);
+    // API keys served from their own Codex home instead of the proxy's.
+    let router = match proxy_config::var_os("CODEX_OPENAI_PROXY_TENANTS") {
+        Some(path) => {
+            let tenants = Arc::new(TenantRouter::load(Path::new(&path), &state, static_dir).await?);
+            // Reloaded with the proxy's own config.
+            let _ = state.tenants.set(tenants.clone());
+            router.layer(axum::middleware::from_fn_with_state(
+                tenants,
+                tenants::dispatch,
+            ))
+        }
+        None => router,
+    };
+    Ok(router)
+}
+
+/// Every endpoint of the proxy, for mounting it in another axum app; see
+/// [`AppStateBuilder`]. Unlike the standalone binary this serves no tenants.
+pub fn router(state: AppState) -> Router {
+    build_router(
+        state,
+        &PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("static"),
+    )
+}
+
+/// CORS for `state`'s origins, any origin when none are configured.
+fn cors_layer(state: &AppState) -> CorsLayer {
+    let origins = if state.cors_origins.is_empty() {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(state.cors_origins.iter().cloned())
+    };
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(Any)
+        .allow_headers(Any)
+}
+
+/// Every endpoint of the proxy, served from `state`.
+fn build_router(state: AppState, static_dir: &Path) -> Router {
+    let cors = cors_layer(&state);
+
+    // Endpoints that run a turn, subject to rate limiting.
+    let turn_routes = Router::new()
+        .route("/v1/chat/completions", post(handle_chat_completions))
+        .route("/v1/responses", post(responses::handle_responses))
+        // Anthropic Messages API
+        .route("/v1/messages", post(messages::handle_messages))
+        // Without /v1 prefix (Cursor compatibility)
+        .route("/chat/completions", post(handle_chat_completions))
+        .route("/responses", post(responses::handle_responses))
+        // Azure OpenAI URL shape
+        .route(
+            "/openai/deployments/{deployment}/chat/completions",
+            post(azure::handle_azure_chat_completions),
+        )
+        .route("/v1/ws", get(ws::handle_ws))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::rate_limit,
+        ));
+
+    // Batch inputs are allowed to be far larger than any other request.
+    let batch_routes = Router::new()
+        .route(
+            "/v1/batches",
+            post(batches::handle_create_batch)
+                .get(batches::handle_list_batches)
+                .layer(DefaultBodyLimit::max(batches::MAX_BATCH_INPUT_BYTES)),
+        )
+        .route("/v1/batches/{id}", get(batches::handle_get_batch))
+        .route(
+            "/v1/batches/{id}/cancel",
+            post(batches::handle_cancel_batch),
+        )
+        .route("/v1/batches/{id}/output", get(batches::handle_batch_output))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            idempotency::idempotency,
+        ));
+
+    // Uploads, imported rollouts among them, may be as large as the OpenAI
+    // Files API allows.
+    let file_routes = Router::new()
+        .route(
+            "/v1/files",
+            post(files::handle_upload_file).layer(DefaultBodyLimit::max(files::MAX_FILE_BYTES)),
+        )
+        .route("/v1/files/{id}/content", get(files::handle_file_content))
+        .route(
+            "/v1/conversations/import",
+            post(conversations::handle_import_conversation)
+                .layer(DefaultBodyLimit::max(files::MAX_FILE_BYTES)),
+        );
+
+    // Settings of the whole process, which tenants get no say over.
+    let operator_routes = if state.tenant.is_none() {
+        Router::new()
+            .route("/admin/log-level", put(admin::handle_set_log_level))
+            .route("/admin/config/reload", post(admin::handle_reload_config))
+    } else {
+        Router::new()
+    };
+    // Every conversation and the process itself, behind the admin token.
+    let admin_routes = Router::new()
+        .route("/v1/sessions", get(sessions::handle_list_sessions))
+        .route("/admin/threads", get(admin::handle_list_threads))
+        .route(
+            "/admin/threads/{id}/abort",
+            post(admin::handle_abort_thread),
+        )
+        .merge(operator_routes)
+        .route("/admin/config", get(admin::handle_get_config))
+        .route("/admin/turns", get(admin::handle_turn_metrics))
+        .route("/admin/streams", get(admin::handle_stream_metrics))
+        .route("/admin/rate-limits", get(admin::handle_rate_limits))
+        .route("/admin/usage", get(usage::handle_usage))
+        .route("/admin/requests", get(admin::handle_list_requests))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            admin_auth::require_admin,
+        ));
+
+    let routes = Router::new()
+        .merge(turn_routes)
+        // With /v1 prefix (OpenAI standard)
+        .route("/v1/models", get(handle_models))
+        .route(
+            "/v1/chat/completions/{id}/cancel",
+            post(completions::handle_cancel_completion),
+        )
+        .route(
+            "/v1/responses/{id}",
+            get(response_store::handle_get_response),
+        )
+        .route(
+            "/v1/conversations/{id}",
+            delete(conversations::handle_delete_conversation),
+        )
+        .route(
+            "/v1/conversations/{id}/fork",
+            post(conversations::handle_fork_conversation),
+        )
+        .route(
+            "/v1/conversations/{id}/turns/{turn_id}/diff",
+            get(turn_diffs::handle_turn_diff),
+        )
+        .route(
+            "/v1/conversations/{id}/approvals/{approval_id}",
+            post(approvals::handle_approval_decision),
+        )
+        // Without /v1 prefix (Cursor compatibility)
+        .route("/models", get(handle_models))
+        .route(
+            "/chat/completions/{id}/cancel",
+            post(completions::handle_cancel_completion),
+        )
+        .route("/responses/{id}", get(response_store::handle_get_response))
+        // MCP (streamable HTTP) facade
+        .nest_service("/mcp", mcp::McpFacade::service(state.clone()))
+        // Probes
+        .route("/healthz", get(health::handle_healthz))
+        .route("/readyz", get(health::handle_readyz))
+        // Admin routes
+        .merge(admin_routes)
+        // Bundled dashboard and chat UI
+        .route(
+            "/admin",
+            get(|| async { axum::response::Redirect::temporary("/static/admin.html") }),
+        )
+        .route(
+            "/",
+            get(|| async { axum::response::Redirect::temporary("/static/chat.html") }),
+        )
+        // Log viewer routes
+        .route("/logs", get(handle_logs_redirect))
+        .route("/logs/stream", get(handle_logs_stream))
+        .route(
+            "/logs.html",
+            get(|| async { axum::response::Redirect::permanent("/static/logs.html") }),
+        )
+        .route(
+            "/logs.css",
+            get(|| async { axum::response::Redirect::permanent("/static/logs.css") }),
+        )
+        .route(
+            "/logs.js",
+            get(|| async { axum::response::Redirect::permanent("/static/logs.js") }),
+        )
+        // Static files
+        .nest_service("/static", ServeDir::new(static_dir))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            idempotency::idempotency,
+        ));
+
+    // The limit wraps the idempotency layer, which buffers bodies itself.
+    body_limit::limit(routes, state.max_request_body_bytes.clone())
+        .merge(batch_routes)
+        .merge(file_routes)
+        .with_state(state.clone())
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            interceptors::intercept,
+        ))
+        .layer(cors)
+        .layer(axum::middleware::from_fn_with_state(
+            state,
+            access_log::access_log,
+        ))
+}
+
+async fn handle_chat_completions(
+    State(state): State<AppState>,
+    Extension(context): Extension<RequestContext>,
+    headers: HeaderMap,
+    body: axum::Json<ChatCompletionRequest>,
+) -> Response {
+    // Log ALL incoming chat completion requests
+    log_message(
+        serde_json::json!({
+            "type": "incoming_request",
+            "endpoint": "/chat/completions",
+            "model": body.model,
+            "stream": body.stream
+        })
+        .to_string(),
+    );
+
+    context.set_model(&body.model);
+    let priority = match turn_slots::request_priority(&headers) {
+        Ok(priority) => priority,
+        Err(e) => return e.into_response(),
+    };
+    let mut body = body.0;
+    match (base_instructions_header(&headers), profile_header(&headers)) {
+        (Ok(instructions), Ok(profile)) => {
+            body.base_instructions = body.base_instructions.or(instructions);
+            body.profile = body.profile.or(profile);
+        }
+        (Err(e), _) | (_, Err(e)) => return e.into_response(),
+    }
+    // A Codex turn acts on the workspace, so sampling it twice would act twice.
+    if body.candidates() != 1 {
+        return ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            "n other than 1 is only supported in forward mode",
+            "invalid_request_error",
+        )
+        .with_code("unsupported_parameter")
+        .into_response();
+    }
+    // Nor can it sample deterministically: Codex turns take no seed.
+    if body.seed.is_some() {
+        return ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            "seed is only supported in forward mode",
+            "invalid_request_error",
+        )
+        .with_code("unsupported_parameter")
+        .into_response();
+    }
+    if body.stream {
+        return handle_stream(state, context, priority, body).await;
+    }
+    handle_once(state, context, priority, body).await
+}
+
+async fn handle_models(State(state): State<AppState>) -> Response {
+    match state.configs.load(Vec::new()).await {
+        Ok(config) => {
+            model_list(
+                &state.thread_manager,
+                &config,
+                &state.model_map,
+                state.max_output_tokens.get(),
+            )
+            .await
+        }
+        Err(e) => ProxyError::internal(e.to_string()).into_response(),
+    }
+}
+
+/// The `/v1/models` list, with the context window and capabilities of the
+/// Codex model behind each entry for clients that size requests by them.
+async fn model_list(
+    thread_manager: &ThreadManager,
+    config: &Config,
+    model_map: &ModelMapping,
+    max_output_tokens: Option<u64>,
+) -> Response {
+    log_message(
+        serde_json::json!({
+            "type": "incoming_request",
+            "endpoint": "/models"
+        })
+        .to_string(),
+    );
+
+    let models_manager = thread_manager.get_models_manager();
+    let mut data = Vec::new();
+    for id in advertised_models(thread_manager, config, model_map).await {
+        let codex_model = model_map.codex_model(&id);
+        let info = models_manager
+            .construct_model_info(&codex_model, config)
+            .await;
+        data.push(model_entry(&id, &info, max_output_tokens));
+    }
+    let models = serde_json::json!({
+        "object": "list",
+        "data": data,
+    });
+    json_response(StatusCode::OK, models.to_string())
+}
+
+/// One `/v1/models` entry. Codex models have no output limit of their own,
+/// so `max_output_tokens` is the proxy's cap, if any.
+fn model_entry(id: &str, info: &ModelInfo, max_output_tokens: Option<u64>) -> serde_json::Value {
+    let reasoning_efforts = info
+        .supported_reasoning_levels
+        .iter()
+        .map(|preset| preset.effort)
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "id": id,
+        "object": "model",
+        "owned_by": "codex",
+        "context_window": info.context_window,
+        "max_output_tokens": max_output_tokens,
+        "capabilities": {
+            "parallel_tool_calls": info.supports_parallel_tool_calls,
+            "reasoning": !reasoning_efforts.is_empty(),
+            "reasoning_efforts": reasoning_efforts,
+            "reasoning_summaries": info.supports_reasoning_summaries,
+            "verbosity": info.support_verbosity,
+        },
+    })
+}
+
+async fn handle_once(
+    state: AppState,
+    context: RequestContext,
+    priority: i32,
+    body: ChatCompletionRequest,
+) -> Response {
+    let original_model = body.model.clone();
+
+    log_message(
+        serde_json::json!({
+            "type": "cursor_request",
+            "message": format!("Request: model={}, stream={}", body.model, body.stream)
+        })
+        .to_string(),
+    );
+
+    let output_schema = match OutputSchema::from_response_format(body.response_format.as_ref()) {
+        Ok(schema) => schema,
+        Err(e) => return e.into_response(),
+    };
+
+    let merged_text = match merged_text_from_request(&body) {
+        Some(text) => text,
+        None => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "no user content found".to_string(),
+                "invalid_request_error",
+            );
+        }
+    };
+
+    let turn = match body
+        .turn_options(
+            merged_text.clone(),
+            state.approvals.has_webhook(),
+            priority,
+            output_schema.as_ref(),
+        )
+        .prepare(&state, &context)
+        .await
+    {
+        Ok(turn) => turn,
+        Err(e) => return e.into_response(),
+    };
+
+    // Attachments are written into the workspace, so those requests always run.
+    let cache_key = match &state.response_cache {
+        Some(_) if body.conversation_id.is_none() && attachments_from_request(&body).is_empty() => {
+            Some(ResponseCache::key(
+                "chat.completions",
+                &serde_json::json!({
+                    "model": body.model,
+                    "input": merged_text,
+                    "images": image_urls_from_request(&body),
+                    "approval_policy": body.approval_policy,
+                    "sandbox_mode": body.sandbox_mode,
+                    "dry_run": body.dry_run,
+                    "cwd": body.cwd,
+                    "reasoning_effort": body.reasoning_effort,
+                    "max_output_tokens": body.max_output_tokens(),
+                    "response_format": body.response_format,
+                    "base_instructions": body.base_instructions,
+                    "profile": body.profile,
+                    "mcp_servers": body.mcp_servers,
+                }),
+            ))
+        }
+        _ => None,
+    };
+    if let (Some(cache), Some(key)) = (&state.response_cache, &cache_key)
+        && let Some(cached) = cache.get(key)
+    {
+        return with_cache_status(json_response(StatusCode::OK, cached), true);
+    }
+
+    let model = state.model_map.codex_model(&body.model);
+    let fingerprint = system_fingerprint(&model);
+    let max_output_tokens =
+        output_cap::effective(state.max_output_tokens.get(), body.max_output_tokens());
+
+    log_message(
+        serde_json::json!({
+            "type": "codex_forward",
+            "message": format!("Forward to Codex: original_model={}, mapped_model={}, conv_id={}",
+                body.model, model, body.conversation_id.as_deref().unwrap_or("new"))
+        })
+        .to_string(),
+    );
+
+    let thread = match turn.thread(&state, &context).await {
+        Ok(thread) => thread,
+        Err(e) => return e.into_response(),
+    };
+    // Named before the turn starts, as its diff is recorded under this id.
+    let completion_id = format!("chatcmpl-codex-{}", uuid::Uuid::new_v4());
+    context.set_response_id(&completion_id);
+    let mut turn = match turn_setup::submit(&state, &context, thread, turn).await {
+        Ok(turn) => turn,
+        Err(e) => return e.into_response(),
+    };
+
+    let tool_calls = Arc::new(Mutex::new(Vec::<ToolCall>::new()));
+    let tool_calls_for_task = tool_calls.clone();
+    let changed_files = Arc::new(Mutex::new(Vec::<ChangedFile>::new()));
+    let changed_files_for_task = changed_files.clone();
+    let proposed_patches = Arc::new(Mutex::new(Vec::<ProposedPatch>::new()));
+    let proposed_patches_for_task = proposed_patches.clone();
+    let plan = Arc::new(Mutex::new(None::<UpdatePlanArgs>));
+    let plan_for_task = plan.clone();
+    let approvals = state.approvals.clone();
+    let context_for_task = context.clone();
+
+    let turn_span = turn.span();
+    let task = async move {
+        let mut final_text = String::new();
+        let mut agent_text = AgentText::default();
+        let mut output_cap = OutputCap::new(max_output_tokens);
+        let mut citations = Citations::default();
+        let mut aborted = None;
+        let mut schema_retried = false;
+        loop {
+            let mut ev = turn
+                .thread
+                .next_event()
+                .await
+                .map_err(|e| ProxyError::internal(format!("event error: {e}")))?;
+            if ev.id != turn.submission_id {
+                continue;
+            }
+            if !context_for_task.intercept_event(&mut ev.msg) {
+                continue;
+            }
+            // Past the output cap only the end of the interrupted turn matters.
+            if output_cap.reached() {
+                if output_cap::ends_turn(&ev.msg) {
+                    break;
+                }
+                continue;
+            }
+            if let Some(text) = agent_text.text(&ev.msg) {
+                final_text.push_str(output_cap.take(&text));
+                if output_cap.reached() {
+                    output_cap::interrupt(&turn.thread).await;
+                }
+            }
+            context_for_task.record_event(&ev.msg);
+            match ev.msg {
+                EventMsg::RawResponseItem(raw) => {
+                    // Log reasoning
+                    if let ResponseItem::Reasoning { id, summary, .. } = &raw.item {
+                        log_message(
+                            serde_json::json!({
+                                "type": "reasoning_detected",
+                                "id": id,
+                                "summary_count": summary.len(),
+                            })
+                            .to_string(),
+                        );
+                    }
+
+                    citations.record(&raw.item);
+                    // Collect tool calls
+                    if let Some(tc) = codex_tool_call(&raw.item) {
+                        tool_calls_for_task.lock().await.push(tc);
+                    }
+                }
+                EventMsg::McpToolCallBegin(begin) => {
+                    tool_calls_for_task.lock().await.push(mcp_tool_call(&begin));
+                }
+                EventMsg::TurnComplete(_) => {
+                    let Some(Err(message)) = output_schema
+                        .as_ref()
+                        .map(|schema| schema.check(&final_text))
+                    else {
+                        break;
+                    };
+                    if schema_retried {
+                        return Err(structured_output::validation_error(message));
+                    }
+                    // Asked once more, as the stream does, before failing.
+                    schema_retried = true;
+                    approvals.clear_turn(&turn.submission_id);
+                    turn.resubmit(structured_output::correction_prompt(&message))
+                        .await?;
+                    final_text.clear();
+                    agent_text = AgentText::default();
+                    output_cap = OutputCap::new(max_output_tokens);
+                    citations = Citations::default();
+                }
+                EventMsg::Error(err) => return Err(upstream_errors::turn_error(&err)),
+                EventMsg::Warning(warn) => {
+                    info!("warning from Codex: {}", warn.message);
+                }
+                EventMsg::TokenCount(count) => {
+                    if let Some(info) = count.info {
+                        context_for_task.add_tokens(info.last_token_usage.total_tokens);
+                    }
+                }
+                EventMsg::PatchApplyEnd(patch) if patch.success => {
+                    let mut files = changed_files_for_task.lock().await;
+                    codex_events::record_changed_files(&mut files, &patch.changes);
+                }
+                EventMsg::PlanUpdate(update) => {
+                    *plan_for_task.lock().await = Some(update);
+                }
+                msg @ (EventMsg::ExecApprovalRequest(_)
+                | EventMsg::ApplyPatchApprovalRequest(_))
+                    if turn.dry_run =>
+                {
+                    if let Some(patch) = ProposedPatch::from_event(&msg) {
+                        proposed_patches_for_task.lock().await.push(patch);
+                    }
+                    approvals
+                        .deny_dry_run(&turn.thread, turn.thread_id, &ev.id, &msg)
+                        .await;
+                }
+                msg @ (EventMsg::ExecApprovalRequest(_)
+                | EventMsg::ApplyPatchApprovalRequest(_)) => {
+                    // Answered by the webhook, or denied under `never`.
+                    approvals
+                        .handle(
+                            &turn.thread,
+                            turn.thread_id,
+                            &ev.id,
+                            turn.approval_policy,
+                            &msg,
+                        )
+                        .await;
+                }
+                EventMsg::TurnAborted(abort) => {
+                    aborted = Some(codex_events::abort_reason(&abort.reason));
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        // An aborted turn keeps the text it got to, ending as a stop.
+        let cut_off = if aborted.is_some() {
+            Some("stop")
+        } else if output_cap.reached() {
+            Some("length")
+        } else {
+            None
+        };
+        Ok((final_text, cut_off, aborted, citations))
+    };
+    let handle = tokio::spawn(task.instrument(turn_span));
+
+    let (final_text, cut_off, abort_reason, citations) = match handle.await {
+        Ok(Ok((text, cut_off, abort_reason, citations))) => {
+            (text.trim().to_string(), cut_off, abort_reason, citations)
+        }
+        Ok(Err(e)) => return e.into_response(),
+        Err(join_err) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                join_err.to_string(),
+                "internal_error",
+            );
+        }
+    };
+
+    let tool_calls_snapshot = {
+        let guard = tool_calls.lock().await;
+        guard.clone()
+    };
+
+    let resp = ChatCompletionResponse {
+        id: completion_id,
+        object: "chat.completion".to_string(),
+        created: now_ts(),
+        model: original_model.clone(), // ⚠️ Use original model name
+        system_fingerprint: fingerprint,
+        choices: vec![ChatChoice {
+            index: 0,
+            message: ChatMessageResponse {
+                role: "assistant".to_string(),
+                content: final_text.clone(),
+                tool_calls: if tool_calls_snapshot.is_empty() {
+                    None
+                } else {
+                    Some(tool_calls_snapshot.clone())
+                },
+                annotations: citations.chat_annotations(&final_text),
+            },
+            finish_reason: if let Some(reason) = cut_off {
+                reason.to_string()
+            } else if !tool_calls_snapshot.is_empty() {
+                "tool_calls".to_string()
+            } else {
+                "stop".to_string()
+            },
+            abort_reason,
+        }],
+        usage: Usage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens: 0,
+        },
+        changed_files: changed_files.lock().await.clone(),
+        proposed_patches: proposed_patches.lock().await.clone(),
+        plan: plan.lock().await.take(),
+        seed: None,
+    };
+
+    // Log response to Cursor
+    log_message(
+        serde_json::json!({
+            "type": "cursor_response",
+            "message": format!("Response to Cursor: model={}, finish_reason={}",
+                original_model, resp.choices[0].finish_reason)
+        })
+        .to_string(),
+    );
+
+    let body = serde_json::to_string(&resp).unwrap_or_else(|_| "{}".to_string());
+    if let (Some(cache), Some(key)) = (&state.response_cache, cache_key) {
+        cache.put(key, body.clone());
+        return with_cache_status(json_response(StatusCode::OK, body), false);
+    }
+    json_response(StatusCode::OK, body)
+}
+
+async fn handle_stream(
+    state: AppState,
+    context: RequestContext,
+    priority: i32,
+    body: ChatCompletionRequest,
+) -> Response {
+    log_message(
+        serde_json::json!({
+            "type": "stream_start",
+            "model": body.model,
+        })
+        .to_string(),
+    );
+
+    let original_model = body.model.clone();
+    let max_output_tokens =
+        output_cap::effective(state.max_output_tokens.get(), body.max_output_tokens());
+
+    let shaping = match DeltaShaping::from_request(
+        body.stream_granularity.as_deref(),
+        body.stream_min_interval_ms,
+    ) {
+        Ok(shaping) => shaping,
+        Err(e) => return e.into_response(),
+    };
+
+    let output_schema = match OutputSchema::from_response_format(body.response_format.as_ref()) {
+        Ok(schema) => schema,
+        Err(e) => return e.into_response(),
+    };
+
+    let merged_text = match merged_text_from_request(&body) {
+        Some(text) => text,
+        None => {
+            log_message(
+                serde_json::json!({
+                    "type": "stream_error",
+                    "error": "no user content found"
+                })
+                .to_string(),
+            );
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "no user content found".to_string(),
+                "invalid_request_error",
+            );
+        }
+    };
+
+    let turn = match body
+        .turn_options(merged_text, true, priority, output_schema.as_ref())
+        .prepare(&state, &context)
+        .await
+    {
+        Ok(turn) => turn,
+        Err(e) => return e.into_response(),
+    };
+
+    let include_usage = body.include_usage();
+    let (thread, thread_id) = match turn.thread(&state, &context).await {
+        Ok(t) => {
+            log_message(
+                serde_json::json!({
+                    "type": "stream_thread_created",
+                    "model": body.model
+                })
+                .to_string(),
+            );
+            t
+        }
+        Err(e) => {
+            log_message(
+                serde_json::json!({
+                    "type": "stream_error",
+                    "error": format!("failed to create thread: {}", e)
+                })
+                .to_string(),
+            );
+            return e.into_response();
+        }
+    };
+
+    let model = state.model_map.codex_model(&body.model);
+    let tool_seen = Arc::new(AtomicBool::new(false));
+    let tool_seen_for_task = tool_seen.clone();
+    // Every chunk, starting with the role chunk, carries the id clients pass to
+    // `POST /v1/chat/completions/{id}/cancel`.
+    let chunk_meta = ChunkMeta::new(&original_model, &model, None);
+    context.set_response_id(&chunk_meta.id);
+    let completion_guard = state.completions.track(&chunk_meta.id, thread_id);
+    let mut turn = match turn_setup::submit(&state, &context, (thread, thread_id), turn).await {
+        Ok(turn) => turn,
+        Err(e) => {
+            log_message(
+                serde_json::json!({
+                    "type": "stream_submit_error",
+                    "error": e.message
+                })
+                .to_string(),
+            );
+            return e.into_response();
+        }
+    };
+    log_message(
+        serde_json::json!({
+            "type": "stream_submitted",
+            "submission_id": turn.submission_id
+        })
+        .to_string(),
+    );
+    // A cancel that arrived before the turn was submitted interrupted nothing.
+    if completion_guard.is_cancelled()
+        && let Err(e) = turn.thread.submit(Op::Interrupt).await
+    {
+        warn!("failed to interrupt cancelled completion: {e}");
+    }
+
+    let approvals = state.approvals.clone();
+    let include_reasoning = body.include_reasoning;
+    let context_for_task = context.clone();
+    let (tx, rx) = state.stream_buffers.channel();
+    let rx = state.stream_buffers.shape(rx, shaping);
+    let _ = tx.send(role_chunk(&chunk_meta)).await;
+
+    let turn_span = turn.span();
+    let task = async move {
+        // Dropped before `tx`, so a finished stream is no longer cancellable.
+        let _completion_guard = completion_guard;
+        let mut agent_text = AgentText::default();
+        let mut output_cap = OutputCap::new(max_output_tokens);
+        let mut citations = Citations::default();
+        let mut usage = TurnUsage::default();
+        let mut tool_call_indices = ToolCallIndices::default();
+        // Everything streamed as content, which annotation indices point into.
+        let mut streamed_text = String::new();
+        // With an output schema the answer is held back until it matches, so
+        // one that does not can be retried without the client seeing it.
+        let mut held_text = String::new();
+        let mut schema_retried = false;
+        loop {
+            let mut ev = match turn.thread.next_event().await {
+                Ok(ev) => ev,
+                Err(e) => {
+                    log_message(
+                        serde_json::json!({
+                            "type": "stream_event_error",
+                            "error": format!("{}", e)
+                        })
+                        .to_string(),
+                    );
+                    let error = ProxyError::internal(format!("event error: {e}"));
+                    send_stream_error(&tx, &error, &chunk_meta).await;
+                    break;
+                }
+            };
+            if ev.id != turn.submission_id {
+                continue;
+            }
+            if !context_for_task.intercept_event(&mut ev.msg) {
+                continue;
+            }
+            // The client already has its `length` finish; wait out the
+            // interrupt, counting what the turn used, then end the stream.
+            if output_cap.reached() {
+                if let EventMsg::TokenCount(count) = &ev.msg
+                    && let Some(info) = &count.info
+                {
+                    context_for_task.add_tokens(info.last_token_usage.total_tokens);
+                    usage.add(&info.last_token_usage);
+                }
+                if output_cap::ends_turn(&ev.msg) {
+                    if include_usage {
+                        let _ = tx.send(usage_chunk(usage, &chunk_meta)).await;
+                    }
+                    let _ = tx
+                        .send(serde_json::Value::String("[DONE]".to_string()))
+                        .await;
+                    break;
+                }
+                continue;
+            }
+            if let Some(text) = agent_text.text(&ev.msg) {
+                let text = output_cap.take(&text);
+                if !text.is_empty() {
+                    streamed_text.push_str(text);
+                    if output_schema.is_some() {
+                        held_text.push_str(text);
+                    } else {
+                        let chunk = stream_chunk(Some(text), None, false, &chunk_meta);
+                        let _ = tx.send(chunk).await;
+                    }
+                }
+                if output_cap.reached() {
+                    if !held_text.is_empty() {
+                        let chunk = stream_chunk(Some(&held_text), None, false, &chunk_meta);
+                        let _ = tx.send(chunk).await;
+                    }
+                    let chunk = stream_chunk_with_finish(None, None, "length", &chunk_meta);
+                    let _ = tx.send(chunk).await;
+                    output_cap::interrupt(&turn.thread).await;
+                    continue;
+                }
+            }
+            context_for_task.record_event(&ev.msg);
+            match ev.msg {
+                EventMsg::AgentReasoningDelta(d) if include_reasoning => {
+                    let chunk = reasoning_chunk(&d.delta, &chunk_meta);
+                    let _ = tx.send(chunk).await;
+                }
+                EventMsg::RawResponseItem(raw) => {
+                    // Log reasoning items
+                    if let ResponseItem::Reasoning { id, summary, .. } = &raw.item {
+                        log_message(
+                            serde_json::json!({
+                                "type": "reasoning_item",
+                                "id": id,
+                                "summary_count": summary.len(),
+                            })
+                            .to_string(),
+                        );
+                    }
+
+                    citations.record(&raw.item);
+                    // Send tool calls to Cursor
+                    if let Some(tc) = codex_tool_call(&raw.item) {
+                        tool_seen_for_task.store(true, Ordering::Relaxed);
+                        log_message(
+                            serde_json::json!({
+                                "type": "tool_call_forwarded",
+                                "name": tc.function.name.clone()
+                            })
+                            .to_string(),
+                        );
+                        let index = tool_call_indices.index(&tc.id);
+                        let chunk = stream_chunk(None, Some((index, tc)), false, &chunk_meta);
+                        let _ = tx.send(chunk).await;
+                    }
+                }
+                EventMsg::McpToolCallBegin(begin) => {
+                    tool_seen_for_task.store(true, Ordering::Relaxed);
+                    let tc = mcp_tool_call(&begin);
+                    let index = tool_call_indices.index(&tc.id);
+                    let chunk = stream_chunk(None, Some((index, tc)), false, &chunk_meta);
+                    let _ = tx.send(chunk).await;
+                }
+                EventMsg::TurnComplete(_done) => {
+                    log_message(
+                        serde_json::json!({
+                            "type": "stream_complete"
+                        })
+                        .to_string(),
+                    );
+
+                    // ⚠️ last_agent_message already went out through agent_text
+                    // Sending it again causes "looping detected" error in Cursor
+
+                    if let Some(Err(message)) = output_schema
+                        .as_ref()
+                        .map(|schema| schema.check(&streamed_text))
+                    {
+                        if !schema_retried {
+                            schema_retried = true;
+                            approvals.clear_turn(&turn.submission_id);
+                            let retry = structured_output::correction_prompt(&message);
+                            if let Err(error) = turn.resubmit(retry).await {
+                                send_stream_error(&tx, &error, &chunk_meta).await;
+                                break;
+                            }
+                            agent_text = AgentText::default();
+                            output_cap = OutputCap::new(max_output_tokens);
+                            citations = Citations::default();
+                            streamed_text.clear();
+                            held_text.clear();
+                            continue;
+                        }
+                        // The retry did not match either: fail as a non-streamed
+                        // completion does, without the held-back answer.
+                        if include_usage {
+                            let _ = tx.send(usage_chunk(usage, &chunk_meta)).await;
+                        }
+                        let error = structured_output::validation_error(message);
+                        send_stream_error(&tx, &error, &chunk_meta).await;
+                        break;
+                    }
+                    if !held_text.is_empty() {
+                        let chunk = stream_chunk(Some(&held_text), None, false, &chunk_meta);
+                        let _ = tx.send(chunk).await;
+                    }
+                    if let Some(annotations) = citations.chat_annotations(&streamed_text) {
+                        let mut chunk = stream_chunk(None, None, false, &chunk_meta);
+                        chunk["choices"][0]["delta"]["annotations"] =
+                            serde_json::Value::Array(annotations);
+                        let _ = tx.send(chunk).await;
+                    }
+                    let finish_reason = if tool_seen_for_task.load(Ordering::Relaxed) {
+                        "tool_calls"
+                    } else {
+                        "stop"
+                    };
+                    let chunk = stream_chunk_with_finish(
+                        None, // Don't send content again
+                        None,
+                        finish_reason,
+                        &chunk_meta,
+                    );
+                    let _ = tx.send(chunk).await;
+                    if include_usage {
+                        let _ = tx.send(usage_chunk(usage, &chunk_meta)).await;
+                    }
+                    let _ = tx
+                        .send(serde_json::Value::String("[DONE]".to_string()))
+                        .await;
+                    break;
+                }
+                EventMsg::Error(err) => {
+                    log_message(
+                        serde_json::json!({
+                            "type": "stream_codex_error",
+                            "error": err.message
+                        })
+                        .to_string(),
+                    );
+                    let error = upstream_errors::turn_error(&err);
+                    send_stream_error(&tx, &error, &chunk_meta).await;
+                    break;
+                }
+                EventMsg::Warning(warn) => {
+                    info!("warning from Codex: {}", warn.message);
+                }
+                EventMsg::TokenCount(count) => {
+                    if let Some(info) = count.info {
+                        context_for_task.add_tokens(info.last_token_usage.total_tokens);
+                        usage.add(&info.last_token_usage);
+                    }
+                }
+                msg @ EventMsg::PatchApplyEnd(_) => {
+                    if let Some(event) = codex_events::patch_event(&msg) {
+                        let _ = tx.send(event.into_event()).await;
+                    }
+                }
+                msg @ EventMsg::PlanUpdate(_) => {
+                    if let Some(event) = codex_events::plan_event(&msg) {
+                        let _ = tx.send(event.into_event()).await;
+                    }
+                }
+                msg @ (EventMsg::ExecCommandBegin(_) | EventMsg::ExecCommandEnd(_)) => {
+                    if let Some(event) = codex_events::exec_event(&msg) {
+                        let _ = tx.send(event.into_event()).await;
+                    }
+                }
+                msg @ (EventMsg::ExecApprovalRequest(_)
+                | EventMsg::ApplyPatchApprovalRequest(_))
+                    if turn.dry_run =>
+                {
+                    if let Some(patch) = ProposedPatch::from_event(&msg) {
+                        let _ = tx.send(patch.event().into_event()).await;
+                    }
+                    approvals
+                        .deny_dry_run(&turn.thread, turn.thread_id, &ev.id, &msg)
+                        .await;
+                }
+                msg @ (EventMsg::ExecApprovalRequest(_)
+                | EventMsg::ApplyPatchApprovalRequest(_)) => {
+                    if let Some(request) = approvals
+                        .handle(
+                            &turn.thread,
+                            turn.thread_id,
+                            &ev.id,
+                            turn.approval_policy,
+                            &msg,
+                        )
+                        .await
+                    {
+                        let _ = tx.send(request.into_event()).await;
+                    }
+                }
+                EventMsg::TurnAborted(abort) => {
+                    let reason = codex_events::abort_reason(&abort.reason);
+                    log_message(
+                        serde_json::json!({
+                            "type": "stream_aborted",
+                            "reason": reason,
+                        })
+                        .to_string(),
+                    );
+                    let mut chunk = stream_chunk_with_finish(None, None, "stop", &chunk_meta);
+                    chunk["choices"][0]["abort_reason"] = reason.into();
+                    let _ = tx.send(chunk).await;
+                    if include_usage {
+                        let _ = tx.send(usage_chunk(usage, &chunk_meta)).await;
+                    }
+                    let _ = tx
+                        .send(serde_json::Value::String("[DONE]".to_string()))
+                        .await;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        approvals.clear_turn(&turn.submission_id);
+    };
+    tokio::spawn(task.instrument(turn_span));
+
+    let stream = ReceiverStream::new(rx).map(move |json_val| match json_val {
+        serde_json::Value::String(s) if s == "[DONE]" => {
+            log_message(
+                serde_json::json!({
+                    "type": "stream_send_done"
+                })
+                .to_string(),
+            );
+            Ok::<Event, std::convert::Infallible>(Event::default().data(s))
+        }
+        // Approval requests and progress events go out as custom named events.
+        other if other.get("type").is_some() => Ok(responses::named_event(&other)),
+        other => {
+            let data = serde_json::to_string(&other).unwrap_or_else(|_| "{}".to_string());
+            Ok::<Event, std::convert::Infallible>(Event::default().data(data))
+        }
+    });
+
+    state.sse_keep_alive.apply(Sse::new(stream)).into_response()
+}
+
+/// Loads the Codex config every proxy thread runs with for `codex_model`.
+async fn thread_config(
+    state: &AppState,
+    codex_model: String,
+    extra_overrides: Vec<(String, toml::Value)>,
+) -> Result<Config, ProxyError> {
+    let instructions = state.model_map.default_instructions(&codex_model);
+    let mut overrides = vec![
+        ("model".to_string(), toml::Value::String(codex_model)),
+        (
+            "approval_policy".to_string(),
+            toml::Value::String("never".to_string()),
+        ),
+        (
+            "sandbox_mode".to_string(),
+            toml::Value::String("read-only".to_string()), // ⚠️ ReadOnly: no tool execution
+        ),
+    ];
+    overrides.extend(extra_overrides);
+    let profile_requested = overrides.iter().any(|(key, _)| key == "profile");
+
+    let mut config = state.configs.load(overrides).await.map_err(|e| {
+        if profile_requested && e.kind() == std::io::ErrorKind::NotFound {
+            ProxyError::new(
+                StatusCode::BAD_REQUEST,
+                e.to_string(),
+                "invalid_request_error",
+            )
+            .with_code("profile_not_found")
+        } else {
+            ProxyError::internal(e.to_string())
+        }
+    })?;
+    // The operator's instructions go ahead of any from the Codex config.
+    if let Some(instructions) = instructions {
+        config.developer_instructions = Some(match config.developer_instructions.take() {
+            Some(existing) => format!("{instructions}\n\n{existing}"),
+            None => instructions,
+        });
+    }
+    Ok(config)
+}
+
+/// Continues `conversation_id`, or starts a thread. `base_instructions`
+/// replace Codex's system prompt when the thread is started or resumed; a
+/// thread that is already loaded keeps its own.
+async fn get_or_create_thread(
+    state: &AppState,
+    model: &str,
+    conversation_id: Option<String>,
+    extra_overrides: Vec<(String, toml::Value)>,
+    base_instructions: Option<&str>,
+) -> Result<(Arc<CodexThread>, ThreadId), ProxyError> {
+    let codex_model = state.model_map.resolve(model)?;
+    let parallel_requested = extra_overrides
+        .iter()
+        .any(|(key, _)| key == PARALLEL_TOOL_CALLS_OVERRIDE);
+    let mut config = thread_config(state, codex_model.clone(), extra_overrides).await?;
+    if let Some(instructions) = base_instructions {
+        config.base_instructions = Some(instructions.to_string());
+    }
+
+    if let Some(cid) = conversation_id {
+        let tid = conversations::parse_conversation_id(&cid)?;
+        if parallel_requested
+            && let Ok(stored) = state.threads.stored(tid)
+            && stored.parallel_tool_calls != config.model_supports_parallel_tool_calls
+        {
+            return Err(ProxyError::new(
+                StatusCode::BAD_REQUEST,
+                "parallel_tool_calls is fixed when a conversation starts and cannot change on later turns",
+                "invalid_request_error",
+            )
+            .with_code("unsupported_parameter"));
+        }
+        let thread = state.threads.get_or_resume(tid, config).await?;
+        return Ok((thread, tid));
+    }
+
+    let parallel_tool_calls = config.model_supports_parallel_tool_calls;
+    let new_thread = state
+        .thread_manager
+        .start_thread(config)
+        .await
+        .map_err(|e| ProxyError::internal(e.to_string()))?;
+    state.threads.insert(
+        new_thread.thread_id,
+        new_thread.thread.clone(),
+        codex_model,
+        parallel_tool_calls,
+    );
+    Ok((new_thread.thread, new_thread.thread_id))
+}
+
+/// Header carrying a request's `base_instructions`, for clients that cannot
+/// add body fields. Header values are single lines; multi-line prompts go
+/// in the body.
+pub(crate) const BASE_INSTRUCTIONS_HEADER: &str = "x-codex-base-instructions";
+
+/// Header naming the Codex config profile (`[profiles.<name>]`) a request
+/// runs with, so one proxy can offer presets such as "fast" or "thorough".
+pub(crate) const PROFILE_HEADER: &str = "x-codex-profile";
+
+/// Reads [`BASE_INSTRUCTIONS_HEADER`]; a blank value counts as absent.
+fn base_instructions_header(headers: &HeaderMap) -> Result<Option<String>, ProxyError> {
+    text_header(headers, BASE_INSTRUCTIONS_HEADER)
+}
+
+/// Reads [`PROFILE_HEADER`]; a blank value counts as absent.
+fn profile_header(headers: &HeaderMap) -> Result<Option<String>, ProxyError> {
+    text_header(headers, PROFILE_HEADER)
+}
+
+fn text_header(headers: &HeaderMap, name: &str) -> Result<Option<String>, ProxyError> {
+    let Some(value) = headers.get(name) else {
+        return Ok(None);
+    };
+    let value = String::from_utf8(value.as_bytes().to_vec()).map_err(|_| {
+        ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            format!("{name} must be UTF-8"),
+            "invalid_request_error",
+        )
+    })?;
+    Ok(Some(value.trim().to_string()).filter(|value| !value.is_empty()))
+}
+
+/// Config key a request's `parallel_tool_calls` is passed to Codex under.
+const PARALLEL_TOOL_CALLS_OVERRIDE: &str = "model_supports_parallel_tool_calls";
+
+/// The config override selecting `profile`, if one was asked for.
+fn profile_override(profile: Option<&str>) -> Option<(String, toml::Value)> {
+    profile.map(|profile| {
+        (
+            "profile".to_string(),
+            toml::Value::String(profile.to_string()),
+        )
+    })
+}
+
+fn stream_chunk(
+    content: Option<&str>,
+    tool_call: Option<(usize, ToolCall)>,
+    _done: bool,
+    meta: &ChunkMeta,
+) -> serde_json::Value {
+    let mut delta = serde_json::Map::new();
+    if let Some(text) = content {
+        delta.insert(
+            "content".to_string(),
+            serde_json::Value::String(text.to_string()),
+        );
+    }
+    if let Some((index, tc)) = tool_call {
+        delta.insert("tool_calls".to_string(), tool_call_delta(index, tc));
+    }
+
+    serde_json::json!({
+        "id": meta.id,
+        "object": "chat.completion.chunk",
+        "created": meta.created,
+        "model": meta.model,  // ⚠️ Always include model field
+        "system_fingerprint": meta.system_fingerprint,
+        "choices": [{
+            "index": 0,
+            "delta": delta,
+            "finish_reason": null,
+        }],
+    })
+}
+
+/// The `id`, `created`, `model` and `system_fingerprint` every chunk of one
+/// chat completion stream shares.
+#[derive(Debug, Clone)]
+struct ChunkMeta {
+    id: String,
+    created: u64,
+    model: String,
+    system_fingerprint: String,
+    /// The request's `seed`, echoed on the finish chunk.
+    seed: Option<i64>,
+}
+
+impl ChunkMeta {
+    /// Chunks for a request naming `model`, which runs `codex_model`.
+    fn new(model: &str, codex_model: &str, seed: Option<i64>) -> Self {
+        Self {
+            id: format!("chatcmpl-codex-{}", uuid::Uuid::new_v4()),
+            created: now_ts(),
+            model: model.to_string(),
+            system_fingerprint: system_fingerprint(codex_model),
+            seed,
+        }
+    }
+}
+
+/// Identifies the backend configuration behind a client-facing model: the
+/// Codex model it maps to and the proxy version. Stable until either
+/// changes, like OpenAI's.
+fn system_fingerprint(codex_model: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.update([0]);
+    hasher.update(codex_model.as_bytes());
+    let digest = format!("{:x}", hasher.finalize());
+    format!("fp_{}", &digest[..10])
+}
+
+/// The `index` of each tool call in one streamed choice: 0 for the first
+/// call id seen, counting up for each new one, as clients assemble deltas by
+/// index.
+#[derive(Debug, Default)]
+struct ToolCallIndices {
+    ids: Vec<String>,
+}
+
+impl ToolCallIndices {
+    fn index(&mut self, id: &str) -> usize {
+        match self.ids.iter().position(|known| known == id) {
+            Some(index) => index,
+            None => {
+                self.ids.push(id.to_string());
+                self.ids.len() - 1
+            }
+        }
+    }
+}
+
+/// A chunk's `tool_calls` delta carrying the whole of `tc` at `index`, its
+/// place among the choice's tool calls; see [`ToolCallIndices`]. The name
+/// and arguments arrive in one delta since Codex reports finished calls.
+fn tool_call_delta(index: usize, tc: ToolCall) -> serde_json::Value {
+    let mut call = serde_json::json!({
+        "index": index,
+        "id": tc.id,
+        "type": tc.kind,
+        "function": {
+            "name": tc.function.name,
+            "arguments": tc.function.arguments,
+        }
+    });
+    if let Some(server) = tc.server {
+        call["server"] = serde_json::Value::String(server);
+    }
+    serde_json::json!([call])
+}
+
+/// The last chunk of a failed stream: no choices, and an `error` object OpenAI
+/// SDKs raise as an API error.
+fn error_chunk(error: &ProxyError, meta: &ChunkMeta) -> serde_json::Value {
+    serde_json::json!({
+        "id": meta.id,
+        "object": "chat.completion.chunk",
+        "created": meta.created,
+        "model": meta.model,
+        "system_fingerprint": meta.system_fingerprint,
+        "choices": [],
+        "error": error.error_object(),
+    })
+}
+
+/// Ends a chat completion stream with `error`, then `[DONE]`.
+async fn send_stream_error(
+    tx: &StreamSender<serde_json::Value>,
+    error: &ProxyError,
+    meta: &ChunkMeta,
+) {
+    log_message(
+        serde_json::json!({
+            "type": "stream_send_error",
+            "error": error.message,
+        })
+        .to_string(),
+    );
+    let _ = tx.send(error_chunk(error, meta)).await;
+    let _ = tx
+        .send(serde_json::Value::String("[DONE]".to_string()))
+        .await;
+}
+
+/// The chunk every chat completion stream opens with, announcing the
+/// assistant role before any content.
+fn role_chunk(meta: &ChunkMeta) -> serde_json::Value {
+    let mut chunk = stream_chunk(Some(""), None, false, meta);
+    chunk["choices"][0]["delta"]["role"] = serde_json::Value::String("assistant".to_string());
+    chunk
+}
+
+/// A chunk carrying reasoning text in the `reasoning_content` delta field.
+fn reasoning_chunk(reasoning: &str, meta: &ChunkMeta) -> serde_json::Value {
+    let mut chunk = stream_chunk(None, None, false, meta);
+    chunk["choices"][0]["delta"]["reasoning_content"] =
+        serde_json::Value::String(reasoning.to_string());
+    chunk
+}
+
+fn stream_chunk_with_finish(
+    content: Option<&str>,
+    tool_call: Option<(usize, ToolCall)>,
+    finish_reason: &str,
+    meta: &ChunkMeta,
+) -> serde_json::Value {
+    let mut delta = serde_json::Map::new();
+    if let Some(text) = content {
+        delta.insert(
+            "content".to_string(),
+            serde_json::Value::String(text.to_string()),
+        );
+    }
+    if let Some((index, tc)) = tool_call {
+        delta.insert("tool_calls".to_string(), tool_call_delta(index, tc));
+    }
+
+    let mut chunk = serde_json::json!({
+        "id": meta.id,
+        "object": "chat.completion.chunk",
+        "created": meta.created,
+        "model": meta.model,  // ⚠️ Always include model field
+        "system_fingerprint": meta.system_fingerprint,
+        "choices": [{
+            "index": 0,
+            "delta": delta,
+            "finish_reason": finish_reason,
+        }],
+    });
+    if let Some(seed) = meta.seed {
+        chunk["seed"] = seed.into();
+    }
+    chunk
+}
+
+/// The last chunk of a stream asked for with `include_usage`: no choices,
+/// only the token usage.
+fn usage_chunk(usage: TurnUsage, meta: &ChunkMeta) -> serde_json::Value {
+    serde_json::json!({
+        "id": meta.id,
+        "object": "chat.completion.chunk",
+        "created": meta.created,
+        "model": meta.model,
+        "system_fingerprint": meta.system_fingerprint,
+        "choices": [],
+        "usage": Usage::from(usage),
+    })
+}
+
+/// The chat `tool_calls` entry for a tool call item. MCP tools already
+/// arrive as function calls named `mcp__<server>__<tool>`; local shell calls
+/// are shown as a `shell` function with the `shell` tool's arguments.
+fn map_tool_call(item: &ResponseItem) -> Option<ToolCall> {
+    match item {
+        ResponseItem::FunctionCall {
+            call_id,
+            name,
+            arguments,
+            ..
+        } => Some(ToolCall {
+            id: call_id.clone(),
+            kind: "function".to_string(),
+            function: ToolFunction {
+                name: name.clone(),
+                arguments: arguments.clone(),
+            },
+            server: None,
+        }),
+        ResponseItem::CustomToolCall {
+            call_id,
+            name,
+            input,
+            ..
+        } => Some(ToolCall {
+            id: call_id.clone(),
+            kind: "function".to_string(),
+            function: ToolFunction {
+                name: name.clone(),
+                arguments: input.clone(),
+            },
+            server: None,
+        }),
+        ResponseItem::LocalShellCall {
+            id,
+            call_id,
+            action: LocalShellAction::Exec(exec),
+            ..
+        } => Some(ToolCall {
+            id: call_id
+                .clone()
+                .or_else(|| id.clone())
+                .unwrap_or_else(|| format!("call_{}", uuid::Uuid::new_v4().simple())),
+            kind: "function".to_string(),
+            function: ToolFunction {
+                name: "shell".to_string(),
+                arguments: serde_json::json!({
+                    "command": exec.command,
+                    "workdir": exec.working_directory,
+                    "timeout_ms": exec.timeout_ms,
+                })
+                .to_string(),
+            },
+            server: None,
+        }),
+        _ => None,
+    }
+}
+
+/// A tool call Codex made, except calls to MCP tools, which
+/// [`mcp_tool_call`] reports once Codex says which server they went to.
+fn codex_tool_call(item: &ResponseItem) -> Option<ToolCall> {
+    map_tool_call(item).filter(|tc| !codex_events::is_mcp_tool(&tc.function.name))
+}
+
+/// A call Codex made to a tool of a configured MCP server.
+fn mcp_tool_call(begin: &McpToolCallBeginEvent) -> ToolCall {
+    ToolCall {
+        id: begin.call_id.clone(),
+        kind: "function".to_string(),
+        function: ToolFunction {
+            name: begin.invocation.tool.clone(),
+            arguments: codex_events::mcp_arguments(&begin.invocation),
+        },
+        server: Some(begin.invocation.server.clone()),
+    }
+}
+
+/// The model names `/v1/models` lists; see [`ModelMapping::advertised`].
+async fn advertised_models(
+    thread_manager: &ThreadManager,
+    config: &Config,
+    model_map: &ModelMapping,
+) -> Vec<String> {
+    let mut codex_models = thread_manager
+        .list_models(config)
+        .await
+        .into_iter()
+        .map(|preset| preset.model)
+        .collect::<Vec<_>>();
+    if let Some(model) = &config.model
+        && !codex_models.contains(model)
+    {
+        codex_models.push(model.clone());
+    }
+    model_map.advertised(&codex_models)
+}
+
+/// Models Codex can run with `config`: its model presets and the configured
+/// model.
+async fn known_models(thread_manager: &ThreadManager, config: &Config) -> HashSet<String> {
+    let mut known = thread_manager
+        .list_models(config)
+        .await
+        .into_iter()
+        .map(|preset| preset.model)
+        .collect::<HashSet<_>>();
+    known.extend(config.model.clone());
+    known
+}
+
+fn merge_messages(msgs: &[ChatMessage]) -> Option<String> {
+    let mut parts = Vec::new();
+    for m in msgs {
+        let content = match &m.content {
+            serde_json::Value::String(s) => s.clone(),
+            serde_json::Value::Array(arr) => arr
+                .iter()
+                .filter_map(|v| v.get("text").or_else(|| v.get("content")))
+                .filter_map(|v| v.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => String::new(),
+        };
+        if content.trim().is_empty() {
+            continue;
+        }
+        parts.push(format!("{}: {}", m.role, content));
+    }
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("\n"))
+    }
+}
+
+fn merged_text_from_request(body: &ChatCompletionRequest) -> Option<String> {
+    if let Some(msgs) = &body.messages {
+        return merge_messages(msgs);
+    }
+    None
+}
+
+fn image_urls_from_request(body: &ChatCompletionRequest) -> Vec<String> {
+    body.messages
+        .iter()
+        .flatten()
+        .flat_map(|m| images::collect_image_urls(&m.content))
+        .collect()
+}
+
+fn attachments_from_request(body: &ChatCompletionRequest) -> Vec<attachments::Attachment> {
+    body.messages
+        .iter()
+        .flatten()
+        .flat_map(|m| attachments::collect_attachments(&m.content))
+        .collect()
+}
+
+fn now_ts() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn error_response(status: StatusCode, msg: String, kind: &str) -> Response {
+    json_response(
+        status,
+        serde_json::json!({
+            "error": {
+                "message": msg,
+                "type": kind,
+            }
+        })
+        .to_string(),
+    )
+}
+
+/// An error surfaced to clients as an OpenAI-style error body.
+#[derive(Debug)]
+struct ProxyError {
+    status: StatusCode,
+    message: String,
+    kind: &'static str,
+    code: Option<&'static str>,
+    /// Seconds for a `Retry-After` header.
+    retry_after: Option<u64>,
+}
+
+impl ProxyError {
+    fn new(status: StatusCode, message: impl Into<String>, kind: &'static str) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            kind,
+            code: None,
+            retry_after: None,
+        }
+    }
+
+    fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message, "internal_error")
+    }
+
+    fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    fn with_retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+
+    /// The OpenAI `error` object for this error.
+    fn error_object(&self) -> serde_json::Value {
+        serde_json::json!({
+            "message": self.message,
+            "type": self.kind,
+            "code": self.code,
+        })
+    }
+}
+
+impl std::fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl IntoResponse for ProxyError {
+    fn into_response(self) -> Response {
+        let mut response = json_response(
+            self.status,
+            serde_json::json!({ "error": self.error_object() }).to_string(),
+        );
+        if let Some(secs) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+fn json_response(status: StatusCode, body: String) -> Response {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(axum::body::Body::from(body))
+        .unwrap_or_else(|_| {
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(axum::body::Body::from("internal error"))
+                .unwrap()
+        })
+}
+
+// Helper function to log messages
+fn log_message(msg: String) {
+    let _ = LOG_CHANNEL.send(redaction::redact_log(msg));
+}
+
+// Redirect to logs.html
+async fn handle_logs_redirect() -> Response {
+    Response::builder()
+        .status(StatusCode::MOVED_PERMANENTLY)
+        .header("Location", "/static/logs.html")
+        .body(axum::body::Body::empty())
+        .unwrap()
+}
+
+// SSE stream endpoint for logs
+async fn handle_logs_stream()
+-> Sse<impl futures::Stream<Item = Result<Event, std::convert::Infallible>>> {
+    let rx = LOG_CHANNEL.subscribe();
+    let stream = BroadcastStream::new(rx).map(|msg: Result<String, BroadcastStreamRecvError>| {
+        let data = msg.unwrap_or_else(|_| "{}".to_string());
+        Ok::<Event, std::convert::Infallible>(Event::default().data(data))
+    });
+
+    Sse::new(stream).keep_alive(axum::response::sse::KeepAlive::default())
+}
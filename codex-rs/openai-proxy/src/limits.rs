@@ -0,0 +1,96 @@
+//! The proxy-wide limits a config reload puts in force while the proxy runs:
+//! rate limits, turn slots, the output cap and the request body limit. Turns
+//! already running keep the output cap they started with; everything else in
+//! `proxy.toml` is read once at startup.
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+use anyhow::Context;
+
+use crate::AppState;
+use crate::DEFAULT_MAX_REQUEST_BODY_BYTES;
+use crate::app_state::env_limit;
+use crate::proxy_config;
+
+/// The reloadable limits, as read from `CODEX_OPENAI_PROXY_*` and
+/// `proxy.toml`'s `[limits]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Limits {
+    pub(crate) rate_limit_rpm: Option<u64>,
+    pub(crate) rate_limit_tpm: Option<u64>,
+    pub(crate) max_concurrent_turns: Option<usize>,
+    pub(crate) turn_queue_size: usize,
+    pub(crate) max_output_tokens: Option<u64>,
+    pub(crate) max_request_body_bytes: Option<usize>,
+}
+
+impl Limits {
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        // With a turn limit, how many requests may wait (by x-priority) for a
+        // slot instead of failing; 0 (the default) sheds load immediately.
+        let turn_queue_size = match proxy_config::var("CODEX_OPENAI_PROXY_TURN_QUEUE_SIZE") {
+            Ok(value) => value
+                .parse::<usize>()
+                .context("parse CODEX_OPENAI_PROXY_TURN_QUEUE_SIZE")?,
+            Err(_) => 0,
+        };
+        // Largest request body outside batches; 0 lifts the limit.
+        let max_request_body_bytes =
+            match proxy_config::var_os("CODEX_OPENAI_PROXY_MAX_REQUEST_BODY_BYTES") {
+                Some(_) => {
+                    env_limit("CODEX_OPENAI_PROXY_MAX_REQUEST_BODY_BYTES")?.map(|n| n as usize)
+                }
+                None => Some(DEFAULT_MAX_REQUEST_BODY_BYTES),
+            };
+        Ok(Self {
+            // Per-client requests and tokens per minute; unset or 0 disables
+            // a limit.
+            rate_limit_rpm: env_limit("CODEX_OPENAI_PROXY_RATE_LIMIT_RPM")?,
+            rate_limit_tpm: env_limit("CODEX_OPENAI_PROXY_RATE_LIMIT_TPM")?,
+            // 0 (the default) leaves the number of simultaneous turns
+            // unlimited.
+            max_concurrent_turns: env_limit("CODEX_OPENAI_PROXY_MAX_CONCURRENT_TURNS")?
+                .map(|n| n as usize),
+            turn_queue_size,
+            // Caps every answer, on top of what requests ask for; unset or 0
+            // leaves answers uncapped.
+            max_output_tokens: env_limit("CODEX_OPENAI_PROXY_MAX_OUTPUT_TOKENS")?,
+            max_request_body_bytes,
+        })
+    }
+
+    /// Puts these limits in force for `state` and the tenants sharing them.
+    /// Tenants with rate limits of their own keep those.
+    pub(crate) fn apply(&self, state: &AppState) {
+        state
+            .rate_limiter
+            .set_limits(self.rate_limit_rpm, self.rate_limit_tpm);
+        state
+            .turn_slots
+            .resize(self.max_concurrent_turns, self.turn_queue_size);
+        state.max_output_tokens.set(self.max_output_tokens);
+        state
+            .max_request_body_bytes
+            .set(self.max_request_body_bytes.map(|n| n as u64));
+    }
+}
+
+/// A limit read on every use, so a reload applies from the next request.
+#[derive(Debug, Default)]
+pub(crate) struct LiveLimit(AtomicU64);
+
+impl LiveLimit {
+    /// `None` (or 0) is unlimited.
+    pub(crate) fn new(limit: Option<u64>) -> Self {
+        Self(AtomicU64::new(limit.unwrap_or(0)))
+    }
+
+    pub(crate) fn get(&self) -> Option<u64> {
+        Some(self.0.load(Ordering::Relaxed)).filter(|limit| *limit > 0)
+    }
+
+    pub(crate) fn set(&self, limit: Option<u64>) {
+        self.0.store(limit.unwrap_or(0), Ordering::Relaxed);
+    }
+}
@@ -1,879 +1,7 @@
-use std::env;
-use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering;
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
-
-use anyhow::Context;
-use axum::Router;
-use axum::extract::State;
-use axum::http::StatusCode;
-use axum::http::header::CONTENT_TYPE;
-use axum::response::IntoResponse;
-use axum::response::Response;
-use axum::response::sse::Event;
-use axum::response::sse::Sse;
-use axum::routing::{get, post};
-use codex_core::CodexThread;
-use codex_core::ThreadManager;
-use codex_core::auth::AuthManager;
-use codex_core::config::Config;
-use codex_core::protocol::AskForApproval;
-use codex_core::protocol::Op;
-use codex_core::protocol::SandboxPolicy;
-use codex_core::protocol::Submission;
-use codex_protocol::config_types::ReasoningSummary;
-use codex_protocol::models::ResponseItem;
-use codex_protocol::protocol::EventMsg;
-use codex_protocol::protocol::SessionSource;
-use codex_protocol::user_input::UserInput;
-use serde::Deserialize;
-use serde::Serialize;
-use tokio::sync::broadcast;
-use tokio::sync::Mutex;
-use tokio::sync::mpsc;
-use tokio_stream::StreamExt;
-use tokio_stream::wrappers::ReceiverStream;
-use tokio_stream::wrappers::BroadcastStream;
-use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
-use tower_http::cors::{Any, CorsLayer};
-use tower_http::services::ServeDir;
-use tower_http::trace::TraceLayer;
-use tracing::info;
-use tracing_subscriber::EnvFilter;
-
-// Global log broadcast channel
-static LOG_CHANNEL: once_cell::sync::Lazy<broadcast::Sender<String>> =
-    once_cell::sync::Lazy::new(|| {
-        let (tx, _rx) = broadcast::channel(1000);
-        tx
-    });
-
-#[derive(Clone)]
-struct AppState {
-    thread_manager: Arc<ThreadManager>,
-}
-
-#[derive(Debug, Deserialize)]
-struct ChatMessage {
-    role: String,
-    content: serde_json::Value,
-}
-
-#[derive(Debug, Deserialize)]
-struct ChatCompletionRequest {
-    model: String,
-    #[serde(default)]
-    messages: Option<Vec<ChatMessage>>,
-    #[serde(default)]
-    stream: bool,
-    #[serde(default)]
-    conversation_id: Option<String>,
-}
-
-#[derive(Debug, Serialize)]
-struct ChatCompletionResponse {
-    id: String,
-    object: String,
-    created: u64,
-    model: String,
-    choices: Vec<ChatChoice>,
-    usage: Usage,
-}
-
-#[derive(Debug, Serialize)]
-struct Usage {
-    prompt_tokens: u32,
-    completion_tokens: u32,
-    total_tokens: u32,
-}
-
-#[derive(Debug, Serialize)]
-struct ChatChoice {
-    index: usize,
-    message: ChatMessageResponse,
-    finish_reason: String,
-}
-
-#[derive(Debug, Serialize)]
-struct ChatMessageResponse {
-    role: String,
-    content: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    tool_calls: Option<Vec<ToolCall>>,
-}
-
-#[derive(Debug, Serialize, Clone)]
-struct ToolCall {
-    id: String,
-    #[serde(rename = "type")]
-    kind: String,
-    function: ToolFunction,
-}
-
-#[derive(Debug, Serialize, Clone)]
-struct ToolFunction {
-    name: String,
-    arguments: String,
-}
+use clap::Parser;
+use codex_openai_proxy::Cli;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .with_target(false)
-        .init();
-
-    let config = Config::load_with_cli_overrides(vec![])
-        .await
-        .context("load config")?;
-
-    let auth_manager = Arc::new(AuthManager::new(
-        config.codex_home.clone(),
-        false,
-        config.cli_auth_credentials_store_mode,
-    ));
-
-    let thread_manager = Arc::new(ThreadManager::new(
-        config.codex_home.clone(),
-        auth_manager,
-        SessionSource::Exec,
-    ));
-
-    let state = AppState { thread_manager };
-
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
-
-    let static_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("static");
-    info!("Static files directory: {:?}", static_dir);
-
-    let router = Router::new()
-        // With /v1 prefix (OpenAI standard)
-        .route("/v1/models", get(handle_models))
-        .route("/v1/chat/completions", post(handle_chat_completions))
-        // Without /v1 prefix (Cursor compatibility)
-        .route("/models", get(handle_models))
-        .route("/chat/completions", post(handle_chat_completions))
-        // Log viewer routes
-        .route("/logs", get(handle_logs_redirect))
-        .route("/logs/stream", get(handle_logs_stream))
-        .route("/logs.html", get(|| async {
-            axum::response::Redirect::permanent("/static/logs.html")
-        }))
-        .route("/logs.css", get(|| async {
-            axum::response::Redirect::permanent("/static/logs.css")
-        }))
-        .route("/logs.js", get(|| async {
-            axum::response::Redirect::permanent("/static/logs.js")
-        }))
-        // Static files
-        .nest_service("/static", ServeDir::new(&static_dir))
-        .with_state(state)
-        .layer(cors)
-        .layer(TraceLayer::new_for_http());
-
-    let addr: SocketAddr = env::var("CODEX_OPENAI_PROXY_ADDR")
-        .unwrap_or_else(|_| "127.0.0.1:11435".to_string())
-        .parse()
-        .context("parse CODEX_OPENAI_PROXY_ADDR")?;
-
-    info!("codex-openai-proxy (pure forwarding mode) listening on http://{addr}");
-    info!("Web logs available at http://{addr}/logs");
-
-    // Send initial log message
-    log_message(serde_json::json!({
-        "type": "info",
-        "message": format!("Proxy started in PURE FORWARDING mode (no tool execution) on {}", addr)
-    }).to_string());
-
-    axum::serve(
-        tokio::net::TcpListener::bind(addr)
-            .await
-            .context("bind listener")?,
-        router,
-    )
-    .await
-    .context("run server")?;
-
-    Ok(())
-}
-
-async fn handle_chat_completions(
-    State(state): State<AppState>,
-    body: axum::Json<ChatCompletionRequest>,
-) -> Response {
-    // Log ALL incoming chat completion requests
-    log_message(serde_json::json!({
-        "type": "incoming_request",
-        "endpoint": "/chat/completions",
-        "model": body.model,
-        "stream": body.stream
-    }).to_string());
-
-    if body.stream {
-        return handle_stream(state, body.0).await;
-    }
-    handle_once(state, body.0).await
-}
-
-async fn handle_models() -> Response {
-    log_message(serde_json::json!({
-        "type": "incoming_request",
-        "endpoint": "/models"
-    }).to_string());
-
-    // Return reversed model names for Cursor
-    // Codex models: gpt-5.2-codex, gpt-5.1-codex-max, gpt-5.1-codex-mini, gpt-5.2
-    // Reversed: xedoc-2.5-tpg, xam-xedoc-1.5-tpg, inim-xedoc-1.5-tpg, 2.5-tpg
-    let models = serde_json::json!({
-        "object": "list",
-        "data": [
-            {"id": "xedoc-2.5-tpg", "object": "model", "owned_by": "codex"},
-            {"id": "xam-xedoc-1.5-tpg", "object": "model", "owned_by": "codex"},
-            {"id": "inim-xedoc-1.5-tpg", "object": "model", "owned_by": "codex"},
-            {"id": "2.5-tpg", "object": "model", "owned_by": "codex"},
-        ]
-    });
-    json_response(StatusCode::OK, models.to_string())
-}
-
-async fn handle_once(state: AppState, body: ChatCompletionRequest) -> Response {
-    let original_model = body.model.clone();
-
-    log_message(serde_json::json!({
-        "type": "cursor_request",
-        "message": format!("Request: model={}, stream={}", body.model, body.stream)
-    }).to_string());
-
-    let merged_text = match merged_text_from_request(&body) {
-        Some(text) => text,
-        None => {
-            return error_response(
-                StatusCode::BAD_REQUEST,
-                "no user content found".to_string(),
-                "invalid_request_error",
-            );
-        }
-    };
-
-    let model = map_model(&body.model);
-
-    log_message(serde_json::json!({
-        "type": "codex_forward",
-        "message": format!("Forward to Codex: original_model={}, mapped_model={}, conv_id={}",
-            body.model, model, body.conversation_id.as_deref().unwrap_or("new"))
-    }).to_string());
-
-    let (thread, _conv_id) = match get_or_create_thread(&state, &model, body.conversation_id)
-        .await
-    {
-        Ok(t) => t,
-        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e, "internal_error"),
-    };
-
-    let submission_id = uuid::Uuid::new_v4().to_string();
-    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
-    let payload_text = merged_text.clone();
-    let tool_calls = Arc::new(Mutex::new(Vec::<ToolCall>::new()));
-    let tool_calls_for_task = tool_calls.clone();
-
-    let handle = tokio::spawn(async move {
-        let submission = Submission {
-            id: submission_id.clone(),
-            op: Op::UserTurn {
-                items: vec![UserInput::Text {
-                    text: payload_text.clone(),
-                }],
-                cwd,
-                approval_policy: AskForApproval::Never,
-                sandbox_policy: SandboxPolicy::ReadOnly,  // ⚠️ ReadOnly: Codex won't execute tools
-                model: model.clone(),
-                effort: None,
-                summary: ReasoningSummary::Detailed,
-                final_output_json_schema: None,
-            },
-        };
-
-        thread
-            .submit_with_id(submission)
-            .await
-            .map_err(|e| format!("submit error: {e}"))?;
-
-        let mut final_text = String::new();
-        loop {
-            let ev = thread
-                .next_event()
-                .await
-                .map_err(|e| format!("event error: {e}"))?;
-            if ev.id != submission_id {
-                continue;
-            }
-            match ev.msg {
-                EventMsg::AgentMessage(m) => {
-                    final_text.push_str(&m.message);
-                    final_text.push('\n');
-                }
-                EventMsg::AgentMessageDelta(d) => final_text.push_str(&d.delta),
-                EventMsg::RawResponseItem(raw) => {
-                    // Log reasoning
-                    if let ResponseItem::Reasoning { id, summary, .. } = &raw.item {
-                        log_message(serde_json::json!({
-                            "type": "reasoning_detected",
-                            "id": id,
-                            "summary_count": summary.len(),
-                        }).to_string());
-                    }
-
-                    // Collect tool calls
-                    if let Some(tc) = map_tool_call(&raw.item) {
-                        tool_calls_for_task.lock().await.push(tc);
-                    }
-                }
-                EventMsg::TurnComplete(done) => {
-                    if let Some(msg) = done.last_agent_message {
-                        final_text = msg;
-                    }
-                    break;
-                }
-                EventMsg::Error(err) => return Err(format!("Codex error: {}", err.message)),
-                EventMsg::Warning(warn) => {
-                    info!("warning from Codex: {}", warn.message);
-                }
-                EventMsg::TurnAborted(abort) => {
-                    return Err(format!("Turn aborted: {:?}", abort.reason));
-                }
-                _ => {}
-            }
-        }
-
-        Ok(final_text)
-    });
-
-    let final_text = match handle.await {
-        Ok(Ok(text)) => text.trim().to_string(),
-        Ok(Err(e)) => {
-            return error_response(StatusCode::INTERNAL_SERVER_ERROR, e, "internal_error");
-        }
-        Err(join_err) => {
-            return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                join_err.to_string(),
-                "internal_error",
-            );
-        }
-    };
-
-    let tool_calls_snapshot = {
-        let guard = tool_calls.lock().await;
-        guard.clone()
-    };
-
-    let resp = ChatCompletionResponse {
-        id: format!("chatcmpl-codex-{}", uuid::Uuid::new_v4()),
-        object: "chat.completion".to_string(),
-        created: now_ts(),
-        model: original_model.clone(),  // ⚠️ Use original model name
-        choices: vec![ChatChoice {
-            index: 0,
-            message: ChatMessageResponse {
-                role: "assistant".to_string(),
-                content: final_text.clone(),
-                tool_calls: if tool_calls_snapshot.is_empty() {
-                    None
-                } else {
-                    Some(tool_calls_snapshot.clone())
-                },
-            },
-            finish_reason: if !tool_calls_snapshot.is_empty() {
-                "tool_calls".to_string()
-            } else {
-                "stop".to_string()
-            },
-        }],
-        usage: Usage {
-            prompt_tokens: 0,
-            completion_tokens: 0,
-            total_tokens: 0,
-        },
-    };
-
-    // Log response to Cursor
-    log_message(serde_json::json!({
-        "type": "cursor_response",
-        "message": format!("Response to Cursor: model={}, finish_reason={}",
-            original_model, resp.choices[0].finish_reason)
-    }).to_string());
-
-    let body = serde_json::to_string(&resp).unwrap_or_else(|_| "{}".to_string());
-    json_response(StatusCode::OK, body)
-}
-
-async fn handle_stream(state: AppState, body: ChatCompletionRequest) -> Response {
-    log_message(serde_json::json!({
-        "type": "stream_start",
-        "model": body.model,
-    }).to_string());
-
-    let original_model = body.model.clone();
-
-    let merged_text = match merged_text_from_request(&body) {
-        Some(text) => text,
-        None => {
-            log_message(serde_json::json!({
-                "type": "stream_error",
-                "error": "no user content found"
-            }).to_string());
-            return error_response(
-                StatusCode::BAD_REQUEST,
-                "no user content found".to_string(),
-                "invalid_request_error",
-            );
-        }
-    };
-
-    let (thread, _conv_id) = match get_or_create_thread(&state, &body.model, body.conversation_id)
-        .await
-    {
-        Ok(t) => {
-            log_message(serde_json::json!({
-                "type": "stream_thread_created",
-                "model": body.model
-            }).to_string());
-            t
-        }
-        Err(e) => {
-            log_message(serde_json::json!({
-                "type": "stream_error",
-                "error": format!("failed to create thread: {}", e)
-            }).to_string());
-            return error_response(StatusCode::INTERNAL_SERVER_ERROR, e, "internal_error");
-        }
-    };
-
-    let submission_id = uuid::Uuid::new_v4().to_string();
-    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
-    let payload_text = merged_text.clone();
-    let model = map_model(&body.model);
-    let tool_seen = Arc::new(AtomicBool::new(false));
-    let tool_seen_for_task = tool_seen.clone();
-    let model_for_response = original_model.clone();
-
-    let (tx, rx) = mpsc::channel(16);
-
-    tokio::spawn(async move {
-        let submission = Submission {
-            id: submission_id.clone(),
-            op: Op::UserTurn {
-                items: vec![UserInput::Text {
-                    text: payload_text.clone(),
-                }],
-                cwd,
-                approval_policy: AskForApproval::Never,
-                sandbox_policy: SandboxPolicy::ReadOnly,  // ⚠️ ReadOnly: Codex won't execute tools
-                model: model.clone(),
-                effort: None,
-                summary: ReasoningSummary::Detailed,
-                final_output_json_schema: None,
-            },
-        };
-
-        if let Err(e) = thread.submit_with_id(submission).await {
-            log_message(serde_json::json!({
-                "type": "stream_submit_error",
-                "error": format!("{}", e)
-            }).to_string());
-            let _ = tx.send(Err(format!("submit error: {e}"))).await;
-            return;
-        }
-
-        log_message(serde_json::json!({
-            "type": "stream_submitted",
-            "submission_id": submission_id
-        }).to_string());
-
-        loop {
-            let ev = match thread.next_event().await {
-                Ok(ev) => ev,
-                Err(e) => {
-                    log_message(serde_json::json!({
-                        "type": "stream_event_error",
-                        "error": format!("{}", e)
-                    }).to_string());
-                    let _ = tx.send(Err(format!("event error: {e}"))).await;
-                    return;
-                }
-            };
-            if ev.id != submission_id {
-                continue;
-            }
-            match ev.msg {
-                EventMsg::AgentMessage(m) => {
-                    let chunk =
-                        stream_chunk(Some(&m.message), None, false, &model_for_response);
-                    let _ = tx.send(Ok(chunk)).await;
-                }
-                EventMsg::AgentMessageDelta(d) => {
-                    let chunk =
-                        stream_chunk(Some(&d.delta), None, false, &model_for_response);
-                    let _ = tx.send(Ok(chunk)).await;
-                }
-                EventMsg::RawResponseItem(raw) => {
-                    // Log reasoning items
-                    if let ResponseItem::Reasoning { id, summary, .. } = &raw.item {
-                        log_message(serde_json::json!({
-                            "type": "reasoning_item",
-                            "id": id,
-                            "summary_count": summary.len(),
-                        }).to_string());
-                    }
-
-                    // Send tool calls to Cursor
-                    if let Some(tc) = map_tool_call(&raw.item) {
-                        tool_seen_for_task.store(true, Ordering::Relaxed);
-                        log_message(serde_json::json!({
-                            "type": "tool_call_forwarded",
-                            "name": tc.function.name.clone()
-                        }).to_string());
-                        let chunk =
-                            stream_chunk(None, Some(tc), false, &model_for_response);
-                        let _ = tx.send(Ok(chunk)).await;
-                    }
-                }
-                EventMsg::TurnComplete(_done) => {
-                    log_message(serde_json::json!({
-                        "type": "stream_complete"
-                    }).to_string());
-
-                    // ⚠️ Don't send last_agent_message here - we already sent it via AgentMessageDelta
-                    // Sending it again causes "looping detected" error in Cursor
-
-                    let finish_reason = if tool_seen_for_task.load(Ordering::Relaxed) {
-                        "tool_calls"
-                    } else {
-                        "stop"
-                    };
-                    let chunk = stream_chunk_with_finish(
-                        None,  // Don't send content again
-                        None,
-                        finish_reason,
-                        &model_for_response,
-                    );
-                    let _ = tx.send(Ok(chunk)).await;
-                    let _ = tx
-                        .send(Ok(serde_json::Value::String("[DONE]".to_string())))
-                        .await;
-                    break;
-                }
-                EventMsg::Error(err) => {
-                    log_message(serde_json::json!({
-                        "type": "stream_codex_error",
-                        "error": err.message
-                    }).to_string());
-                    let _ = tx.send(Err(format!("Codex error: {}", err.message))).await;
-                    break;
-                }
-                EventMsg::Warning(warn) => {
-                    info!("warning from Codex: {}", warn.message);
-                }
-                EventMsg::TurnAborted(abort) => {
-                    log_message(serde_json::json!({
-                        "type": "stream_aborted",
-                        "reason": format!("{:?}", abort.reason)
-                    }).to_string());
-                    let _ = tx
-                        .send(Err(format!("Turn aborted: {:?}", abort.reason)))
-                        .await;
-                    break;
-                }
-                _ => {}
-            }
-        }
-    });
-
-    let stream = ReceiverStream::new(rx).map(|msg| match msg {
-        Ok(json_val) => match json_val {
-            serde_json::Value::String(s) if s == "[DONE]" => {
-                log_message(serde_json::json!({
-                    "type": "stream_send_done"
-                }).to_string());
-                Ok::<Event, std::convert::Infallible>(Event::default().data(s))
-            }
-            other => {
-                let data = serde_json::to_string(&other).unwrap_or_else(|_| "{}".to_string());
-                Ok::<Event, std::convert::Infallible>(Event::default().data(data))
-            }
-        },
-        Err(err) => {
-            log_message(serde_json::json!({
-                "type": "stream_send_error",
-                "error": err.clone()
-            }).to_string());
-            Ok(Event::default().data(
-                serde_json::to_string(&serde_json::json!({
-                    "error": err,
-                }))
-                .unwrap_or_else(|_| "{}".to_string()),
-            ))
-        }
-    });
-
-    Sse::new(stream)
-        .keep_alive(axum::response::sse::KeepAlive::default())
-        .into_response()
-}
-
-async fn get_or_create_thread(
-    state: &AppState,
-    model: &str,
-    conversation_id: Option<String>,
-) -> Result<(Arc<CodexThread>, String), String> {
-    let overrides = vec![
-        ("model".to_string(), toml::Value::String(map_model(model))),
-        (
-            "approval_policy".to_string(),
-            toml::Value::String("never".to_string()),
-        ),
-        (
-            "sandbox_mode".to_string(),
-            toml::Value::String("read-only".to_string()),  // ⚠️ ReadOnly: no tool execution
-        ),
-    ];
-
-    let config = Config::load_with_cli_overrides(overrides)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    if let Some(cid) = conversation_id {
-        let tid = codex_protocol::ThreadId::from_string(&cid)
-            .map_err(|e| format!("invalid conversation_id: {e}"))?;
-        let thread = state
-            .thread_manager
-            .get_thread(tid)
-            .await
-            .map_err(|e| format!("thread not found: {e}"))?;
-        return Ok((thread, cid));
-    }
-
-    let new_thread = state
-        .thread_manager
-        .start_thread(config)
-        .await
-        .map_err(|e| e.to_string())?;
-    Ok((new_thread.thread, new_thread.thread_id.to_string()))
-}
-
-fn stream_chunk(
-    content: Option<&str>,
-    tool_call: Option<ToolCall>,
-    _done: bool,
-    model: &str,
-) -> serde_json::Value {
-    let mut delta = serde_json::Map::new();
-    if let Some(text) = content {
-        delta.insert(
-            "content".to_string(),
-            serde_json::Value::String(text.to_string()),
-        );
-    }
-    if let Some(tc) = tool_call {
-        delta.insert(
-            "tool_calls".to_string(),
-            serde_json::json!([{
-                "index": 0,
-                "id": tc.id,
-                "type": tc.kind,
-                "function": {
-                    "name": tc.function.name,
-                    "arguments": tc.function.arguments,
-                }
-            }]),
-        );
-    }
-
-    serde_json::json!({
-        "id": format!("chatcmpl-codex-{}", uuid::Uuid::new_v4()),
-        "object": "chat.completion.chunk",
-        "created": now_ts(),
-        "model": model,  // ⚠️ Always include model field
-        "choices": [{
-            "index": 0,
-            "delta": delta,
-            "finish_reason": null,
-        }],
-    })
-}
-
-fn stream_chunk_with_finish(
-    content: Option<&str>,
-    tool_call: Option<ToolCall>,
-    finish_reason: &str,
-    model: &str,
-) -> serde_json::Value {
-    let mut delta = serde_json::Map::new();
-    if let Some(text) = content {
-        delta.insert(
-            "content".to_string(),
-            serde_json::Value::String(text.to_string()),
-        );
-    }
-    if let Some(tc) = tool_call {
-        delta.insert(
-            "tool_calls".to_string(),
-            serde_json::json!([{
-                "index": 0,
-                "id": tc.id,
-                "type": tc.kind,
-                "function": {
-                    "name": tc.function.name,
-                    "arguments": tc.function.arguments,
-                }
-            }]),
-        );
-    }
-
-    serde_json::json!({
-        "id": format!("chatcmpl-codex-{}", uuid::Uuid::new_v4()),
-        "object": "chat.completion.chunk",
-        "created": now_ts(),
-        "model": model,  // ⚠️ Always include model field
-        "choices": [{
-            "index": 0,
-            "delta": delta,
-            "finish_reason": finish_reason,
-        }],
-    })
-}
-
-fn map_tool_call(item: &ResponseItem) -> Option<ToolCall> {
-    match item {
-        ResponseItem::FunctionCall {
-            call_id,
-            name,
-            arguments,
-            ..
-        } => Some(ToolCall {
-            id: call_id.clone(),
-            kind: "function".to_string(),
-            function: ToolFunction {
-                name: name.clone(),
-                arguments: arguments.clone(),
-            },
-        }),
-        ResponseItem::CustomToolCall {
-            call_id,
-            name,
-            input,
-            ..
-        } => Some(ToolCall {
-            id: call_id.clone(),
-            kind: "function".to_string(),
-            function: ToolFunction {
-                name: name.clone(),
-                arguments: input.clone(),
-            },
-        }),
-        _ => None,
-    }
-}
-
-fn map_model(model: &str) -> String {
-    // Reverse the model name string
-    // Cursor uses reversed model names (e.g., "2.5-tpg" -> "gpt-5.2")
-    model.chars().rev().collect()
-}
-
-fn merge_messages(msgs: &[ChatMessage]) -> Option<String> {
-    let mut parts = Vec::new();
-    for m in msgs {
-        let content = match &m.content {
-            serde_json::Value::String(s) => s.clone(),
-            serde_json::Value::Array(arr) => arr
-                .iter()
-                .filter_map(|v| v.get("text").or_else(|| v.get("content")))
-                .filter_map(|v| v.as_str())
-                .collect::<Vec<_>>()
-                .join("\n"),
-            _ => String::new(),
-        };
-        if content.trim().is_empty() {
-            continue;
-        }
-        parts.push(format!("{}: {}", m.role, content));
-    }
-    if parts.is_empty() {
-        None
-    } else {
-        Some(parts.join("\n"))
-    }
-}
-
-fn merged_text_from_request(body: &ChatCompletionRequest) -> Option<String> {
-    if let Some(msgs) = &body.messages {
-        return merge_messages(msgs);
-    }
-    None
-}
-
-fn now_ts() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|d| d.as_secs())
-        .unwrap_or(0)
-}
-
-fn error_response(status: StatusCode, msg: String, kind: &str) -> Response {
-    json_response(
-        status,
-        serde_json::json!({
-            "error": {
-                "message": msg,
-                "type": kind,
-            }
-        })
-        .to_string(),
-    )
-}
-
-fn json_response(status: StatusCode, body: String) -> Response {
-    Response::builder()
-        .status(status)
-        .header(CONTENT_TYPE, "application/json; charset=utf-8")
-        .body(axum::body::Body::from(body))
-        .unwrap_or_else(|_| {
-            Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(axum::body::Body::from("internal error"))
-                .unwrap()
-        })
-}
-
-// Helper function to log messages
-fn log_message(msg: String) {
-    let _ = LOG_CHANNEL.send(msg);
-}
-
-// Redirect to logs.html
-async fn handle_logs_redirect() -> Response {
-    Response::builder()
-        .status(StatusCode::MOVED_PERMANENTLY)
-        .header("Location", "/static/logs.html")
-        .body(axum::body::Body::empty())
-        .unwrap()
-}
-
-// SSE stream endpoint for logs
-async fn handle_logs_stream() -> Sse<impl futures::Stream<Item = Result<Event, std::convert::Infallible>>> {
-    let rx = LOG_CHANNEL.subscribe();
-    let stream = BroadcastStream::new(rx).map(|msg: Result<String, BroadcastStreamRecvError>| {
-        let data = msg.unwrap_or_else(|_| "{}".to_string());
-        Ok::<Event, std::convert::Infallible>(Event::default().data(data))
-    });
-
-    Sse::new(stream).keep_alive(axum::response::sse::KeepAlive::default())
+    codex_openai_proxy::run_main(Cli::parse()).await
 }
@@ -0,0 +1,448 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use codex_protocol::openai_models::ReasoningEffort;
+use codex_protocol::protocol::EventMsg;
+use rmcp::ErrorData as McpError;
+use rmcp::RoleServer;
+use rmcp::ServerHandler;
+use rmcp::model::CallToolRequestParam;
+use rmcp::model::CallToolResult;
+use rmcp::model::Content;
+use rmcp::model::Implementation;
+use rmcp::model::JsonObject;
+use rmcp::model::ListResourceTemplatesResult;
+use rmcp::model::ListResourcesResult;
+use rmcp::model::ListToolsResult;
+use rmcp::model::PaginatedRequestParam;
+use rmcp::model::RawResource;
+use rmcp::model::RawResourceTemplate;
+use rmcp::model::ReadResourceRequestParam;
+use rmcp::model::ReadResourceResult;
+use rmcp::model::Resource;
+use rmcp::model::ResourceContents;
+use rmcp::model::ResourceTemplate;
+use rmcp::model::ServerCapabilities;
+use rmcp::model::ServerInfo;
+use rmcp::model::Tool;
+use rmcp::service::RequestContext;
+use rmcp::transport::StreamableHttpServerConfig;
+use rmcp::transport::StreamableHttpService;
+use rmcp::transport::streamable_http_server::session::local::LocalSessionManager;
+use serde::Deserialize;
+use tracing::Instrument;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::access_log::RequestContext as HttpRequestContext;
+use crate::codex_events;
+use crate::codex_events::AgentText;
+use crate::codex_events::ChangedFile;
+use crate::conversations::parse_conversation_id;
+use crate::log_message;
+use crate::output_cap;
+use crate::output_cap::OutputCap;
+use crate::turn_setup;
+use crate::turn_setup::TurnOptions;
+use crate::upstream_errors;
+
+const TURN_TOOL: &str = "codex_turn";
+const CONVERSATION_URI_PREFIX: &str = "codex://conversations/";
+
+/// Arguments of the `codex_turn` tool.
+#[derive(Debug, Deserialize, PartialEq)]
+struct TurnArgs {
+    prompt: String,
+    model: String,
+    #[serde(default)]
+    conversation_id: Option<String>,
+    #[serde(default)]
+    cwd: Option<String>,
+    /// `read-only` (default) or, when the proxy allows it,
+    /// `danger-full-access`; see [`crate::sandbox_mode`].
+    #[serde(default)]
+    sandbox_mode: Option<String>,
+    #[serde(default)]
+    reasoning_effort: Option<ReasoningEffort>,
+    #[serde(default)]
+    max_output_tokens: Option<u64>,
+}
+
+/// Exposes the proxy as an MCP server: a `codex_turn` tool that runs one turn
+/// (optionally continuing a conversation) and the live conversations as
+/// `codex://conversations/{id}` resources.
+#[derive(Clone)]
+pub(crate) struct McpFacade {
+    state: AppState,
+}
+
+impl McpFacade {
+    /// The streamable HTTP service mounted at `/mcp`.
+    pub(crate) fn service(
+        state: AppState,
+    ) -> StreamableHttpService<McpFacade, LocalSessionManager> {
+        StreamableHttpService::new(
+            move || {
+                Ok(McpFacade {
+                    state: state.clone(),
+                })
+            },
+            Arc::new(LocalSessionManager::default()),
+            StreamableHttpServerConfig::default(),
+        )
+    }
+
+    fn turn_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "prompt": { "type": "string", "description": "What to ask Codex." },
+                "model": {
+                    "type": "string",
+                    "description": "Model to run the turn with, as listed by GET /v1/models.",
+                },
+                "conversation_id": {
+                    "type": "string",
+                    "description": "Continue this conversation instead of starting a new one.",
+                },
+                "cwd": { "type": "string", "description": "Workspace directory to run in." },
+                "sandbox_mode": {
+                    "type": "string",
+                    "enum": ["read-only", "danger-full-access"],
+                    "description": "danger-full-access only works when the proxy allows it.",
+                },
+                "reasoning_effort": {
+                    "type": "string",
+                    "enum": ["none", "minimal", "low", "medium", "high", "xhigh"],
+                },
+                "max_output_tokens": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "description": "Cut the answer off after about this many tokens.",
+                },
+            },
+            "required": ["prompt", "model"],
+            "additionalProperties": false,
+        });
+        let schema = match schema {
+            serde_json::Value::Object(schema) => schema,
+            _ => JsonObject::new(),
+        };
+        Tool::new(
+            Cow::Borrowed(TURN_TOOL),
+            Cow::Borrowed(
+                "Run one Codex turn and return its final answer. Pass the returned \
+                 conversation_id to continue the conversation.",
+            ),
+            Arc::new(schema),
+        )
+    }
+
+    /// Runs one turn to completion and returns its final message (cut off
+    /// at the output cap, with `finish_reason` "length"), the conversation
+    /// it ran in and the files it changed.
+    /// `http` is the context of the HTTP request carrying the tool call, which
+    /// charges the turn to the caller's usage.
+    async fn run_turn(
+        &self,
+        args: TurnArgs,
+        http: HttpRequestContext,
+    ) -> Result<serde_json::Value, ProxyError> {
+        let state = &self.state;
+        http.set_model(&args.model);
+        let options = TurnOptions {
+            model: args.model,
+            text: args.prompt,
+            conversation_id: args.conversation_id,
+            sandbox_mode: args.sandbox_mode,
+            cwd: args.cwd,
+            effort: args.reasoning_effort,
+            ..TurnOptions::default()
+        };
+        let prepared = options.prepare(state, &http).await?;
+        let thread = prepared.thread(state, &http).await?;
+        let turn = turn_setup::submit(state, &http, thread, prepared).await?;
+        let thread_id = turn.thread_id;
+        let mut output_cap = OutputCap::new(output_cap::effective(
+            state.max_output_tokens.get(),
+            args.max_output_tokens,
+        ));
+        let turn_span = turn.span();
+        let run = async {
+            let thread = &turn.thread;
+            let mut message = String::new();
+            let mut agent_text = AgentText::default();
+            let mut changed_files = Vec::<ChangedFile>::new();
+            loop {
+                let mut ev = thread
+                    .next_event()
+                    .await
+                    .map_err(|e| ProxyError::internal(format!("event error: {e}")))?;
+                if ev.id != turn.submission_id {
+                    continue;
+                }
+                if !http.intercept_event(&mut ev.msg) {
+                    continue;
+                }
+                if let EventMsg::TokenCount(count) = &ev.msg
+                    && let Some(info) = &count.info
+                {
+                    http.add_tokens(info.last_token_usage.total_tokens);
+                }
+                // Past the output cap only the end of the interrupted turn matters.
+                if output_cap.reached() {
+                    if output_cap::ends_turn(&ev.msg) {
+                        break;
+                    }
+                    continue;
+                }
+                if let Some(text) = agent_text.text(&ev.msg) {
+                    message.push_str(output_cap.take(&text));
+                    if output_cap.reached() {
+                        output_cap::interrupt(thread).await;
+                        continue;
+                    }
+                }
+                http.record_event(&ev.msg);
+                match ev.msg {
+                    EventMsg::PatchApplyEnd(patch) if patch.success => {
+                        codex_events::record_changed_files(&mut changed_files, &patch.changes);
+                    }
+                    EventMsg::TurnComplete(_) => break,
+                    EventMsg::Error(err) => return Err(upstream_errors::turn_error(&err)),
+                    msg @ (EventMsg::ExecApprovalRequest(_)
+                    | EventMsg::ApplyPatchApprovalRequest(_)) => {
+                        // Denied outright; the warning has nowhere to go.
+                        state
+                            .approvals
+                            .handle(thread, thread_id, &ev.id, turn.approval_policy, &msg)
+                            .await;
+                    }
+                    EventMsg::TurnAborted(abort) => {
+                        return Err(ProxyError::internal(format!(
+                            "Turn aborted: {:?}",
+                            abort.reason
+                        )));
+                    }
+                    _ => {}
+                }
+            }
+            Ok((message, changed_files))
+        };
+        let (message, changed_files) = run.instrument(turn_span).await?;
+
+        log_message(
+            serde_json::json!({
+                "type": "mcp_turn_complete",
+                "conversation_id": thread_id.to_string(),
+            })
+            .to_string(),
+        );
+        Ok(serde_json::json!({
+            "conversation_id": thread_id.to_string(),
+            "message": message.trim(),
+            "finish_reason": if output_cap.reached() { "length" } else { "stop" },
+            "changed_files": changed_files,
+        }))
+    }
+}
+
+impl ServerHandler for McpFacade {
+    fn get_info(&self) -> ServerInfo {
+        ServerInfo {
+            capabilities: ServerCapabilities::builder()
+                .enable_tools()
+                .enable_resources()
+                .build(),
+            server_info: Implementation {
+                name: "codex-openai-proxy".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                ..Implementation::default()
+            },
+            instructions: Some(format!(
+                "Call {TURN_TOOL} to run a Codex turn. Conversations started through it are \
+                 listed as {CONVERSATION_URI_PREFIX}{{id}} resources."
+            )),
+            ..ServerInfo::default()
+        }
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        Ok(ListToolsResult {
+            tools: vec![Self::turn_tool()],
+            next_cursor: None,
+            meta: None,
+        })
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if request.name != TURN_TOOL {
+            return Err(McpError::invalid_params(
+                format!("unknown tool: {}", request.name),
+                None,
+            ));
+        }
+        let arguments = serde_json::Value::Object(request.arguments.unwrap_or_default());
+        let args = serde_json::from_value::<TurnArgs>(arguments)
+            .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+        let http = context
+            .extensions
+            .get::<http::request::Parts>()
+            .and_then(|parts| parts.extensions.get::<HttpRequestContext>())
+            .cloned()
+            .unwrap_or_else(|| {
+                HttpRequestContext::new(format!("req_{}", uuid::Uuid::new_v4().simple()))
+            });
+
+        // Turn failures are reported as tool errors so the calling model sees them.
+        match self.run_turn(args, http).await {
+            Ok(result) => {
+                let text = result["message"].as_str().unwrap_or_default().to_string();
+                Ok(CallToolResult {
+                    content: vec![Content::text(text)],
+                    structured_content: Some(result),
+                    is_error: Some(false),
+                    meta: None,
+                })
+            }
+            Err(e) => Ok(CallToolResult::error(vec![Content::text(e.message)])),
+        }
+    }
+
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, McpError> {
+        let resources = self
+            .state
+            .threads
+            .summaries()
+            .into_iter()
+            .map(|summary| {
+                Resource::new(
+                    RawResource {
+                        uri: format!("{CONVERSATION_URI_PREFIX}{}", summary.id),
+                        name: summary.id,
+                        title: None,
+                        description: Some(format!("Codex conversation on {}", summary.model)),
+                        mime_type: Some("application/json".to_string()),
+                        size: None,
+                        icons: None,
+                        meta: None,
+                    },
+                    None,
+                )
+            })
+            .collect();
+        Ok(ListResourcesResult {
+            resources,
+            next_cursor: None,
+            meta: None,
+        })
+    }
+
+    async fn list_resource_templates(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourceTemplatesResult, McpError> {
+        Ok(ListResourceTemplatesResult {
+            resource_templates: vec![ResourceTemplate::new(
+                RawResourceTemplate {
+                    uri_template: format!("{CONVERSATION_URI_PREFIX}{{id}}"),
+                    name: "codex-conversation".to_string(),
+                    title: Some("Codex conversation".to_string()),
+                    description: Some(
+                        "A conversation's model, activity and rollout file.".to_string(),
+                    ),
+                    mime_type: Some("application/json".to_string()),
+                },
+                None,
+            )],
+            next_cursor: None,
+            meta: None,
+        })
+    }
+
+    async fn read_resource(
+        &self,
+        ReadResourceRequestParam { uri }: ReadResourceRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, McpError> {
+        let not_found = |message: String| {
+            McpError::resource_not_found(message, Some(serde_json::json!({ "uri": uri })))
+        };
+        let Some(id) = uri.strip_prefix(CONVERSATION_URI_PREFIX) else {
+            return Err(not_found(format!("unknown resource {uri}")));
+        };
+        let thread_id = parse_conversation_id(id).map_err(|e| not_found(e.message))?;
+        let stored = self
+            .state
+            .threads
+            .stored(thread_id)
+            .map_err(|e| not_found(e.message))?;
+        let summary = self
+            .state
+            .threads
+            .summaries()
+            .into_iter()
+            .find(|summary| summary.id == id);
+        let text = serde_json::json!({
+            "id": id,
+            "model": stored.model,
+            "rollout_path": stored.rollout_path,
+            "live": summary.is_some(),
+            "summary": summary,
+        })
+        .to_string();
+        Ok(ReadResourceResult {
+            contents: vec![ResourceContents::TextResourceContents {
+                uri: uri.clone(),
+                mime_type: Some("application/json".to_string()),
+                text,
+                meta: None,
+            }],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn turn_tool_requires_prompt_and_model() {
+        let schema = McpFacade::turn_tool().input_schema;
+        assert_eq!(
+            schema.get("required"),
+            Some(&serde_json::json!(["prompt", "model"]))
+        );
+        assert_eq!(
+            serde_json::from_value::<TurnArgs>(
+                serde_json::json!({ "prompt": "hi", "model": "2.5-tpg" })
+            )
+            .ok(),
+            Some(TurnArgs {
+                prompt: "hi".to_string(),
+                model: "2.5-tpg".to_string(),
+                conversation_id: None,
+                cwd: None,
+                sandbox_mode: None,
+                reasoning_effort: None,
+                max_output_tokens: None,
+            })
+        );
+        assert!(serde_json::from_value::<TurnArgs>(serde_json::json!({ "prompt": "hi" })).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+//! `mcp_servers` on chat and Responses requests: tool servers a client
+//! brings to its conversation. Each is added to the `mcp_servers` table of
+//! the config the conversation's thread starts with, so its tools reach the
+//! model like those of servers in `config.toml`.
+//!
+//! Clients only pick from what the operator allows: a stdio server's
+//! command must be one of the allowed commands, and an HTTP server's URL
+//! must start with one of the allowed URL prefixes. With nothing allowed
+//! (the default) the extension is rejected.
+
+use axum::http::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ProxyError;
+
+/// A tool server named in a request.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct McpServerRequest {
+    /// The server's name, which prefixes its tools' names.
+    name: String,
+    /// The command a stdio server is started with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    args: Vec<String>,
+    /// The endpoint of a streamable HTTP server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    /// Only these of the server's tools are offered to the model; unset
+    /// offers all of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    allowed_tools: Option<Vec<String>>,
+}
+
+/// The tool servers requests may bring.
+#[derive(Debug)]
+pub(crate) struct McpServerAllowlist {
+    /// Commands stdio servers may be started with, matched exactly.
+    commands: Vec<String>,
+    /// Prefixes the URLs of HTTP servers must start with.
+    url_prefixes: Vec<String>,
+}
+
+fn invalid_server(message: String) -> ProxyError {
+    ProxyError::new(StatusCode::BAD_REQUEST, message, "invalid_request_error")
+}
+
+fn server_not_allowed(message: String) -> ProxyError {
+    ProxyError::new(StatusCode::FORBIDDEN, message, "permission_error")
+        .with_code("mcp_server_not_allowed")
+}
+
+impl McpServerAllowlist {
+    pub(crate) fn new(commands: Vec<String>, url_prefixes: Vec<String>) -> Self {
+        Self {
+            commands,
+            url_prefixes,
+        }
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "commands": self.commands,
+            "url_prefixes": self.url_prefixes,
+        })
+    }
+
+    /// Checks `servers` against the allowlist and returns the config
+    /// overrides that add them to a thread.
+    pub(crate) fn overrides(
+        &self,
+        servers: &[McpServerRequest],
+    ) -> Result<Vec<(String, toml::Value)>, ProxyError> {
+        let mut overrides = Vec::with_capacity(servers.len());
+        for server in servers {
+            let name = &server.name;
+            let valid_name = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !valid_name {
+                return Err(invalid_server(format!(
+                    "mcp_servers: name {name:?} must be letters, digits, '_' or '-'"
+                )));
+            }
+            if overrides.iter().any(|(key, _)| *key == config_key(name)) {
+                return Err(invalid_server(format!(
+                    "mcp_servers: {name} is named more than once"
+                )));
+            }
+
+            let mut table = toml::Table::new();
+            match (&server.command, &server.url) {
+                (Some(command), None) => {
+                    if !self.commands.contains(command) {
+                        return Err(server_not_allowed(format!(
+                            "mcp_servers: {name} runs {command}, which is not an allowed command"
+                        )));
+                    }
+                    table.insert("command".to_string(), command.clone().into());
+                    table.insert("args".to_string(), server.args.clone().into());
+                }
+                (None, Some(url)) => {
+                    if !self
+                        .url_prefixes
+                        .iter()
+                        .any(|prefix| url.starts_with(prefix.as_str()))
+                    {
+                        return Err(server_not_allowed(format!(
+                            "mcp_servers: {name} is at {url}, which is not an allowed URL"
+                        )));
+                    }
+                    table.insert("url".to_string(), url.clone().into());
+                }
+                _ => {
+                    return Err(invalid_server(format!(
+                        "mcp_servers: {name} needs exactly one of command and url"
+                    )));
+                }
+            }
+            if let Some(tools) = &server.allowed_tools {
+                table.insert("enabled_tools".to_string(), tools.clone().into());
+            }
+            overrides.push((config_key(name), toml::Value::Table(table)));
+        }
+        Ok(overrides)
+    }
+}
+
+fn config_key(name: &str) -> String {
+    format!("mcp_servers.{name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn server(json: serde_json::Value) -> McpServerRequest {
+        serde_json::from_value(json).expect("server")
+    }
+
+    #[test]
+    fn allowed_servers_become_config_overrides() {
+        let allowlist = McpServerAllowlist::new(
+            vec!["docs-mcp".to_string()],
+            vec!["https://tools.example.com/".to_string()],
+        );
+        let overrides = allowlist
+            .overrides(&[
+                server(serde_json::json!({
+                    "name": "docs",
+                    "command": "docs-mcp",
+                    "args": ["--stdio"],
+                    "allowed_tools": ["search"],
+                })),
+                server(serde_json::json!({
+                    "name": "tickets",
+                    "url": "https://tools.example.com/tickets",
+                })),
+            ])
+            .expect("overrides");
+        assert_eq!(
+            overrides,
+            vec![
+                (
+                    "mcp_servers.docs".to_string(),
+                    toml::toml! {
+                        command = "docs-mcp"
+                        args = ["--stdio"]
+                        enabled_tools = ["search"]
+                    }
+                    .into(),
+                ),
+                (
+                    "mcp_servers.tickets".to_string(),
+                    toml::toml! { url = "https://tools.example.com/tickets" }.into(),
+                ),
+            ]
+        );
+
+        let status =
+            |json: serde_json::Value| allowlist.overrides(&[server(json)]).err().map(|e| e.status);
+        assert_eq!(
+            status(serde_json::json!({ "name": "sh", "command": "sh" })),
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            status(serde_json::json!({ "name": "evil", "url": "https://evil.example.com/" })),
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            status(serde_json::json!({ "name": "docs" })),
+            Some(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            status(serde_json::json!({ "name": "a.b", "command": "docs-mcp" })),
+            Some(StatusCode::BAD_REQUEST)
+        );
+    }
+}
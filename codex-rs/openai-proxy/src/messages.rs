@@ -0,0 +1,1022 @@
+use axum::Extension;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::response::sse::Event;
+use axum::response::sse::Sse;
+use codex_protocol::ThreadId;
+use codex_protocol::openai_models::ReasoningEffort;
+use codex_protocol::protocol::EventMsg;
+use futures::StreamExt;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::Instrument;
+use tracing::info;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::access_log::RequestContext;
+use crate::attachments;
+use crate::attachments::Attachment;
+use crate::base_instructions_header;
+use crate::codex_events;
+use crate::codex_events::AgentText;
+use crate::dry_run::ProposedPatch;
+use crate::images;
+use crate::json_response;
+use crate::log_message;
+use crate::mcp_servers::McpServerRequest;
+use crate::output_cap;
+use crate::output_cap::OutputCap;
+use crate::profile_header;
+use crate::profile_override;
+use crate::response_cache::ResponseCache;
+use crate::response_cache::with_cache_status;
+use crate::responses::named_event;
+use crate::stream_buffer::DeltaShaping;
+use crate::stream_buffer::StreamItem;
+use crate::stream_buffer::StreamSender;
+use crate::structured_output;
+use crate::structured_output::OutputSchema;
+use crate::turn_setup;
+use crate::turn_setup::SubmittedTurn;
+use crate::turn_setup::TurnOptions;
+use crate::turn_slots;
+use crate::upstream_errors;
+
+/// An Anthropic Messages API request. `conversation_id`, `approval_policy`,
+/// `sandbox_mode`, `dry_run`, `cwd`, `reasoning_effort` and `mcp_servers` are
+/// proxy extensions shared with the OpenAI endpoints.
+#[derive(Debug, Deserialize)]
+pub(crate) struct MessagesRequest {
+    model: String,
+    messages: Vec<AnthropicMessage>,
+    /// Either a plain string or an array of text blocks.
+    #[serde(default)]
+    system: Option<serde_json::Value>,
+    #[serde(default)]
+    stream: bool,
+    /// Caps the answer's text; the tighter of this and
+    /// `CODEX_OPENAI_PROXY_MAX_OUTPUT_TOKENS` applies, and an answer cut off
+    /// there stops with `max_tokens`.
+    #[serde(default)]
+    max_tokens: Option<u64>,
+    /// Spaces text deltas at least this many milliseconds apart, merging
+    /// those that arrive sooner; overrides
+    /// `CODEX_OPENAI_PROXY_STREAM_MIN_INTERVAL_MS`, and 0 turns it off.
+    #[serde(default)]
+    stream_min_interval_ms: Option<u64>,
+    /// `word` or `sentence` re-chunks text deltas to end on those
+    /// boundaries; overrides `CODEX_OPENAI_PROXY_STREAM_GRANULARITY`.
+    #[serde(default)]
+    stream_granularity: Option<String>,
+    #[serde(default)]
+    tools: Vec<AnthropicTool>,
+    #[serde(default)]
+    thinking: Option<Thinking>,
+    /// A `json_schema` format the answer is asked for and validated against;
+    /// see [`crate::structured_output`].
+    #[serde(default)]
+    output_format: Option<serde_json::Value>,
+    #[serde(default)]
+    metadata: Option<MessagesMetadata>,
+    #[serde(default)]
+    conversation_id: Option<String>,
+    /// Interrupts a turn already running on `conversation_id` instead of
+    /// queueing behind it; overrides `CODEX_OPENAI_PROXY_INTERRUPT_PREVIOUS_TURN`.
+    #[serde(default)]
+    interrupt_previous_turn: Option<bool>,
+    #[serde(default)]
+    approval_policy: Option<String>,
+    #[serde(default)]
+    sandbox_mode: Option<String>,
+    /// Runs the turn read-only and returns the patches Codex would apply
+    /// instead; see [`crate::dry_run`].
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default, alias = "workspace")]
+    cwd: Option<String>,
+    /// Forwarded to Codex as the turn's reasoning effort.
+    #[serde(default)]
+    reasoning_effort: Option<ReasoningEffort>,
+    /// Replaces Codex's built-in system prompt for the thread this request
+    /// starts or resumes; see [`crate::BASE_INSTRUCTIONS_HEADER`].
+    #[serde(default)]
+    base_instructions: Option<String>,
+    /// The Codex config profile the thread this request starts or resumes
+    /// runs with; see [`crate::PROFILE_HEADER`].
+    #[serde(default)]
+    profile: Option<String>,
+    /// Tool servers added to the thread this request starts; see
+    /// [`crate::mcp_servers`].
+    #[serde(default)]
+    mcp_servers: Vec<McpServerRequest>,
+}
+
+impl MessagesRequest {
+    /// What this request asks of its turn, given its merged `text`.
+    fn turn_options(
+        &self,
+        text: String,
+        answerable: bool,
+        priority: i32,
+        output_schema: Option<&OutputSchema>,
+    ) -> TurnOptions {
+        TurnOptions {
+            model: self.model.clone(),
+            text,
+            conversation_id: self.conversation_id.clone(),
+            thread_overrides: profile_override(self.profile.as_deref())
+                .into_iter()
+                .collect(),
+            base_instructions: self.base_instructions.clone(),
+            mcp_servers: self.mcp_servers.clone(),
+            user: self
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.user_id.clone()),
+            approval_policy: self.approval_policy.clone(),
+            answerable,
+            sandbox_mode: self.sandbox_mode.clone(),
+            dry_run: self.dry_run,
+            cwd: self.cwd.clone(),
+            effort: self.reasoning_effort,
+            output_schema: output_schema.map(OutputSchema::schema),
+            image_urls: message_image_urls(&self.messages),
+            attachments: message_attachments(&self.messages),
+            priority,
+            interrupt_previous_turn: self.interrupt_previous_turn,
+        }
+    }
+}
+
+/// Anthropic request `metadata`.
+#[derive(Debug, Deserialize)]
+struct MessagesMetadata {
+    /// The client's end user, recorded for usage attribution.
+    #[serde(default)]
+    user_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicMessage {
+    role: String,
+    /// Either a plain string or an array of content blocks.
+    content: serde_json::Value,
+}
+
+/// A client-executed tool; rejected, see [`check_tools`].
+#[derive(Debug, Deserialize)]
+struct AnthropicTool {
+    name: String,
+}
+
+/// Extended thinking; when `enabled`, Codex reasoning is streamed as thinking
+/// blocks. `budget_tokens` is accepted and ignored.
+#[derive(Debug, Deserialize)]
+struct Thinking {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+struct MessagesUsage {
+    input_tokens: i64,
+    output_tokens: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct MessagesResponse {
+    id: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    role: &'static str,
+    model: String,
+    content: Vec<serde_json::Value>,
+    stop_reason: &'static str,
+    stop_sequence: Option<String>,
+    /// Proxy extension: why the turn was aborted (`interrupted`, `replaced`
+    /// or `review_ended`) when it did not run to its end.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    abort_reason: Option<&'static str>,
+    usage: MessagesUsage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conversation_id: Option<String>,
+    /// Patches a `dry_run` turn would have applied.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    proposed_patches: Vec<ProposedPatch>,
+}
+
+/// What a running turn reports back to the handler.
+enum TurnEvent {
+    Text(String),
+    Thinking(String),
+    /// Progress and approval events, streamed as custom SSE events.
+    Codex(serde_json::Value),
+    /// A patch a dry run declined.
+    Patch(ProposedPatch),
+    Usage(MessagesUsage),
+    /// The turn ended, with `abort_reason` set when it was aborted.
+    Done {
+        stop_reason: &'static str,
+        abort_reason: Option<&'static str>,
+    },
+    Failed(ProxyError),
+}
+
+impl StreamItem for TurnEvent {
+    fn is_delta(&self) -> bool {
+        matches!(self, Self::Text(_) | Self::Thinking(_))
+    }
+
+    fn coalesce(&mut self, next: &Self) -> bool {
+        match (self, next) {
+            (Self::Text(text), Self::Text(next)) | (Self::Thinking(text), Self::Thinking(next)) => {
+                text.push_str(next);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn delta_text(&self) -> Option<String> {
+        match self {
+            Self::Text(text) | Self::Thinking(text) => Some(text.clone()),
+            _ => None,
+        }
+    }
+
+    fn with_delta_text(&self, text: &str) -> Self {
+        match self {
+            Self::Thinking(_) => Self::Thinking(text.to_string()),
+            _ => Self::Text(text.to_string()),
+        }
+    }
+}
+
+/// `POST /v1/messages`: the Anthropic Messages API on top of the same thread
+/// pipeline as `/v1/chat/completions`, for clients that only speak Anthropic.
+pub(crate) async fn handle_messages(
+    State(state): State<AppState>,
+    Extension(context): Extension<RequestContext>,
+    headers: HeaderMap,
+    body: axum::Json<MessagesRequest>,
+) -> Response {
+    log_message(
+        serde_json::json!({
+            "type": "incoming_request",
+            "endpoint": "/messages",
+            "model": body.model,
+            "stream": body.stream
+        })
+        .to_string(),
+    );
+
+    context.set_model(&body.model);
+    let priority = match turn_slots::request_priority(&headers) {
+        Ok(priority) => priority,
+        Err(e) => return anthropic_error(e),
+    };
+    let mut body = body.0;
+    match (base_instructions_header(&headers), profile_header(&headers)) {
+        (Ok(instructions), Ok(profile)) => {
+            body.base_instructions = body.base_instructions.or(instructions);
+            body.profile = body.profile.or(profile);
+        }
+        (Err(e), _) | (_, Err(e)) => return anthropic_error(e),
+    }
+    let Some(text) = merge_anthropic_messages(body.system.as_ref(), &body.messages) else {
+        return anthropic_error(ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            "no user content found",
+            "invalid_request_error",
+        ));
+    };
+    if let Err(e) = check_tools(&body.tools) {
+        return anthropic_error(e);
+    }
+    if body.stream {
+        return handle_messages_stream(state, context, priority, body, text).await;
+    }
+    handle_messages_once(state, context, priority, body, text).await
+}
+
+async fn handle_messages_once(
+    state: AppState,
+    context: RequestContext,
+    priority: i32,
+    body: MessagesRequest,
+    text: String,
+) -> Response {
+    // Attachments are written into the workspace, so those requests always run.
+    let cache_key = match &state.response_cache {
+        Some(_)
+            if body.conversation_id.is_none() && message_attachments(&body.messages).is_empty() =>
+        {
+            Some(ResponseCache::key(
+                "messages",
+                &serde_json::json!({
+                    "model": body.model,
+                    "input": text,
+                    "images": message_image_urls(&body.messages),
+                    "approval_policy": body.approval_policy,
+                    "sandbox_mode": body.sandbox_mode,
+                    "dry_run": body.dry_run,
+                    "cwd": body.cwd,
+                    "reasoning_effort": body.reasoning_effort,
+                    "output_format": body.output_format,
+                    "base_instructions": body.base_instructions,
+                    "profile": body.profile,
+                    "mcp_servers": body.mcp_servers,
+                    "max_tokens": body.max_tokens,
+                }),
+            ))
+        }
+        _ => None,
+    };
+    if let (Some(cache), Some(key)) = (&state.response_cache, &cache_key)
+        && let Some(cached) = cache.get(key)
+    {
+        return with_cache_status(json_response(StatusCode::OK, cached), true);
+    }
+
+    // Named before the turn starts, as its diff is recorded under this id.
+    let id = message_id();
+    context.set_response_id(&id);
+    let has_webhook = state.approvals.has_webhook();
+    let (tx, mut events) = state.stream_buffers.collecting_channel();
+    let thread_id = match start_turn(&state, &context, priority, &body, text, has_webhook, tx).await
+    {
+        Ok(thread_id) => thread_id,
+        Err(e) => return anthropic_error(e),
+    };
+
+    let mut text = String::new();
+    let mut content = Vec::new();
+    let mut usage = MessagesUsage::default();
+    let mut stop_reason = "end_turn";
+    let mut abort_reason = None;
+    let mut proposed_patches = Vec::new();
+    while let Some(event) = events.recv().await {
+        match event {
+            TurnEvent::Text(delta) => text.push_str(&delta),
+            TurnEvent::Patch(patch) => proposed_patches.push(patch),
+            TurnEvent::Usage(turn_usage) => usage = turn_usage,
+            TurnEvent::Done {
+                stop_reason: stop,
+                abort_reason: abort,
+            } => {
+                stop_reason = stop;
+                abort_reason = abort;
+                break;
+            }
+            TurnEvent::Failed(error) => return anthropic_error(error),
+            TurnEvent::Thinking(_) | TurnEvent::Codex(_) => {}
+        }
+    }
+    if !text.trim().is_empty() {
+        content.push(serde_json::json!({ "type": "text", "text": text.trim() }));
+    }
+
+    let response = MessagesResponse {
+        id,
+        kind: "message",
+        role: "assistant",
+        model: body.model.clone(),
+        content,
+        stop_reason,
+        stop_sequence: None,
+        abort_reason,
+        usage,
+        conversation_id: Some(thread_id.to_string()),
+        proposed_patches,
+    };
+    let response_body = serde_json::to_string(&response).unwrap_or_else(|_| "{}".to_string());
+    if let (Some(cache), Some(key)) = (&state.response_cache, cache_key) {
+        let replay = MessagesResponse {
+            conversation_id: None,
+            ..response
+        };
+        cache.put(
+            key,
+            serde_json::to_string(&replay).unwrap_or_else(|_| "{}".to_string()),
+        );
+        return with_cache_status(json_response(StatusCode::OK, response_body), false);
+    }
+    json_response(StatusCode::OK, response_body)
+}
+
+async fn handle_messages_stream(
+    state: AppState,
+    context: RequestContext,
+    priority: i32,
+    body: MessagesRequest,
+    text: String,
+) -> Response {
+    let shaping = match DeltaShaping::from_request(
+        body.stream_granularity.as_deref(),
+        body.stream_min_interval_ms,
+    ) {
+        Ok(shaping) => shaping,
+        Err(e) => return anthropic_error(e),
+    };
+    let id = message_id();
+    context.set_response_id(&id);
+    let (tx, events) = state.stream_buffers.channel();
+    let events = state.stream_buffers.shape(events, shaping);
+    let thread_id = match start_turn(&state, &context, priority, &body, text, true, tx).await {
+        Ok(thread_id) => thread_id,
+        Err(e) => return anthropic_error(e),
+    };
+
+    let mut builder = MessageEventBuilder::new(id, body.model.clone(), thread_id);
+    let start = futures::stream::iter(vec![builder.start()]);
+    let request_id = context.request_id().to_string();
+    let body_events = ReceiverStream::new(events).flat_map(move |event| {
+        let payloads = match event {
+            TurnEvent::Text(delta) => builder.text_delta(&delta),
+            TurnEvent::Thinking(delta) => builder.thinking_delta(&delta),
+            TurnEvent::Codex(event) => vec![event],
+            TurnEvent::Patch(patch) => vec![patch.event().into_event()],
+            TurnEvent::Usage(usage) => {
+                builder.usage = usage;
+                Vec::new()
+            }
+            TurnEvent::Done {
+                stop_reason,
+                abort_reason,
+            } => builder.finish(stop_reason, abort_reason),
+            TurnEvent::Failed(error) => {
+                log_message(
+                    serde_json::json!({
+                        "type": "messages_stream_error",
+                        "error": error.message
+                    })
+                    .to_string(),
+                );
+                vec![serde_json::json!({
+                    "type": "error",
+                    "error": { "type": anthropic_error_type(&error), "message": error.message },
+                    "request_id": request_id,
+                })]
+            }
+        };
+        futures::stream::iter(payloads)
+    });
+    let stream = start
+        .chain(body_events)
+        .map(|payload| Ok::<Event, std::convert::Infallible>(named_event(&payload)));
+
+    state.sse_keep_alive.apply(Sse::new(stream)).into_response()
+}
+
+/// Runs the turn through the shared setup, then handles its events on their
+/// own task. Returns the conversation id.
+async fn start_turn(
+    state: &AppState,
+    context: &RequestContext,
+    priority: i32,
+    body: &MessagesRequest,
+    text: String,
+    answerable: bool,
+    tx: StreamSender<TurnEvent>,
+) -> Result<ThreadId, ProxyError> {
+    let output_schema = OutputSchema::from_output_format(body.output_format.as_ref())?;
+    let turn = body
+        .turn_options(text, answerable, priority, output_schema.as_ref())
+        .prepare(state, context)
+        .await?;
+    let thread = turn.thread(state, context).await?;
+    let turn = turn_setup::submit(state, context, thread, turn).await?;
+    let thread_id = turn.thread_id;
+    let turn_span = turn.span();
+    let turn = MessagesTurn {
+        state: state.clone(),
+        context: context.clone(),
+        turn,
+        max_output_tokens: output_cap::effective(state.max_output_tokens.get(), body.max_tokens),
+        output_schema,
+        include_thinking: body
+            .thinking
+            .as_ref()
+            .is_some_and(|thinking| thinking.kind == "enabled"),
+    };
+    tokio::spawn(turn.run(tx).instrument(turn_span));
+    Ok(thread_id)
+}
+
+struct MessagesTurn {
+    state: AppState,
+    context: RequestContext,
+    turn: SubmittedTurn,
+    max_output_tokens: Option<u64>,
+    output_schema: Option<OutputSchema>,
+    include_thinking: bool,
+}
+
+impl MessagesTurn {
+    async fn run(self, tx: StreamSender<TurnEvent>) {
+        let turn = &self.turn;
+        let mut usage = MessagesUsage::default();
+        let mut agent_text = AgentText::default();
+        let mut output_cap = OutputCap::new(self.max_output_tokens);
+        let mut answer = String::new();
+        loop {
+            let mut ev = match turn.thread.next_event().await {
+                Ok(ev) => ev,
+                Err(e) => {
+                    let _ = tx
+                        .send(TurnEvent::Failed(ProxyError::internal(format!(
+                            "event error: {e}"
+                        ))))
+                        .await;
+                    break;
+                }
+            };
+            if ev.id != turn.submission_id {
+                continue;
+            }
+            if !self.context.intercept_event(&mut ev.msg) {
+                continue;
+            }
+            // Past `max_tokens` wait out the interrupt, counting what the
+            // turn used, then stop the message there.
+            if output_cap.reached() {
+                if let EventMsg::TokenCount(count) = &ev.msg
+                    && let Some(info) = &count.info
+                {
+                    self.context.add_tokens(info.last_token_usage.total_tokens);
+                    usage.input_tokens += info.last_token_usage.input_tokens;
+                    usage.output_tokens += info.last_token_usage.output_tokens;
+                }
+                if output_cap::ends_turn(&ev.msg) {
+                    let _ = tx.send(TurnEvent::Usage(usage)).await;
+                    let _ = tx
+                        .send(TurnEvent::Done {
+                            stop_reason: "max_tokens",
+                            abort_reason: None,
+                        })
+                        .await;
+                    break;
+                }
+                continue;
+            }
+            if let Some(text) = agent_text.text(&ev.msg) {
+                let text = output_cap.take(&text);
+                if !text.is_empty() {
+                    answer.push_str(text);
+                    let _ = tx.send(TurnEvent::Text(text.to_string())).await;
+                }
+                if output_cap.reached() {
+                    output_cap::interrupt(&turn.thread).await;
+                    continue;
+                }
+            }
+            self.context.record_event(&ev.msg);
+            let event = match ev.msg {
+                EventMsg::AgentReasoningDelta(d) if self.include_thinking => {
+                    TurnEvent::Thinking(d.delta)
+                }
+                EventMsg::TokenCount(count) => {
+                    let Some(info) = count.info else {
+                        continue;
+                    };
+                    let last = info.last_token_usage;
+                    self.context.add_tokens(last.total_tokens);
+                    usage.input_tokens += last.input_tokens;
+                    usage.output_tokens += last.output_tokens;
+                    TurnEvent::Usage(usage)
+                }
+                EventMsg::TurnComplete(_) => {
+                    if let Some(Err(message)) = self
+                        .output_schema
+                        .as_ref()
+                        .map(|schema| schema.check(&answer))
+                    {
+                        let error = structured_output::validation_error(message);
+                        let _ = tx.send(TurnEvent::Failed(error)).await;
+                        break;
+                    }
+                    let _ = tx
+                        .send(TurnEvent::Done {
+                            stop_reason: "end_turn",
+                            abort_reason: None,
+                        })
+                        .await;
+                    break;
+                }
+                EventMsg::Error(err) => {
+                    let _ = tx
+                        .send(TurnEvent::Failed(upstream_errors::turn_error(&err)))
+                        .await;
+                    break;
+                }
+                // The answer so far ends the message, as at `end_turn`.
+                EventMsg::TurnAborted(abort) => {
+                    let _ = tx
+                        .send(TurnEvent::Done {
+                            stop_reason: "end_turn",
+                            abort_reason: Some(codex_events::abort_reason(&abort.reason)),
+                        })
+                        .await;
+                    break;
+                }
+                EventMsg::Warning(warn) => {
+                    info!("warning from Codex: {}", warn.message);
+                    continue;
+                }
+                msg @ EventMsg::PatchApplyEnd(_) => match codex_events::patch_event(&msg) {
+                    Some(event) => TurnEvent::Codex(event.into_event()),
+                    None => continue,
+                },
+                msg @ EventMsg::PlanUpdate(_) => match codex_events::plan_event(&msg) {
+                    Some(event) => TurnEvent::Codex(event.into_event()),
+                    None => continue,
+                },
+                msg @ (EventMsg::ExecCommandBegin(_) | EventMsg::ExecCommandEnd(_)) => {
+                    match codex_events::exec_event(&msg) {
+                        Some(event) => TurnEvent::Codex(event.into_event()),
+                        None => continue,
+                    }
+                }
+                msg @ (EventMsg::ExecApprovalRequest(_)
+                | EventMsg::ApplyPatchApprovalRequest(_))
+                    if turn.dry_run =>
+                {
+                    self.state
+                        .approvals
+                        .deny_dry_run(&turn.thread, turn.thread_id, &ev.id, &msg)
+                        .await;
+                    match ProposedPatch::from_event(&msg) {
+                        Some(patch) => TurnEvent::Patch(patch),
+                        None => continue,
+                    }
+                }
+                msg @ (EventMsg::ExecApprovalRequest(_)
+                | EventMsg::ApplyPatchApprovalRequest(_)) => {
+                    match self
+                        .state
+                        .approvals
+                        .handle(
+                            &turn.thread,
+                            turn.thread_id,
+                            &ev.id,
+                            turn.approval_policy,
+                            &msg,
+                        )
+                        .await
+                    {
+                        Some(request) => TurnEvent::Codex(request.into_event()),
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            let _ = tx.send(event).await;
+        }
+        self.state.approvals.clear_turn(&turn.submission_id);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    Text,
+    Thinking,
+}
+
+/// Builds the Anthropic streaming event sequence: `message_start`, content
+/// blocks (`content_block_start`, `content_block_delta`, `content_block_stop`),
+/// then `message_delta` and `message_stop`. Each event's `type` doubles as its
+/// SSE `event:` name.
+struct MessageEventBuilder {
+    id: String,
+    model: String,
+    conversation_id: ThreadId,
+    /// Index the next content block gets.
+    next_index: usize,
+    /// The text or thinking block currently receiving deltas.
+    open_block: Option<BlockKind>,
+    usage: MessagesUsage,
+}
+
+impl MessageEventBuilder {
+    fn new(id: String, model: String, conversation_id: ThreadId) -> Self {
+        Self {
+            id,
+            model,
+            conversation_id,
+            next_index: 0,
+            open_block: None,
+            usage: MessagesUsage::default(),
+        }
+    }
+
+    fn start(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "message_start",
+            "message": {
+                "id": self.id,
+                "type": "message",
+                "role": "assistant",
+                "model": self.model,
+                "content": [],
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": MessagesUsage::default(),
+                "conversation_id": self.conversation_id.to_string(),
+            },
+        })
+    }
+
+    fn text_delta(&mut self, text: &str) -> Vec<serde_json::Value> {
+        self.delta(
+            BlockKind::Text,
+            serde_json::json!({ "type": "text_delta", "text": text }),
+        )
+    }
+
+    fn thinking_delta(&mut self, thinking: &str) -> Vec<serde_json::Value> {
+        self.delta(
+            BlockKind::Thinking,
+            serde_json::json!({ "type": "thinking_delta", "thinking": thinking }),
+        )
+    }
+
+    /// Appends `delta` to the open block of `kind`, closing the previous
+    /// block and opening a new one when the kind changes.
+    fn delta(&mut self, kind: BlockKind, delta: serde_json::Value) -> Vec<serde_json::Value> {
+        let mut events = Vec::new();
+        if self.open_block != Some(kind) {
+            events.extend(self.close_block());
+            let content_block = match kind {
+                BlockKind::Text => serde_json::json!({ "type": "text", "text": "" }),
+                BlockKind::Thinking => serde_json::json!({ "type": "thinking", "thinking": "" }),
+            };
+            events.push(serde_json::json!({
+                "type": "content_block_start",
+                "index": self.next_index,
+                "content_block": content_block,
+            }));
+            self.open_block = Some(kind);
+        }
+        events.push(serde_json::json!({
+            "type": "content_block_delta",
+            "index": self.next_index,
+            "delta": delta,
+        }));
+        events
+    }
+
+    fn close_block(&mut self) -> Option<serde_json::Value> {
+        self.open_block.take()?;
+        let event = serde_json::json!({ "type": "content_block_stop", "index": self.next_index });
+        self.next_index += 1;
+        Some(event)
+    }
+
+    /// Closes the message with `stop_reason`, and `abort_reason` as a proxy
+    /// extension when the turn was aborted.
+    fn finish(&mut self, stop_reason: &str, abort_reason: Option<&str>) -> Vec<serde_json::Value> {
+        let mut events = Vec::new();
+        events.extend(self.close_block());
+        let mut delta = serde_json::json!({ "stop_reason": stop_reason, "stop_sequence": null });
+        if let Some(abort_reason) = abort_reason {
+            delta["abort_reason"] = abort_reason.into();
+        }
+        events.push(serde_json::json!({
+            "type": "message_delta",
+            "delta": delta,
+            "usage": self.usage,
+        }));
+        events.push(serde_json::json!({ "type": "message_stop" }));
+        events
+    }
+}
+
+fn message_id() -> String {
+    format!("msg_{}", uuid::Uuid::new_v4().simple())
+}
+
+fn message_image_urls(messages: &[AnthropicMessage]) -> Vec<String> {
+    messages
+        .iter()
+        .flat_map(|m| images::collect_image_urls(&m.content))
+        .collect()
+}
+
+fn message_attachments(messages: &[AnthropicMessage]) -> Vec<Attachment> {
+    messages
+        .iter()
+        .flat_map(|m| attachments::collect_attachments(&m.content))
+        .collect()
+}
+
+/// Flattens the system prompt and messages into the role-prefixed turn text
+/// the other endpoints send to Codex.
+fn merge_anthropic_messages(
+    system: Option<&serde_json::Value>,
+    messages: &[AnthropicMessage],
+) -> Option<String> {
+    let mut parts = Vec::new();
+    if let Some(system) = system.map(block_text)
+        && !system.trim().is_empty()
+    {
+        parts.push(format!("system: {}", system.trim()));
+    }
+    for message in messages {
+        let text = block_text(&message.content);
+        if !text.trim().is_empty() {
+            parts.push(format!("{}: {}", message.role, text.trim()));
+        }
+    }
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("\n"))
+    }
+}
+
+/// Text of a string or content block array; tool calls and results are
+/// rendered inline so the model sees the earlier exchange.
+fn block_text(content: &serde_json::Value) -> String {
+    let blocks = match content {
+        serde_json::Value::String(text) => return text.clone(),
+        serde_json::Value::Array(blocks) => blocks,
+        _ => return String::new(),
+    };
+    blocks
+        .iter()
+        .filter_map(|block| {
+            let field = |name: &str| block.get(name).and_then(|v| v.as_str()).unwrap_or_default();
+            match block.get("type").and_then(|t| t.as_str()) {
+                Some("text") => Some(field("text").to_string()),
+                Some("tool_use") => Some(format!(
+                    "[tool call {} {}: {}]",
+                    field("id"),
+                    field("name"),
+                    block.get("input").cloned().unwrap_or_default()
+                )),
+                Some("tool_result") => Some(format!(
+                    "[tool result {}]: {}",
+                    field("tool_use_id"),
+                    block.get("content").map(block_text).unwrap_or_default()
+                )),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Rejects client-executed tools. Codex runs its own tools and has no way to
+/// hand a call back to the client, so its calls never come back as
+/// `tool_use` blocks and accepting tools would only pretend to.
+fn check_tools(tools: &[AnthropicTool]) -> Result<(), ProxyError> {
+    match tools.first() {
+        Some(tool) => Err(ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "tools: client tools such as {:?} are not supported; Codex runs its own tools",
+                tool.name
+            ),
+            "invalid_request_error",
+        )
+        .with_code("unsupported_parameter")),
+        None => Ok(()),
+    }
+}
+
+/// Renders `error` in the Anthropic error shape.
+fn anthropic_error(error: ProxyError) -> Response {
+    let mut response = json_response(
+        error.status,
+        serde_json::json!({
+            "type": "error",
+            "error": { "type": anthropic_error_type(&error), "message": error.message },
+        })
+        .to_string(),
+    );
+    if let Some(secs) = error.retry_after {
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    }
+    response
+}
+
+fn anthropic_error_type(error: &ProxyError) -> &'static str {
+    match error.status {
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY | StatusCode::CONFLICT => {
+            "invalid_request_error"
+        }
+        StatusCode::UNAUTHORIZED => "authentication_error",
+        StatusCode::FORBIDDEN => "permission_error",
+        StatusCode::NOT_FOUND | StatusCode::GONE => "not_found_error",
+        StatusCode::TOO_MANY_REQUESTS if error.code == Some("server_overloaded") => {
+            "overloaded_error"
+        }
+        StatusCode::TOO_MANY_REQUESTS => "rate_limit_error",
+        StatusCode::SERVICE_UNAVAILABLE => "overloaded_error",
+        _ => "api_error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn types(events: &[serde_json::Value]) -> Vec<&str> {
+        events
+            .iter()
+            .filter_map(|e| e.get("type").and_then(|t| t.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn messages_flatten_to_role_prefixed_text() {
+        let messages = vec![
+            AnthropicMessage {
+                role: "user".to_string(),
+                content: serde_json::json!("list files"),
+            },
+            AnthropicMessage {
+                role: "assistant".to_string(),
+                content: serde_json::json!([
+                    {"type": "tool_use", "id": "toolu_1", "name": "ls", "input": {"path": "."}},
+                ]),
+            },
+            AnthropicMessage {
+                role: "user".to_string(),
+                content: serde_json::json!([
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "a.rs"},
+                ]),
+            },
+        ];
+        assert_eq!(
+            merge_anthropic_messages(
+                Some(&serde_json::json!([{"type": "text", "text": "be brief"}])),
+                &messages
+            ),
+            Some(
+                "system: be brief\nuser: list files\nassistant: [tool call toolu_1 ls: {\"path\":\".\"}]\nuser: [tool result toolu_1]: a.rs"
+                    .to_string()
+            )
+        );
+        assert_eq!(merge_anthropic_messages(None, &[]), None);
+    }
+
+    #[test]
+    fn stream_events_open_and_close_blocks_in_order() {
+        let mut builder =
+            MessageEventBuilder::new("msg_1".to_string(), "2.5-tpg".to_string(), ThreadId::new());
+        let mut events = vec![builder.start()];
+        events.extend(builder.thinking_delta("hmm"));
+        events.extend(builder.text_delta("Hel"));
+        events.extend(builder.text_delta("lo"));
+        events.extend(builder.finish("end_turn", Some("interrupted")));
+
+        assert_eq!(
+            types(&events),
+            vec![
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        assert_eq!(events[4]["index"], serde_json::json!(1));
+        assert_eq!(
+            events[8]["delta"],
+            serde_json::json!({
+                "stop_reason": "end_turn",
+                "stop_sequence": null,
+                "abort_reason": "interrupted",
+            })
+        );
+    }
+
+    #[test]
+    fn errors_use_anthropic_types() {
+        let overloaded = ProxyError::new(StatusCode::TOO_MANY_REQUESTS, "busy", "server_error")
+            .with_code("server_overloaded");
+        assert_eq!(anthropic_error_type(&overloaded), "overloaded_error");
+        let limited = ProxyError::new(StatusCode::TOO_MANY_REQUESTS, "slow down", "requests");
+        assert_eq!(anthropic_error_type(&limited), "rate_limit_error");
+        assert_eq!(
+            anthropic_error_type(&ProxyError::internal("boom")),
+            "api_error"
+        );
+    }
+}
@@ -0,0 +1,449 @@
+use std::net::SocketAddr;
+
+use anyhow::Context;
+use axum::Json;
+use axum::Router;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::routing::get;
+use axum::routing::post;
+use serde_json::Value;
+use serde_json::json;
+
+/// Provider id the mock backend is registered under in the Codex config.
+const MOCK_PROVIDER_ID: &str = "openai-proxy-mock";
+
+/// What the mock model answers when no trigger matches, streamed one word
+/// per delta.
+pub(crate) const MOCK_REPLY: &str = "This is a scripted reply from the mock backend.";
+
+/// What it answers once a scripted tool call has its output.
+const MOCK_TOOL_REPLY: &str = "The mock tool call finished.";
+
+/// What it answers when asked for JSON.
+const MOCK_JSON_REPLY: &str = r#"{"answer": 42}"#;
+
+/// What it answers after a scripted web search, linking a source.
+const MOCK_SEARCH_REPLY: &str =
+    "According to [the Rust site](https://www.rust-lang.org/), Rust is fast.";
+
+/// The patch the mock model applies, adding one file.
+const MOCK_PATCH: &str = "*** Begin Patch\n*** Add File: mock.txt\n+mock\n*** End Patch\n";
+
+/// Markers in the last user message that pick a script other than the plain
+/// reply.
+const TOOL_TRIGGER: &str = "[mock:tool]";
+const PATCH_TRIGGER: &str = "[mock:patch]";
+const PLAN_TRIGGER: &str = "[mock:plan]";
+const LOCAL_SHELL_TRIGGER: &str = "[mock:local_shell]";
+const PARALLEL_SHELL_TRIGGER: &str = "[mock:parallel_shell]";
+const WEB_SEARCH_TRIGGER: &str = "[mock:web_search]";
+const JSON_TRIGGER: &str = "[mock:json]";
+const INSTRUCTIONS_TRIGGER: &str = "[mock:instructions]";
+const ECHO_TRIGGER: &str = "[mock:echo]";
+const REQUEST_FIELDS_TRIGGER: &str = "[mock:request_fields]";
+const CONTEXT_LENGTH_TRIGGER: &str = "[mock:context_length]";
+const SERVER_ERROR_TRIGGER: &str = "[mock:error]";
+const RATE_LIMIT_TRIGGER: &str = "[mock:rate_limit]";
+
+/// How the mock model answers one request.
+#[derive(Debug, PartialEq)]
+enum Script {
+    Reply(&'static str),
+    /// Repeats the request's `instructions`, the system prompt Codex sent.
+    Instructions(String),
+    /// Repeats the last user message, as the model received it.
+    Echo(String),
+    /// Lists the top-level fields of the request Codex sent, space-separated.
+    RequestFields(String),
+    ToolCall,
+    /// A `shell` call running `apply_patch` with [`MOCK_PATCH`].
+    Patch,
+    /// An `update_plan` call with a two-step plan.
+    Plan,
+    /// A `local_shell_call` item, the Responses API's built-in shell tool.
+    LocalShellCall,
+    /// Two `local_shell_call` items in one response.
+    ParallelShellCalls,
+    /// A `web_search_call` item followed by a reply citing a link.
+    WebSearch,
+    /// A `response.failed` event with this error code.
+    Failed(&'static str),
+    /// The request itself fails with this status.
+    HttpError(StatusCode),
+}
+
+impl Script {
+    fn for_request(body: &Value) -> Self {
+        let input = body
+            .get("input")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        if input
+            .iter()
+            .any(|item| item.get("type").and_then(Value::as_str) == Some("function_call_output"))
+        {
+            return Script::Reply(MOCK_TOOL_REPLY);
+        }
+        let prompt = last_user_text(input);
+        if prompt.contains(SERVER_ERROR_TRIGGER) {
+            Script::HttpError(StatusCode::INTERNAL_SERVER_ERROR)
+        } else if prompt.contains(RATE_LIMIT_TRIGGER) {
+            Script::HttpError(StatusCode::TOO_MANY_REQUESTS)
+        } else if prompt.contains(CONTEXT_LENGTH_TRIGGER) {
+            Script::Failed("context_length_exceeded")
+        } else if prompt.contains(TOOL_TRIGGER) {
+            Script::ToolCall
+        } else if prompt.contains(PATCH_TRIGGER) {
+            Script::Patch
+        } else if prompt.contains(PLAN_TRIGGER) {
+            Script::Plan
+        } else if prompt.contains(PARALLEL_SHELL_TRIGGER) {
+            Script::ParallelShellCalls
+        } else if prompt.contains(LOCAL_SHELL_TRIGGER) {
+            Script::LocalShellCall
+        } else if prompt.contains(WEB_SEARCH_TRIGGER) {
+            Script::WebSearch
+        } else if prompt.contains(JSON_TRIGGER) {
+            Script::Reply(MOCK_JSON_REPLY)
+        } else if prompt.contains(INSTRUCTIONS_TRIGGER) {
+            let instructions = body.get("instructions").and_then(Value::as_str);
+            Script::Instructions(instructions.unwrap_or_default().to_string())
+        } else if prompt.contains(REQUEST_FIELDS_TRIGGER) {
+            let fields = body
+                .as_object()
+                .map(|fields| fields.keys().map(String::as_str).collect::<Vec<_>>())
+                .unwrap_or_default();
+            Script::RequestFields(fields.join(" "))
+        } else if prompt.contains(ECHO_TRIGGER) {
+            Script::Echo(prompt)
+        } else {
+            Script::Reply(MOCK_REPLY)
+        }
+    }
+
+    /// The Responses API events the script streams.
+    fn events(&self) -> Vec<Value> {
+        let mut events = vec![json!({
+            "type": "response.created",
+            "response": { "id": "resp_mock" },
+        })];
+        match self {
+            Script::Reply(text) => push_reply(&mut events, text),
+            Script::Instructions(instructions) => push_reply(&mut events, instructions),
+            Script::Echo(prompt) => push_reply(&mut events, prompt),
+            Script::RequestFields(fields) => push_reply(&mut events, fields),
+            Script::ToolCall => events.push(json!({
+                "type": "response.output_item.done",
+                "item": {
+                    "type": "function_call",
+                    "call_id": "call_mock",
+                    "name": "shell",
+                    "arguments": json!({ "command": ["echo", "mock"] }).to_string(),
+                },
+            })),
+            Script::Patch => events.push(json!({
+                "type": "response.output_item.done",
+                "item": {
+                    "type": "function_call",
+                    "call_id": "call_mock_patch",
+                    "name": "shell",
+                    "arguments": json!({ "command": ["apply_patch", MOCK_PATCH] }).to_string(),
+                },
+            })),
+            Script::Plan => events.push(json!({
+                "type": "response.output_item.done",
+                "item": {
+                    "type": "function_call",
+                    "call_id": "call_mock_plan",
+                    "name": "update_plan",
+                    "arguments": json!({
+                        "explanation": "Mock plan",
+                        "plan": [
+                            { "step": "Read the code", "status": "completed" },
+                            { "step": "Change it", "status": "in_progress" },
+                        ],
+                    })
+                    .to_string(),
+                },
+            })),
+            Script::LocalShellCall => events.push(json!({
+                "type": "response.output_item.done",
+                "item": {
+                    "type": "local_shell_call",
+                    "call_id": "call_mock_shell",
+                    "status": "completed",
+                    "action": { "type": "exec", "command": ["echo", "mock"] },
+                },
+            })),
+            Script::ParallelShellCalls => {
+                for (call_id, word) in [("call_mock_shell_1", "one"), ("call_mock_shell_2", "two")]
+                {
+                    events.push(json!({
+                        "type": "response.output_item.done",
+                        "item": {
+                            "type": "local_shell_call",
+                            "call_id": call_id,
+                            "status": "completed",
+                            "action": { "type": "exec", "command": ["echo", word] },
+                        },
+                    }));
+                }
+            }
+            Script::WebSearch => {
+                events.push(json!({
+                    "type": "response.output_item.done",
+                    "item": {
+                        "type": "web_search_call",
+                        "id": "ws_mock",
+                        "status": "completed",
+                        "action": { "type": "search", "query": "rust" },
+                    },
+                }));
+                push_reply(&mut events, MOCK_SEARCH_REPLY);
+            }
+            Script::Failed(code) => {
+                events.push(json!({
+                    "type": "response.failed",
+                    "response": {
+                        "id": "resp_mock",
+                        "status": "failed",
+                        "error": { "code": code, "message": format!("mock backend: {code}") },
+                    },
+                }));
+                return events;
+            }
+            Script::HttpError(_) => return Vec::new(),
+        }
+        events.push(json!({
+            "type": "response.completed",
+            "response": {
+                "id": "resp_mock",
+                "usage": {
+                    "input_tokens": 10,
+                    "input_tokens_details": null,
+                    "output_tokens": 5,
+                    "output_tokens_details": null,
+                    "total_tokens": 15,
+                },
+            },
+        }));
+        events
+    }
+}
+
+/// An assistant message streamed one word per delta.
+fn push_reply(events: &mut Vec<Value>, text: &str) {
+    events.push(json!({
+        "type": "response.output_item.added",
+        "item": {
+            "type": "message",
+            "role": "assistant",
+            "id": "msg_mock",
+            "content": [],
+        },
+    }));
+    events.extend(
+        text.split_inclusive(' ')
+            .map(|delta| json!({ "type": "response.output_text.delta", "delta": delta })),
+    );
+    events.push(json!({
+        "type": "response.output_item.done",
+        "item": {
+            "type": "message",
+            "role": "assistant",
+            "id": "msg_mock",
+            "content": [{ "type": "output_text", "text": text }],
+        },
+    }));
+}
+
+fn last_user_text(input: &[Value]) -> String {
+    input
+        .iter()
+        .rev()
+        .find(|item| item.get("role").and_then(Value::as_str) == Some("user"))
+        .and_then(|item| item.get("content"))
+        .and_then(Value::as_array)
+        .map(|content| {
+            content
+                .iter()
+                .filter_map(|part| part.get("text").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .unwrap_or_default()
+}
+
+/// `POST /v1/responses` on the mock backend.
+async fn handle_responses(Json(body): Json<Value>) -> Response {
+    let script = Script::for_request(&body);
+    if let Script::HttpError(status) = script {
+        let error = json!({
+            "error": {
+                "message": format!("mock backend: {status}"),
+                "type": "server_error",
+            },
+        });
+        return (status, Json(error)).into_response();
+    }
+    let body = script
+        .events()
+        .iter()
+        .map(|event| {
+            let kind = event
+                .get("type")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            format!("event: {kind}\ndata: {event}\n\n")
+        })
+        .collect::<String>();
+    ([(CONTENT_TYPE, "text/event-stream")], body).into_response()
+}
+
+/// Starts a deterministic stand-in for the model provider on a local port.
+/// Every request gets a scripted answer picked by markers in its last user
+/// message, so the proxy's HTTP surface can be tested without credentials
+/// or network access.
+pub(crate) async fn spawn() -> anyhow::Result<SocketAddr> {
+    let router = Router::new()
+        .route("/v1/responses", post(handle_responses))
+        .route("/v1/models", get(handle_models));
+    serve(router).await
+}
+
+/// `GET /v1/models` on a local backend: no models beyond Codex's own.
+pub(crate) async fn handle_models() -> Json<Value> {
+    Json(json!({ "data": [] }))
+}
+
+/// Serves `router` on a local port.
+pub(crate) async fn serve(router: Router) -> anyhow::Result<SocketAddr> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("bind local backend")?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, router).await {
+            tracing::error!("local backend stopped: {e}");
+        }
+    });
+    Ok(addr)
+}
+
+/// Config overrides that send every model request to the backend [`serve`]d
+/// at `addr`, without retries so scripted errors surface at once.
+pub(crate) fn config_overrides(addr: SocketAddr) -> Vec<(String, toml::Value)> {
+    let mut provider = toml::Table::new();
+    provider.insert("name".to_string(), "mock".into());
+    provider.insert("base_url".to_string(), format!("http://{addr}/v1").into());
+    provider.insert("wire_api".to_string(), "responses".into());
+    provider.insert("request_max_retries".to_string(), 0.into());
+    provider.insert("stream_max_retries".to_string(), 0.into());
+    vec![
+        (
+            "model_provider".to_string(),
+            MOCK_PROVIDER_ID.to_string().into(),
+        ),
+        (
+            format!("model_providers.{MOCK_PROVIDER_ID}"),
+            toml::Value::Table(provider),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn request(text: &str) -> Value {
+        json!({
+            "input": [{
+                "type": "message",
+                "role": "user",
+                "content": [{ "type": "input_text", "text": text }],
+            }],
+        })
+    }
+
+    #[test]
+    fn markers_pick_the_script() {
+        assert_eq!(
+            Script::for_request(&request("hi")),
+            Script::Reply(MOCK_REPLY)
+        );
+        assert_eq!(
+            Script::for_request(&request("[mock:rate_limit] hi")),
+            Script::HttpError(StatusCode::TOO_MANY_REQUESTS)
+        );
+        assert_eq!(
+            Script::for_request(&request("[mock:context_length]")),
+            Script::Failed("context_length_exceeded")
+        );
+        assert_eq!(
+            Script::for_request(&request("[mock:tool]")),
+            Script::ToolCall
+        );
+        assert_eq!(Script::for_request(&request("[mock:patch]")), Script::Patch);
+        assert_eq!(Script::for_request(&request("[mock:plan]")), Script::Plan);
+        assert_eq!(
+            Script::for_request(&request("[mock:local_shell]")),
+            Script::LocalShellCall
+        );
+        assert_eq!(
+            Script::for_request(&request("[mock:parallel_shell]")),
+            Script::ParallelShellCalls
+        );
+        assert_eq!(
+            Script::for_request(&request("[mock:web_search]")),
+            Script::WebSearch
+        );
+        assert_eq!(
+            Script::for_request(&request("[mock:json]")),
+            Script::Reply(MOCK_JSON_REPLY)
+        );
+        let mut with_instructions = request("[mock:instructions]");
+        with_instructions["instructions"] = json!("Be terse.");
+        assert_eq!(
+            Script::for_request(&with_instructions),
+            Script::Instructions("Be terse.".to_string())
+        );
+
+        assert_eq!(
+            Script::for_request(&request("[mock:echo] hi")),
+            Script::Echo("[mock:echo] hi".to_string())
+        );
+        let mut with_fields = request("[mock:request_fields]");
+        with_fields["model"] = json!("gpt-5.2");
+        let Script::RequestFields(fields) = Script::for_request(&with_fields) else {
+            panic!("expected a request fields script");
+        };
+        assert!(fields.split(' ').any(|field| field == "model"));
+
+        let mut answered = request("[mock:tool]");
+        answered["input"]
+            .as_array_mut()
+            .expect("input")
+            .push(json!({ "type": "function_call_output", "call_id": "call_mock" }));
+        assert_eq!(
+            Script::for_request(&answered),
+            Script::Reply(MOCK_TOOL_REPLY)
+        );
+
+        let deltas = Script::Reply(MOCK_REPLY)
+            .events()
+            .iter()
+            .filter_map(|event| {
+                event
+                    .get("delta")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+            })
+            .collect::<String>();
+        assert_eq!(deltas, MOCK_REPLY);
+    }
+}
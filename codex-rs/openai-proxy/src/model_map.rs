@@ -0,0 +1,421 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::info;
+
+use crate::ProxyError;
+
+/// The `CODEX_OPENAI_PROXY_MODEL_MAP` file, e.g.
+///
+/// ```toml
+/// default = "gpt-5.2-codex"
+/// unmatched = "reject"
+///
+/// [aliases]
+/// "2.5-tpg" = "gpt-5.2"
+/// "gpt-4o" = "gpt-5.2"
+/// "claude-*" = "gpt-5.2-codex"
+/// "*-mini" = "gpt-5.1-codex-mini"
+///
+/// [models."gpt-5.1-codex-mini"]
+/// default_instructions = "Keep answers short."
+/// ```
+///
+/// An alias may contain one `*` matching any run of characters. Exact aliases
+/// win over wildcards, and among wildcards the longest pattern wins. Names
+/// nothing matches go to `default`, or are handled as `unmatched` says when
+/// there is none. `/v1/models` lists the exact aliases, and Codex's own
+/// models too when unmatched names pass through.
+///
+/// Every model the map points at must be one Codex knows, unless
+/// `allow_unknown_models = true` (for models only a custom provider serves).
+///
+/// `default_instructions` are prepended to the developer instructions of
+/// every new thread; a Codex model's `[models]` entry replaces them for it.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModelMapFile {
+    #[serde(default)]
+    default: Option<String>,
+    #[serde(default)]
+    unmatched: Unmatched,
+    #[serde(default)]
+    allow_unknown_models: bool,
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+    #[serde(default)]
+    default_instructions: Option<String>,
+    /// Settings per Codex model.
+    #[serde(default)]
+    models: BTreeMap<String, ModelSettings>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct ModelSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_instructions: Option<String>,
+}
+
+/// What happens to a model name no alias matches and no `default` covers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Unmatched {
+    /// Run the name as given.
+    #[default]
+    Passthrough,
+    /// Fail the request with `model_not_found`.
+    Reject,
+}
+
+#[derive(Debug, Default)]
+struct ModelMap {
+    exact: BTreeMap<String, String>,
+    /// `(prefix, suffix, model)` per wildcard alias, longest pattern first.
+    wildcards: Vec<(String, String, String)>,
+    default: Option<String>,
+    unmatched: Unmatched,
+    allow_unknown_models: bool,
+    default_instructions: Option<String>,
+    models: BTreeMap<String, ModelSettings>,
+}
+
+impl ModelMap {
+    fn parse(contents: &str) -> anyhow::Result<Self> {
+        let file: ModelMapFile = toml::from_str(contents)?;
+        if file
+            .default
+            .as_deref()
+            .is_some_and(|model| model.trim().is_empty())
+        {
+            anyhow::bail!("default must not be empty");
+        }
+        let mut map = Self {
+            default: file.default,
+            unmatched: file.unmatched,
+            allow_unknown_models: file.allow_unknown_models,
+            default_instructions: file.default_instructions,
+            models: file.models,
+            ..Self::default()
+        };
+        for (alias, model) in file.aliases {
+            if model.trim().is_empty() || model.contains('*') {
+                anyhow::bail!("alias {alias:?} must map to a model name");
+            }
+            match alias.split_once('*') {
+                None => {
+                    map.exact.insert(alias, model);
+                }
+                Some((_, suffix)) if suffix.contains('*') => {
+                    anyhow::bail!("alias {alias:?} has more than one `*`");
+                }
+                Some((prefix, suffix)) => {
+                    map.wildcards
+                        .push((prefix.to_string(), suffix.to_string(), model));
+                }
+            }
+        }
+        map.wildcards
+            .sort_by_key(|(prefix, suffix, _)| std::cmp::Reverse(prefix.len() + suffix.len()));
+        Ok(map)
+    }
+
+    /// The Codex model for `model`, or `None` when it is rejected.
+    fn resolve(&self, model: &str) -> Option<String> {
+        if let Some(mapped) = self.exact.get(model) {
+            return Some(mapped.clone());
+        }
+        let wildcard = self.wildcards.iter().find(|(prefix, suffix, _)| {
+            model.len() >= prefix.len() + suffix.len()
+                && model.starts_with(prefix.as_str())
+                && model.ends_with(suffix.as_str())
+        });
+        match (wildcard, &self.default, self.unmatched) {
+            (Some((_, _, mapped)), _, _) => Some(mapped.clone()),
+            (None, Some(default), _) => Some(default.clone()),
+            (None, None, Unmatched::Passthrough) => Some(model.to_string()),
+            (None, None, Unmatched::Reject) => None,
+        }
+    }
+
+    /// The names `/v1/models` lists: the exact aliases, then those of
+    /// `codex_models` that run as themselves.
+    fn advertised(&self, codex_models: &[String]) -> Vec<String> {
+        let mut advertised = self.exact.keys().cloned().collect::<Vec<_>>();
+        for model in codex_models {
+            if !advertised.contains(model) && self.resolve(model).as_ref() == Some(model) {
+                advertised.push(model.clone());
+            }
+        }
+        advertised
+    }
+
+    fn default_instructions(&self, codex_model: &str) -> Option<&str> {
+        self.models
+            .get(codex_model)
+            .and_then(|settings| settings.default_instructions.as_deref())
+            .or(self.default_instructions.as_deref())
+            .filter(|instructions| !instructions.trim().is_empty())
+    }
+
+    /// The models this map points at that `is_known` does not recognize.
+    fn unknown_models(&self, is_known: &dyn Fn(&str) -> bool) -> Vec<String> {
+        let mut unknown = self
+            .exact
+            .values()
+            .chain(self.wildcards.iter().map(|(_, _, model)| model))
+            .chain(&self.default)
+            .filter(|model| !is_known(model))
+            .cloned()
+            .collect::<Vec<_>>();
+        unknown.sort();
+        unknown.dedup();
+        unknown
+    }
+}
+
+/// The mapping one proxy resolves model names with, and the file it was
+/// loaded from so `POST /admin/config/reload` can read it again. Without a
+/// file every name runs as given.
+#[derive(Debug, Default)]
+pub(crate) struct ModelMapping {
+    current: RwLock<(Option<PathBuf>, ModelMap)>,
+}
+
+impl ModelMapping {
+    /// The mapping in `path`. `is_known` tells whether Codex can run a
+    /// model; the mapping is rejected when it points at one it can't.
+    pub(crate) fn load(path: &Path, is_known: &dyn Fn(&str) -> bool) -> anyhow::Result<Self> {
+        let mapping = Self::default();
+        mapping.replace(path, is_known)?;
+        Ok(mapping)
+    }
+
+    /// Reads `path` and uses it for every later request, or keeps the
+    /// current mapping if it fails to load.
+    fn replace(&self, path: &Path, is_known: &dyn Fn(&str) -> bool) -> anyhow::Result<()> {
+        let map = read(path)?;
+        let unknown = map.unknown_models(is_known);
+        if !map.allow_unknown_models && !unknown.is_empty() {
+            anyhow::bail!(
+                "model map {} points at unknown model(s) {}; set allow_unknown_models = true \
+                 if a custom provider serves them",
+                path.display(),
+                unknown.join(", ")
+            );
+        }
+        info!(
+            "model map {}: {} alias(es), {} wildcard(s)",
+            path.display(),
+            map.exact.len(),
+            map.wildcards.len()
+        );
+        if let Ok(mut current) = self.current.write() {
+            *current = (Some(path.to_path_buf()), map);
+        }
+        Ok(())
+    }
+
+    /// The Codex model to run for a client-facing model name, or a
+    /// `model_not_found` error when the map rejects it.
+    pub(crate) fn resolve(&self, model: &str) -> Result<String, ProxyError> {
+        let resolved = match self.current.read() {
+            Ok(current) => current.1.resolve(model),
+            Err(_) => ModelMap::default().resolve(model),
+        };
+        resolved.ok_or_else(|| {
+            ProxyError::new(
+                StatusCode::NOT_FOUND,
+                format!("The model `{model}` does not exist or you do not have access to it."),
+                "invalid_request_error",
+            )
+            .with_code("model_not_found")
+        })
+    }
+
+    /// [`Self::resolve`] for a name the request already got past: its turn
+    /// runs on a thread `get_or_create_thread` accepted the name for.
+    pub(crate) fn codex_model(&self, model: &str) -> String {
+        self.resolve(model).unwrap_or_else(|_| model.to_string())
+    }
+
+    /// The model names to advertise, given the models Codex can run.
+    pub(crate) fn advertised(&self, codex_models: &[String]) -> Vec<String> {
+        match self.current.read() {
+            Ok(current) => current.1.advertised(codex_models),
+            Err(_) => ModelMap::default().advertised(codex_models),
+        }
+    }
+
+    /// The operator's instructions for threads running `codex_model`, if any.
+    pub(crate) fn default_instructions(&self, codex_model: &str) -> Option<String> {
+        let current = self.current.read().ok()?;
+        current
+            .1
+            .default_instructions(codex_model)
+            .map(str::to_string)
+    }
+
+    /// The file the mapping was loaded from, if any.
+    pub(crate) fn path(&self) -> Option<PathBuf> {
+        self.current
+            .read()
+            .ok()
+            .and_then(|current| current.0.clone())
+    }
+
+    /// Whether a model map file is in use.
+    pub(crate) fn is_loaded(&self) -> bool {
+        self.path().is_some()
+    }
+
+    /// Re-reads the file the mapping was loaded from, if any. On error the
+    /// current mapping is kept.
+    pub(crate) fn reload(&self, is_known: &dyn Fn(&str) -> bool) -> anyhow::Result<()> {
+        match self.path() {
+            Some(path) => self.replace(&path, is_known),
+            None => Ok(()),
+        }
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        let Ok(current) = self.current.read() else {
+            return serde_json::Value::Null;
+        };
+        let (path, map) = &*current;
+        let wildcards = map
+            .wildcards
+            .iter()
+            .map(|(prefix, suffix, model)| (format!("{prefix}*{suffix}"), model.clone()))
+            .collect::<BTreeMap<_, _>>();
+        serde_json::json!({
+            "path": path,
+            "aliases": map.exact,
+            "wildcards": wildcards,
+            "default": map.default,
+            "unmatched": map.unmatched,
+            "allow_unknown_models": map.allow_unknown_models,
+            "default_instructions": map.default_instructions,
+            "models": map.models,
+        })
+    }
+}
+
+fn read(path: &Path) -> anyhow::Result<ModelMap> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("read model map {}", path.display()))?;
+    ModelMap::parse(&contents).with_context(|| format!("parse model map {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn exact_aliases_win_over_the_longest_wildcard() {
+        let map = ModelMap::parse(
+            r#"
+            default = "gpt-5.2-codex"
+
+            [aliases]
+            "gpt-4o" = "gpt-5.2"
+            "gpt-*" = "gpt-5.2"
+            "gpt-*-mini" = "gpt-5.1-codex-mini"
+            "#,
+        )
+        .expect("valid model map");
+        let resolve = |model| map.resolve(model).expect("mapped");
+        assert_eq!(resolve("gpt-4o"), "gpt-5.2");
+        assert_eq!(resolve("gpt-4o-mini"), "gpt-5.1-codex-mini");
+        assert_eq!(resolve("gpt-4.1"), "gpt-5.2");
+        assert_eq!(resolve("claude-sonnet"), "gpt-5.2-codex");
+
+        assert!(ModelMap::parse("[aliases]\n\"a*b*\" = \"gpt-5.2\"").is_err());
+        assert!(ModelMap::parse("[aliases]\n\"a\" = \"\"").is_err());
+        assert!(ModelMap::parse("unmatched = \"reverse\"").is_err());
+    }
+
+    #[test]
+    fn unmatched_names_pass_through_unless_rejected() {
+        let codex_models = vec!["gpt-5.2".to_string(), "gpt-5.2-codex".to_string()];
+
+        let empty = ModelMap::parse("").expect("empty model map");
+        assert_eq!(empty.resolve("gpt-5.2"), Some("gpt-5.2".to_string()));
+        assert_eq!(empty.advertised(&codex_models), codex_models);
+
+        let strict = ModelMap::parse(
+            r#"
+            unmatched = "reject"
+
+            [aliases]
+            "2.5-tpg" = "gpt-5.2"
+            "#,
+        )
+        .expect("valid model map");
+        assert_eq!(strict.resolve("2.5-tpg"), Some("gpt-5.2".to_string()));
+        assert_eq!(strict.resolve("gpt-5.2"), None);
+        assert_eq!(
+            strict.advertised(&codex_models),
+            vec!["2.5-tpg".to_string()]
+        );
+
+        let with_default = ModelMap::parse("default = \"gpt-5.2-codex\"").expect("valid");
+        assert_eq!(
+            with_default.advertised(&codex_models),
+            vec!["gpt-5.2-codex".to_string()]
+        );
+    }
+
+    #[test]
+    fn unknown_targets_are_reported() {
+        let map = ModelMap::parse(
+            r#"
+            default = "gpt-5.2-codex"
+
+            [aliases]
+            "a" = "gpt-5.2"
+            "b*" = "my-finetune"
+            "c" = "my-finetune"
+            "#,
+        )
+        .expect("valid model map");
+        let known = |model: &str| model.starts_with("gpt-");
+        assert_eq!(map.unknown_models(&known), vec!["my-finetune".to_string()]);
+    }
+
+    #[test]
+    fn model_instructions_replace_the_default_ones() {
+        let map = ModelMap::parse(
+            r#"
+            default_instructions = "Be polite."
+
+            [models."gpt-5.1-codex-mini"]
+            default_instructions = "Be brief."
+
+            [models."gpt-5.2"]
+            default_instructions = " "
+            "#,
+        )
+        .expect("valid model map");
+        assert_eq!(
+            map.default_instructions("gpt-5.2-codex"),
+            Some("Be polite.")
+        );
+        assert_eq!(
+            map.default_instructions("gpt-5.1-codex-mini"),
+            Some("Be brief.")
+        );
+        assert_eq!(map.default_instructions("gpt-5.2"), None);
+
+        assert_eq!(ModelMap::default().default_instructions("gpt-5.2"), None);
+        assert!(ModelMap::parse("[models.\"gpt-5.2\"]\nsandbox = true").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Content moderation before a turn is submitted: a local rule file of
+//! regexes and/or an external endpoint speaking OpenAI's moderation API.
+//! Flagged input is rejected with a 400 `content_policy` error and never
+//! reaches the model.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use regex::Regex;
+use serde::Deserialize;
+use tracing::info;
+use tracing::warn;
+
+use crate::AppState;
+use crate::ProxyError;
+
+/// An external moderation endpoint, e.g. `https://api.openai.com/v1/moderations`.
+#[derive(Debug)]
+struct Endpoint {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+    timeout: Duration,
+}
+
+/// The body of a moderation response; only `flagged` and the flagged
+/// categories are used.
+#[derive(Debug, Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+    #[serde(default)]
+    categories: serde_json::Map<String, serde_json::Value>,
+}
+
+/// The checks user input goes through before a turn starts.
+#[derive(Debug)]
+pub(crate) struct Moderation {
+    rules_path: Option<PathBuf>,
+    rules: Vec<Regex>,
+    endpoint: Option<Endpoint>,
+}
+
+impl Moderation {
+    /// `None` when neither a rule file nor an endpoint is configured.
+    pub(crate) fn new(
+        rules_path: Option<&Path>,
+        endpoint: Option<(String, Option<String>, Duration)>,
+    ) -> anyhow::Result<Option<Self>> {
+        if rules_path.is_none() && endpoint.is_none() {
+            return Ok(None);
+        }
+        let rules = match rules_path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("read moderation rules {}", path.display()))?;
+                let rules = parse_rules(&text)
+                    .with_context(|| format!("parse moderation rules {}", path.display()))?;
+                info!("{} moderation rules from {}", rules.len(), path.display());
+                rules
+            }
+            None => Vec::new(),
+        };
+        let endpoint = endpoint.map(|(url, api_key, timeout)| Endpoint {
+            client: reqwest::Client::new(),
+            url,
+            api_key,
+            timeout,
+        });
+        Ok(Some(Self {
+            rules_path: rules_path.map(Path::to_path_buf),
+            rules,
+            endpoint,
+        }))
+    }
+
+    /// Rejects `input` if a local rule matches it or the endpoint flags it.
+    /// An endpoint that fails or times out rejects the request too.
+    async fn check(&self, input: &str) -> Result<(), ProxyError> {
+        if let Some(line) = self.rules.iter().position(|rule| rule.is_match(input)) {
+            info!("input rejected by moderation rule {}", line + 1);
+            return Err(rejected("The input was rejected by the content policy."));
+        }
+        let Some(endpoint) = &self.endpoint else {
+            return Ok(());
+        };
+        let mut request = endpoint
+            .client
+            .post(&endpoint.url)
+            .timeout(endpoint.timeout)
+            .json(&serde_json::json!({ "input": input }));
+        if let Some(api_key) = &endpoint.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = match request
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+        {
+            Ok(response) => response.json::<ModerationResponse>().await,
+            Err(e) => Err(e),
+        };
+        let response = response.map_err(|e| {
+            warn!("moderation endpoint failed: {e}");
+            ProxyError::new(
+                StatusCode::BAD_GATEWAY,
+                "The moderation service could not check the input.",
+                "server_error",
+            )
+            .with_code("moderation_unavailable")
+        })?;
+        let categories = response
+            .results
+            .iter()
+            .filter(|result| result.flagged)
+            .flat_map(|result| {
+                result
+                    .categories
+                    .iter()
+                    .filter(|(_, flagged)| flagged.as_bool() == Some(true))
+                    .map(|(category, _)| category.as_str())
+            })
+            .collect::<Vec<_>>();
+        if response.results.iter().any(|result| result.flagged) {
+            let message = if categories.is_empty() {
+                "The input was flagged by the content policy.".to_string()
+            } else {
+                format!(
+                    "The input was flagged by the content policy: {}.",
+                    categories.join(", ")
+                )
+            };
+            return Err(rejected(&message));
+        }
+        Ok(())
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "rules_path": self.rules_path,
+            "rules": self.rules.len(),
+            "endpoint": self.endpoint.as_ref().map(|endpoint| serde_json::json!({
+                "url": endpoint.url,
+                "api_key": endpoint.api_key.is_some(),
+                "timeout_secs": endpoint.timeout.as_secs(),
+            })),
+        })
+    }
+}
+
+/// Checks `input` against `state`'s moderation, if any.
+pub(crate) async fn check(state: &AppState, input: &str) -> Result<(), ProxyError> {
+    match &state.moderation {
+        Some(moderation) => moderation.check(input).await,
+        None => Ok(()),
+    }
+}
+
+fn rejected(message: &str) -> ProxyError {
+    ProxyError::new(StatusCode::BAD_REQUEST, message, "invalid_request_error")
+        .with_code("content_policy")
+}
+
+/// One regex per line; blank lines and lines starting with `#` are skipped.
+fn parse_rules(text: &str) -> anyhow::Result<Vec<Regex>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| Regex::new(line).with_context(|| format!("invalid rule {line:?}")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn local_rules_reject_matching_input() {
+        let rules = parse_rules("# blocked topics\n\n(?i)\\bforbidden\\b\n").expect("rules");
+        let moderation = Moderation {
+            rules_path: None,
+            rules,
+            endpoint: None,
+        };
+
+        assert!(moderation.check("an allowed question").await.is_ok());
+        let error = moderation
+            .check("something Forbidden")
+            .await
+            .expect_err("rejected");
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.code, Some("content_policy"));
+
+        assert!(parse_rules("(unclosed").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Output-token caps (`max_tokens`, `max_completion_tokens`,
+//! `max_output_tokens`) and the Responses `max_tool_calls` cap. Codex has no
+//! per-turn output or tool call limit, so the proxy counts the assistant text
+//! and tool calls it delivers and cuts the answer off, ending the turn, once a
+//! cap is reached.
+
+use codex_core::CodexThread;
+use codex_core::protocol::Op;
+use codex_protocol::models::ResponseItem;
+use codex_protocol::protocol::EventMsg;
+use tracing::warn;
+
+/// Matches the estimate Codex itself truncates tool output with.
+const APPROX_BYTES_PER_TOKEN: usize = 4;
+
+/// The tighter of the proxy-wide cap and the one a request asked for.
+pub(crate) fn effective(configured: Option<u64>, requested: Option<u64>) -> Option<u64> {
+    match (configured, requested) {
+        (Some(configured), Some(requested)) => Some(configured.min(requested)),
+        (configured, requested) => configured.or(requested),
+    }
+}
+
+/// How much assistant text one response may still deliver.
+#[derive(Debug)]
+pub(crate) struct OutputCap {
+    /// Bytes left; `None` when uncapped.
+    remaining: Option<usize>,
+    reached: bool,
+}
+
+impl OutputCap {
+    pub(crate) fn new(max_tokens: Option<u64>) -> Self {
+        Self {
+            remaining: max_tokens.map(|tokens| {
+                usize::try_from(tokens)
+                    .unwrap_or(usize::MAX)
+                    .saturating_mul(APPROX_BYTES_PER_TOKEN)
+            }),
+            reached: false,
+        }
+    }
+
+    /// The part of `text` that still fits. Once text had to be cut (or
+    /// dropped) the cap is [`reached`](Self::reached).
+    pub(crate) fn take<'a>(&mut self, text: &'a str) -> &'a str {
+        let Some(remaining) = self.remaining.as_mut() else {
+            return text;
+        };
+        if text.len() <= *remaining {
+            *remaining -= text.len();
+            return text;
+        }
+        let mut end = *remaining;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        *remaining = 0;
+        self.reached = true;
+        &text[..end]
+    }
+
+    pub(crate) fn reached(&self) -> bool {
+        self.reached
+    }
+}
+
+/// How many more tool calls one response may make.
+#[derive(Debug)]
+pub(crate) struct ToolCallCap {
+    /// `None` when uncapped.
+    remaining: Option<u64>,
+    reached: bool,
+}
+
+impl ToolCallCap {
+    pub(crate) fn new(max_tool_calls: Option<u64>) -> Self {
+        Self {
+            remaining: max_tool_calls,
+            reached: false,
+        }
+    }
+
+    /// Whether `item` may go out. A tool call past the cap may not, and
+    /// leaves the cap [`reached`](Self::reached).
+    pub(crate) fn admit(&mut self, item: &ResponseItem) -> bool {
+        let is_tool_call = matches!(
+            item,
+            ResponseItem::FunctionCall { .. }
+                | ResponseItem::CustomToolCall { .. }
+                | ResponseItem::LocalShellCall { .. }
+                | ResponseItem::WebSearchCall { .. }
+        );
+        let Some(remaining) = self.remaining.as_mut().filter(|_| is_tool_call) else {
+            return true;
+        };
+        if *remaining == 0 {
+            self.reached = true;
+            return false;
+        }
+        *remaining -= 1;
+        true
+    }
+
+    pub(crate) fn reached(&self) -> bool {
+        self.reached
+    }
+}
+
+/// Stops the turn whose answer was cut off; its remaining events are
+/// drained until one that [`ends_turn`].
+pub(crate) async fn interrupt(thread: &CodexThread) {
+    if let Err(e) = thread.submit(Op::Interrupt).await {
+        warn!("failed to interrupt turn past its cap: {e}");
+    }
+}
+
+/// Whether `msg` is the last event of an interrupted turn.
+pub(crate) fn ends_turn(msg: &EventMsg) -> bool {
+    matches!(
+        msg,
+        EventMsg::TurnComplete(_) | EventMsg::TurnAborted(_) | EventMsg::Error(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn text_is_cut_at_the_cap() {
+        let mut cap = OutputCap::new(Some(2));
+        assert_eq!(cap.take("abcde"), "abcde");
+        assert!(!cap.reached());
+        assert_eq!(cap.take("éfgh"), "éf");
+        assert!(cap.reached());
+
+        let mut cap = OutputCap::new(Some(2));
+        assert_eq!(cap.take("abcdefg"), "abcdefg");
+        assert_eq!(cap.take("é"), "");
+        assert!(cap.reached());
+        assert_eq!(cap.take("more"), "");
+
+        let mut uncapped = OutputCap::new(None);
+        assert_eq!(uncapped.take(&"x".repeat(10_000)).len(), 10_000);
+        assert!(!uncapped.reached());
+
+        assert_eq!(effective(Some(100), Some(10)), Some(10));
+        assert_eq!(effective(None, Some(10)), Some(10));
+        assert_eq!(effective(Some(100), None), Some(100));
+    }
+
+    #[test]
+    fn tool_calls_past_the_cap_are_held_back() {
+        let call = ResponseItem::FunctionCall {
+            id: None,
+            name: "shell".to_string(),
+            arguments: "{}".to_string(),
+            call_id: "call_1".to_string(),
+        };
+        let message = ResponseItem::Message {
+            id: None,
+            role: "assistant".to_string(),
+            content: Vec::new(),
+        };
+
+        let mut cap = ToolCallCap::new(Some(1));
+        assert!(cap.admit(&call));
+        assert!(cap.admit(&message));
+        assert!(!cap.reached());
+        assert!(!cap.admit(&call));
+        assert!(cap.reached());
+
+        let mut uncapped = ToolCallCap::new(None);
+        assert!((0..100).all(|_| uncapped.admit(&call)));
+        assert!(!uncapped.reached());
+    }
+}
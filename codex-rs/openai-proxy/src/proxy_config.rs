@@ -0,0 +1,425 @@
+//! The `--config` file, `proxy.toml`: the proxy's own settings in one place
+//! instead of `CODEX_OPENAI_PROXY_*` variables. Each setting stands in for
+//! the variable of the same name, so the variable still wins when both are
+//! set, and CLI flags win over both.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::env;
+use std::env::VarError;
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+/// The loaded file and its settings, keyed by the variable each stands in
+/// for; replaced by [`reload`].
+static FILE_SETTINGS: RwLock<Option<(PathBuf, HashMap<String, String>)>> = RwLock::new(None);
+
+/// Keys of `[limits]`, each standing in for `CODEX_OPENAI_PROXY_<KEY>`.
+const LIMIT_KEYS: &[&str] = &[
+    "rate_limit_rpm",
+    "rate_limit_tpm",
+    "max_concurrent_turns",
+    "turn_queue_size",
+    "max_output_tokens",
+    "max_request_body_bytes",
+    "batch_concurrency",
+    "thread_idle_ttl_mins",
+    "response_cache_ttl_secs",
+    "idempotency_ttl_secs",
+    "response_store_ttl_secs",
+    "ledger_retention_days",
+    "upstream_max_attempts",
+    "upstream_retry_backoff_ms",
+    "circuit_min_requests",
+    "circuit_failure_rate_percent",
+    "circuit_open_secs",
+];
+
+/// A `proxy.toml`, e.g.
+///
+/// ```toml
+/// listen = "0.0.0.0:11435"
+/// tenants = "tenants.toml"
+/// model_map = "models.toml"
+///
+/// [limits]
+/// rate_limit_rpm = 60
+/// max_concurrent_turns = 4
+///
+/// [sandbox]
+/// mode = "workspace-write"
+/// approval_policy = "never"
+///
+/// [cors]
+/// allow_origins = ["https://app.example.com"]
+/// ```
+///
+/// Relative paths are resolved against the file's directory.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ProxyConfigFile {
+    /// Address to listen on, as `CODEX_OPENAI_PROXY_ADDR`.
+    listen: Option<String>,
+    /// API keys and their Codex homes, as `CODEX_OPENAI_PROXY_TENANTS`.
+    tenants: Option<PathBuf>,
+    /// Model aliases, as `CODEX_OPENAI_PROXY_MODEL_MAP`.
+    model_map: Option<PathBuf>,
+    /// Forward mode upstreams, as `CODEX_OPENAI_PROXY_ROUTES`.
+    routes: Option<PathBuf>,
+    /// The request ledger, as `CODEX_OPENAI_PROXY_LEDGER_PATH`.
+    ledger_path: Option<PathBuf>,
+    #[serde(default)]
+    audit: AuditSection,
+    #[serde(default)]
+    redaction: RedactionSection,
+    #[serde(default)]
+    moderation: ModerationSection,
+    #[serde(default)]
+    turn_webhook: TurnWebhookSection,
+    #[serde(default)]
+    limits: BTreeMap<String, u64>,
+    #[serde(default)]
+    workspace: WorkspaceSection,
+    #[serde(default)]
+    sandbox: SandboxSection,
+    #[serde(default)]
+    cors: CorsSection,
+    #[serde(default)]
+    pub(crate) tls: TlsSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AuditSection {
+    /// As `CODEX_OPENAI_PROXY_AUDIT_LOG`.
+    log: Option<PathBuf>,
+    /// `hash` or `full`, as `CODEX_OPENAI_PROXY_AUDIT_LOG_CONTENT`.
+    content: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RedactionSection {
+    /// Built-in patterns, as `CODEX_OPENAI_PROXY_REDACT`.
+    #[serde(default)]
+    builtin: Vec<String>,
+    /// Extra regexes, as `CODEX_OPENAI_PROXY_REDACT_PATTERNS`.
+    #[serde(default)]
+    patterns: Vec<String>,
+    /// As `CODEX_OPENAI_PROXY_REDACT_PROMPTS`.
+    prompts: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModerationSection {
+    /// Regex file, as `CODEX_OPENAI_PROXY_MODERATION_RULES`.
+    rules: Option<PathBuf>,
+    /// As `CODEX_OPENAI_PROXY_MODERATION_URL`.
+    url: Option<String>,
+    /// As `CODEX_OPENAI_PROXY_MODERATION_API_KEY`.
+    api_key: Option<String>,
+    /// As `CODEX_OPENAI_PROXY_MODERATION_TIMEOUT_SECS`.
+    timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TurnWebhookSection {
+    /// As `CODEX_OPENAI_PROXY_TURN_WEBHOOK_URL`.
+    url: Option<String>,
+    /// As `CODEX_OPENAI_PROXY_TURN_WEBHOOK_TIMEOUT_SECS`.
+    timeout_secs: Option<u64>,
+    /// As `CODEX_OPENAI_PROXY_TURN_WEBHOOK_MIN_SECS`.
+    min_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorkspaceSection {
+    #[serde(default)]
+    roots: Vec<PathBuf>,
+    /// `reject` or `clamp`.
+    outside: Option<String>,
+}
+
+/// Codex's sandbox and approval defaults for every thread, on top of the
+/// Codex home's `config.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SandboxSection {
+    mode: Option<String>,
+    approval_policy: Option<String>,
+    network_access: Option<bool>,
+    /// As `CODEX_OPENAI_PROXY_ALLOW_DANGER_FULL_ACCESS`.
+    allow_danger_full_access: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CorsSection {
+    /// Origins browsers may call the proxy from; empty allows any.
+    #[serde(default)]
+    allow_origins: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TlsSection {
+    pub(crate) cert: Option<PathBuf>,
+    pub(crate) key: Option<PathBuf>,
+}
+
+impl ProxyConfigFile {
+    fn parse(text: &str, dir: &Path) -> anyhow::Result<Self> {
+        let mut file: Self = toml::from_str(text)?;
+        if let Some(key) = file
+            .limits
+            .keys()
+            .find(|key| !LIMIT_KEYS.contains(&key.as_str()))
+        {
+            anyhow::bail!(
+                "unknown limit {key:?}; expected one of {}",
+                LIMIT_KEYS.join(", ")
+            );
+        }
+        if file.tls.cert.is_some() != file.tls.key.is_some() {
+            anyhow::bail!("[tls] needs both cert and key");
+        }
+        let paths = [
+            &mut file.tenants,
+            &mut file.model_map,
+            &mut file.routes,
+            &mut file.ledger_path,
+            &mut file.audit.log,
+            &mut file.moderation.rules,
+            &mut file.tls.cert,
+            &mut file.tls.key,
+        ];
+        for path in paths.into_iter().flatten() {
+            *path = dir.join(&*path);
+        }
+        for root in &mut file.workspace.roots {
+            *root = dir.join(&*root);
+        }
+        Ok(file)
+    }
+
+    /// The variables the file's settings stand in for.
+    fn settings(&self) -> anyhow::Result<HashMap<String, String>> {
+        let mut settings = HashMap::new();
+        let mut set = |name: &str, value: String| {
+            settings.insert(format!("CODEX_OPENAI_PROXY_{name}"), value);
+        };
+        let paths = [
+            ("TENANTS", &self.tenants),
+            ("MODEL_MAP", &self.model_map),
+            ("ROUTES", &self.routes),
+            ("LEDGER_PATH", &self.ledger_path),
+            ("AUDIT_LOG", &self.audit.log),
+            ("MODERATION_RULES", &self.moderation.rules),
+        ];
+        for (name, path) in paths {
+            if let Some(path) = path {
+                set(name, path.display().to_string());
+            }
+        }
+        if let Some(content) = &self.audit.content {
+            set("AUDIT_LOG_CONTENT", content.clone());
+        }
+        if let Some(listen) = &self.listen {
+            set("ADDR", listen.clone());
+        }
+        for (key, value) in &self.limits {
+            set(&key.to_uppercase(), value.to_string());
+        }
+        if !self.workspace.roots.is_empty() {
+            let roots = env::join_paths(&self.workspace.roots).context("join workspace roots")?;
+            set("WORKSPACE_ROOTS", roots.to_string_lossy().into_owned());
+        }
+        if let Some(outside) = &self.workspace.outside {
+            set("WORKSPACE_OUTSIDE", outside.clone());
+        }
+        let redaction = &self.redaction;
+        if !redaction.builtin.is_empty() {
+            set("REDACT", redaction.builtin.join(","));
+        }
+        if !redaction.patterns.is_empty() {
+            set("REDACT_PATTERNS", redaction.patterns.join("\n"));
+        }
+        if let Some(prompts) = redaction.prompts {
+            set("REDACT_PROMPTS", prompts.to_string());
+        }
+        let moderation = &self.moderation;
+        if let Some(url) = &moderation.url {
+            set("MODERATION_URL", url.clone());
+        }
+        if let Some(api_key) = &moderation.api_key {
+            set("MODERATION_API_KEY", api_key.clone());
+        }
+        if let Some(timeout_secs) = moderation.timeout_secs {
+            set("MODERATION_TIMEOUT_SECS", timeout_secs.to_string());
+        }
+        let turn_webhook = &self.turn_webhook;
+        if let Some(url) = &turn_webhook.url {
+            set("TURN_WEBHOOK_URL", url.clone());
+        }
+        if let Some(timeout_secs) = turn_webhook.timeout_secs {
+            set("TURN_WEBHOOK_TIMEOUT_SECS", timeout_secs.to_string());
+        }
+        if let Some(min_secs) = turn_webhook.min_secs {
+            set("TURN_WEBHOOK_MIN_SECS", min_secs.to_string());
+        }
+        if let Some(allow) = self.sandbox.allow_danger_full_access {
+            set("ALLOW_DANGER_FULL_ACCESS", allow.to_string());
+        }
+        if !self.cors.allow_origins.is_empty() {
+            set("CORS_ORIGINS", self.cors.allow_origins.join(","));
+        }
+        Ok(settings)
+    }
+
+    /// `-c`-style overrides for the `[sandbox]` defaults.
+    pub(crate) fn config_overrides(&self) -> Vec<(String, toml::Value)> {
+        let sandbox = &self.sandbox;
+        let mut overrides = Vec::new();
+        if let Some(mode) = &sandbox.mode {
+            overrides.push(("sandbox_mode".to_string(), mode.clone().into()));
+        }
+        if let Some(policy) = &sandbox.approval_policy {
+            overrides.push(("approval_policy".to_string(), policy.clone().into()));
+        }
+        if let Some(network_access) = sandbox.network_access {
+            overrides.push((
+                "sandbox_workspace_write.network_access".to_string(),
+                network_access.into(),
+            ));
+        }
+        overrides
+    }
+}
+
+/// Reads the `--config` file and makes its settings the fallback for unset
+/// `CODEX_OPENAI_PROXY_*` variables.
+pub(crate) fn load(path: &Path) -> anyhow::Result<ProxyConfigFile> {
+    let (file, settings) = read(path)?;
+    let mut loaded = FILE_SETTINGS
+        .write()
+        .map_err(|_| anyhow::anyhow!("proxy config poisoned"))?;
+    if loaded.is_some() {
+        anyhow::bail!("a proxy config is already loaded");
+    }
+    *loaded = Some((path.to_path_buf(), settings));
+    Ok(file)
+}
+
+/// Re-reads the `--config` file, if one was loaded, keeping the current
+/// settings when it fails to load. Only what is read after startup picks the
+/// new settings up; see [`crate::limits`].
+pub(crate) fn reload() -> anyhow::Result<()> {
+    let Some(path) = path() else {
+        return Ok(());
+    };
+    let (_, settings) = read(&path)?;
+    if let Ok(mut loaded) = FILE_SETTINGS.write() {
+        *loaded = Some((path, settings));
+    }
+    Ok(())
+}
+
+/// The `--config` file, if one was loaded.
+pub(crate) fn path() -> Option<PathBuf> {
+    let loaded = FILE_SETTINGS.read().ok()?;
+    loaded.as_ref().map(|(path, _)| path.clone())
+}
+
+fn read(path: &Path) -> anyhow::Result<(ProxyConfigFile, HashMap<String, String>)> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("read proxy config {}", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let file = ProxyConfigFile::parse(&text, dir)
+        .with_context(|| format!("parse proxy config {}", path.display()))?;
+    let settings = file.settings()?;
+    Ok((file, settings))
+}
+
+/// `env::var`, falling back to the `--config` file.
+pub(crate) fn var(name: &str) -> Result<String, VarError> {
+    match env::var(name) {
+        Err(VarError::NotPresent) => file_setting(name).ok_or(VarError::NotPresent),
+        value => value,
+    }
+}
+
+/// `env::var_os`, falling back to the `--config` file.
+pub(crate) fn var_os(name: &str) -> Option<OsString> {
+    env::var_os(name).or_else(|| file_setting(name).map(OsString::from))
+}
+
+fn file_setting(name: &str) -> Option<String> {
+    let loaded = FILE_SETTINGS.read().ok()?;
+    loaded.as_ref()?.1.get(name).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn settings_stand_in_for_variables() {
+        let file = ProxyConfigFile::parse(
+            r#"
+listen = "0.0.0.0:8080"
+model_map = "models.toml"
+
+[limits]
+rate_limit_rpm = 60
+
+[sandbox]
+mode = "read-only"
+network_access = false
+
+[cors]
+allow_origins = ["https://a.example", "https://b.example"]
+"#,
+            Path::new("/etc/proxy"),
+        )
+        .expect("parse");
+
+        let settings = file.settings().expect("settings");
+        let mut settings = settings.into_iter().collect::<Vec<_>>();
+        settings.sort();
+        let expected = [
+            ("CODEX_OPENAI_PROXY_ADDR", "0.0.0.0:8080"),
+            (
+                "CODEX_OPENAI_PROXY_CORS_ORIGINS",
+                "https://a.example,https://b.example",
+            ),
+            ("CODEX_OPENAI_PROXY_MODEL_MAP", "/etc/proxy/models.toml"),
+            ("CODEX_OPENAI_PROXY_RATE_LIMIT_RPM", "60"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        assert_eq!(settings, expected);
+        assert_eq!(
+            file.config_overrides(),
+            vec![
+                ("sandbox_mode".to_string(), "read-only".into()),
+                (
+                    "sandbox_workspace_write.network_access".to_string(),
+                    false.into()
+                ),
+            ]
+        );
+
+        let error = ProxyConfigFile::parse("[limits]\nrpm = 1\n", Path::new("."))
+            .expect_err("unknown limit");
+        assert!(error.to_string().starts_with("unknown limit \"rpm\""));
+        assert!(ProxyConfigFile::parse("[tls]\ncert = \"c.pem\"\n", Path::new(".")).is_err());
+    }
+}
@@ -0,0 +1,543 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+use axum::extract::ConnectInfo;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::http::header::AUTHORIZATION;
+use axum::http::header::RETRY_AFTER;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::access_log::RequestContext;
+use crate::access_log::hold_until_body_ends;
+use crate::usage::key_fingerprint;
+
+/// Clients untouched for this long are dropped once the table grows past
+/// [`PRUNE_THRESHOLD`]; their buckets would have refilled anyway.
+const IDLE_CLIENT_TTL: Duration = Duration::from_secs(10 * 60);
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Clients listed by [`RateLimiter::status`], those closest to a limit first.
+const STATUS_CLIENTS: usize = 50;
+
+/// A bucket holding up to a minute's allowance, refilled continuously. Tokens
+/// may go negative when a turn uses more than was left; the client then waits
+/// for the debt to be repaid.
+#[derive(Debug, Clone)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    refill_per_sec: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn per_minute(limit: u64, now: Instant) -> Self {
+        let capacity = limit as f64;
+        Self {
+            capacity,
+            available: capacity,
+            refill_per_sec: capacity / 60.0,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * self.refill_per_sec).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Time until at least `amount` is available.
+    fn wait_for(&self, amount: f64) -> Duration {
+        if self.available >= amount {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((amount - self.available) / self.refill_per_sec)
+    }
+
+    /// Time until the bucket is full again.
+    fn reset_after(&self) -> Duration {
+        Duration::from_secs_f64((self.capacity - self.available).max(0.0) / self.refill_per_sec)
+    }
+
+    fn remaining(&self) -> u64 {
+        self.available.max(0.0) as u64
+    }
+}
+
+#[derive(Debug)]
+struct ClientBuckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    last_seen: Instant,
+}
+
+/// Which limit rejected a request; doubles as the OpenAI error `type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exceeded {
+    Requests,
+    Tokens,
+}
+
+/// Outcome of admitting one request, with the values for the
+/// `x-ratelimit-*` headers.
+#[derive(Debug)]
+struct Admission {
+    exceeded: Option<(Exceeded, Duration)>,
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+}
+
+/// Requests and tokens per minute; `None` is unlimited.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Limits {
+    rpm: Option<u64>,
+    tpm: Option<u64>,
+}
+
+/// Per-client token buckets for requests per minute and tokens per minute.
+/// Clients are identified by API key, or by IP address when they send none.
+pub(crate) struct RateLimiter {
+    limits: RwLock<Limits>,
+    clients: Mutex<HashMap<String, ClientBuckets>>,
+    /// Requests turned away with a 429 since startup.
+    rejected: AtomicU64,
+}
+
+impl RateLimiter {
+    /// Neither limit set lets every request through.
+    pub(crate) fn new(rpm: Option<u64>, tpm: Option<u64>) -> Self {
+        Self {
+            limits: RwLock::new(Limits { rpm, tpm }),
+            clients: Mutex::new(HashMap::new()),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Replaces the limits, as on a config reload. Clients start over with
+    /// full buckets of the new size.
+    pub(crate) fn set_limits(&self, rpm: Option<u64>, tpm: Option<u64>) {
+        let Ok(mut limits) = self.limits.write() else {
+            return;
+        };
+        if *limits == (Limits { rpm, tpm }) {
+            return;
+        }
+        *limits = Limits { rpm, tpm };
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.clear();
+        }
+    }
+
+    fn limits(&self) -> Limits {
+        self.limits.read().map(|limits| *limits).unwrap_or_default()
+    }
+
+    /// Limits, rejections and what the busiest clients have left, for
+    /// `GET /admin/rate-limits`. API keys are shown as fingerprints.
+    pub(crate) fn status(&self) -> serde_json::Value {
+        let Limits { rpm, tpm } = self.limits();
+        let now = Instant::now();
+        let remaining = |bucket: &Option<Bucket>| {
+            bucket.clone().map(|mut bucket| {
+                bucket.refill(now);
+                bucket.remaining()
+            })
+        };
+        let mut clients = self
+            .clients
+            .lock()
+            .map(|clients| {
+                clients
+                    .iter()
+                    .map(|(client, buckets)| {
+                        let client = match client.strip_prefix("key:") {
+                            Some(key) => format!("key:{}", key_fingerprint(key)),
+                            None => client.clone(),
+                        };
+                        (
+                            client,
+                            remaining(&buckets.requests),
+                            remaining(&buckets.tokens),
+                            now.saturating_duration_since(buckets.last_seen).as_secs(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let tracked = clients.len();
+        // Fraction of a minute's allowance left, whichever limit is tighter.
+        let headroom = |(_, requests, tokens, _): &(String, Option<u64>, Option<u64>, u64)| {
+            let requests = requests
+                .zip(rpm)
+                .map(|(left, limit)| left as f64 / limit as f64);
+            let tokens = tokens
+                .zip(tpm)
+                .map(|(left, limit)| left as f64 / limit as f64);
+            requests.into_iter().chain(tokens).fold(1.0, f64::min)
+        };
+        clients.sort_by(|a, b| headroom(a).total_cmp(&headroom(b)));
+        clients.truncate(STATUS_CLIENTS);
+        serde_json::json!({
+            "requests_per_minute": rpm,
+            "tokens_per_minute": tpm,
+            "total_rejected": self.rejected.load(Ordering::Relaxed),
+            "tracked_clients": tracked,
+            "clients": clients
+                .into_iter()
+                .map(|(client, requests, tokens, idle_secs)| {
+                    serde_json::json!({
+                        "client": client,
+                        "remaining_requests": requests,
+                        "remaining_tokens": tokens,
+                        "idle_secs": idle_secs,
+                    })
+                })
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Takes one request from the client's request bucket. The token bucket is
+    /// only checked here, and charged by [`Self::charge_tokens`] once the turn
+    /// reports what it used.
+    fn admit(&self, client: &str, now: Instant) -> Admission {
+        let Limits { rpm, tpm } = self.limits();
+        let Ok(mut clients) = self.clients.lock() else {
+            return Admission {
+                exceeded: None,
+                requests: None,
+                tokens: None,
+            };
+        };
+        if clients.len() > PRUNE_THRESHOLD {
+            clients.retain(|_, buckets| {
+                now.saturating_duration_since(buckets.last_seen) < IDLE_CLIENT_TTL
+            });
+        }
+        let buckets = clients
+            .entry(client.to_string())
+            .or_insert_with(|| ClientBuckets {
+                requests: rpm.map(|limit| Bucket::per_minute(limit, now)),
+                tokens: tpm.map(|limit| Bucket::per_minute(limit, now)),
+                last_seen: now,
+            });
+        buckets.last_seen = now;
+
+        let mut exceeded = None;
+        if let Some(requests) = &mut buckets.requests {
+            requests.refill(now);
+            let wait = requests.wait_for(1.0);
+            if wait > Duration::ZERO {
+                exceeded = Some((Exceeded::Requests, wait));
+            }
+        }
+        if let Some(tokens) = &mut buckets.tokens {
+            tokens.refill(now);
+            let wait = tokens.wait_for(1.0);
+            if exceeded.is_none() && wait > Duration::ZERO {
+                exceeded = Some((Exceeded::Tokens, wait));
+            }
+        }
+        if exceeded.is_none()
+            && let Some(requests) = &mut buckets.requests
+        {
+            requests.available -= 1.0;
+        }
+
+        Admission {
+            exceeded,
+            requests: buckets.requests.clone(),
+            tokens: buckets.tokens.clone(),
+        }
+    }
+
+    fn charge_tokens(&self, client: &str, used: i64) {
+        if used <= 0 {
+            return;
+        }
+        let now = Instant::now();
+        if let Ok(mut clients) = self.clients.lock()
+            && let Some(tokens) = clients
+                .get_mut(client)
+                .and_then(|buckets| buckets.tokens.as_mut())
+        {
+            tokens.refill(now);
+            tokens.available -= used as f64;
+        }
+    }
+}
+
+/// Charges the tokens a request's turn used when dropped, i.e. once its
+/// response (or SSE stream) is done.
+struct TokenCharge {
+    limiter: Arc<RateLimiter>,
+    client: String,
+    context: RequestContext,
+}
+
+impl Drop for TokenCharge {
+    fn drop(&mut self) {
+        if let Some(used) = self.context.tokens() {
+            self.limiter.charge_tokens(&self.client, used);
+        }
+    }
+}
+
+/// The API key the caller sent, if any.
+pub(crate) fn api_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            // `x-api-key` (Anthropic) or `api-key` (Azure OpenAI).
+            ["x-api-key", "api-key"]
+                .into_iter()
+                .find_map(|name| headers.get(name).and_then(|value| value.to_str().ok()))
+        })
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+/// Identifies the caller: its API key when it sends one, otherwise its IP.
+pub(crate) fn client_key(headers: &HeaderMap, peer: Option<SocketAddr>) -> String {
+    match (api_key(headers), peer) {
+        (Some(key), _) => format!("key:{key}"),
+        (None, Some(peer)) => format!("ip:{}", peer.ip()),
+        (None, None) => "ip:unknown".to_string(),
+    }
+}
+
+/// Formats a reset time the way OpenAI does, e.g. `1s` or `6m0s`.
+pub(crate) fn format_reset(duration: Duration) -> String {
+    let secs = duration.as_secs_f64().ceil() as u64;
+    if secs >= 60 {
+        format!("{}m{}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+fn insert_header(headers: &mut HeaderMap, name: &'static str, value: String) {
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(name, value);
+    }
+}
+
+fn set_rate_limit_headers(headers: &mut HeaderMap, admission: &Admission) {
+    if let Some(requests) = &admission.requests {
+        insert_header(
+            headers,
+            "x-ratelimit-limit-requests",
+            (requests.capacity as u64).to_string(),
+        );
+        insert_header(
+            headers,
+            "x-ratelimit-remaining-requests",
+            requests.remaining().to_string(),
+        );
+        insert_header(
+            headers,
+            "x-ratelimit-reset-requests",
+            format_reset(requests.reset_after()),
+        );
+    }
+    if let Some(tokens) = &admission.tokens {
+        insert_header(
+            headers,
+            "x-ratelimit-limit-tokens",
+            (tokens.capacity as u64).to_string(),
+        );
+        insert_header(
+            headers,
+            "x-ratelimit-remaining-tokens",
+            tokens.remaining().to_string(),
+        );
+        insert_header(
+            headers,
+            "x-ratelimit-reset-tokens",
+            format_reset(tokens.reset_after()),
+        );
+    }
+}
+
+/// Middleware enforcing [`RateLimiter`] on the turn endpoints. Rejected
+/// requests get an OpenAI-style 429 with `Retry-After`.
+pub(crate) async fn rate_limit(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limiter = state.rate_limiter.clone();
+    let limits = limiter.limits();
+    if limits.rpm.is_none() && limits.tpm.is_none() {
+        return next.run(request).await;
+    }
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0);
+    let client = client_key(request.headers(), peer);
+    let admission = limiter.admit(&client, Instant::now());
+
+    if let Some((exceeded, wait)) = admission.exceeded {
+        limiter.rejected.fetch_add(1, Ordering::Relaxed);
+        let (kind, message) = match exceeded {
+            Exceeded::Requests => (
+                "requests",
+                format!(
+                    "Rate limit reached for requests per minute (limit {}). Please try again in {}.",
+                    limits.rpm.unwrap_or_default(),
+                    format_reset(wait)
+                ),
+            ),
+            Exceeded::Tokens => (
+                "tokens",
+                format!(
+                    "Rate limit reached for tokens per minute (limit {}). Please try again in {}.",
+                    limits.tpm.unwrap_or_default(),
+                    format_reset(wait)
+                ),
+            ),
+        };
+        let mut response = ProxyError::new(StatusCode::TOO_MANY_REQUESTS, message, kind)
+            .with_code("rate_limit_exceeded")
+            .into_response();
+        let headers = response.headers_mut();
+        set_rate_limit_headers(headers, &admission);
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from(wait.as_secs_f64().ceil() as u64),
+        );
+        return response;
+    }
+
+    let context = request.extensions().get::<RequestContext>().cloned();
+    let mut response = next.run(request).await;
+    set_rate_limit_headers(response.headers_mut(), &admission);
+    match context {
+        Some(context) if limits.tpm.is_some() => hold_until_body_ends(
+            response,
+            TokenCharge {
+                limiter,
+                client,
+                context,
+            },
+        ),
+        _ => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn requests_are_limited_per_client() {
+        let limiter = RateLimiter::new(Some(2), None);
+        let now = Instant::now();
+
+        assert_eq!(limiter.admit("key:a", now).exceeded, None);
+        assert_eq!(limiter.admit("key:a", now).exceeded, None);
+        let third = limiter.admit("key:a", now);
+        let (kind, wait) = third.exceeded.expect("third request is limited");
+        assert_eq!(kind, Exceeded::Requests);
+        assert!(wait > Duration::from_secs(29) && wait <= Duration::from_secs(31));
+        assert_eq!(limiter.admit("key:b", now).exceeded, None);
+        assert_eq!(
+            limiter
+                .admit("key:a", now + Duration::from_secs(31))
+                .exceeded,
+            None
+        );
+    }
+
+    #[test]
+    fn new_limits_apply_to_every_client_at_once() {
+        let limiter = RateLimiter::new(Some(1), None);
+        let now = Instant::now();
+        assert_eq!(limiter.admit("key:a", now).exceeded, None);
+        assert!(limiter.admit("key:a", now).exceeded.is_some());
+
+        limiter.set_limits(Some(3), None);
+        assert_eq!(limiter.admit("key:a", now).exceeded, None);
+        assert_eq!(limiter.status()["requests_per_minute"], 3);
+
+        limiter.set_limits(None, None);
+        assert_eq!(
+            limiter.status()["requests_per_minute"],
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn token_debt_blocks_until_repaid() {
+        let limiter = RateLimiter::new(None, Some(600));
+        let now = Instant::now();
+
+        assert_eq!(limiter.admit("key:a", now).exceeded, None);
+        limiter.charge_tokens("key:a", 1200);
+        let blocked = limiter.admit("key:a", Instant::now());
+        assert_eq!(
+            blocked.exceeded.map(|(kind, _)| kind),
+            Some(Exceeded::Tokens)
+        );
+        assert_eq!(
+            limiter
+                .admit("key:a", Instant::now() + Duration::from_secs(61))
+                .exceeded,
+            None
+        );
+    }
+
+    #[test]
+    fn status_lists_the_tightest_clients_without_their_keys() {
+        let limiter = RateLimiter::new(Some(4), None);
+        let now = Instant::now();
+        for _ in 0..3 {
+            limiter.admit("key:sk-secret", now);
+        }
+        limiter.admit("ip:10.0.0.1", now);
+
+        let status = limiter.status();
+        assert_eq!(status["requests_per_minute"], 4);
+        assert_eq!(status["tracked_clients"], 2);
+        let clients = status["clients"].as_array().expect("clients");
+        assert_eq!(
+            clients[0]["client"],
+            format!("key:{}", key_fingerprint("sk-secret"))
+        );
+        assert_eq!(clients[0]["remaining_requests"], 1);
+        assert_eq!(clients[1]["client"], "ip:10.0.0.1");
+        assert_eq!(clients[1]["remaining_tokens"], serde_json::Value::Null);
+        assert!(!status.to_string().contains("sk-secret"));
+    }
+
+    #[test]
+    fn clients_are_keyed_by_api_key_then_ip() {
+        let mut headers = HeaderMap::new();
+        let peer = Some(SocketAddr::from(([10, 0, 0, 1], 4000)));
+        assert_eq!(client_key(&headers, peer), "ip:10.0.0.1");
+        headers.insert("api-key", HeaderValue::from_static("azure-key"));
+        assert_eq!(client_key(&headers, peer), "key:azure-key");
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer sk-test"));
+        assert_eq!(client_key(&headers, peer), "key:sk-test");
+        assert_eq!(format_reset(Duration::from_millis(90_500)), "1m31s");
+    }
+}
@@ -0,0 +1,259 @@
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use anyhow::Context;
+use axum::Router;
+use axum::body::Body;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Method;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::routing::get;
+use axum::routing::post;
+use codex_app_server_protocol::AuthMode;
+use codex_core::WireApi;
+use codex_core::config::Config;
+use futures::StreamExt;
+use tracing::info;
+use tracing::warn;
+
+use crate::mock_backend;
+
+/// Provider id the recording relay is registered under in the Codex config.
+const RECORDER_PROVIDER_ID: &str = "openai-proxy-recorder";
+
+/// Extension of the files model streams are recorded to and replayed from.
+const RECORDING_EXTENSION: &str = "sse";
+
+/// Relays model requests to the configured provider, writing the raw event
+/// stream of every `POST /responses` to its own numbered file in `dir`.
+struct Recorder {
+    client: reqwest::Client,
+    upstream: String,
+    dir: PathBuf,
+    next: AtomicUsize,
+}
+
+impl Recorder {
+    fn create_file(&self) -> Option<std::fs::File> {
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!("{n:06}.{RECORDING_EXTENSION}"));
+        match std::fs::File::create(&path) {
+            Ok(file) => {
+                info!("recording model stream to {}", path.display());
+                Some(file)
+            }
+            Err(e) => {
+                warn!("cannot record to {}: {e}", path.display());
+                None
+            }
+        }
+    }
+}
+
+/// Starts the recording relay in front of the model provider `config` uses
+/// and returns the config overrides that route Codex through it. Every model
+/// request a turn makes is recorded, so a turn with tool calls leaves one
+/// file per round trip.
+pub(crate) async fn record(
+    config: &Config,
+    auth_mode: Option<AuthMode>,
+    dir: &Path,
+) -> anyhow::Result<Vec<(String, toml::Value)>> {
+    let mut provider = config.model_provider.clone();
+    if provider.wire_api != WireApi::Responses {
+        anyhow::bail!(
+            "recording needs a Responses API provider; {} uses chat completions",
+            provider.name
+        );
+    }
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("create recordings dir {}", dir.display()))?;
+    let upstream = provider
+        .base_url
+        .clone()
+        .unwrap_or_else(|| default_base_url(auth_mode).to_string());
+    let recorder = Recorder {
+        client: reqwest::Client::new(),
+        upstream: upstream.trim_end_matches('/').to_string(),
+        dir: dir.to_path_buf(),
+        next: AtomicUsize::new(recordings(dir)?.len()),
+    };
+    let addr =
+        mock_backend::serve(Router::new().fallback(relay).with_state(Arc::new(recorder))).await?;
+
+    // Same provider, name included so Codex keeps treating it as OpenAI
+    // when it is, just reached through the relay.
+    provider.base_url = Some(format!("http://{addr}"));
+    let provider = toml::Value::try_from(&provider).context("serialize model provider")?;
+    Ok(vec![
+        (
+            "model_provider".to_string(),
+            RECORDER_PROVIDER_ID.to_string().into(),
+        ),
+        (format!("model_providers.{RECORDER_PROVIDER_ID}"), provider),
+    ])
+}
+
+/// Where Codex sends requests for a provider without a `base_url`.
+fn default_base_url(auth_mode: Option<AuthMode>) -> &'static str {
+    if auth_mode == Some(AuthMode::ChatGPT) {
+        "https://chatgpt.com/backend-api/codex"
+    } else {
+        "https://api.openai.com/v1"
+    }
+}
+
+async fn relay(
+    State(recorder): State<Arc<Recorder>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let path = uri
+        .path_and_query()
+        .map_or(uri.path(), |path| path.as_str());
+    let mut request = recorder
+        .client
+        .request(method.clone(), format!("{}{path}", recorder.upstream))
+        .body(body);
+    for (name, value) in &headers {
+        // Compressed streams would be recorded compressed.
+        if name != header::HOST && name != header::CONTENT_LENGTH && name != header::ACCEPT_ENCODING
+        {
+            request = request.header(name, value);
+        }
+    }
+    let upstream = match request.send().await {
+        Ok(upstream) => upstream,
+        Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+    };
+
+    let status = upstream.status();
+    let mut response_headers = upstream.headers().clone();
+    response_headers.remove(header::CONTENT_LENGTH);
+    response_headers.remove(header::TRANSFER_ENCODING);
+    let mut file =
+        (method == Method::POST && uri.path().ends_with("/responses") && status.is_success())
+            .then(|| recorder.create_file())
+            .flatten();
+    let stream = upstream.bytes_stream().map(move |chunk| {
+        if let (Ok(bytes), Some(out)) = (&chunk, file.as_mut())
+            && let Err(e) = out.write_all(bytes)
+        {
+            warn!("recording stopped: {e}");
+            file = None;
+        }
+        chunk
+    });
+
+    let mut response = Response::new(Body::from_stream(stream));
+    *response.status_mut() = status;
+    *response.headers_mut() = response_headers;
+    response
+}
+
+/// Serves recorded model streams back in file-name order, starting over after
+/// the last, so a client sees exactly the events of the recorded session.
+struct Replayer {
+    files: Vec<PathBuf>,
+    next: AtomicUsize,
+}
+
+impl Replayer {
+    fn next_file(&self) -> &Path {
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        &self.files[n % self.files.len()]
+    }
+}
+
+/// Starts a local backend answering from the recordings in `dir` and returns
+/// the config overrides that send model requests to it.
+pub(crate) async fn replay(dir: &Path) -> anyhow::Result<Vec<(String, toml::Value)>> {
+    let files = recordings(dir)?;
+    if files.is_empty() {
+        anyhow::bail!("no .{RECORDING_EXTENSION} recordings in {}", dir.display());
+    }
+    let replayer = Replayer {
+        files,
+        next: AtomicUsize::new(0),
+    };
+    let router = Router::new()
+        .route("/v1/responses", post(handle_replay))
+        .route("/v1/models", get(mock_backend::handle_models))
+        .with_state(Arc::new(replayer));
+    Ok(mock_backend::config_overrides(
+        mock_backend::serve(router).await?,
+    ))
+}
+
+async fn handle_replay(State(replayer): State<Arc<Replayer>>) -> Response {
+    let path = replayer.next_file();
+    match tokio::fs::read(path).await {
+        Ok(body) => {
+            info!("replaying {}", path.display());
+            ([(header::CONTENT_TYPE, "text/event-stream")], body).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("read {}: {e}", path.display()),
+        )
+            .into_response(),
+    }
+}
+
+/// The recordings in `dir`, in the order they were made.
+fn recordings(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("read {}", dir.display())),
+    };
+    let mut files = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == RECORDING_EXTENSION)
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn replay_cycles_through_recordings_in_order() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        for name in ["000001.sse", "000000.sse", "notes.txt"] {
+            std::fs::write(dir.path().join(name), "").expect("write recording");
+        }
+        let replayer = Replayer {
+            files: recordings(dir.path()).expect("list recordings"),
+            next: AtomicUsize::new(0),
+        };
+        let served = (0..3)
+            .map(|_| {
+                replayer
+                    .next_file()
+                    .file_name()
+                    .expect("file name")
+                    .to_owned()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(served, ["000000.sse", "000001.sse", "000000.sse"]);
+    }
+}
@@ -0,0 +1,199 @@
+//! PII redaction: built-in patterns for emails, API keys and card numbers
+//! plus a deployment's own regexes. Matches are replaced with
+//! `[REDACTED:<pattern>]` in the `/logs` stream and the audit log, and, when
+//! configured, in prompts before they reach the model.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use regex::Regex;
+
+/// The redactor the `/logs` stream is scrubbed with, installed by the first
+/// state built with one.
+static LOG_REDACTOR: OnceLock<Arc<Redactor>> = OnceLock::new();
+
+/// Names of the built-in patterns, for `CODEX_OPENAI_PROXY_REDACT`; `all`
+/// picks every one.
+const BUILTINS: &[&str] = &["email", "api_key", "credit_card"];
+
+/// Name custom patterns are replaced under.
+const CUSTOM: &str = "pattern";
+
+/// One pattern and what its matches are replaced with.
+#[derive(Debug)]
+struct Rule {
+    name: &'static str,
+    regex: Regex,
+}
+
+impl Rule {
+    fn builtin(name: &str) -> anyhow::Result<Self> {
+        let (name, pattern) = match name {
+            "email" => ("email", r"(?i)\b[a-z0-9._%+-]+@[a-z0-9.-]+\.[a-z]{2,}\b"),
+            "api_key" => (
+                "api_key",
+                r"\b(?:sk-[A-Za-z0-9_-]{16,}|AKIA[0-9A-Z]{16}|gh[pousr]_[A-Za-z0-9]{36,}|xox[abprs]-[A-Za-z0-9-]{10,}|AIza[0-9A-Za-z_-]{35})\b",
+            ),
+            "credit_card" => ("credit_card", r"\b(?:\d[ -]?){12,18}\d\b"),
+            _ => anyhow::bail!(
+                "unknown redaction pattern {name:?}; expected all or one of {}",
+                BUILTINS.join(", ")
+            ),
+        };
+        Ok(Self {
+            name,
+            regex: Regex::new(pattern)?,
+        })
+    }
+
+    /// Whether `found` is what the rule is after; card numbers must also
+    /// pass the Luhn check, so order ids and timestamps are left alone.
+    fn accepts(&self, found: &str) -> bool {
+        self.name != "credit_card" || luhn_valid(found)
+    }
+}
+
+/// The patterns a deployment redacts.
+#[derive(Debug)]
+pub(crate) struct Redactor {
+    rules: Vec<Rule>,
+    /// Also scrub prompts before they are sent to the model.
+    prompts: bool,
+}
+
+impl Redactor {
+    /// `None` when neither built-in nor custom patterns are configured.
+    pub(crate) fn new(
+        builtins: &[String],
+        patterns: &[String],
+        prompts: bool,
+    ) -> anyhow::Result<Option<Self>> {
+        let mut rules = Vec::new();
+        for name in builtins {
+            if name == "all" {
+                for builtin in BUILTINS {
+                    rules.push(Rule::builtin(builtin)?);
+                }
+            } else {
+                rules.push(Rule::builtin(name)?);
+            }
+        }
+        for pattern in patterns {
+            let regex = Regex::new(pattern)
+                .with_context(|| format!("invalid redaction pattern {pattern:?}"))?;
+            rules.push(Rule {
+                name: CUSTOM,
+                regex,
+            });
+        }
+        Ok((!rules.is_empty()).then_some(Self { rules, prompts }))
+    }
+
+    /// `text` with every match replaced.
+    pub(crate) fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for rule in &self.rules {
+            let replacement = format!("[REDACTED:{}]", rule.name);
+            let redacted = rule.regex.replace_all(&text, |caps: &regex::Captures| {
+                if rule.accepts(&caps[0]) {
+                    replacement.clone()
+                } else {
+                    caps[0].to_string()
+                }
+            });
+            if let Cow::Owned(redacted) = redacted {
+                text = Cow::Owned(redacted);
+            }
+        }
+        text
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "patterns": self.rules.iter().map(|rule| rule.name).collect::<Vec<_>>(),
+            "prompts": self.prompts,
+        })
+    }
+}
+
+/// A prompt as it is sent to the model: redacted if the deployment scrubs
+/// prompts.
+pub(crate) fn scrub_prompt(redactor: Option<&Redactor>, prompt: &str) -> String {
+    match redactor {
+        Some(redactor) if redactor.prompts => redactor.redact(prompt).into_owned(),
+        _ => prompt.to_string(),
+    }
+}
+
+/// Scrubs the `/logs` stream with `redactor` from now on, unless another
+/// one already does.
+pub(crate) fn redact_logs_with(redactor: Arc<Redactor>) {
+    let _ = LOG_REDACTOR.set(redactor);
+}
+
+/// A `/logs` line, redacted if logs are scrubbed.
+pub(crate) fn redact_log(line: String) -> String {
+    match LOG_REDACTOR.get() {
+        Some(redactor) => match redactor.redact(&line) {
+            Cow::Borrowed(_) => line,
+            Cow::Owned(redacted) => redacted,
+        },
+        None => line,
+    }
+}
+
+fn luhn_valid(number: &str) -> bool {
+    let digits = number
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .collect::<Vec<_>>();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| match (i % 2 == 1, digit * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => digit,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn builtin_and_custom_patterns_are_redacted() {
+        let redactor = Redactor::new(&["all".to_string()], &[r"\bEMP-\d{6}\b".to_string()], false)
+            .expect("valid patterns")
+            .expect("a redactor");
+
+        assert_eq!(
+            redactor.redact(
+                "Mail ana@example.com with key sk-abcdefghijklmnop1234, card 4111 1111 1111 1111, \
+                 order 1234567890123 and badge EMP-123456."
+            ),
+            "Mail [REDACTED:email] with key [REDACTED:api_key], card [REDACTED:credit_card], \
+             order 1234567890123 and badge [REDACTED:pattern]."
+        );
+        assert!(matches!(redactor.redact("nothing here"), Cow::Borrowed(_)));
+        assert_eq!(
+            scrub_prompt(Some(&redactor), "ana@example.com"),
+            "ana@example.com"
+        );
+
+        assert!(
+            Redactor::new(&[], &[], true)
+                .expect("no patterns")
+                .is_none()
+        );
+        assert!(Redactor::new(&["phone".to_string()], &[], false).is_err());
+        assert!(Redactor::new(&[], &["(".to_string()], false).is_err());
+    }
+}
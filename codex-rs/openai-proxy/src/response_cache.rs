@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use axum::http::HeaderValue;
+use axum::response::Response;
+use sha2::Digest;
+use sha2::Sha256;
+
+/// Tells clients whether a non-streaming response came from the cache.
+const CACHE_STATUS_HEADER: &str = "x-cache";
+
+/// Oldest entries are dropped past this many.
+const MAX_CACHE_ENTRIES: usize = 1024;
+
+struct CachedResponse {
+    body: String,
+    stored_at: Instant,
+}
+
+/// Opt-in cache of non-streaming response bodies for identical requests, so
+/// repeated prompts (e.g. from eval harnesses) return without running a turn.
+/// Only requests that start a new conversation are cached.
+pub(crate) struct ResponseCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, CachedResponse>>,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Derives the cache key for a request to `endpoint` from everything that
+    /// shapes its answer: model, merged input and parameters.
+    pub(crate) fn key(endpoint: &str, request: &serde_json::Value) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(endpoint.as_bytes());
+        hasher.update([0]);
+        hasher.update(request.to_string().as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<String> {
+        let mut entries = self.entries.lock().ok()?;
+        match entries.get(key) {
+            Some(entry) if entry.stored_at.elapsed() < self.ttl => Some(entry.body.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub(crate) fn put(&self, key: String, body: String) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if entries.len() >= MAX_CACHE_ENTRIES {
+            entries.retain(|_, entry| entry.stored_at.elapsed() < self.ttl);
+        }
+        if entries.len() >= MAX_CACHE_ENTRIES
+            && let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored_at)
+                .map(|(key, _)| key.clone())
+        {
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            key,
+            CachedResponse {
+                body,
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "ttl_secs": self.ttl.as_secs(),
+            "max_entries": MAX_CACHE_ENTRIES,
+        })
+    }
+}
+
+/// Marks `response` as a cache `hit` or `miss`.
+pub(crate) fn with_cache_status(mut response: Response, hit: bool) -> Response {
+    let status = if hit { "hit" } else { "miss" };
+    response
+        .headers_mut()
+        .insert(CACHE_STATUS_HEADER, HeaderValue::from_static(status));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn identical_requests_share_an_entry_until_it_expires() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let request = serde_json::json!({ "model": "2.5-tpg", "input": "hi" });
+        let key = ResponseCache::key("chat.completions", &request);
+
+        assert_eq!(cache.get(&key), None);
+        cache.put(key.clone(), "{\"ok\":true}".to_string());
+        assert_eq!(
+            cache.get(&ResponseCache::key("chat.completions", &request)),
+            Some("{\"ok\":true}".to_string())
+        );
+        assert_ne!(key, ResponseCache::key("responses", &request));
+
+        let expired = ResponseCache::new(Duration::ZERO);
+        expired.put(key.clone(), "{}".to_string());
+        assert_eq!(expired.get(&key), None);
+    }
+}
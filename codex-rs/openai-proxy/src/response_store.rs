@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use axum::Extension;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::access_log::RequestContext;
+use crate::json_response;
+
+/// Oldest responses are dropped past this many.
+const MAX_STORED_RESPONSES: usize = 4096;
+
+struct StoredResponse {
+    response: serde_json::Value,
+    /// Usage owner of the request that created it; nobody else may read it.
+    owner: String,
+    stored_at: Instant,
+}
+
+/// Responses API objects kept for `GET /v1/responses/{id}`, including the
+/// `metadata` their requests attached. Entries live in memory for `ttl` and
+/// are only returned to the tenant (or API key) that created them.
+pub(crate) struct ResponseStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, StoredResponse>>,
+}
+
+impl ResponseStore {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keeps `response` under its `id`, replacing an earlier snapshot.
+    pub(crate) fn put(&self, owner: &str, response: serde_json::Value) {
+        let Some(id) = response.get("id").and_then(|id| id.as_str()) else {
+            return;
+        };
+        let id = id.to_string();
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if entries.len() >= MAX_STORED_RESPONSES {
+            entries.retain(|_, entry| entry.stored_at.elapsed() < self.ttl);
+        }
+        if entries.len() >= MAX_STORED_RESPONSES
+            && !entries.contains_key(&id)
+            && let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored_at)
+                .map(|(id, _)| id.clone())
+        {
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            id,
+            StoredResponse {
+                response,
+                owner: owner.to_string(),
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    pub(crate) fn get(&self, owner: &str, id: &str) -> Option<serde_json::Value> {
+        let mut entries = self.entries.lock().ok()?;
+        match entries.get(id) {
+            Some(entry) if entry.stored_at.elapsed() >= self.ttl => {
+                entries.remove(id);
+                None
+            }
+            Some(entry) if entry.owner == owner => Some(entry.response.clone()),
+            _ => None,
+        }
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "ttl_secs": self.ttl.as_secs(),
+            "max_entries": MAX_STORED_RESPONSES,
+        })
+    }
+}
+
+/// `GET /v1/responses/{id}`: a response created by the caller, as it was
+/// when its turn ended.
+pub(crate) async fn handle_get_response(
+    State(state): State<AppState>,
+    Extension(context): Extension<RequestContext>,
+    Path(id): Path<String>,
+) -> Response {
+    let response = state
+        .response_store
+        .as_ref()
+        .and_then(|store| store.get(context.owner().unwrap_or_default(), &id));
+    match response {
+        Some(response) => json_response(StatusCode::OK, response.to_string()),
+        None => ProxyError::new(
+            StatusCode::NOT_FOUND,
+            format!("No response found with id '{id}'."),
+            "invalid_request_error",
+        )
+        .with_code("response_not_found")
+        .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn responses_are_only_returned_to_their_owner_until_they_expire() {
+        let store = ResponseStore::new(Duration::from_secs(60));
+        let response = serde_json::json!({ "id": "resp-1", "metadata": { "run": "7" } });
+        store.put("alice", response.clone());
+
+        assert_eq!(store.get("alice", "resp-1"), Some(response));
+        assert_eq!(store.get("bob", "resp-1"), None);
+        assert_eq!(store.get("alice", "resp-2"), None);
+
+        let expired = ResponseStore::new(Duration::ZERO);
+        expired.put("alice", serde_json::json!({ "id": "resp-1" }));
+        assert_eq!(expired.get("alice", "resp-1"), None);
+    }
+}
@@ -0,0 +1,1624 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::Extension;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::response::sse::Event;
+use axum::response::sse::Sse;
+use codex_protocol::models::ContentItem;
+use codex_protocol::models::ResponseItem;
+use codex_protocol::openai_models::ReasoningEffort;
+use codex_protocol::plan_tool::UpdatePlanArgs;
+use codex_protocol::protocol::EventMsg;
+use codex_protocol::protocol::FileChange;
+use codex_protocol::protocol::TokenUsage;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::Instrument;
+use tracing::info;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::access_log::RequestContext;
+use crate::attachments;
+use crate::audit_log::TurnUsage;
+use crate::base_instructions_header;
+use crate::citations::Citations;
+use crate::codex_events;
+use crate::codex_events::AgentText;
+use crate::codex_events::ChangedFile;
+use crate::dry_run::ProposedPatch;
+use crate::error_response;
+use crate::images;
+use crate::json_response;
+use crate::log_message;
+use crate::mcp_servers::McpServerRequest;
+use crate::now_ts;
+use crate::output_cap;
+use crate::output_cap::OutputCap;
+use crate::output_cap::ToolCallCap;
+use crate::profile_header;
+use crate::profile_override;
+use crate::response_cache::ResponseCache;
+use crate::response_cache::with_cache_status;
+use crate::stream_buffer::DeltaShaping;
+use crate::stream_buffer::StreamSender;
+use crate::structured_output;
+use crate::structured_output::OutputSchema;
+use crate::turn_setup;
+use crate::turn_setup::TurnOptions;
+use crate::turn_slots;
+use crate::upstream_errors;
+
+/// OpenAI's limits on a request's `metadata`.
+const MAX_METADATA_PAIRS: usize = 16;
+const MAX_METADATA_KEY_LEN: usize = 64;
+const MAX_METADATA_VALUE_LEN: usize = 512;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ResponsesRequest {
+    model: String,
+    /// Either a plain string or an array of input items / messages.
+    #[serde(default)]
+    input: serde_json::Value,
+    #[serde(default)]
+    instructions: Option<String>,
+    #[serde(default)]
+    stream: bool,
+    /// Spaces text deltas at least this many milliseconds apart, merging
+    /// those that arrive sooner; overrides
+    /// `CODEX_OPENAI_PROXY_STREAM_MIN_INTERVAL_MS`, and 0 turns it off.
+    #[serde(default)]
+    stream_min_interval_ms: Option<u64>,
+    /// `word` or `sentence` re-chunks text deltas to end on those
+    /// boundaries; overrides `CODEX_OPENAI_PROXY_STREAM_GRANULARITY`.
+    #[serde(default)]
+    stream_granularity: Option<String>,
+    #[serde(default)]
+    conversation_id: Option<String>,
+    /// Not a Responses API field, but accepted by Chat Completions; read
+    /// only to reject it, as Codex turns take no seed.
+    #[serde(default)]
+    seed: Option<serde_json::Value>,
+    /// Interrupts a turn already running on `conversation_id` instead of
+    /// queueing behind it; overrides `CODEX_OPENAI_PROXY_INTERRUPT_PREVIOUS_TURN`.
+    #[serde(default)]
+    interrupt_previous_turn: Option<bool>,
+    /// `never` (default) or `on-request`; see [`crate::approvals`].
+    #[serde(default)]
+    approval_policy: Option<String>,
+    /// `read-only` (default) or `danger-full-access`; see
+    /// [`crate::sandbox_mode`].
+    #[serde(default)]
+    sandbox_mode: Option<String>,
+    /// Runs the turn read-only and returns the patches Codex would apply as
+    /// `proposed_patch` output items instead; see [`crate::dry_run`].
+    #[serde(default)]
+    dry_run: bool,
+    /// Directory to run the turn in; see [`crate::workspace`].
+    #[serde(default, alias = "workspace")]
+    cwd: Option<String>,
+    #[serde(default)]
+    reasoning: ResponsesReasoning,
+    /// Optional output sections to attach, e.g. `reasoning.encrypted_content`.
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    tools: Vec<ResponsesTool>,
+    /// Cuts the answer off, leaving the response `incomplete`.
+    #[serde(default)]
+    max_output_tokens: Option<u64>,
+    /// Ends the turn, leaving the response `incomplete`, when the agent tries
+    /// to make more tool calls than this.
+    #[serde(default)]
+    max_tool_calls: Option<u64>,
+    /// The client's end user, recorded for usage attribution.
+    #[serde(default)]
+    pub(crate) user: Option<String>,
+    /// Client-defined string pairs returned with the response.
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+    /// `false` keeps the response and its conversation only for the
+    /// lifetime of the request.
+    #[serde(default)]
+    store: Option<bool>,
+    /// Output text options; a `json_schema` format is asked for and
+    /// validated against, see [`crate::structured_output`].
+    #[serde(default)]
+    text: Option<serde_json::Value>,
+    /// Replaces Codex's built-in system prompt for the thread this request
+    /// starts or resumes; see [`crate::BASE_INSTRUCTIONS_HEADER`].
+    #[serde(default)]
+    base_instructions: Option<String>,
+    /// The Codex config profile the thread this request starts or resumes
+    /// runs with; see [`crate::PROFILE_HEADER`].
+    #[serde(default)]
+    profile: Option<String>,
+    /// Tool servers added to the thread this request starts; see
+    /// [`crate::mcp_servers`].
+    #[serde(default)]
+    mcp_servers: Vec<McpServerRequest>,
+}
+
+impl ResponsesRequest {
+    fn stored(&self) -> bool {
+        self.store != Some(false)
+    }
+
+    /// Config overrides for a thread started by this request.
+    fn thread_overrides(&self) -> Vec<(String, toml::Value)> {
+        let mut overrides = tool_config_overrides(&self.tools);
+        overrides.extend(profile_override(self.profile.as_deref()));
+        overrides
+    }
+
+    /// What this request asks of its turn, given its merged `text`.
+    fn turn_options(
+        &self,
+        text: String,
+        answerable: bool,
+        priority: i32,
+        output_schema: Option<&OutputSchema>,
+    ) -> TurnOptions {
+        TurnOptions {
+            model: self.model.clone(),
+            text,
+            conversation_id: self.conversation_id.clone(),
+            thread_overrides: self.thread_overrides(),
+            base_instructions: self.base_instructions.clone(),
+            mcp_servers: self.mcp_servers.clone(),
+            user: self.user.clone(),
+            approval_policy: self.approval_policy.clone(),
+            answerable,
+            sandbox_mode: self.sandbox_mode.clone(),
+            dry_run: self.dry_run,
+            cwd: self.cwd.clone(),
+            effort: self.reasoning.effort,
+            output_schema: output_schema.map(OutputSchema::schema),
+            image_urls: images::collect_image_urls(&self.input),
+            attachments: attachments::collect_attachments(&self.input),
+            priority,
+            interrupt_previous_turn: self.interrupt_previous_turn,
+        }
+    }
+}
+
+/// The Responses `reasoning` object; only `effort` is forwarded to Codex.
+#[derive(Debug, Default, Deserialize)]
+struct ResponsesReasoning {
+    #[serde(default)]
+    effort: Option<ReasoningEffort>,
+}
+
+/// Tool definitions accepted in a Responses request.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponsesTool {
+    /// A client-executed function; rejected, see [`check_tools`].
+    Function {
+        name: String,
+    },
+    /// Built-in web search, mapped onto Codex's own web search tool.
+    WebSearch,
+    WebSearchPreview,
+}
+
+/// Config overrides needed to enable the requested built-in tools.
+fn tool_config_overrides(tools: &[ResponsesTool]) -> Vec<(String, toml::Value)> {
+    let wants_web_search = tools.iter().any(|tool| {
+        matches!(
+            tool,
+            ResponsesTool::WebSearch | ResponsesTool::WebSearchPreview
+        )
+    });
+    if wants_web_search {
+        vec![(
+            "features.web_search_request".to_string(),
+            toml::Value::Boolean(true),
+        )]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Rejects client-executed function tools. Codex runs its own tools and has
+/// no way to hand a call back to the client, so accepting them would only
+/// pretend to.
+fn check_tools(tools: &[ResponsesTool]) -> Result<(), ProxyError> {
+    match tools.iter().find_map(|tool| match tool {
+        ResponsesTool::Function { name } => Some(name),
+        ResponsesTool::WebSearch | ResponsesTool::WebSearchPreview => None,
+    }) {
+        Some(name) => Err(ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "tools: function tools such as {name:?} are not supported; Codex runs its own tools"
+            ),
+            "invalid_request_error",
+        )
+        .with_code("unsupported_parameter")),
+        None => Ok(()),
+    }
+}
+
+/// Rejects a `seed`, which chat completions reject in agent mode too;
+/// ignoring it would promise reproducible answers that Codex cannot give.
+fn check_seed(body: &ResponsesRequest) -> Result<(), ProxyError> {
+    match body.seed {
+        Some(_) => Err(ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            "seed is not supported; Codex turns take no seed",
+            "invalid_request_error",
+        )
+        .with_code("unsupported_parameter")),
+        None => Ok(()),
+    }
+}
+
+/// Whether `item` is one of Codex's own tool calls or their outputs, which
+/// are not the client's to run and stay out of the response's `output`.
+fn is_codex_tool_item(item: &ResponseItem) -> bool {
+    matches!(
+        item,
+        ResponseItem::FunctionCall { .. }
+            | ResponseItem::FunctionCallOutput { .. }
+            | ResponseItem::CustomToolCall { .. }
+            | ResponseItem::CustomToolCallOutput { .. }
+            | ResponseItem::LocalShellCall { .. }
+    )
+}
+
+/// `include` values the proxy can fill. Others, such as logprobs, are
+/// rejected rather than answered with empty data, as Codex does not surface
+/// them.
+const SUPPORTED_INCLUDES: &[&str] = &["reasoning.encrypted_content"];
+
+/// Output sections requested through the `include` parameter.
+#[derive(Debug, Default, Clone, Copy)]
+struct ResponseIncludes {
+    reasoning_encrypted_content: bool,
+}
+
+impl ResponseIncludes {
+    fn parse(values: &[String]) -> Result<Self, ProxyError> {
+        let mut includes = Self::default();
+        for value in values {
+            match value.as_str() {
+                "reasoning.encrypted_content" => includes.reasoning_encrypted_content = true,
+                other => {
+                    return Err(ProxyError::new(
+                        StatusCode::BAD_REQUEST,
+                        format!(
+                            "unsupported include value '{other}'; supported values are: {}",
+                            SUPPORTED_INCLUDES.join(", ")
+                        ),
+                        "invalid_request_error",
+                    )
+                    .with_code("unsupported_parameter"));
+                }
+            }
+        }
+        Ok(includes)
+    }
+
+    /// Serializes an output item, dropping or attaching sections according to
+    /// the requested includes.
+    fn render_item(&self, item: &ResponseItem) -> serde_json::Value {
+        let mut value = serde_json::to_value(item).unwrap_or_else(|_| serde_json::json!({}));
+        if matches!(item, ResponseItem::Reasoning { .. })
+            && !self.reasoning_encrypted_content
+            && let Some(obj) = value.as_object_mut()
+        {
+            obj.remove("encrypted_content");
+        }
+        value
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+struct ResponsesResponse {
+    id: String,
+    object: String,
+    created_at: u64,
+    model: String,
+    status: String,
+    output: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conversation_id: Option<String>,
+    /// Files Codex modified during the turn.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    changed_files: Vec<ChangedFile>,
+    /// Why the response failed, as `{code, message}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<serde_json::Value>,
+    /// Why an `incomplete` response was cut off, as `{reason}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    incomplete_details: Option<serde_json::Value>,
+    /// Tokens used so far; streamed responses only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<TurnUsage>,
+    /// The plan as Codex last updated it during the turn.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<UpdatePlanArgs>,
+    /// The request's `metadata`, echoed back.
+    metadata: BTreeMap<String, String>,
+    /// Whether the response can be retrieved later.
+    store: bool,
+}
+
+/// Builds the ordered sequence of Responses streaming events for one response.
+/// Each event's `type` field doubles as its SSE `event:` name.
+struct ResponseEventBuilder {
+    response: ResponsesResponse,
+    includes: ResponseIncludes,
+    sequence_number: u64,
+    /// Index of the reasoning summary part currently being streamed.
+    summary_index: u64,
+    citations: Citations,
+    /// The assistant message whose text is streaming, until its item lands.
+    message: Option<StreamingMessage>,
+}
+
+/// Where the streaming assistant message sits in the response.
+struct StreamingMessage {
+    item_id: String,
+    output_index: usize,
+}
+
+impl ResponseEventBuilder {
+    fn new(response: ResponsesResponse, includes: ResponseIncludes) -> Self {
+        Self {
+            response,
+            includes,
+            sequence_number: 0,
+            summary_index: 0,
+            citations: Citations::default(),
+            message: None,
+        }
+    }
+
+    fn event(&mut self, kind: &str, fields: serde_json::Value) -> serde_json::Value {
+        let mut event = serde_json::json!({
+            "type": kind,
+            "sequence_number": self.sequence_number,
+        });
+        if let (Some(event), serde_json::Value::Object(fields)) = (event.as_object_mut(), fields) {
+            event.extend(fields);
+        }
+        self.sequence_number += 1;
+        event
+    }
+
+    fn response_event(&mut self, kind: &str) -> serde_json::Value {
+        let response = serde_json::to_value(&self.response).unwrap_or_default();
+        self.event(kind, serde_json::json!({ "response": response }))
+    }
+
+    fn created(&mut self) -> serde_json::Value {
+        self.response_event("response.created")
+    }
+
+    fn in_progress(&mut self) -> serde_json::Value {
+        self.response_event("response.in_progress")
+    }
+
+    /// Streams assistant text. The first delta of a message opens it with
+    /// `output_item.added` and `content_part.added`; [`Self::output_item`]
+    /// closes it when the completed message arrives.
+    fn output_text_delta(&mut self, delta: &str) -> Vec<serde_json::Value> {
+        let mut events = Vec::new();
+        if self.message.is_none() {
+            let item_id = format!("msg_{}", uuid::Uuid::new_v4().simple());
+            let output_index = self.response.output.len();
+            let item = serde_json::json!({
+                "id": item_id,
+                "type": "message",
+                "status": "in_progress",
+                "role": "assistant",
+                "content": [],
+            });
+            self.response.output.push(item.clone());
+            events.push(self.event(
+                "response.output_item.added",
+                serde_json::json!({ "output_index": output_index, "item": item }),
+            ));
+            events.push(self.content_part_event(
+                "response.content_part.added",
+                &item_id,
+                output_index,
+                0,
+                empty_output_text(),
+            ));
+            self.message = Some(StreamingMessage {
+                item_id,
+                output_index,
+            });
+        }
+        let Some(StreamingMessage {
+            item_id,
+            output_index,
+        }) = &self.message
+        else {
+            return events;
+        };
+        let fields = serde_json::json!({
+            "item_id": item_id,
+            "output_index": output_index,
+            "content_index": 0,
+            "delta": delta,
+        });
+        events.push(self.event("response.output_text.delta", fields));
+        events
+    }
+
+    /// Whether an assistant message is streaming.
+    fn message_open(&self) -> bool {
+        self.message.is_some()
+    }
+
+    fn content_part_event(
+        &mut self,
+        kind: &str,
+        item_id: &str,
+        output_index: usize,
+        content_index: usize,
+        part: serde_json::Value,
+    ) -> serde_json::Value {
+        self.event(
+            kind,
+            serde_json::json!({
+                "item_id": item_id,
+                "output_index": output_index,
+                "content_index": content_index,
+                "part": part,
+            }),
+        )
+    }
+
+    /// Reasoning summary text streamed ahead of the reasoning item itself,
+    /// which lands at the next output index.
+    fn reasoning_summary_delta(&mut self, delta: &str) -> serde_json::Value {
+        let output_index = self.response.output.len();
+        let summary_index = self.summary_index;
+        self.event(
+            "response.reasoning_summary_text.delta",
+            serde_json::json!({
+                "output_index": output_index,
+                "summary_index": summary_index,
+                "delta": delta,
+            }),
+        )
+    }
+
+    fn reasoning_section_break(&mut self) {
+        self.summary_index += 1;
+    }
+
+    /// Emits an MCP call as an `mcp_call` output item: `output_item.added`
+    /// when it begins, and `output_item.done` with its output when it ends.
+    fn mcp_call(&mut self, item: serde_json::Value) -> serde_json::Value {
+        let begun = self
+            .response
+            .output
+            .iter()
+            .position(|output| output["type"] == "mcp_call" && output["id"] == item["id"]);
+        match begun {
+            Some(output_index) => {
+                self.response.output[output_index] = item.clone();
+                self.event(
+                    "response.output_item.done",
+                    serde_json::json!({ "output_index": output_index, "item": item }),
+                )
+            }
+            None => {
+                let output_index = self.response.output.len();
+                self.response.output.push(item.clone());
+                self.event(
+                    "response.output_item.added",
+                    serde_json::json!({ "output_index": output_index, "item": item }),
+                )
+            }
+        }
+    }
+
+    /// Emits a dry run's proposed patch as a `proposed_patch` output item.
+    fn proposed_patch(&mut self, patch: &ProposedPatch) -> Vec<serde_json::Value> {
+        let item = patch.output_item();
+        let output_index = self.response.output.len();
+        let events = vec![
+            self.event(
+                "response.output_item.added",
+                serde_json::json!({ "output_index": output_index, "item": item }),
+            ),
+            self.event(
+                "response.output_item.done",
+                serde_json::json!({ "output_index": output_index, "item": item }),
+            ),
+        ];
+        self.response.output.push(item);
+        events
+    }
+
+    /// Emits the events for one completed output item. A message closes the
+    /// one streaming, at its output index, or is opened and closed at once:
+    /// each content part announces its citations with
+    /// `output_text.annotation.added`, then ends with `output_text.done` and
+    /// `content_part.done`.
+    fn output_item(&mut self, item: ResponseItem) -> Vec<serde_json::Value> {
+        self.citations.record(&item);
+        let mut rendered = self.includes.render_item(&item);
+        let mut output_index = self.response.output.len();
+        let mut events = Vec::new();
+
+        if matches!(item, ResponseItem::Message { .. }) {
+            self.citations.annotate_item(&mut rendered);
+            let streamed = self.message.take();
+            let item_id = match &streamed {
+                Some(message) => {
+                    output_index = message.output_index;
+                    message.item_id.clone()
+                }
+                None => rendered["id"]
+                    .as_str()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("msg_{}", uuid::Uuid::new_v4().simple())),
+            };
+            rendered["id"] = serde_json::json!(item_id);
+            if streamed.is_none() {
+                let mut added = rendered.clone();
+                added["status"] = serde_json::json!("in_progress");
+                added["content"] = serde_json::json!([]);
+                self.response.output.push(added.clone());
+                events.push(self.event(
+                    "response.output_item.added",
+                    serde_json::json!({ "output_index": output_index, "item": added }),
+                ));
+            }
+            let parts = rendered["content"].as_array().cloned().unwrap_or_default();
+            for (content_index, part) in parts.iter().enumerate() {
+                if streamed.is_none() || content_index > 0 {
+                    events.push(self.content_part_event(
+                        "response.content_part.added",
+                        &item_id,
+                        output_index,
+                        content_index,
+                        empty_output_text(),
+                    ));
+                }
+                let annotations = part["annotations"].as_array().into_iter().flatten();
+                for (annotation_index, annotation) in annotations.enumerate() {
+                    events.push(self.event(
+                        "response.output_text.annotation.added",
+                        serde_json::json!({
+                            "item_id": item_id,
+                            "output_index": output_index,
+                            "content_index": content_index,
+                            "annotation_index": annotation_index,
+                            "annotation": annotation,
+                        }),
+                    ));
+                }
+                if part["type"] == "output_text" {
+                    events.push(self.event(
+                        "response.output_text.done",
+                        serde_json::json!({
+                            "item_id": item_id,
+                            "output_index": output_index,
+                            "content_index": content_index,
+                            "text": part["text"],
+                        }),
+                    ));
+                }
+                events.push(self.content_part_event(
+                    "response.content_part.done",
+                    &item_id,
+                    output_index,
+                    content_index,
+                    part.clone(),
+                ));
+            }
+        }
+
+        if matches!(item, ResponseItem::Reasoning { .. }) {
+            self.summary_index = 0;
+        }
+        events.push(self.event(
+            "response.output_item.done",
+            serde_json::json!({ "output_index": output_index, "item": rendered.clone() }),
+        ));
+        if output_index < self.response.output.len() {
+            self.response.output[output_index] = rendered;
+        } else {
+            self.response.output.push(rendered);
+        }
+        events
+    }
+
+    /// Adds what one model call used to the response's usage and reports
+    /// the running total with a `response.usage` event.
+    fn usage(&mut self, usage: &TokenUsage) -> serde_json::Value {
+        let total = self.response.usage.get_or_insert_default();
+        total.add(usage);
+        let total = *total;
+        self.event("response.usage", serde_json::json!({ "usage": total }))
+    }
+
+    /// Adds a successful patch's files to the final response's summary.
+    fn record_patch(&mut self, changes: &HashMap<PathBuf, FileChange>) {
+        codex_events::record_changed_files(&mut self.response.changed_files, changes);
+    }
+
+    /// Keeps the latest plan for the final response.
+    fn record_plan(&mut self, plan: UpdatePlanArgs) {
+        self.response.plan = Some(plan);
+    }
+
+    fn completed(&mut self) -> serde_json::Value {
+        self.response.status = "completed".to_string();
+        self.response_event("response.completed")
+    }
+
+    /// Ends a response that hit a cap or whose answer failed validation.
+    fn incomplete(&mut self, details: serde_json::Value) -> serde_json::Value {
+        self.response.status = "incomplete".to_string();
+        self.response.incomplete_details = Some(details);
+        self.response_event("response.incomplete")
+    }
+
+    fn failed(&mut self, error: &ProxyError) -> serde_json::Value {
+        self.response.status = "failed".to_string();
+        self.response.error = Some(serde_json::json!({
+            "code": error.code.unwrap_or(error.kind),
+            "message": error.message,
+        }));
+        self.response_event("response.failed")
+    }
+}
+
+pub(crate) async fn handle_responses(
+    State(state): State<AppState>,
+    Extension(context): Extension<RequestContext>,
+    headers: HeaderMap,
+    body: axum::Json<ResponsesRequest>,
+) -> Response {
+    log_message(
+        serde_json::json!({
+            "type": "incoming_request",
+            "endpoint": "/responses",
+            "model": body.model,
+            "stream": body.stream
+        })
+        .to_string(),
+    );
+
+    context.set_model(&body.model);
+    let priority = match turn_slots::request_priority(&headers) {
+        Ok(priority) => priority,
+        Err(e) => return e.into_response(),
+    };
+    let mut body = body.0;
+    match (base_instructions_header(&headers), profile_header(&headers)) {
+        (Ok(instructions), Ok(profile)) => {
+            body.base_instructions = body.base_instructions.or(instructions);
+            body.profile = body.profile.or(profile);
+        }
+        (Err(e), _) | (_, Err(e)) => return e.into_response(),
+    }
+    if body.stream {
+        return handle_responses_stream(state, context, priority, body).await;
+    }
+    handle_responses_once(state, context, priority, body).await
+}
+
+pub(crate) async fn handle_responses_once(
+    state: AppState,
+    context: RequestContext,
+    priority: i32,
+    body: ResponsesRequest,
+) -> Response {
+    let includes = match ResponseIncludes::parse(&body.include) {
+        Ok(includes) => includes,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = check_metadata(&body.metadata) {
+        return e.into_response();
+    }
+    if let Err(e) = check_store(&body) {
+        return e.into_response();
+    }
+    if let Err(e) = check_tools(&body.tools) {
+        return e.into_response();
+    }
+    if let Err(e) = check_seed(&body) {
+        return e.into_response();
+    }
+    let stored = body.stored();
+    let output_schema = match OutputSchema::from_text_format(body.text.as_ref()) {
+        Ok(schema) => schema,
+        Err(e) => return e.into_response(),
+    };
+
+    let merged_text = match merge_responses_input(&body.input, body.instructions.as_deref()) {
+        Some(text) => text,
+        None => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "no user content found".to_string(),
+                "invalid_request_error",
+            );
+        }
+    };
+
+    let turn = match body
+        .turn_options(
+            merged_text.clone(),
+            state.approvals.has_webhook(),
+            priority,
+            output_schema.as_ref(),
+        )
+        .prepare(&state, &context)
+        .await
+    {
+        Ok(turn) => turn,
+        Err(e) => return e.into_response(),
+    };
+
+    // Attachments are written into the workspace, so those requests always run.
+    let cache_key = match &state.response_cache {
+        Some(_)
+            if stored
+                && body.conversation_id.is_none()
+                && attachments::collect_attachments(&body.input).is_empty() =>
+        {
+            Some(ResponseCache::key(
+                "responses",
+                &serde_json::json!({
+                    "model": body.model,
+                    "input": merged_text,
+                    "images": images::collect_image_urls(&body.input),
+                    "approval_policy": body.approval_policy,
+                    "sandbox_mode": body.sandbox_mode,
+                    "dry_run": body.dry_run,
+                    "cwd": body.cwd,
+                    "reasoning_effort": body.reasoning.effort,
+                    "include": body.include,
+                    "thread_overrides": body.thread_overrides(),
+                    "mcp_servers": body.mcp_servers,
+                    "max_output_tokens": body.max_output_tokens,
+                    "max_tool_calls": body.max_tool_calls,
+                    "metadata": body.metadata,
+                    "text": body.text,
+                    "base_instructions": body.base_instructions,
+                }),
+            ))
+        }
+        _ => None,
+    };
+    if let (Some(cache), Some(key)) = (&state.response_cache, &cache_key)
+        && let Some(cached) = cache.get(key)
+    {
+        return with_cache_status(json_response(StatusCode::OK, cached), true);
+    }
+
+    let thread = match turn.thread(&state, &context).await {
+        Ok(thread) => thread,
+        Err(e) => return e.into_response(),
+    };
+    let response_id = format!("resp-codex-{}", uuid::Uuid::new_v4());
+    context.set_response_id(&response_id);
+    let turn = match turn_setup::submit(&state, &context, thread, turn).await {
+        Ok(turn) => turn,
+        Err(e) => return e.into_response(),
+    };
+
+    let max_output_tokens =
+        output_cap::effective(state.max_output_tokens.get(), body.max_output_tokens);
+    let max_tool_calls = body.max_tool_calls;
+    let output_items = Arc::new(Mutex::new(Vec::<ResponseItem>::new()));
+    let output_items_for_task = output_items.clone();
+    let changed_files = Arc::new(Mutex::new(Vec::<ChangedFile>::new()));
+    let changed_files_for_task = changed_files.clone();
+    let proposed_patches = Arc::new(Mutex::new(Vec::<ProposedPatch>::new()));
+    let proposed_patches_for_task = proposed_patches.clone();
+    let plan = Arc::new(Mutex::new(None::<UpdatePlanArgs>));
+    let plan_for_task = plan.clone();
+    // `mcp_call` items by call id, shown in place of the function calls.
+    let mcp_calls = Arc::new(Mutex::new(HashMap::<String, serde_json::Value>::new()));
+    let mcp_calls_for_task = mcp_calls.clone();
+    let approvals = state.approvals.clone();
+    let context_for_task = context.clone();
+    let thread_id = turn.thread_id;
+    let thread_for_discard = turn.thread.clone();
+
+    let turn_span = turn.span();
+    let task = async move {
+        let thread = &turn.thread;
+        let mut final_text = String::new();
+        let mut agent_text = AgentText::default();
+        let mut output_cap = OutputCap::new(max_output_tokens);
+        let mut tool_call_cap = ToolCallCap::new(max_tool_calls);
+        let mut aborted = None;
+        loop {
+            let mut ev = thread
+                .next_event()
+                .await
+                .map_err(|e| ProxyError::internal(format!("event error: {e}")))?;
+            if ev.id != turn.submission_id {
+                continue;
+            }
+            if !context_for_task.intercept_event(&mut ev.msg) {
+                continue;
+            }
+            // Past a cap only the end of the interrupted turn matters.
+            if output_cap.reached() || tool_call_cap.reached() {
+                if output_cap::ends_turn(&ev.msg) {
+                    break;
+                }
+                continue;
+            }
+            if let Some(text) = agent_text.text(&ev.msg) {
+                final_text.push_str(output_cap.take(&text));
+                if output_cap.reached() {
+                    output_cap::interrupt(thread).await;
+                }
+            }
+            context_for_task.record_event(&ev.msg);
+            match ev.msg {
+                // Assistant text is appended once below from the final message.
+                EventMsg::RawResponseItem(raw)
+                    if !matches!(raw.item, ResponseItem::Message { .. }) =>
+                {
+                    if !tool_call_cap.admit(&raw.item) {
+                        output_cap::interrupt(thread).await;
+                        continue;
+                    }
+                    output_items_for_task.lock().await.push(raw.item);
+                }
+                EventMsg::TurnComplete(_) => break,
+                EventMsg::Error(err) => return Err(upstream_errors::turn_error(&err)),
+                EventMsg::Warning(warn) => {
+                    info!("warning from Codex: {}", warn.message);
+                }
+                EventMsg::TokenCount(count) => {
+                    if let Some(info) = count.info {
+                        context_for_task.add_tokens(info.last_token_usage.total_tokens);
+                    }
+                }
+                EventMsg::PatchApplyEnd(patch) if patch.success => {
+                    let mut files = changed_files_for_task.lock().await;
+                    codex_events::record_changed_files(&mut files, &patch.changes);
+                }
+                EventMsg::PlanUpdate(update) => {
+                    *plan_for_task.lock().await = Some(update);
+                }
+                msg @ EventMsg::McpToolCallEnd(_) => {
+                    if let Some(item) = codex_events::mcp_call_item(&msg) {
+                        let call_id = item["id"].as_str().unwrap_or_default().to_string();
+                        mcp_calls_for_task.lock().await.insert(call_id, item);
+                    }
+                }
+                msg @ (EventMsg::ExecApprovalRequest(_)
+                | EventMsg::ApplyPatchApprovalRequest(_))
+                    if turn.dry_run =>
+                {
+                    if let Some(patch) = ProposedPatch::from_event(&msg) {
+                        proposed_patches_for_task.lock().await.push(patch);
+                    }
+                    approvals
+                        .deny_dry_run(thread, thread_id, &ev.id, &msg)
+                        .await;
+                }
+                msg @ (EventMsg::ExecApprovalRequest(_)
+                | EventMsg::ApplyPatchApprovalRequest(_)) => {
+                    // Answered by the webhook, or denied under `never`.
+                    approvals
+                        .handle(thread, thread_id, &ev.id, turn.approval_policy, &msg)
+                        .await;
+                }
+                EventMsg::TurnAborted(abort) => {
+                    aborted = Some(codex_events::abort_reason(&abort.reason));
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        if !final_text.trim().is_empty() {
+            output_items_for_task
+                .lock()
+                .await
+                .push(assistant_message(final_text.trim()));
+        }
+
+        let incomplete = if let Some(reason) = aborted {
+            Some(incomplete_details(reason))
+        } else if output_cap.reached() {
+            Some(incomplete_details("max_output_tokens"))
+        } else if tool_call_cap.reached() {
+            Some(incomplete_details("max_tool_calls"))
+        } else if let Some(Err(message)) = output_schema
+            .as_ref()
+            .map(|schema| schema.check(&final_text))
+        {
+            Some(structured_output::incomplete_details(message))
+        } else {
+            None
+        };
+        Ok(incomplete)
+    };
+    let handle = tokio::spawn(task.instrument(turn_span));
+    let turn_result = handle.await;
+    if !stored {
+        state.threads.discard(thread_id, &thread_for_discard).await;
+    }
+
+    let incomplete = match turn_result {
+        Ok(Ok(incomplete)) => incomplete,
+        Ok(Err(e)) => return e.into_response(),
+        Err(join_err) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                join_err.to_string(),
+                "internal_error",
+            );
+        }
+    };
+
+    let output_items_snapshot = {
+        let guard = output_items.lock().await;
+        guard.clone()
+    };
+    let citations = Citations::from_items(&output_items_snapshot);
+    let mcp_calls = mcp_calls.lock().await.clone();
+
+    let resp = ResponsesResponse {
+        id: response_id,
+        object: "response".to_string(),
+        created_at: now_ts(),
+        model: body.model.clone(),
+        status: if incomplete.is_some() {
+            "incomplete"
+        } else {
+            "completed"
+        }
+        .to_string(),
+        output: output_items_snapshot
+            .iter()
+            .filter_map(|item| {
+                if let ResponseItem::FunctionCall { call_id, .. } = item
+                    && let Some(mcp_call) = mcp_calls.get(call_id)
+                {
+                    return Some(mcp_call.clone());
+                }
+                if is_codex_tool_item(item) {
+                    return None;
+                }
+                let mut rendered = includes.render_item(item);
+                citations.annotate_item(&mut rendered);
+                Some(rendered)
+            })
+            .chain(
+                proposed_patches
+                    .lock()
+                    .await
+                    .iter()
+                    .map(ProposedPatch::output_item),
+            )
+            .collect(),
+        conversation_id: stored.then(|| thread_id.to_string()),
+        changed_files: changed_files.lock().await.clone(),
+        error: None,
+        incomplete_details: incomplete,
+        usage: None,
+        plan: plan.lock().await.take(),
+        metadata: body.metadata.clone(),
+        store: stored,
+    };
+    if let Some(store) = state.response_store.as_ref().filter(|_| resp.store) {
+        store.put(
+            context.owner().unwrap_or_default(),
+            serde_json::to_value(&resp).unwrap_or_default(),
+        );
+    }
+
+    let body = serde_json::to_string(&resp).unwrap_or_else(|_| "{}".to_string());
+    if let (Some(cache), Some(key)) = (&state.response_cache, cache_key) {
+        // Replays must not hand other clients this conversation.
+        let replay = ResponsesResponse {
+            conversation_id: None,
+            ..resp
+        };
+        cache.put(
+            key,
+            serde_json::to_string(&replay).unwrap_or_else(|_| "{}".to_string()),
+        );
+        return with_cache_status(json_response(StatusCode::OK, body), false);
+    }
+    json_response(StatusCode::OK, body)
+}
+
+async fn handle_responses_stream(
+    state: AppState,
+    context: RequestContext,
+    priority: i32,
+    body: ResponsesRequest,
+) -> Response {
+    let includes = match ResponseIncludes::parse(&body.include) {
+        Ok(includes) => includes,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = check_metadata(&body.metadata) {
+        return e.into_response();
+    }
+    if let Err(e) = check_store(&body) {
+        return e.into_response();
+    }
+    if let Err(e) = check_tools(&body.tools) {
+        return e.into_response();
+    }
+    if let Err(e) = check_seed(&body) {
+        return e.into_response();
+    }
+    let stored = body.stored();
+    let shaping = match DeltaShaping::from_request(
+        body.stream_granularity.as_deref(),
+        body.stream_min_interval_ms,
+    ) {
+        Ok(shaping) => shaping,
+        Err(e) => return e.into_response(),
+    };
+
+    let output_schema = match OutputSchema::from_text_format(body.text.as_ref()) {
+        Ok(schema) => schema,
+        Err(e) => return e.into_response(),
+    };
+
+    let merged_text = match merge_responses_input(&body.input, body.instructions.as_deref()) {
+        Some(text) => text,
+        None => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "no user content found".to_string(),
+                "invalid_request_error",
+            );
+        }
+    };
+
+    let turn = match body
+        .turn_options(merged_text, true, priority, output_schema.as_ref())
+        .prepare(&state, &context)
+        .await
+    {
+        Ok(turn) => turn,
+        Err(e) => return e.into_response(),
+    };
+    let thread = match turn.thread(&state, &context).await {
+        Ok(thread) => thread,
+        Err(e) => return e.into_response(),
+    };
+    let response_id = format!("resp-codex-{}", uuid::Uuid::new_v4());
+    context.set_response_id(&response_id);
+    let turn = match turn_setup::submit(&state, &context, thread, turn).await {
+        Ok(turn) => turn,
+        Err(e) => return e.into_response(),
+    };
+
+    let max_output_tokens =
+        output_cap::effective(state.max_output_tokens.get(), body.max_output_tokens);
+    let max_tool_calls = body.max_tool_calls;
+    let thread_id = turn.thread_id;
+    let mut events = ResponseEventBuilder::new(
+        ResponsesResponse {
+            id: response_id,
+            object: "response".to_string(),
+            created_at: now_ts(),
+            model: body.model.clone(),
+            status: "in_progress".to_string(),
+            output: Vec::new(),
+            conversation_id: stored.then(|| thread_id.to_string()),
+            changed_files: Vec::new(),
+            error: None,
+            incomplete_details: None,
+            usage: None,
+            plan: None,
+            metadata: body.metadata.clone(),
+            store: stored,
+        },
+        includes,
+    );
+
+    let approvals = state.approvals.clone();
+    let response_store = state.response_store.clone().filter(|_| stored);
+    // Unstored conversations are thrown away once the turn ends.
+    let threads = (!stored).then(|| state.threads.clone());
+    let owner = context.owner().unwrap_or_default().to_string();
+    let context_for_task = context.clone();
+    let (tx, rx) = state.stream_buffers.channel();
+    let rx = state.stream_buffers.shape(rx, shaping);
+
+    let _ = tx.send(events.created()).await;
+    let _ = tx.send(events.in_progress()).await;
+
+    let turn_span = turn.span();
+    let task = async move {
+        let thread = &turn.thread;
+        let mut agent_text = AgentText::default();
+        let mut output_cap = OutputCap::new(max_output_tokens);
+        let mut tool_call_cap = ToolCallCap::new(max_tool_calls);
+        // Text of the message still streaming, for closing it early.
+        let mut message_text = String::new();
+        // Text of the last complete message, the answer a schema applies to.
+        let mut last_message = String::new();
+        loop {
+            let mut ev = match thread.next_event().await {
+                Ok(ev) => ev,
+                Err(e) => {
+                    let error = ProxyError::internal(format!("event error: {e}"));
+                    send_failed(&tx, &mut events, &error).await;
+                    break;
+                }
+            };
+            if ev.id != turn.submission_id {
+                continue;
+            }
+            if !context_for_task.intercept_event(&mut ev.msg) {
+                continue;
+            }
+            // The client already has `response.incomplete`; wait out the interrupt.
+            if output_cap.reached() || tool_call_cap.reached() {
+                if output_cap::ends_turn(&ev.msg) {
+                    break;
+                }
+                continue;
+            }
+            if let Some(text) = agent_text.text(&ev.msg) {
+                let text = output_cap.take(&text);
+                if !text.is_empty() {
+                    message_text.push_str(text);
+                    for chunk in events.output_text_delta(text) {
+                        let _ = tx.send(chunk).await;
+                    }
+                }
+                if output_cap.reached() {
+                    for chunk in events.output_item(assistant_message(message_text.trim())) {
+                        let _ = tx.send(chunk).await;
+                    }
+                    let _ = tx
+                        .send(events.incomplete(incomplete_details("max_output_tokens")))
+                        .await;
+                    output_cap::interrupt(thread).await;
+                    continue;
+                }
+            }
+            context_for_task.record_event(&ev.msg);
+            match ev.msg {
+                EventMsg::AgentReasoningDelta(d) => {
+                    let chunk = events.reasoning_summary_delta(&d.delta);
+                    let _ = tx.send(chunk).await;
+                }
+                EventMsg::AgentReasoningSectionBreak(_) => events.reasoning_section_break(),
+                EventMsg::RawResponseItem(raw) => {
+                    if !tool_call_cap.admit(&raw.item) {
+                        let _ = tx
+                            .send(events.incomplete(incomplete_details("max_tool_calls")))
+                            .await;
+                        output_cap::interrupt(thread).await;
+                        continue;
+                    }
+                    if matches!(raw.item, ResponseItem::Message { .. }) {
+                        last_message = std::mem::take(&mut message_text);
+                    }
+                    // MCP calls are streamed as `mcp_call` items from their
+                    // begin and end; Codex's other tool calls are its own.
+                    if is_codex_tool_item(&raw.item) {
+                        continue;
+                    }
+                    for chunk in events.output_item(raw.item) {
+                        let _ = tx.send(chunk).await;
+                    }
+                }
+                EventMsg::TurnComplete(_) => {
+                    // Text that arrived only with the turn's end has no item.
+                    if events.message_open() {
+                        last_message = std::mem::take(&mut message_text);
+                        for chunk in events.output_item(assistant_message(last_message.trim())) {
+                            let _ = tx.send(chunk).await;
+                        }
+                    }
+                    let event = match output_schema
+                        .as_ref()
+                        .map(|schema| schema.check(&last_message))
+                    {
+                        Some(Err(message)) => {
+                            events.incomplete(structured_output::incomplete_details(message))
+                        }
+                        _ => events.completed(),
+                    };
+                    let _ = tx.send(event).await;
+                    break;
+                }
+                EventMsg::Error(err) => {
+                    let error = upstream_errors::turn_error(&err);
+                    send_failed(&tx, &mut events, &error).await;
+                    break;
+                }
+                EventMsg::Warning(warn) => {
+                    info!("warning from Codex: {}", warn.message);
+                }
+                EventMsg::TokenCount(count) => {
+                    if let Some(info) = count.info {
+                        context_for_task.add_tokens(info.last_token_usage.total_tokens);
+                        let event = events.usage(&info.last_token_usage);
+                        let _ = tx.send(event).await;
+                    }
+                }
+                msg @ EventMsg::PatchApplyEnd(_) => {
+                    if let Some(event) = codex_events::patch_event(&msg) {
+                        let chunk = events.event(event.kind, event.fields);
+                        let _ = tx.send(chunk).await;
+                    }
+                    if let EventMsg::PatchApplyEnd(patch) = &msg
+                        && patch.success
+                    {
+                        events.record_patch(&patch.changes);
+                    }
+                }
+                msg @ (EventMsg::ExecCommandBegin(_) | EventMsg::ExecCommandEnd(_)) => {
+                    if let Some(event) = codex_events::exec_event(&msg) {
+                        let chunk = events.event(event.kind, event.fields);
+                        let _ = tx.send(chunk).await;
+                    }
+                }
+                msg @ (EventMsg::McpToolCallBegin(_) | EventMsg::McpToolCallEnd(_)) => {
+                    if let Some(item) = codex_events::mcp_call_item(&msg) {
+                        let _ = tx.send(events.mcp_call(item)).await;
+                    }
+                }
+                msg @ EventMsg::PlanUpdate(_) => {
+                    if let Some(event) = codex_events::plan_event(&msg) {
+                        let chunk = events.event(event.kind, event.fields);
+                        let _ = tx.send(chunk).await;
+                    }
+                    if let EventMsg::PlanUpdate(update) = msg {
+                        events.record_plan(update);
+                    }
+                }
+                msg @ (EventMsg::ExecApprovalRequest(_)
+                | EventMsg::ApplyPatchApprovalRequest(_))
+                    if turn.dry_run =>
+                {
+                    if let Some(patch) = ProposedPatch::from_event(&msg) {
+                        for chunk in events.proposed_patch(&patch) {
+                            let _ = tx.send(chunk).await;
+                        }
+                    }
+                    approvals
+                        .deny_dry_run(thread, thread_id, &ev.id, &msg)
+                        .await;
+                }
+                msg @ (EventMsg::ExecApprovalRequest(_)
+                | EventMsg::ApplyPatchApprovalRequest(_)) => {
+                    if let Some(request) = approvals
+                        .handle(thread, thread_id, &ev.id, turn.approval_policy, &msg)
+                        .await
+                    {
+                        let chunk = events.event(request.kind, request.fields);
+                        let _ = tx.send(chunk).await;
+                    }
+                }
+                EventMsg::TurnAborted(abort) => {
+                    // The answer so far is kept as the response's message.
+                    if !message_text.trim().is_empty() {
+                        for chunk in events.output_item(assistant_message(message_text.trim())) {
+                            let _ = tx.send(chunk).await;
+                        }
+                    }
+                    let reason = codex_events::abort_reason(&abort.reason);
+                    let _ = tx.send(events.incomplete(incomplete_details(reason))).await;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        if let Some(store) = response_store {
+            store.put(
+                &owner,
+                serde_json::to_value(&events.response).unwrap_or_default(),
+            );
+        }
+        approvals.clear_turn(&turn.submission_id);
+        if let Some(threads) = threads {
+            threads.discard(thread_id, thread).await;
+        }
+    };
+    tokio::spawn(task.instrument(turn_span));
+
+    let stream = ReceiverStream::new(rx)
+        .map(|json_val| Ok::<Event, std::convert::Infallible>(named_event(&json_val)));
+
+    state.sse_keep_alive.apply(Sse::new(stream)).into_response()
+}
+
+/// Ends a Responses stream with `response.failed`.
+async fn send_failed(
+    tx: &StreamSender<serde_json::Value>,
+    events: &mut ResponseEventBuilder,
+    error: &ProxyError,
+) {
+    log_message(
+        serde_json::json!({
+            "type": "responses_stream_error",
+            "error": error.message,
+        })
+        .to_string(),
+    );
+    let _ = tx.send(events.failed(error)).await;
+}
+
+/// Unstored requests cannot continue a conversation: its history (and this
+/// turn) is already persisted.
+fn check_store(body: &ResponsesRequest) -> Result<(), ProxyError> {
+    if !body.stored() && body.conversation_id.is_some() {
+        return Err(ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            "store: false cannot be combined with conversation_id",
+            "invalid_request_error",
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects `metadata` beyond OpenAI's limits: 16 pairs, 64-character keys
+/// and 512-character values.
+fn check_metadata(metadata: &BTreeMap<String, String>) -> Result<(), ProxyError> {
+    let invalid = |message: String| {
+        ProxyError::new(StatusCode::BAD_REQUEST, message, "invalid_request_error")
+            .with_code("invalid_metadata")
+    };
+    if metadata.len() > MAX_METADATA_PAIRS {
+        return Err(invalid(format!(
+            "metadata has {} pairs; at most {MAX_METADATA_PAIRS} are allowed",
+            metadata.len()
+        )));
+    }
+    for (key, value) in metadata {
+        if key.chars().count() > MAX_METADATA_KEY_LEN {
+            return Err(invalid(format!(
+                "metadata key {key:?} is longer than {MAX_METADATA_KEY_LEN} characters"
+            )));
+        }
+        if value.chars().count() > MAX_METADATA_VALUE_LEN {
+            return Err(invalid(format!(
+                "metadata value for {key:?} is longer than {MAX_METADATA_VALUE_LEN} characters"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// `incomplete_details` of a response cut off at its `max_output_tokens` or
+/// `max_tool_calls`, or by its turn being aborted; see [`structured_output::incomplete_details`] for
+/// answers that failed validation.
+fn incomplete_details(reason: &str) -> serde_json::Value {
+    serde_json::json!({ "reason": reason })
+}
+
+/// Wraps a Responses event payload in an SSE frame whose `event:` name matches
+/// the payload's `type` field.
+pub(crate) fn named_event(payload: &serde_json::Value) -> Event {
+    let data = serde_json::to_string(payload).unwrap_or_else(|_| "{}".to_string());
+    let event = Event::default().data(data);
+    match payload.get("type").and_then(|v| v.as_str()) {
+        Some(name) => event.event(name),
+        None => event,
+    }
+}
+
+/// An `output_text` content part before any of its text.
+fn empty_output_text() -> serde_json::Value {
+    serde_json::json!({ "type": "output_text", "text": "", "annotations": [] })
+}
+
+fn assistant_message(text: &str) -> ResponseItem {
+    ResponseItem::Message {
+        id: None,
+        role: "assistant".to_string(),
+        content: vec![ContentItem::OutputText {
+            text: text.to_string(),
+        }],
+    }
+}
+
+fn merge_responses_input(input: &serde_json::Value, instructions: Option<&str>) -> Option<String> {
+    let mut parts = Vec::new();
+    if let Some(instr) = instructions
+        && !instr.trim().is_empty()
+    {
+        parts.push(format!("system: {}", instr.trim()));
+    }
+
+    let items = match input {
+        serde_json::Value::String(text) => {
+            vec![serde_json::json!({ "role": "user", "content": text })]
+        }
+        serde_json::Value::Array(items) => items.clone(),
+        _ => Vec::new(),
+    };
+
+    for item in &items {
+        let Some(obj) = item.as_object() else {
+            continue;
+        };
+
+        let role = obj.get("role").and_then(|v| v.as_str()).unwrap_or("user");
+
+        let content_text = match obj.get("content").or_else(|| obj.get("text")) {
+            Some(serde_json::Value::String(s)) => s.clone(),
+            Some(serde_json::Value::Array(arr)) => arr
+                .iter()
+                .filter_map(|v| v.get("text").or_else(|| v.get("content")))
+                .filter_map(|v| v.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => String::new(),
+        };
+
+        if !content_text.trim().is_empty() {
+            parts.push(format!("{role}: {}", content_text.trim()));
+        }
+    }
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn include_rejects_values_the_proxy_cannot_fill() {
+        assert!(ResponseIncludes::parse(&["reasoning.encrypted_content".to_string()]).is_ok());
+        for value in ["message.output_text.logprobs", "bogus"] {
+            let err = ResponseIncludes::parse(&[value.to_string()]).expect_err(value);
+            assert_eq!(err.status, StatusCode::BAD_REQUEST);
+            assert_eq!(err.code, Some("unsupported_parameter"));
+        }
+    }
+
+    #[test]
+    fn encrypted_reasoning_is_only_rendered_when_included() {
+        let item = ResponseItem::Reasoning {
+            id: "rs_1".to_string(),
+            summary: Vec::new(),
+            content: None,
+            encrypted_content: Some("opaque".to_string()),
+        };
+
+        let default = ResponseIncludes::default().render_item(&item);
+        assert_eq!(default.get("encrypted_content"), None);
+
+        let included = ResponseIncludes::parse(&["reasoning.encrypted_content".to_string()])
+            .expect("valid include")
+            .render_item(&item);
+        assert_eq!(
+            included.get("encrypted_content"),
+            Some(&serde_json::json!("opaque"))
+        );
+    }
+
+    #[test]
+    fn reasoning_effort_is_read_from_the_reasoning_object() {
+        let body: ResponsesRequest = serde_json::from_value(serde_json::json!({
+            "model": "2.5-tpg",
+            "input": "hi",
+            "reasoning": {"effort": "high", "summary": "auto"},
+        }))
+        .expect("request");
+        assert_eq!(body.reasoning.effort, Some(ReasoningEffort::High));
+    }
+
+    #[test]
+    fn reasoning_summary_deltas_track_section_breaks() {
+        let mut events = ResponseEventBuilder::new(
+            ResponsesResponse {
+                id: "resp_1".to_string(),
+                object: "response".to_string(),
+                created_at: 0,
+                model: "gpt-test".to_string(),
+                status: "in_progress".to_string(),
+                output: Vec::new(),
+                conversation_id: None,
+                changed_files: Vec::new(),
+                error: None,
+                incomplete_details: None,
+                usage: None,
+                plan: None,
+                metadata: BTreeMap::new(),
+                store: true,
+            },
+            ResponseIncludes::default(),
+        );
+
+        let first = events.reasoning_summary_delta("thinking");
+        events.reasoning_section_break();
+        let second = events.reasoning_summary_delta("more");
+
+        assert_eq!(
+            first["type"],
+            serde_json::json!("response.reasoning_summary_text.delta")
+        );
+        assert_eq!(first["summary_index"], serde_json::json!(0));
+        assert_eq!(second["summary_index"], serde_json::json!(1));
+        assert_eq!(second["output_index"], serde_json::json!(0));
+    }
+
+    #[test]
+    fn streamed_text_opens_and_closes_its_message() {
+        let mut events = ResponseEventBuilder::new(
+            ResponsesResponse {
+                id: "resp_1".to_string(),
+                object: "response".to_string(),
+                created_at: 0,
+                model: "gpt-test".to_string(),
+                status: "in_progress".to_string(),
+                output: Vec::new(),
+                conversation_id: None,
+                changed_files: Vec::new(),
+                error: None,
+                incomplete_details: None,
+                usage: None,
+                plan: None,
+                metadata: BTreeMap::new(),
+                store: true,
+            },
+            ResponseIncludes::default(),
+        );
+
+        let mut emitted = events.output_text_delta("Hello ");
+        emitted.extend(events.output_text_delta("world"));
+        emitted.extend(events.output_item(assistant_message("Hello world")));
+
+        let kinds = emitted
+            .iter()
+            .map(|event| event["type"].as_str().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                "response.output_item.added",
+                "response.content_part.added",
+                "response.output_text.delta",
+                "response.output_text.delta",
+                "response.output_text.done",
+                "response.content_part.done",
+                "response.output_item.done",
+            ]
+        );
+        let item_id = emitted[0]["item"]["id"].clone();
+        assert!(item_id.is_string());
+        for event in &emitted {
+            assert_eq!(event["output_index"], serde_json::json!(0));
+        }
+        for event in &emitted[1..6] {
+            assert_eq!(event["item_id"], item_id);
+            assert_eq!(event["content_index"], serde_json::json!(0));
+        }
+        assert_eq!(emitted[4]["text"], serde_json::json!("Hello world"));
+        assert_eq!(emitted[6]["item"]["id"], item_id);
+        assert_eq!(events.response.output.len(), 1);
+    }
+}
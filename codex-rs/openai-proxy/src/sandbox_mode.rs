@@ -0,0 +1,80 @@
+//! The sandbox a request's turns run in. Turns are read-only unless the
+//! deployment opts in with `CODEX_OPENAI_PROXY_ALLOW_DANGER_FULL_ACCESS`,
+//! after which requests may ask for `danger-full-access`: no sandbox at all,
+//! for trusted CI automation that needs to build, test and push.
+
+use axum::http::StatusCode;
+use codex_core::protocol::SandboxPolicy;
+use tracing::warn;
+
+use crate::ProxyError;
+
+/// Resolves a request's `sandbox_mode`: `read-only` (the default), or
+/// `danger-full-access` when the proxy allows it.
+pub(crate) fn sandbox_policy(
+    requested: Option<&str>,
+    allow_full_access: bool,
+) -> Result<SandboxPolicy, ProxyError> {
+    match requested {
+        None | Some("read-only") => Ok(SandboxPolicy::ReadOnly),
+        Some("danger-full-access") if allow_full_access => Ok(SandboxPolicy::DangerFullAccess),
+        Some("danger-full-access") => Err(ProxyError::new(
+            StatusCode::FORBIDDEN,
+            "sandbox_mode \"danger-full-access\" is not enabled on this proxy",
+            "permission_error",
+        )
+        .with_code("danger_full_access_disabled")),
+        Some(other) => Err(ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "unsupported sandbox_mode {other:?}; expected \"read-only\" or \"danger-full-access\""
+            ),
+            "invalid_request_error",
+        )),
+    }
+}
+
+/// Says at startup, as loudly as the logs allow, that requests may run
+/// commands on this host unsandboxed.
+pub(crate) fn warn_full_access_allowed(audited: bool) {
+    warn!("==================================================================");
+    warn!("DANGER: sandbox_mode \"danger-full-access\" is allowed.");
+    warn!("Any client with access to this proxy can have Codex run arbitrary");
+    warn!("commands and edit any file this process can reach, unsandboxed.");
+    warn!("Only enable this for trusted automation on a disposable host.");
+    if !audited {
+        warn!("No audit log is configured; set CODEX_OPENAI_PROXY_AUDIT_LOG");
+        warn!("to keep a record of which requests ran with full access.");
+    }
+    warn!("==================================================================");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn full_access_requires_opt_in() {
+        assert_eq!(
+            sandbox_policy(None, false).ok(),
+            Some(SandboxPolicy::ReadOnly)
+        );
+        assert_eq!(
+            sandbox_policy(Some("danger-full-access"), true).ok(),
+            Some(SandboxPolicy::DangerFullAccess)
+        );
+        assert_eq!(
+            sandbox_policy(Some("danger-full-access"), false)
+                .err()
+                .map(|e| e.status),
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            sandbox_policy(Some("workspace-write"), true)
+                .err()
+                .map(|e| e.status),
+            Some(StatusCode::BAD_REQUEST)
+        );
+    }
+}
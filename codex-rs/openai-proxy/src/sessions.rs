@@ -0,0 +1,236 @@
+//! `GET /v1/sessions`: the Codex sessions recorded in the Codex home, from
+//! the CLI, the IDE or the proxy itself, newest first, so client UIs can
+//! offer a history browser. A session can be continued with
+//! `POST /v1/conversations/import`.
+
+use std::path::Path;
+
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use codex_core::RolloutRecorder;
+use codex_core::ThreadItem;
+use codex_core::parse_cursor;
+use codex_core::parse_turn_item;
+use codex_protocol::items::TurnItem;
+use codex_protocol::models::ResponseItem;
+use codex_protocol::protocol::RolloutItem;
+use codex_protocol::protocol::RolloutLine;
+use codex_protocol::protocol::SessionMetaLine;
+use codex_protocol::protocol::SessionSource;
+use codex_protocol::protocol::USER_MESSAGE_BEGIN;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::io::AsyncBufReadExt;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::json_response;
+
+/// Sessions returned without a `limit`.
+const DEFAULT_LIST_LIMIT: usize = 20;
+
+/// Most sessions returned at once.
+const MAX_LIST_LIMIT: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SessionsQuery {
+    #[serde(default)]
+    limit: Option<usize>,
+    /// The `next_cursor` of the previous page.
+    #[serde(default)]
+    after: Option<String>,
+}
+
+/// One past session.
+#[derive(Debug, PartialEq, Serialize)]
+struct SessionSummary {
+    /// The session's conversation id.
+    id: String,
+    object: &'static str,
+    /// RFC 3339 times the session started and was last written to.
+    created_at: Option<String>,
+    updated_at: Option<String>,
+    first_message: Option<String>,
+    /// The model the session's first turn ran with.
+    model: Option<String>,
+    model_provider: Option<String>,
+    source: SessionSource,
+    cwd: String,
+    /// The rollout, as `POST /v1/conversations/import` takes it.
+    rollout_path: String,
+}
+
+/// `GET /v1/sessions?limit=&after=`: past sessions, newest first (20 by
+/// default, at most 100), with a `next_cursor` while there are more.
+pub(crate) async fn handle_list_sessions(
+    State(state): State<AppState>,
+    Query(query): Query<SessionsQuery>,
+) -> Response {
+    let cursor = match query.after.as_deref() {
+        Some(after) => match parse_cursor(after) {
+            Some(cursor) => Some(cursor),
+            None => {
+                return ProxyError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("invalid after cursor: {after}"),
+                    "invalid_request_error",
+                )
+                .into_response();
+            }
+        },
+        None => None,
+    };
+    let config = match state.configs.load(Vec::new()).await {
+        Ok(config) => config,
+        Err(e) => return ProxyError::internal(e.to_string()).into_response(),
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    let page = match RolloutRecorder::list_threads(
+        &config.codex_home,
+        limit,
+        cursor.as_ref(),
+        &[],
+        None,
+        &config.model_provider_id,
+    )
+    .await
+    {
+        Ok(page) => page,
+        Err(e) => {
+            return ProxyError::internal(format!("failed to list sessions: {e}")).into_response();
+        }
+    };
+
+    let mut sessions = Vec::with_capacity(page.items.len());
+    for item in page.items {
+        let model = first_model(&item.path).await;
+        if let Some(session) = summarize(item, model) {
+            sessions.push(session);
+        }
+    }
+    let next_cursor = page
+        .next_cursor
+        .and_then(|cursor| serde_json::to_value(cursor).ok());
+    json_response(
+        StatusCode::OK,
+        serde_json::json!({
+            "object": "list",
+            "data": sessions,
+            "has_more": next_cursor.is_some(),
+            "next_cursor": next_cursor,
+        })
+        .to_string(),
+    )
+}
+
+/// A listed rollout as a session, if it starts with its session metadata.
+fn summarize(item: ThreadItem, model: Option<String>) -> Option<SessionSummary> {
+    let meta = item
+        .head
+        .first()
+        .and_then(|first| serde_json::from_value::<SessionMetaLine>(first.clone()).ok())?
+        .meta;
+    let first_message = item
+        .head
+        .iter()
+        .filter_map(|value| serde_json::from_value::<ResponseItem>(value.clone()).ok())
+        .find_map(|item| match parse_turn_item(&item) {
+            Some(TurnItem::UserMessage(user)) => Some(user.message()),
+            _ => None,
+        })
+        .map(|message| match message.find(USER_MESSAGE_BEGIN) {
+            Some(at) => message[at + USER_MESSAGE_BEGIN.len()..].trim().to_string(),
+            None => message,
+        });
+    Some(SessionSummary {
+        id: meta.id.to_string(),
+        object: "session",
+        created_at: item.created_at,
+        updated_at: item.updated_at,
+        first_message,
+        model,
+        model_provider: meta.model_provider,
+        source: meta.source,
+        cwd: meta.cwd.display().to_string(),
+        rollout_path: item.path.display().to_string(),
+    })
+}
+
+/// The model of the first turn recorded in the rollout at `path`.
+async fn first_model(path: &Path) -> Option<String> {
+    let file = tokio::fs::File::open(path).await.ok()?;
+    let mut lines = tokio::io::BufReader::new(file).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if let Ok(RolloutLine {
+            item: RolloutItem::TurnContext(context),
+            ..
+        }) = serde_json::from_str(&line)
+        {
+            return Some(context.model);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codex_protocol::models::ContentItem;
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+
+    #[test]
+    fn sessions_are_summarized_from_the_rollout_head() {
+        let meta = serde_json::json!({
+            "id": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "timestamp": "2026-01-02T03:04:05.000Z",
+            "cwd": "/work",
+            "originator": "codex_cli_rs",
+            "cli_version": "0.0.0",
+            "instructions": null,
+            "source": "cli",
+            "model_provider": "openai",
+        });
+        let message = |role: &str, text: &str| {
+            serde_json::to_value(ResponseItem::Message {
+                id: None,
+                role: role.to_string(),
+                content: vec![ContentItem::InputText {
+                    text: text.to_string(),
+                }],
+            })
+            .expect("serialize message")
+        };
+        let item = ThreadItem {
+            path: PathBuf::from("/home/.codex/sessions/2026/01/02/rollout.jsonl"),
+            head: vec![
+                meta,
+                message("user", "<environment_context>/work</environment_context>"),
+                message("user", "fix the flaky test"),
+            ],
+            created_at: Some("2026-01-02T03:04:05.000Z".to_string()),
+            updated_at: None,
+        };
+        assert_eq!(
+            summarize(item, Some("gpt-5".to_string())),
+            Some(SessionSummary {
+                id: "67e55044-10b1-426f-9247-bb680e5fe0c8".to_string(),
+                object: "session",
+                created_at: Some("2026-01-02T03:04:05.000Z".to_string()),
+                updated_at: None,
+                first_message: Some("fix the flaky test".to_string()),
+                model: Some("gpt-5".to_string()),
+                model_provider: Some("openai".to_string()),
+                source: SessionSource::Cli,
+                cwd: "/work".to_string(),
+                rollout_path: "/home/.codex/sessions/2026/01/02/rollout.jsonl".to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! Keep-alives on idle SSE streams. A long tool execution can leave a stream
+//! silent for minutes; buffering proxies (nginx) and some SSE parsers drop it
+//! unless something arrives, and not all of them count comment lines.
+
+use std::time::Duration;
+
+use axum::response::sse::Event;
+use axum::response::sse::KeepAlive;
+use axum::response::sse::Sse;
+
+use crate::DEFAULT_SSE_KEEP_ALIVE_SECS;
+use crate::app_state::env_limit;
+use crate::proxy_config;
+
+/// What idle streams send, and how often.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SseKeepAlive {
+    /// `None` sends nothing.
+    interval: Option<Duration>,
+    /// An `event: ping` with `{"type":"ping"}` data instead of an empty `:`
+    /// comment.
+    ping: bool,
+}
+
+impl Default for SseKeepAlive {
+    fn default() -> Self {
+        Self::new(
+            Some(Duration::from_secs(DEFAULT_SSE_KEEP_ALIVE_SECS)),
+            false,
+        )
+    }
+}
+
+impl SseKeepAlive {
+    pub(crate) fn new(interval: Option<Duration>, ping: bool) -> Self {
+        Self { interval, ping }
+    }
+
+    /// `CODEX_OPENAI_PROXY_SSE_KEEP_ALIVE_SECS` (0 disables keep-alives) and
+    /// `CODEX_OPENAI_PROXY_SSE_KEEP_ALIVE_PAYLOAD` (`comment` or `ping`).
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        let mut keep_alive = Self::default();
+        if proxy_config::var_os("CODEX_OPENAI_PROXY_SSE_KEEP_ALIVE_SECS").is_some() {
+            keep_alive.interval =
+                env_limit("CODEX_OPENAI_PROXY_SSE_KEEP_ALIVE_SECS")?.map(Duration::from_secs);
+        }
+        if let Ok(value) = proxy_config::var("CODEX_OPENAI_PROXY_SSE_KEEP_ALIVE_PAYLOAD") {
+            keep_alive.ping = match value.as_str() {
+                "comment" => false,
+                "ping" => true,
+                _ => anyhow::bail!(
+                    "CODEX_OPENAI_PROXY_SSE_KEEP_ALIVE_PAYLOAD must be comment or ping, got {value:?}"
+                ),
+            };
+        }
+        Ok(keep_alive)
+    }
+
+    pub(crate) fn apply<S>(&self, sse: Sse<S>) -> Sse<S> {
+        let Some(interval) = self.interval else {
+            return sse;
+        };
+        let keep_alive = KeepAlive::new().interval(interval);
+        if self.ping {
+            sse.keep_alive(
+                keep_alive.event(Event::default().event("ping").data(r#"{"type":"ping"}"#)),
+            )
+        } else {
+            sse.keep_alive(keep_alive)
+        }
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "interval_secs": self.interval.map(|interval| interval.as_secs()),
+            "payload": if self.ping { "ping" } else { "comment" },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+    use futures::StreamExt;
+    use pretty_assertions::assert_eq;
+
+    async fn first_frame(keep_alive: SseKeepAlive) -> String {
+        let events = futures::stream::pending::<Result<Event, std::convert::Infallible>>();
+        let mut body = keep_alive
+            .apply(Sse::new(events))
+            .into_response()
+            .into_body()
+            .into_data_stream();
+        let frame = body.next().await.expect("frame").expect("bytes");
+        String::from_utf8(frame.to_vec()).expect("utf-8 frame")
+    }
+
+    #[tokio::test]
+    async fn idle_streams_get_the_configured_payload() {
+        let interval = Some(Duration::from_millis(10));
+        assert_eq!(
+            first_frame(SseKeepAlive::new(interval, false)).await,
+            ":\n\n"
+        );
+        assert_eq!(
+            first_frame(SseKeepAlive::new(interval, true)).await,
+            "event: ping\ndata: {\"type\":\"ping\"}\n\n"
+        );
+    }
+}
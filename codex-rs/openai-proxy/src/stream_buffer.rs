@@ -0,0 +1,644 @@
+//! Buffers between a turn's event loop and the SSE response it feeds. The
+//! event loop must not stall behind a slow client, so besides waiting for
+//! room (the default) a full buffer can merge text deltas into one, or drop
+//! them and flag the stream in the log. Only text deltas are ever merged or
+//! dropped; every other event waits for room.
+//!
+//! Separately, a stream's text deltas can be re-chunked on word or sentence
+//! boundaries (see [`crate::stream_granularity`]), and a minimum interval
+//! between them holds back deltas that arrive faster than that and sends
+//! them merged, for clients that struggle with thousands of tiny SSE frames.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::Instant;
+
+use crate::DEFAULT_STREAM_BUFFER_CAPACITY;
+use crate::ProxyError;
+use crate::app_state::env_limit;
+use crate::log_message;
+use crate::proxy_config;
+use crate::stream_granularity;
+use crate::stream_granularity::StreamGranularity;
+
+/// What a stream does with a text delta when its client is not keeping up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Wait for the client, holding up the turn's event loop.
+    #[default]
+    Block,
+    /// Merge deltas while the buffer is full and send them as one.
+    Coalesce,
+    /// Drop deltas while the buffer is full; the stream is flagged in the log.
+    Drop,
+}
+
+impl BackpressurePolicy {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "block" => Some(Self::Block),
+            "coalesce" => Some(Self::Coalesce),
+            "drop" => Some(Self::Drop),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Block => "block",
+            Self::Coalesce => "coalesce",
+            Self::Drop => "drop",
+        }
+    }
+}
+
+/// `CODEX_OPENAI_PROXY_STREAM_BUFFER_CAPACITY` and
+/// `CODEX_OPENAI_PROXY_STREAM_BACKPRESSURE` (`block`, `coalesce` or `drop`).
+pub(crate) fn settings_from_env() -> anyhow::Result<(usize, BackpressurePolicy)> {
+    let capacity = match proxy_config::var("CODEX_OPENAI_PROXY_STREAM_BUFFER_CAPACITY") {
+        Ok(value) => value
+            .parse::<usize>()
+            .context("parse CODEX_OPENAI_PROXY_STREAM_BUFFER_CAPACITY")?,
+        Err(_) => DEFAULT_STREAM_BUFFER_CAPACITY,
+    };
+    let policy = match proxy_config::var("CODEX_OPENAI_PROXY_STREAM_BACKPRESSURE") {
+        Ok(value) => BackpressurePolicy::parse(&value).with_context(|| {
+            format!(
+                "CODEX_OPENAI_PROXY_STREAM_BACKPRESSURE must be block, coalesce or drop, got {value:?}"
+            )
+        })?,
+        Err(_) => BackpressurePolicy::default(),
+    };
+    Ok((capacity, policy))
+}
+
+/// `CODEX_OPENAI_PROXY_STREAM_MIN_INTERVAL_MS`; 0 or unset sends every delta
+/// as it arrives.
+pub(crate) fn min_interval_from_env() -> anyhow::Result<Option<Duration>> {
+    Ok(env_limit("CODEX_OPENAI_PROXY_STREAM_MIN_INTERVAL_MS")?.map(Duration::from_millis))
+}
+
+/// An event sent down a stream buffer.
+pub(crate) trait StreamItem: Sized {
+    /// Whether this is a text delta, the only kind of event that may be
+    /// merged or dropped.
+    fn is_delta(&self) -> bool;
+
+    /// Appends `next` to this delta if both continue the same text.
+    fn coalesce(&mut self, next: &Self) -> bool;
+
+    /// The text of this delta.
+    fn delta_text(&self) -> Option<String>;
+
+    /// This delta with its text replaced by `text`.
+    fn with_delta_text(&self, text: &str) -> Self;
+
+    /// Gives an event that carries a Responses `sequence_number` the next
+    /// one, for streams whose events were split after being numbered.
+    fn renumber(&mut self, _next_sequence_number: &mut Option<u64>) {}
+}
+
+/// Where the text of a streamed JSON delta lives: chat completion chunks and
+/// Responses text events.
+fn delta_text_pointer(event: &serde_json::Value) -> Option<&'static str> {
+    if event["object"] == "chat.completion.chunk" {
+        let choices = event["choices"].as_array()?;
+        let delta = choices.first()?["delta"].as_object()?;
+        let only_content = choices.len() == 1
+            && choices[0]["finish_reason"].is_null()
+            && delta.len() == 1
+            && delta
+                .get("content")
+                .is_some_and(serde_json::Value::is_string)
+            && event.get("usage").is_none();
+        return only_content.then_some("/choices/0/delta/content");
+    }
+    match event["type"].as_str()? {
+        "response.output_text.delta" | "response.reasoning_summary_text.delta" => {
+            event["delta"].is_string().then_some("/delta")
+        }
+        _ => None,
+    }
+}
+
+impl StreamItem for serde_json::Value {
+    fn is_delta(&self) -> bool {
+        delta_text_pointer(self).is_some()
+    }
+
+    fn coalesce(&mut self, next: &Self) -> bool {
+        let Some(pointer) = delta_text_pointer(self) else {
+            return false;
+        };
+        if delta_text_pointer(next) != Some(pointer) {
+            return false;
+        }
+        // Apart from the text (and Responses sequence numbers) both must be
+        // the same event, e.g. for the same output item.
+        let without_text = |event: &serde_json::Value| {
+            let mut event = event.clone();
+            if let Some(text) = event.pointer_mut(pointer) {
+                *text = serde_json::Value::Null;
+            }
+            if let Some(fields) = event.as_object_mut() {
+                fields.remove("sequence_number");
+            }
+            event
+        };
+        if without_text(self) != without_text(next) {
+            return false;
+        }
+        let next_text = next.pointer(pointer).and_then(|text| text.as_str());
+        match (self.pointer_mut(pointer), next_text) {
+            (Some(serde_json::Value::String(text)), Some(next_text)) => {
+                text.push_str(next_text);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn delta_text(&self) -> Option<String> {
+        let pointer = delta_text_pointer(self)?;
+        self.pointer(pointer)?.as_str().map(str::to_string)
+    }
+
+    fn with_delta_text(&self, text: &str) -> Self {
+        let mut event = self.clone();
+        if let Some(pointer) = delta_text_pointer(self)
+            && let Some(delta) = event.pointer_mut(pointer)
+        {
+            *delta = text.into();
+        }
+        event
+    }
+
+    fn renumber(&mut self, next_sequence_number: &mut Option<u64>) {
+        if let Some(number) = self.get_mut("sequence_number") {
+            let next = next_sequence_number.unwrap_or_else(|| number.as_u64().unwrap_or(0));
+            *number = next.into();
+            *next_sequence_number = Some(next + 1);
+        }
+    }
+}
+
+/// Serialized chat completion chunks, as forward mode streams them.
+impl StreamItem for String {
+    fn is_delta(&self) -> bool {
+        serde_json::from_str::<serde_json::Value>(self).is_ok_and(|event| event.is_delta())
+    }
+
+    fn coalesce(&mut self, next: &Self) -> bool {
+        let (Ok(mut event), Ok(next)) = (
+            serde_json::from_str::<serde_json::Value>(self),
+            serde_json::from_str::<serde_json::Value>(next),
+        ) else {
+            return false;
+        };
+        if !event.coalesce(&next) {
+            return false;
+        }
+        *self = event.to_string();
+        true
+    }
+
+    fn delta_text(&self) -> Option<String> {
+        serde_json::from_str::<serde_json::Value>(self)
+            .ok()?
+            .delta_text()
+    }
+
+    fn with_delta_text(&self, text: &str) -> Self {
+        match serde_json::from_str::<serde_json::Value>(self) {
+            Ok(event) => event.with_delta_text(text).to_string(),
+            Err(_) => self.clone(),
+        }
+    }
+}
+
+/// How a request wants its text deltas shaped: its `stream_granularity` and
+/// `stream_min_interval_ms`, each in place of the proxy's setting.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct DeltaShaping {
+    granularity: Option<StreamGranularity>,
+    min_interval_ms: Option<u64>,
+}
+
+impl DeltaShaping {
+    pub(crate) fn from_request(
+        granularity: Option<&str>,
+        min_interval_ms: Option<u64>,
+    ) -> Result<Self, ProxyError> {
+        Ok(Self {
+            granularity: StreamGranularity::from_request(granularity)?,
+            min_interval_ms,
+        })
+    }
+}
+
+/// How many events an open stream has buffered; `None` once it closed.
+type BufferedEvents = Box<dyn Fn() -> Option<usize> + Send>;
+
+/// Sizes every stream's buffer, applies the backpressure policy and keeps
+/// the counters `GET /admin/streams` reports.
+pub(crate) struct StreamBuffers {
+    capacity: usize,
+    policy: BackpressurePolicy,
+    granularity: StreamGranularity,
+    min_interval: Option<Duration>,
+    next_id: AtomicU64,
+    open: Mutex<HashMap<u64, BufferedEvents>>,
+    blocked_sends: AtomicU64,
+    coalesced_deltas: AtomicU64,
+    dropped_deltas: AtomicU64,
+}
+
+impl StreamBuffers {
+    pub(crate) fn new(capacity: usize, policy: BackpressurePolicy) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            policy,
+            granularity: StreamGranularity::default(),
+            min_interval: None,
+            next_id: AtomicU64::new(0),
+            open: Mutex::new(HashMap::new()),
+            blocked_sends: AtomicU64::new(0),
+            coalesced_deltas: AtomicU64::new(0),
+            dropped_deltas: AtomicU64::new(0),
+        }
+    }
+
+    /// Where text deltas end on streams that do not pick a granularity.
+    pub(crate) fn with_granularity(mut self, granularity: StreamGranularity) -> Self {
+        self.granularity = granularity;
+        self
+    }
+
+    /// The default minimum interval between text deltas on a stream.
+    pub(crate) fn with_min_interval(mut self, min_interval: Option<Duration>) -> Self {
+        self.min_interval = min_interval.filter(|interval| !interval.is_zero());
+        self
+    }
+
+    /// A buffer for an SSE response, under the configured policy.
+    pub(crate) fn channel<T: StreamItem + Send + 'static>(
+        self: &Arc<Self>,
+    ) -> (StreamSender<T>, mpsc::Receiver<T>) {
+        self.channel_with(self.policy)
+    }
+
+    /// A buffer whose receiver collects every event into one response, so
+    /// nothing may be merged or dropped.
+    pub(crate) fn collecting_channel<T: StreamItem + Send + 'static>(
+        self: &Arc<Self>,
+    ) -> (StreamSender<T>, mpsc::Receiver<T>) {
+        self.channel_with(BackpressurePolicy::Block)
+    }
+
+    fn channel_with<T: StreamItem + Send + 'static>(
+        self: &Arc<Self>,
+        policy: BackpressurePolicy,
+    ) -> (StreamSender<T>, mpsc::Receiver<T>) {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        let weak = tx.downgrade();
+        if let Ok(mut open) = self.open.lock() {
+            open.retain(|_, buffered| buffered().is_some());
+            open.insert(
+                self.next_id.fetch_add(1, Ordering::Relaxed),
+                Box::new(move || weak.upgrade().map(|tx| tx.max_capacity() - tx.capacity())),
+            );
+        }
+        let sender = StreamSender {
+            tx,
+            policy,
+            pending: Mutex::new(None),
+            dropped: AtomicU64::new(0),
+            buffers: Arc::clone(self),
+        };
+        (sender, rx)
+    }
+
+    /// Re-chunks the text deltas `rx` yields at the stream's granularity,
+    /// then spaces them at least the minimum interval apart, merging those
+    /// that arrive sooner; other events are never held back. A request's
+    /// `stream_min_interval_ms` overrides the configured interval, and 0
+    /// turns it off.
+    pub(crate) fn shape<T: StreamItem + Send + 'static>(
+        &self,
+        rx: mpsc::Receiver<T>,
+        shaping: DeltaShaping,
+    ) -> mpsc::Receiver<T> {
+        let rx = match shaping.granularity.unwrap_or(self.granularity) {
+            StreamGranularity::Token => rx,
+            granularity => {
+                let (tx, rechunked) = mpsc::channel(self.capacity);
+                tokio::spawn(stream_granularity::rechunk(rx, tx, granularity));
+                rechunked
+            }
+        };
+        let min_interval = match shaping.min_interval_ms {
+            Some(ms) => Some(Duration::from_millis(ms)).filter(|interval| !interval.is_zero()),
+            None => self.min_interval,
+        };
+        let Some(min_interval) = min_interval else {
+            return rx;
+        };
+        let (tx, throttled) = mpsc::channel(self.capacity);
+        tokio::spawn(space_deltas(rx, tx, min_interval));
+        throttled
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "capacity": self.capacity,
+            "policy": self.policy.as_str(),
+            "granularity": self.granularity.as_str(),
+            "min_interval_ms": self.min_interval.map(|interval| interval.as_millis() as u64),
+        })
+    }
+
+    /// Live buffer occupancy and backpressure counters.
+    pub(crate) fn metrics(&self) -> serde_json::Value {
+        let buffered = self
+            .open
+            .lock()
+            .map(|open| open.values().filter_map(|buffered| buffered()).collect())
+            .unwrap_or_else(|_| Vec::new());
+        serde_json::json!({
+            "capacity": self.capacity,
+            "policy": self.policy.as_str(),
+            "open_streams": buffered.len(),
+            "buffered_events": buffered.iter().sum::<usize>(),
+            "full_streams": buffered.iter().filter(|n| **n >= self.capacity).count(),
+            "blocked_sends": self.blocked_sends.load(Ordering::Relaxed),
+            "coalesced_deltas": self.coalesced_deltas.load(Ordering::Relaxed),
+            "dropped_deltas": self.dropped_deltas.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Forwards `rx` to `tx`, holding a delta that comes within `min_interval`
+/// of the last one sent until the interval is up, and merging later deltas
+/// into it meanwhile. Any other event, or the end of the stream, sends the
+/// held delta first.
+async fn space_deltas<T: StreamItem>(
+    mut rx: mpsc::Receiver<T>,
+    tx: mpsc::Sender<T>,
+    min_interval: Duration,
+) {
+    let mut held: Option<T> = None;
+    let mut next_delta_at = Instant::now();
+    loop {
+        let item = match held.take() {
+            Some(delta) => match tokio::time::timeout_at(next_delta_at, rx.recv()).await {
+                Ok(item) => {
+                    held = Some(delta);
+                    item
+                }
+                Err(_) => {
+                    if tx.send(delta).await.is_err() {
+                        return;
+                    }
+                    next_delta_at = Instant::now() + min_interval;
+                    continue;
+                }
+            },
+            None => rx.recv().await,
+        };
+        let Some(item) = item else {
+            break;
+        };
+        if !item.is_delta() {
+            if let Some(delta) = held.take()
+                && tx.send(delta).await.is_err()
+            {
+                return;
+            }
+            if tx.send(item).await.is_err() {
+                return;
+            }
+            continue;
+        }
+        match held.as_mut() {
+            Some(delta) => {
+                if !delta.coalesce(&item) {
+                    // A delta of some other output: the held one goes first.
+                    let earlier = std::mem::replace(delta, item);
+                    if tx.send(earlier).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            None if Instant::now() >= next_delta_at => {
+                if tx.send(item).await.is_err() {
+                    return;
+                }
+                next_delta_at = Instant::now() + min_interval;
+            }
+            None => held = Some(item),
+        }
+    }
+    if let Some(delta) = held {
+        let _ = tx.send(delta).await;
+    }
+}
+
+/// The sending half of a stream buffer; [`StreamSender::send`] applies the
+/// backpressure policy.
+pub(crate) struct StreamSender<T> {
+    tx: mpsc::Sender<T>,
+    policy: BackpressurePolicy,
+    /// Deltas merged while the buffer was full, sent ahead of the next event.
+    pending: Mutex<Option<T>>,
+    dropped: AtomicU64,
+    buffers: Arc<StreamBuffers>,
+}
+
+impl<T: StreamItem> StreamSender<T> {
+    /// Fails only once the client has gone away.
+    pub(crate) async fn send(&self, item: T) -> Result<(), SendError<T>> {
+        if self.policy == BackpressurePolicy::Block || !item.is_delta() {
+            if let Some(pending) = self.take_pending() {
+                self.send_waiting(pending).await?;
+            }
+            return self.send_waiting(item).await;
+        }
+
+        let mut item = item;
+        if let Some(mut pending) = self.take_pending() {
+            if pending.coalesce(&item) {
+                self.buffers
+                    .coalesced_deltas
+                    .fetch_add(1, Ordering::Relaxed);
+                item = pending;
+            } else {
+                self.send_waiting(pending).await?;
+            }
+        }
+        match self.tx.try_send(item) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Closed(item)) => Err(SendError(item)),
+            Err(TrySendError::Full(item)) => {
+                if self.policy == BackpressurePolicy::Coalesce {
+                    if let Ok(mut pending) = self.pending.lock() {
+                        *pending = Some(item);
+                    }
+                } else {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    self.buffers.dropped_deltas.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn take_pending(&self) -> Option<T> {
+        self.pending
+            .lock()
+            .ok()
+            .and_then(|mut pending| pending.take())
+    }
+
+    async fn send_waiting(&self, item: T) -> Result<(), SendError<T>> {
+        match self.tx.try_send(item) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Closed(item)) => Err(SendError(item)),
+            Err(TrySendError::Full(item)) => {
+                self.buffers.blocked_sends.fetch_add(1, Ordering::Relaxed);
+                self.tx.send(item).await
+            }
+        }
+    }
+}
+
+impl<T> Drop for StreamSender<T> {
+    fn drop(&mut self) {
+        if let Some(pending) = self.pending.get_mut().ok().and_then(Option::take) {
+            let _ = self.tx.try_send(pending);
+        }
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            log_message(
+                serde_json::json!({
+                    "type": "stream_deltas_dropped",
+                    "dropped": dropped,
+                })
+                .to_string(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn delta(text: &str) -> serde_json::Value {
+        serde_json::json!({
+            "object": "chat.completion.chunk",
+            "choices": [{ "index": 0, "delta": { "content": text }, "finish_reason": null }],
+        })
+    }
+
+    #[tokio::test]
+    async fn full_buffers_coalesce_or_drop_deltas_but_keep_other_events() {
+        let done = serde_json::Value::String("[DONE]".to_string());
+
+        let buffers = Arc::new(StreamBuffers::new(1, BackpressurePolicy::Coalesce));
+        let (tx, mut rx) = buffers.channel();
+        for text in ["a", "b", "c"] {
+            tx.send(delta(text)).await.expect("send");
+        }
+        assert_eq!(buffers.metrics()["full_streams"], 1);
+        let sent = tokio::spawn(async move { tx.send(done).await });
+        assert_eq!(rx.recv().await, Some(delta("a")));
+        assert_eq!(rx.recv().await, Some(delta("bc")));
+        assert_eq!(
+            rx.recv().await,
+            Some(serde_json::Value::String("[DONE]".to_string()))
+        );
+        sent.await.expect("join").expect("send");
+        assert_eq!(buffers.metrics()["coalesced_deltas"], 1);
+
+        let buffers = Arc::new(StreamBuffers::new(1, BackpressurePolicy::Drop));
+        let (tx, mut rx) = buffers.channel();
+        for text in ["a", "b", "c"] {
+            tx.send(delta(text)).await.expect("send");
+        }
+        drop(tx);
+        assert_eq!(rx.recv().await, Some(delta("a")));
+        assert_eq!(rx.recv().await, None);
+        assert_eq!(buffers.metrics()["dropped_deltas"], 2);
+    }
+
+    #[tokio::test]
+    async fn deltas_arriving_sooner_than_the_interval_are_merged() {
+        let buffers = StreamBuffers::new(16, BackpressurePolicy::Block)
+            .with_min_interval(Some(Duration::from_millis(200)));
+        let (tx, rx) = mpsc::channel(16);
+        let mut rx = buffers.shape(rx, DeltaShaping::default());
+        for text in ["a", "b", "c"] {
+            tx.send(delta(text)).await.expect("send");
+        }
+        assert_eq!(rx.recv().await, Some(delta("a")));
+        assert_eq!(rx.recv().await, Some(delta("bc")));
+
+        tx.send(delta("d")).await.expect("send");
+        let done = serde_json::Value::String("[DONE]".to_string());
+        tx.send(done.clone()).await.expect("send");
+        drop(tx);
+        assert_eq!(rx.recv().await, Some(delta("d")));
+        assert_eq!(rx.recv().await, Some(done));
+        assert_eq!(rx.recv().await, None);
+
+        // A request can turn the interval off.
+        let (tx, rx) = mpsc::channel(16);
+        let shaping = DeltaShaping {
+            min_interval_ms: Some(0),
+            ..DeltaShaping::default()
+        };
+        let mut rx = buffers.shape(rx, shaping);
+        for text in ["a", "b"] {
+            tx.send(delta(text)).await.expect("send");
+        }
+        assert_eq!(rx.recv().await, Some(delta("a")));
+        assert_eq!(rx.recv().await, Some(delta("b")));
+    }
+
+    #[test]
+    fn only_deltas_of_the_same_output_merge() {
+        let mut first = serde_json::json!({
+            "type": "response.output_text.delta",
+            "sequence_number": 3,
+            "output_index": 0,
+            "delta": "Hel",
+        });
+        let same = serde_json::json!({
+            "type": "response.output_text.delta",
+            "sequence_number": 4,
+            "output_index": 0,
+            "delta": "lo",
+        });
+        let other = serde_json::json!({
+            "type": "response.output_text.delta",
+            "sequence_number": 5,
+            "output_index": 1,
+            "delta": "!",
+        });
+        assert!(first.coalesce(&same));
+        assert_eq!(first["delta"], "Hello");
+        assert!(!first.coalesce(&other));
+        assert!(!delta("a").coalesce(&serde_json::json!({ "type": "response.completed" })));
+    }
+}
@@ -0,0 +1,221 @@
+//! Re-chunking of streamed text on word or sentence boundaries. Codex
+//! streams text as the model samples it, so a delta often ends halfway
+//! through a word; text-to-speech and subtitle pipelines downstream of the
+//! proxy need each delta to end where a word or sentence does.
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use tokio::sync::mpsc;
+
+use crate::ProxyError;
+use crate::proxy_config;
+use crate::stream_buffer::StreamItem;
+
+/// Where a streamed text delta may end.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StreamGranularity {
+    /// Wherever the model's tokens end: deltas pass through as they arrive.
+    #[default]
+    Token,
+    /// One delta per word, with the whitespace after it.
+    Word,
+    /// One delta per sentence, with the whitespace after it.
+    Sentence,
+}
+
+/// Closing quotes and brackets that may follow a sentence's final mark.
+const SENTENCE_CLOSERS: &[char] = &['"', '\'', ')', ']', '\u{201d}', '\u{2019}', '\u{bb}'];
+
+impl StreamGranularity {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "token" => Some(Self::Token),
+            "word" => Some(Self::Word),
+            "sentence" => Some(Self::Sentence),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Token => "token",
+            Self::Word => "word",
+            Self::Sentence => "sentence",
+        }
+    }
+
+    /// `CODEX_OPENAI_PROXY_STREAM_GRANULARITY` (`token`, `word` or
+    /// `sentence`), for requests that do not pick one.
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        match proxy_config::var("CODEX_OPENAI_PROXY_STREAM_GRANULARITY") {
+            Ok(value) => Self::parse(&value).with_context(|| {
+                format!(
+                    "CODEX_OPENAI_PROXY_STREAM_GRANULARITY must be token, word or sentence, got {value:?}"
+                )
+            }),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// A request's `stream_granularity`, if it sets one.
+    pub(crate) fn from_request(requested: Option<&str>) -> Result<Option<Self>, ProxyError> {
+        let Some(requested) = requested else {
+            return Ok(None);
+        };
+        Self::parse(requested).map(Some).ok_or_else(|| {
+            ProxyError::new(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "unsupported stream_granularity {requested:?}; expected \"token\", \"word\" or \"sentence\""
+                ),
+                "invalid_request_error",
+            )
+        })
+    }
+
+    /// Byte offsets in `text` after each complete word or sentence and the
+    /// whitespace following it. Text past the last offset may still go on.
+    fn cuts(self, text: &str) -> Vec<usize> {
+        let mut cuts = Vec::new();
+        // Whether the text since the last cut is more than whitespace.
+        let mut in_unit = false;
+        // Whether the next word starts a new unit.
+        let mut unit_ended = false;
+        // Whether the last mark ended a sentence, so whitespace after it
+        // ends the unit.
+        let mut after_stop = false;
+        // Full-width marks end a sentence without any whitespace after them.
+        let mut after_wide_stop = false;
+        for (index, c) in text.char_indices() {
+            if c.is_whitespace() {
+                let ends_unit = match self {
+                    Self::Token => false,
+                    Self::Word => true,
+                    Self::Sentence => after_stop || after_wide_stop || c == '\n',
+                };
+                unit_ended |= in_unit && ends_unit;
+                continue;
+            }
+            let closer = SENTENCE_CLOSERS.contains(&c);
+            if unit_ended || (after_wide_stop && !closer) {
+                cuts.push(index);
+                unit_ended = false;
+            }
+            in_unit = true;
+            if self == Self::Sentence {
+                after_stop = matches!(c, '.' | '!' | '?' | '\u{2026}') || (after_stop && closer);
+                after_wide_stop = matches!(c, '\u{3002}' | '\u{ff01}' | '\u{ff1f}')
+                    || (after_wide_stop && closer);
+            }
+        }
+        if unit_ended {
+            cuts.push(text.len());
+        }
+        cuts
+    }
+}
+
+/// Forwards `rx` to `tx`, splitting text deltas into one per word or
+/// sentence and holding back the unfinished one at the end until more text
+/// completes it. Any other event, or the end of the stream, sends it as is.
+pub(crate) async fn rechunk<T: StreamItem>(
+    mut rx: mpsc::Receiver<T>,
+    tx: mpsc::Sender<T>,
+    granularity: StreamGranularity,
+) {
+    let mut held: Option<T> = None;
+    // Splitting a Responses event in two needs the events after it
+    // renumbered.
+    let mut next_sequence_number = None;
+    while let Some(item) = rx.recv().await {
+        if !item.is_delta() {
+            if let Some(delta) = held.take()
+                && !send(&tx, delta, &mut next_sequence_number).await
+            {
+                return;
+            }
+            if !send(&tx, item, &mut next_sequence_number).await {
+                return;
+            }
+            continue;
+        }
+        let delta = match held.take() {
+            Some(mut delta) => {
+                if delta.coalesce(&item) {
+                    delta
+                } else if send(&tx, delta, &mut next_sequence_number).await {
+                    item
+                } else {
+                    return;
+                }
+            }
+            None => item,
+        };
+        let text = delta.delta_text().unwrap_or_default();
+        let mut start = 0;
+        for cut in granularity.cuts(&text) {
+            let piece = delta.with_delta_text(&text[start..cut]);
+            if !send(&tx, piece, &mut next_sequence_number).await {
+                return;
+            }
+            start = cut;
+        }
+        held = match start {
+            0 => Some(delta),
+            start if start < text.len() => Some(delta.with_delta_text(&text[start..])),
+            _ => None,
+        };
+    }
+    if let Some(delta) = held {
+        send(&tx, delta, &mut next_sequence_number).await;
+    }
+}
+
+async fn send<T: StreamItem>(
+    tx: &mpsc::Sender<T>,
+    mut item: T,
+    next_sequence_number: &mut Option<u64>,
+) -> bool {
+    item.renumber(next_sequence_number);
+    tx.send(item).await.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn pieces(granularity: StreamGranularity, text: &str) -> Vec<&str> {
+        let mut start = 0;
+        let mut pieces = Vec::new();
+        for cut in granularity.cuts(text) {
+            pieces.push(&text[start..cut]);
+            start = cut;
+        }
+        pieces
+    }
+
+    #[test]
+    fn cuts_fall_after_complete_words_and_sentences() {
+        assert_eq!(
+            pieces(StreamGranularity::Word, "  Hello big wor"),
+            vec!["  Hello ", "big "]
+        );
+        assert_eq!(
+            pieces(StreamGranularity::Sentence, "Hi there. Pi is 3.14! How are"),
+            vec!["Hi there. ", "Pi is 3.14! "]
+        );
+        assert_eq!(
+            pieces(StreamGranularity::Sentence, "He said \"no.\" Then\nleft"),
+            vec!["He said \"no.\" ", "Then\n"]
+        );
+        assert_eq!(
+            pieces(StreamGranularity::Sentence, "你好。世界！"),
+            vec!["你好。"]
+        );
+        assert_eq!(
+            pieces(StreamGranularity::Token, "a b. c"),
+            Vec::<&str>::new()
+        );
+    }
+}
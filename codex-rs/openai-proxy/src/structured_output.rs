@@ -0,0 +1,158 @@
+//! Structured outputs: the JSON schema a request supplies through chat
+//! `response_format` or Responses `text.format`. Codex asks the model for
+//! output matching it, and the proxy validates the final answer against it
+//! rather than hand clients JSON that does not match.
+
+use axum::http::StatusCode;
+use serde_json::Value;
+
+use crate::ProxyError;
+
+/// A compiled `json_schema` output format.
+pub(crate) struct OutputSchema {
+    schema: Value,
+    validator: jsonschema::Validator,
+}
+
+impl OutputSchema {
+    /// The schema of a chat `response_format` of type `json_schema`.
+    pub(crate) fn from_response_format(format: Option<&Value>) -> Result<Option<Self>, ProxyError> {
+        match format {
+            Some(format) if format["type"] == "json_schema" => {
+                Self::compile(&format["json_schema"]["schema"]).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The schema of a Responses `text.format` of type `json_schema`.
+    pub(crate) fn from_text_format(text: Option<&Value>) -> Result<Option<Self>, ProxyError> {
+        Self::from_output_format(text.map(|text| &text["format"]))
+    }
+
+    /// The schema of a Messages `output_format` of type `json_schema`, which
+    /// has the shape of a Responses `text.format`.
+    pub(crate) fn from_output_format(format: Option<&Value>) -> Result<Option<Self>, ProxyError> {
+        match format {
+            Some(format) if format["type"] == "json_schema" => {
+                Self::compile(&format["schema"]).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn compile(schema: &Value) -> Result<Self, ProxyError> {
+        let invalid = |message: String| {
+            ProxyError::new(StatusCode::BAD_REQUEST, message, "invalid_request_error")
+                .with_code("invalid_json_schema")
+        };
+        if !schema.is_object() {
+            return Err(invalid(
+                "A json_schema output format requires a 'schema' object.".to_string(),
+            ));
+        }
+        let validator = jsonschema::validator_for(schema)
+            .map_err(|e| invalid(format!("Invalid JSON schema: {e}")))?;
+        Ok(Self {
+            schema: schema.clone(),
+            validator,
+        })
+    }
+
+    /// The schema, as Codex's `final_output_json_schema`.
+    pub(crate) fn schema(&self) -> Value {
+        self.schema.clone()
+    }
+
+    /// Why `answer` does not match the schema, if it does not. An empty
+    /// answer (the turn ended in tool calls) is not checked.
+    pub(crate) fn check(&self, answer: &str) -> Result<(), String> {
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(());
+        }
+        let value: Value = serde_json::from_str(answer)
+            .map_err(|e| format!("The answer is not valid JSON: {e}"))?;
+        self.validator.validate(&value).map_err(|e| {
+            format!(
+                "The answer does not match the JSON schema at '{}': {e}",
+                e.instance_path
+            )
+        })
+    }
+}
+
+/// The error a chat completion ends with when its answer still does not
+/// match after one retry. The upstream answered fine, so this is not a 5xx.
+pub(crate) fn validation_error(message: String) -> ProxyError {
+    ProxyError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        message,
+        "invalid_request_error",
+    )
+    .with_code("json_schema_validation_failed")
+}
+
+/// What a chat completion asks for, once, when its answer does not match,
+/// before anything of the answer has reached the client.
+pub(crate) fn correction_prompt(message: &str) -> String {
+    format!(
+        "{message}\n\nAnswer again with only a JSON value that matches the required JSON schema."
+    )
+}
+
+/// The `incomplete_details` a Responses answer that does not match ends with.
+pub(crate) fn incomplete_details(message: String) -> Value {
+    serde_json::json!({ "reason": "json_schema_validation", "message": message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn answers_are_checked_against_the_schema() {
+        let format = json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": "answer",
+                "schema": {
+                    "type": "object",
+                    "properties": { "answer": { "type": "integer" } },
+                    "required": ["answer"],
+                },
+            },
+        });
+        let schema = OutputSchema::from_response_format(Some(&format))
+            .expect("valid schema")
+            .expect("a json_schema format");
+
+        assert_eq!(schema.check(r#" {"answer": 42} "#), Ok(()));
+        assert_eq!(schema.check(""), Ok(()));
+        assert!(
+            schema
+                .check("Sure! {\"answer\": 42}")
+                .expect_err("not json")
+                .starts_with("The answer is not valid JSON")
+        );
+        assert!(
+            schema
+                .check(r#"{"answer": "42"}"#)
+                .expect_err("wrong type")
+                .contains("at '/answer'")
+        );
+
+        let text = json!({ "format": { "type": "text" } });
+        assert!(matches!(
+            OutputSchema::from_text_format(Some(&text)),
+            Ok(None)
+        ));
+        let broken = json!({ "format": { "type": "json_schema", "schema": { "type": 7 } } });
+        let error = OutputSchema::from_text_format(Some(&broken))
+            .err()
+            .expect("an invalid schema");
+        assert_eq!(error.code, Some("invalid_json_schema"));
+    }
+}
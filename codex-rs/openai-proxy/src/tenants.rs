@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use anyhow::Context;
+use axum::Router;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use codex_core::ThreadManager;
+use codex_core::auth::AuthManager;
+use codex_core::config::ConfigBuilder;
+use codex_protocol::protocol::SessionSource;
+use serde::Deserialize;
+use tower::ServiceExt;
+use tracing::info;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::admin_auth::is_admin_path;
+use crate::attachments::AttachmentStore;
+use crate::batches::BatchRegistry;
+use crate::build_router;
+use crate::completions::RunningCompletions;
+use crate::conversation_store::ConversationStore;
+use crate::files::FileStore;
+use crate::rate_limit::RateLimiter;
+use crate::rate_limit::api_key;
+use crate::response_cache::ResponseCache;
+use crate::turn_diffs::TurnDiffs;
+
+/// Paths served without an API key even when unknown keys are rejected, so
+/// probes keep working.
+const UNAUTHENTICATED_PATHS: [&str; 2] = ["/healthz", "/readyz"];
+
+/// The `CODEX_OPENAI_PROXY_TENANTS` file, e.g.
+///
+/// ```toml
+/// reject_unknown_keys = true
+///
+/// [[tenant]]
+/// name = "alice"
+/// api_keys = ["sk-alice"]
+/// codex_home = "/home/alice/.codex"
+/// rate_limit_rpm = 60
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TenantsFile {
+    /// Answer requests whose API key belongs to no tenant with a 401 instead
+    /// of serving them from the proxy's own Codex home.
+    #[serde(default)]
+    reject_unknown_keys: bool,
+    #[serde(default, rename = "tenant")]
+    tenants: Vec<TenantConfig>,
+}
+
+/// A user with their own Codex login, limits and session history.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TenantConfig {
+    name: String,
+    api_keys: Vec<String>,
+    codex_home: PathBuf,
+    /// Replace the proxy-wide `CODEX_OPENAI_PROXY_RATE_LIMIT_*` limits.
+    #[serde(default)]
+    rate_limit_rpm: Option<u64>,
+    #[serde(default)]
+    rate_limit_tpm: Option<u64>,
+}
+
+impl TenantsFile {
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("read tenants file {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("parse tenants file {}", path.display()))
+    }
+
+    fn parse(contents: &str) -> anyhow::Result<Self> {
+        let file: Self = toml::from_str(contents)?;
+        let mut names = HashSet::new();
+        let mut keys = HashSet::new();
+        for tenant in &file.tenants {
+            if tenant.name.trim().is_empty() {
+                anyhow::bail!("tenant names must not be empty");
+            }
+            if !names.insert(tenant.name.as_str()) {
+                anyhow::bail!("tenant {:?} is defined twice", tenant.name);
+            }
+            if tenant.api_keys.is_empty() {
+                anyhow::bail!("tenant {:?} has no api_keys", tenant.name);
+            }
+            for key in &tenant.api_keys {
+                if key.trim().is_empty() || key.trim() != key {
+                    anyhow::bail!("tenant {:?} has a blank or padded API key", tenant.name);
+                }
+                if !keys.insert(key.as_str()) {
+                    anyhow::bail!("an API key of tenant {:?} is already in use", tenant.name);
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    /// Routes for each tenant, built next to `base`, the proxy's own state.
+    /// Tenants in `previous` with the same name and Codex home keep their
+    /// state, and with it their threads; the others get a fresh one.
+    async fn routes(
+        self,
+        base: &AppState,
+        static_dir: &Path,
+        previous: &HashMap<String, (PathBuf, AppState)>,
+    ) -> anyhow::Result<TenantRoutes> {
+        let mut by_key = HashMap::new();
+        let mut states = HashMap::new();
+        for tenant in self.tenants {
+            let state = match previous.get(&tenant.name) {
+                Some((codex_home, state)) if *codex_home == tenant.codex_home => AppState {
+                    rate_limiter: tenant_rate_limiter(base, &tenant, Some(state)),
+                    ..state.clone()
+                },
+                _ => {
+                    info!(
+                        "tenant {} uses Codex home {}",
+                        tenant.name,
+                        tenant.codex_home.display()
+                    );
+                    tenant_state(base, &tenant).await?
+                }
+            };
+            let router = build_router(state.clone(), static_dir);
+            for key in tenant.api_keys {
+                by_key.insert(key, router.clone());
+            }
+            states.insert(tenant.name, (tenant.codex_home, state));
+        }
+        Ok(TenantRoutes {
+            by_key,
+            reject_unknown_keys: self.reject_unknown_keys,
+            states,
+        })
+    }
+}
+
+/// A tenant's own rate limiter when it sets limits, updated in place from
+/// `previous` when it had one; otherwise the proxy's.
+fn tenant_rate_limiter(
+    base: &AppState,
+    tenant: &TenantConfig,
+    previous: Option<&AppState>,
+) -> Arc<RateLimiter> {
+    if tenant.rate_limit_rpm.is_none() && tenant.rate_limit_tpm.is_none() {
+        return base.rate_limiter.clone();
+    }
+    match previous.filter(|state| !Arc::ptr_eq(&state.rate_limiter, &base.rate_limiter)) {
+        Some(state) => {
+            state
+                .rate_limiter
+                .set_limits(tenant.rate_limit_rpm, tenant.rate_limit_tpm);
+            state.rate_limiter.clone()
+        }
+        None => Arc::new(RateLimiter::new(
+            tenant.rate_limit_rpm,
+            tenant.rate_limit_tpm,
+        )),
+    }
+}
+
+/// State for one tenant. Threads, configs, auth, batches, cached responses,
+/// uploads, turn diffs, approvals and (when set) rate limits are the
+/// tenant's own; turn slots, workspaces, idempotency keys, the ledger and
+/// the proxy-wide limits are shared with the rest of the proxy.
+async fn tenant_state(base: &AppState, tenant: &TenantConfig) -> anyhow::Result<AppState> {
+    let config = ConfigBuilder::default()
+        .codex_home(tenant.codex_home.clone())
+        .build()
+        .await
+        .with_context(|| format!("load config for tenant {:?}", tenant.name))?;
+    let auth_manager = Arc::new(AuthManager::new(
+        config.codex_home.clone(),
+        false,
+        config.cli_auth_credentials_store_mode,
+    ));
+    let thread_manager = Arc::new(ThreadManager::new(
+        config.codex_home.clone(),
+        auth_manager.clone(),
+        SessionSource::Exec,
+    ));
+    let threads = Arc::new(base.threads.for_home(
+        thread_manager.clone(),
+        auth_manager.clone(),
+        ConversationStore::load_from_home(&config.codex_home),
+        AttachmentStore::for_home(&config.codex_home),
+    ));
+    threads.spawn_reaper();
+    let rate_limiter = tenant_rate_limiter(base, tenant, None);
+
+    Ok(AppState {
+        thread_manager,
+        auth_manager,
+        configs: Arc::new(base.configs.for_codex_home_like(config.codex_home.clone())),
+        threads,
+        approvals: Arc::new(base.approvals.for_tenant()),
+        completions: Arc::new(RunningCompletions::default()),
+        files: Arc::new(FileStore::new(
+            config.codex_home.join("openai-proxy").join("files"),
+        )),
+        turn_diffs: Arc::new(TurnDiffs::default()),
+        batches: Arc::new(BatchRegistry::new(base.batches.concurrency())),
+        response_cache: base
+            .response_cache
+            .as_ref()
+            .map(|cache| Arc::new(ResponseCache::new(cache.ttl()))),
+        rate_limiter,
+        tenant: Some(tenant.name.as_str().into()),
+        ..base.clone()
+    })
+}
+
+/// Routes requests to the tenant their API key belongs to, as listed in the
+/// tenants file; see [`TenantRouter::reload`].
+pub(crate) struct TenantRouter {
+    path: PathBuf,
+    /// The proxy's own state, which tenants share what they do not own with.
+    base: AppState,
+    static_dir: PathBuf,
+    routes: RwLock<TenantRoutes>,
+}
+
+struct TenantRoutes {
+    by_key: HashMap<String, Router>,
+    reject_unknown_keys: bool,
+    /// Each tenant's Codex home and state, by name.
+    states: HashMap<String, (PathBuf, AppState)>,
+}
+
+impl TenantRouter {
+    /// Builds each tenant in the tenants file at `path` next to `base`, the
+    /// proxy's own state.
+    pub(crate) async fn load(
+        path: &Path,
+        base: &AppState,
+        static_dir: &Path,
+    ) -> anyhow::Result<Self> {
+        // Kept apart from `base.tenants`, which will hold this router.
+        let base = AppState {
+            tenants: Arc::default(),
+            ..base.clone()
+        };
+        let routes = TenantsFile::load(path)?
+            .routes(&base, static_dir, &HashMap::new())
+            .await?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            base,
+            static_dir: static_dir.to_path_buf(),
+            routes: RwLock::new(routes),
+        })
+    }
+
+    /// Re-reads the tenants file, keeping the current tenants if it fails
+    /// to load. New keys and tenants are served from the next request on;
+    /// removed ones no longer are. A tenant whose Codex home is unchanged
+    /// keeps its threads, running turns included.
+    pub(crate) async fn reload(&self) -> anyhow::Result<()> {
+        let file = TenantsFile::load(&self.path)?;
+        let previous = match self.routes.read() {
+            Ok(routes) => routes.states.clone(),
+            Err(_) => anyhow::bail!("tenants poisoned"),
+        };
+        let routes = file.routes(&self.base, &self.static_dir, &previous).await?;
+        if let Ok(mut current) = self.routes.write() {
+            *current = routes;
+        }
+        Ok(())
+    }
+
+    /// The tenants file.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The router of the tenant `key` belongs to, and whether requests
+    /// without a tenant's key are rejected.
+    fn route(&self, key: Option<&str>) -> (Option<Router>, bool) {
+        let Ok(routes) = self.routes.read() else {
+            return (None, false);
+        };
+        let router = key.and_then(|key| routes.by_key.get(key)).cloned();
+        (router, routes.reject_unknown_keys)
+    }
+}
+
+/// Middleware in front of the proxy's own router: requests with a tenant's
+/// API key are served by that tenant's router, the rest fall through (or get
+/// a 401 when unknown keys are rejected).
+pub(crate) async fn dispatch(
+    State(tenants): State<Arc<TenantRouter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (router, reject_unknown_keys) = tenants.route(api_key(request.headers()));
+    match router {
+        Some(router) => match router.oneshot(request).await {
+            Ok(response) => response,
+            Err(infallible) => match infallible {},
+        },
+        // The admin endpoints check the admin token themselves.
+        None if reject_unknown_keys
+            && !UNAUTHENTICATED_PATHS.contains(&request.uri().path())
+            && !is_admin_path(request.uri().path()) =>
+        {
+            ProxyError::new(
+                StatusCode::UNAUTHORIZED,
+                "missing or unknown API key",
+                "invalid_request_error",
+            )
+            .with_code("invalid_api_key")
+            .into_response()
+        }
+        None => next.run(request).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn tenants_file_rejects_shared_keys_and_names() {
+        let file = TenantsFile::parse(
+            r#"
+            reject_unknown_keys = true
+
+            [[tenant]]
+            name = "alice"
+            api_keys = ["sk-alice", "sk-alice-ci"]
+            codex_home = "/home/alice/.codex"
+            rate_limit_rpm = 60
+
+            [[tenant]]
+            name = "bob"
+            api_keys = ["sk-bob"]
+            codex_home = "/home/bob/.codex"
+            "#,
+        )
+        .expect("valid tenants file");
+        assert!(file.reject_unknown_keys);
+        assert_eq!(file.tenants.len(), 2);
+        assert_eq!(file.tenants[0].rate_limit_rpm, Some(60));
+        assert_eq!(file.tenants[1].rate_limit_rpm, None);
+
+        let tenant = |name: &str, key: &str| {
+            format!("[[tenant]]\nname = {name:?}\napi_keys = [{key:?}]\ncodex_home = \"/tmp\"\n")
+        };
+        let shared_key = tenant("alice", "sk-1") + &tenant("bob", "sk-1");
+        assert!(TenantsFile::parse(&shared_key).is_err());
+        let shared_name = tenant("alice", "sk-1") + &tenant("alice", "sk-2");
+        assert!(TenantsFile::parse(&shared_name).is_err());
+        assert!(TenantsFile::parse(&tenant("alice", " sk-1")).is_err());
+    }
+
+    #[tokio::test]
+    async fn tenants_keep_their_uploads_and_process_settings_apart() {
+        let home = tempfile::tempdir().expect("temp codex home");
+        let alice_home = tempfile::tempdir().expect("alice's codex home");
+        let bob_home = tempfile::tempdir().expect("bob's codex home");
+        let config = ConfigBuilder::default()
+            .codex_home(home.path().to_path_buf())
+            .build()
+            .await
+            .expect("load config");
+        let base = crate::AppStateBuilder::new(config)
+            .ledger(PathBuf::from(":memory:"), None)
+            .build()
+            .expect("build state");
+        let path = home.path().join("tenants.toml");
+        std::fs::write(
+            &path,
+            format!(
+                "[[tenant]]\nname = \"alice\"\napi_keys = [\"sk-alice\"]\ncodex_home = {:?}\n\
+                 [[tenant]]\nname = \"bob\"\napi_keys = [\"sk-bob\"]\ncodex_home = {:?}\n",
+                alice_home.path(),
+                bob_home.path(),
+            ),
+        )
+        .expect("write tenants file");
+        let tenants = TenantRouter::load(&path, &base, home.path())
+            .await
+            .expect("build tenants");
+        let alice = tenants.route(Some("sk-alice")).0.expect("alice's router");
+        let bob = tenants.route(Some("sk-bob")).0.expect("bob's router");
+
+        let upload = Request::post("/v1/files")
+            .header("content-type", "multipart/form-data; boundary=XyZ")
+            .body(axum::body::Body::from(
+                "--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\r\nsecret\r\n--XyZ--\r\n",
+            ))
+            .expect("request");
+        let response = alice.clone().oneshot(upload).await.expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        let file: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+        let uri = format!(
+            "/v1/files/{}/content",
+            file["id"].as_str().expect("file id")
+        );
+
+        let get = |uri: &str| {
+            Request::get(uri)
+                .body(axum::body::Body::empty())
+                .expect("request")
+        };
+        let status = |response: Response| response.status();
+        assert_eq!(
+            status(alice.oneshot(get(&uri)).await.expect("response")),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(bob.clone().oneshot(get(&uri)).await.expect("response")),
+            StatusCode::NOT_FOUND
+        );
+        let reload = Request::post("/admin/config/reload")
+            .body(axum::body::Body::empty())
+            .expect("request");
+        assert_eq!(
+            status(bob.oneshot(reload).await.expect("response")),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn reloads_serve_new_keys_and_keep_tenants_threads() {
+        let home = tempfile::tempdir().expect("temp codex home");
+        let alice_home = tempfile::tempdir().expect("alice's codex home");
+        let bob_home = tempfile::tempdir().expect("bob's codex home");
+        let config = ConfigBuilder::default()
+            .codex_home(home.path().to_path_buf())
+            .build()
+            .await
+            .expect("load config");
+        let base = crate::AppStateBuilder::new(config)
+            .ledger(PathBuf::from(":memory:"), None)
+            .build()
+            .expect("build state");
+        let tenant = |name: &str, keys: &str, codex_home: &Path| {
+            format!("[[tenant]]\nname = {name:?}\napi_keys = {keys}\ncodex_home = {codex_home:?}\n")
+        };
+        let path = home.path().join("tenants.toml");
+        std::fs::write(&path, tenant("alice", r#"["sk-alice"]"#, alice_home.path()))
+            .expect("write tenants file");
+        let tenants = TenantRouter::load(&path, &base, home.path())
+            .await
+            .expect("build tenants");
+        let threads = |tenants: &TenantRouter| {
+            tenants.routes.read().expect("routes").states["alice"]
+                .1
+                .threads
+                .clone()
+        };
+        let alice_threads = threads(&tenants);
+
+        std::fs::write(
+            &path,
+            "reject_unknown_keys = true\n".to_string()
+                + &tenant("alice", r#"["sk-alice-2"]"#, alice_home.path())
+                + &tenant("bob", r#"["sk-bob"]"#, bob_home.path()),
+        )
+        .expect("rewrite tenants file");
+        tenants.reload().await.expect("reload tenants");
+        assert!(tenants.route(Some("sk-alice")).0.is_none());
+        assert!(tenants.route(Some("sk-alice-2")).0.is_some());
+        assert!(tenants.route(Some("sk-bob")).0.is_some());
+        assert!(tenants.route(None).1);
+        assert!(Arc::ptr_eq(&alice_threads, &threads(&tenants)));
+
+        std::fs::write(&path, "[[tenant]]\n").expect("break tenants file");
+        assert!(tenants.reload().await.is_err());
+        assert!(tenants.route(Some("sk-bob")).0.is_some());
+    }
+}
@@ -0,0 +1,622 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use axum::http::StatusCode;
+use codex_core::CodexThread;
+use codex_core::ThreadManager;
+use codex_core::auth::AuthManager;
+use codex_core::config::Config;
+use codex_core::protocol::Op;
+use codex_protocol::ThreadId;
+use serde::Serialize;
+use tokio::sync::OwnedMutexGuard;
+use tracing::info;
+use tracing::warn;
+
+use crate::ProxyError;
+use crate::attachments::AttachmentStore;
+use crate::conversation_store::ConversationStore;
+use crate::conversation_store::StoredConversation;
+use crate::log_message;
+use crate::now_ts;
+
+/// How long an evicted id keeps answering "conversation expired" before it
+/// is forgotten and reported as not found.
+const EXPIRED_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Most evicted ids remembered at once; past it the oldest are forgotten.
+const MAX_EXPIRED_IDS: usize = 10_000;
+
+/// Bookkeeping for a thread created through the proxy.
+struct ThreadEntry {
+    thread: Arc<CodexThread>,
+    model: String,
+    created_at: u64,
+    last_activity: Instant,
+    running_turns: usize,
+    /// Held for the duration of a turn so concurrent requests to the same
+    /// conversation run one after another instead of stealing each other's
+    /// events.
+    turn_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl ThreadEntry {
+    /// Whether no turn has run on the thread, or waited to, for `idle_ttl`.
+    fn is_idle(&self, idle_ttl: Duration) -> bool {
+        // `begin_turn` clones the turn lock before the turn counts as
+        // running, and its guard keeps the clone until the turn ends.
+        self.running_turns == 0
+            && Arc::strong_count(&self.turn_lock) == 1
+            && self.last_activity.elapsed() >= idle_ttl
+    }
+}
+
+/// Admin-facing view of a live thread.
+#[derive(Debug, Serialize)]
+pub(crate) struct ThreadSummary {
+    pub(crate) id: String,
+    pub(crate) model: String,
+    pub(crate) created_at: u64,
+    pub(crate) last_activity_at: u64,
+    pub(crate) turn_running: bool,
+}
+
+/// Tracks the threads the proxy has handed out so idle ones can be evicted
+/// from the [`ThreadManager`] after a configurable TTL.
+pub(crate) struct ThreadRegistry {
+    thread_manager: Arc<ThreadManager>,
+    auth_manager: Arc<AuthManager>,
+    /// Persisted rollout paths so conversations outlive a proxy restart.
+    store: ConversationStore,
+    /// Inline attachments, deleted when their conversation is closed.
+    attachments: AttachmentStore,
+    /// Serializes lazy resumes so two requests cannot resume the same
+    /// rollout twice.
+    resume_lock: tokio::sync::Mutex<()>,
+    entries: Mutex<HashMap<ThreadId, ThreadEntry>>,
+    /// Ids evicted by the reaper and when, kept for [`EXPIRED_RETENTION`]
+    /// so later requests get a clear "conversation expired" error instead
+    /// of "not found".
+    expired: Mutex<HashMap<ThreadId, Instant>>,
+    idle_ttl: Option<Duration>,
+    /// When set, a request arriving while a turn is running on the same
+    /// conversation interrupts that turn instead of waiting for it.
+    interrupt_previous_turn: bool,
+}
+
+impl ThreadRegistry {
+    /// `idle_ttl` of `None` disables eviction.
+    pub(crate) fn new(
+        thread_manager: Arc<ThreadManager>,
+        auth_manager: Arc<AuthManager>,
+        store: ConversationStore,
+        attachments: AttachmentStore,
+        idle_ttl: Option<Duration>,
+        interrupt_previous_turn: bool,
+    ) -> Self {
+        Self {
+            thread_manager,
+            auth_manager,
+            store,
+            attachments,
+            resume_lock: tokio::sync::Mutex::new(()),
+            entries: Mutex::new(HashMap::new()),
+            expired: Mutex::new(HashMap::new()),
+            idle_ttl,
+            interrupt_previous_turn,
+        }
+    }
+
+    /// A registry for another Codex home's threads with the same idle TTL and
+    /// turn policy as this one.
+    pub(crate) fn for_home(
+        &self,
+        thread_manager: Arc<ThreadManager>,
+        auth_manager: Arc<AuthManager>,
+        store: ConversationStore,
+        attachments: AttachmentStore,
+    ) -> Self {
+        Self::new(
+            thread_manager,
+            auth_manager,
+            store,
+            attachments,
+            self.idle_ttl,
+            self.interrupt_previous_turn,
+        )
+    }
+
+    pub(crate) fn insert(
+        &self,
+        thread_id: ThreadId,
+        thread: Arc<CodexThread>,
+        model: String,
+        parallel_tool_calls: Option<bool>,
+    ) {
+        self.store.record(
+            thread_id.to_string(),
+            StoredConversation {
+                rollout_path: thread.rollout_path(),
+                model: model.clone(),
+                parallel_tool_calls,
+            },
+        );
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(
+                thread_id,
+                ThreadEntry {
+                    thread,
+                    model,
+                    created_at: now_ts(),
+                    last_activity: Instant::now(),
+                    running_turns: 0,
+                    turn_lock: Arc::new(tokio::sync::Mutex::new(())),
+                },
+            );
+        }
+    }
+
+    /// Where the inline attachments of these threads' turns are written.
+    pub(crate) fn attachments(&self) -> &AttachmentStore {
+        &self.attachments
+    }
+
+    /// Returns the live thread for `thread_id`, refreshing its idle timer.
+    pub(crate) fn get(&self, thread_id: ThreadId) -> Result<Arc<CodexThread>, ProxyError> {
+        if let Ok(mut entries) = self.entries.lock()
+            && let Some(entry) = entries.get_mut(&thread_id)
+        {
+            entry.last_activity = Instant::now();
+            return Ok(entry.thread.clone());
+        }
+
+        let expired = self
+            .expired
+            .lock()
+            .map(|expired| expired.contains_key(&thread_id))
+            .unwrap_or(false);
+        if expired {
+            return Err(ProxyError::new(
+                StatusCode::NOT_FOUND,
+                format!(
+                    "conversation {thread_id} expired after being idle; start a new conversation"
+                ),
+                "invalid_request_error",
+            )
+            .with_code("conversation_expired"));
+        }
+        Err(ProxyError::new(
+            StatusCode::NOT_FOUND,
+            format!("conversation {thread_id} not found"),
+            "invalid_request_error",
+        )
+        .with_code("conversation_not_found"))
+    }
+
+    /// Returns the persisted rollout path and model for `thread_id`, with the
+    /// same not-found/expired errors as [`Self::get`].
+    pub(crate) fn stored(&self, thread_id: ThreadId) -> Result<StoredConversation, ProxyError> {
+        match self.store.get(&thread_id.to_string()) {
+            Some(stored) => Ok(stored),
+            None => {
+                self.get(thread_id)?;
+                Err(ProxyError::internal(format!(
+                    "conversation {thread_id} has no recorded rollout"
+                )))
+            }
+        }
+    }
+
+    /// Like [`Self::get`], but falls back to resuming the thread from its
+    /// persisted rollout when the proxy no longer holds it in memory, e.g.
+    /// after a restart.
+    pub(crate) async fn get_or_resume(
+        &self,
+        thread_id: ThreadId,
+        mut config: Config,
+    ) -> Result<Arc<CodexThread>, ProxyError> {
+        let not_live = match self.get(thread_id) {
+            Ok(thread) => return Ok(thread),
+            Err(e) => e,
+        };
+        let Some(stored) = self.store.get(&thread_id.to_string()) else {
+            return Err(not_live);
+        };
+
+        config.model_supports_parallel_tool_calls = stored.parallel_tool_calls;
+        let _resuming = self.resume_lock.lock().await;
+        if let Ok(thread) = self.get(thread_id) {
+            return Ok(thread);
+        }
+        let resumed = match self
+            .thread_manager
+            .resume_thread_from_rollout(
+                config,
+                stored.rollout_path.clone(),
+                Arc::clone(&self.auth_manager),
+            )
+            .await
+        {
+            Ok(resumed) => resumed,
+            Err(e) => {
+                warn!(
+                    "failed to resume thread {thread_id} from {}: {e}",
+                    stored.rollout_path.display()
+                );
+                self.store.remove(&thread_id.to_string());
+                return Err(not_live);
+            }
+        };
+
+        info!(
+            "resumed thread {thread_id} from {}",
+            stored.rollout_path.display()
+        );
+        log_message(
+            serde_json::json!({
+                "type": "thread_resumed",
+                "conversation_id": thread_id.to_string(),
+            })
+            .to_string(),
+        );
+        self.insert(
+            resumed.thread_id,
+            resumed.thread.clone(),
+            stored.model,
+            stored.parallel_tool_calls,
+        );
+        Ok(resumed.thread)
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "idle_ttl_secs": self.idle_ttl.map(|ttl| ttl.as_secs()),
+            "interrupt_previous_turn": self.interrupt_previous_turn,
+        })
+    }
+
+    /// Lists every live thread, most recently active first.
+    pub(crate) fn summaries(&self) -> Vec<ThreadSummary> {
+        let now = now_ts();
+        let mut summaries = self
+            .entries
+            .lock()
+            .map(|entries| {
+                entries
+                    .iter()
+                    .map(|(thread_id, entry)| ThreadSummary {
+                        id: thread_id.to_string(),
+                        model: entry.model.clone(),
+                        created_at: entry.created_at,
+                        last_activity_at: now
+                            .saturating_sub(entry.last_activity.elapsed().as_secs()),
+                        turn_running: entry.running_turns > 0,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        summaries.sort_by_key(|summary| std::cmp::Reverse(summary.last_activity_at));
+        summaries
+    }
+
+    /// Waits until no other turn is running on `thread_id` (interrupting it
+    /// first when `interrupt` says so, or by default when configured to), then
+    /// marks a turn as running until the returned guard drops. The reaper
+    /// never evicts a thread mid-turn.
+    pub(crate) async fn begin_turn(
+        self: &Arc<Self>,
+        thread_id: ThreadId,
+        interrupt: Option<bool>,
+    ) -> TurnGuard {
+        let tracked = self.entries.lock().ok().and_then(|entries| {
+            entries
+                .get(&thread_id)
+                .map(|entry| (entry.thread.clone(), entry.turn_lock.clone()))
+        });
+
+        let turn_lock = match tracked {
+            Some((thread, turn_lock)) => Some(match turn_lock.clone().try_lock_owned() {
+                Ok(guard) => guard,
+                Err(_) => {
+                    if interrupt.unwrap_or(self.interrupt_previous_turn) {
+                        info!("interrupting running turn on thread {thread_id}");
+                        if let Err(e) = thread.submit(Op::Interrupt).await {
+                            warn!("failed to interrupt thread {thread_id}: {e}");
+                        }
+                    }
+                    turn_lock.lock_owned().await
+                }
+            }),
+            None => None,
+        };
+
+        if let Ok(mut entries) = self.entries.lock()
+            && let Some(entry) = entries.get_mut(&thread_id)
+        {
+            entry.running_turns += 1;
+            entry.last_activity = Instant::now();
+        }
+        TurnGuard {
+            registry: Arc::clone(self),
+            thread_id,
+            _turn_lock: turn_lock,
+        }
+    }
+
+    /// Interrupts the turn running on `thread_id`. Returns `false` if the
+    /// thread is live but idle.
+    pub(crate) async fn abort_turn(&self, thread_id: ThreadId) -> Result<bool, ProxyError> {
+        let thread = self.get(thread_id)?;
+        let running = self
+            .entries
+            .lock()
+            .ok()
+            .and_then(|entries| entries.get(&thread_id).map(|entry| entry.running_turns > 0))
+            .unwrap_or(false);
+        if !running {
+            return Ok(false);
+        }
+        thread
+            .submit(Op::Interrupt)
+            .await
+            .map_err(|e| ProxyError::internal(format!("failed to interrupt thread: {e}")))?;
+        info!("aborted running turn on thread {thread_id}");
+        Ok(true)
+    }
+
+    /// How many turns run on `thread_id` or wait to.
+    #[cfg(test)]
+    pub(crate) fn turns_holding(&self, thread_id: ThreadId) -> usize {
+        let entries = self.entries.lock().expect("entries");
+        Arc::strong_count(&entries[&thread_id].turn_lock) - 1
+    }
+
+    fn end_turn(&self, thread_id: ThreadId) {
+        if let Ok(mut entries) = self.entries.lock()
+            && let Some(entry) = entries.get_mut(&thread_id)
+        {
+            entry.running_turns = entry.running_turns.saturating_sub(1);
+            entry.last_activity = Instant::now();
+        }
+    }
+
+    /// Removes the thread from the registry, the persisted store and the
+    /// [`ThreadManager`] and asks Codex to shut it down. Returns `false` if the
+    /// thread was neither live nor persisted.
+    pub(crate) async fn close(&self, thread_id: ThreadId) -> bool {
+        let removed = self
+            .entries
+            .lock()
+            .ok()
+            .and_then(|mut entries| entries.remove(&thread_id));
+        self.shut_down(thread_id, removed).await
+    }
+
+    /// Forgets `thread_id`, whose entry, if it was live, is already out of
+    /// the registry, and shuts the entry's thread down.
+    async fn shut_down(&self, thread_id: ThreadId, removed: Option<ThreadEntry>) -> bool {
+        let was_stored = self.store.remove(&thread_id.to_string());
+        self.attachments.remove_conversation(thread_id).await;
+        let Some(entry) = removed else {
+            return was_stored;
+        };
+        self.thread_manager.remove_thread(&thread_id).await;
+        if let Err(e) = entry.thread.submit(Op::Shutdown).await {
+            warn!("failed to shut down thread {thread_id}: {e}");
+        }
+        true
+    }
+
+    /// Closes a conversation that must not outlive its request (`store:
+    /// false`) and deletes its rollout file.
+    pub(crate) async fn discard(&self, thread_id: ThreadId, thread: &CodexThread) {
+        let rollout_path = thread.rollout_path();
+        self.close(thread_id).await;
+        if let Err(e) = std::fs::remove_file(&rollout_path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!(
+                "failed to delete rollout {} of {thread_id}: {e}",
+                rollout_path.display()
+            );
+        }
+    }
+
+    /// Closes every thread that has been idle for longer than the TTL.
+    async fn evict_idle(&self) {
+        let Some(idle_ttl) = self.idle_ttl else {
+            return;
+        };
+        let stale = self.stale(idle_ttl);
+        self.evict(stale, idle_ttl).await;
+    }
+
+    /// The threads idle for `idle_ttl`.
+    fn stale(&self, idle_ttl: Duration) -> Vec<ThreadId> {
+        self.entries
+            .lock()
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter(|(_, entry)| entry.is_idle(idle_ttl))
+                    .map(|(thread_id, _)| *thread_id)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Closes the threads of `stale` that are still idle. Each is checked
+    /// again as it is taken out of the registry, under the same lock
+    /// requests take to pick it up, since one may have since.
+    async fn evict(&self, stale: Vec<ThreadId>, idle_ttl: Duration) {
+        if let Ok(mut expired) = self.expired.lock() {
+            prune_expired(&mut expired, EXPIRED_RETENTION, MAX_EXPIRED_IDS);
+        }
+        for thread_id in stale {
+            let removed = self.entries.lock().ok().and_then(|mut entries| {
+                entries
+                    .get(&thread_id)
+                    .is_some_and(|entry| entry.is_idle(idle_ttl))
+                    .then(|| entries.remove(&thread_id))
+                    .flatten()
+            });
+            if removed.is_none() {
+                continue;
+            }
+            self.shut_down(thread_id, removed).await;
+            if let Ok(mut expired) = self.expired.lock() {
+                if expired.len() >= MAX_EXPIRED_IDS {
+                    prune_expired(&mut expired, EXPIRED_RETENTION, MAX_EXPIRED_IDS - 1);
+                }
+                expired.insert(thread_id, Instant::now());
+            }
+            info!("evicted idle thread {thread_id}");
+            log_message(
+                serde_json::json!({
+                    "type": "thread_expired",
+                    "conversation_id": thread_id.to_string(),
+                })
+                .to_string(),
+            );
+        }
+    }
+
+    /// Spawns the background task that periodically evicts idle threads.
+    pub(crate) fn spawn_reaper(self: &Arc<Self>) {
+        let Some(idle_ttl) = self.idle_ttl else {
+            return;
+        };
+        let period = (idle_ttl / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+        let registry = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                registry.evict_idle().await;
+            }
+        });
+    }
+}
+
+/// Forgets evicted ids older than `retention`, then the oldest of the rest
+/// until at most `max` remain.
+fn prune_expired(expired: &mut HashMap<ThreadId, Instant>, retention: Duration, max: usize) {
+    expired.retain(|_, evicted_at| evicted_at.elapsed() < retention);
+    if expired.len() > max {
+        let mut by_age = expired
+            .iter()
+            .map(|(thread_id, evicted_at)| (*evicted_at, *thread_id))
+            .collect::<Vec<_>>();
+        by_age.sort_unstable_by_key(|(evicted_at, _)| *evicted_at);
+        for (_, thread_id) in by_age.into_iter().take(expired.len() - max) {
+            expired.remove(&thread_id);
+        }
+    }
+}
+
+/// Keeps a thread marked as busy for the lifetime of a turn.
+pub(crate) struct TurnGuard {
+    registry: Arc<ThreadRegistry>,
+    thread_id: ThreadId,
+    _turn_lock: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for TurnGuard {
+    fn drop(&mut self) {
+        self.registry.end_turn(self.thread_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codex_core::config::ConfigBuilder;
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+
+    #[test]
+    fn expired_ids_are_forgotten_by_age_and_count() {
+        let now = Instant::now();
+        let old = ThreadId::new();
+        let older = ThreadId::new();
+        let recent = ThreadId::new();
+        let mut expired = HashMap::from([
+            (older, now - Duration::from_secs(30)),
+            (old, now - Duration::from_secs(20)),
+            (recent, now),
+        ]);
+
+        prune_expired(&mut expired, Duration::from_secs(25), 10);
+        assert_eq!(expired.len(), 2);
+        assert!(!expired.contains_key(&older));
+
+        prune_expired(&mut expired, Duration::from_secs(25), 1);
+        assert_eq!(expired.keys().collect::<Vec<_>>(), vec![&recent]);
+    }
+
+    #[tokio::test]
+    async fn eviction_spares_threads_picked_up_since_they_went_stale() {
+        let codex_home = tempfile::tempdir().expect("temp codex home");
+        let config = ConfigBuilder::default()
+            .codex_home(codex_home.path().to_path_buf())
+            .build()
+            .await
+            .expect("load config");
+        let state = crate::AppStateBuilder::new(config)
+            .ledger(PathBuf::from(":memory:"), None)
+            .thread_idle_ttl(None)
+            .mock_backend()
+            .await
+            .expect("start mock backend")
+            .build()
+            .expect("build state");
+        let (thread, thread_id) =
+            crate::get_or_create_thread(&state, "2.5-tpg", None, Vec::new(), None)
+                .await
+                .expect("start thread");
+        // Everything is stale at once, and no reaper runs on its own.
+        let registry = Arc::new(ThreadRegistry::new(
+            state.thread_manager.clone(),
+            state.auth_manager.clone(),
+            ConversationStore::load_from_home(codex_home.path()),
+            AttachmentStore::for_home(codex_home.path()),
+            Some(Duration::ZERO),
+            false,
+        ));
+        registry.insert(thread_id, thread, "2.5-tpg".to_string(), None);
+
+        // A turn begins between the reaper finding the thread stale and
+        // closing it.
+        let stale = registry.stale(Duration::ZERO);
+        assert_eq!(stale, vec![thread_id]);
+        let turn = registry.begin_turn(thread_id, None).await;
+        registry.evict(stale, Duration::ZERO).await;
+        assert!(registry.get(thread_id).is_ok());
+
+        // So does one queued behind it, before it counts as running.
+        let queued = tokio::spawn({
+            let registry = Arc::clone(&registry);
+            async move { registry.begin_turn(thread_id, None).await }
+        });
+        while registry.turns_holding(thread_id) < 2 {
+            tokio::task::yield_now().await;
+        }
+        registry.evict_idle().await;
+        assert!(registry.get(thread_id).is_ok());
+        drop(turn);
+        let queued = queued.await.expect("queued turn");
+        registry.evict_idle().await;
+        assert!(registry.get(thread_id).is_ok());
+
+        drop(queued);
+        registry.evict_idle().await;
+        assert_eq!(
+            registry.get(thread_id).err().and_then(|e| e.code),
+            Some("conversation_expired")
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! `GET /v1/conversations/{id}/turns/{turn_id}/diff`: the unified git diff
+//! of what a turn changed in its workspace, as Codex reported it in
+//! `TurnDiff` events, so review tooling can gate merges on it. A turn is
+//! named by the id it was answered with: the chat completion, response or
+//! message id, or the WebSocket `turn_id`.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::HeaderName;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use codex_protocol::protocol::EventMsg;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::conversations::parse_conversation_id;
+
+/// Most turns whose diffs are kept; the oldest are forgotten first.
+const MAX_TURNS: usize = 1_000;
+
+/// Says whether the turn is still `in_progress` or `completed`.
+const TURN_STATUS_HEADER: &str = "x-codex-turn-status";
+
+/// A turn's diff so far, and whether the turn is over.
+#[derive(Debug, Default)]
+struct TurnDiff {
+    unified_diff: String,
+    complete: bool,
+}
+
+/// Diffs of recent turns, keyed by conversation and turn id.
+#[derive(Debug, Default)]
+pub(crate) struct TurnDiffs {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    diffs: HashMap<(String, String), TurnDiff>,
+    /// Turns in the order they were first seen.
+    order: VecDeque<(String, String)>,
+}
+
+impl TurnDiffs {
+    /// Notes a turn starting, its diff so far (each `TurnDiff` event carries
+    /// the whole diff) and its end, after which no diff means no changes.
+    pub(crate) fn record_event(&self, conversation_id: &str, turn_id: &str, msg: &EventMsg) {
+        match msg {
+            EventMsg::TurnStarted(_) => self.update(conversation_id, turn_id, |_| {}),
+            EventMsg::TurnDiff(event) => self.update(conversation_id, turn_id, |diff| {
+                diff.unified_diff = event.unified_diff.clone();
+            }),
+            EventMsg::TurnComplete(_) | EventMsg::TurnAborted(_) => {
+                self.update(conversation_id, turn_id, |diff| diff.complete = true);
+            }
+            _ => {}
+        }
+    }
+
+    fn update(&self, conversation_id: &str, turn_id: &str, apply: impl FnOnce(&mut TurnDiff)) {
+        let Ok(mut inner) = self.inner.lock() else {
+            return;
+        };
+        let key = (conversation_id.to_string(), turn_id.to_string());
+        if !inner.diffs.contains_key(&key) {
+            if inner.order.len() >= MAX_TURNS
+                && let Some(oldest) = inner.order.pop_front()
+            {
+                inner.diffs.remove(&oldest);
+            }
+            inner.order.push_back(key.clone());
+        }
+        apply(inner.diffs.entry(key).or_default());
+    }
+
+    /// Forgets the turns of a conversation that is going away.
+    pub(crate) fn remove_conversation(&self, conversation_id: &str) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.diffs.retain(|(id, _), _| id != conversation_id);
+            inner.order.retain(|(id, _)| id != conversation_id);
+        }
+    }
+
+    /// The turn's diff and whether the turn is over.
+    fn get(&self, conversation_id: &str, turn_id: &str) -> Option<(String, bool)> {
+        let inner = self.inner.lock().ok()?;
+        let diff = inner
+            .diffs
+            .get(&(conversation_id.to_string(), turn_id.to_string()))?;
+        Some((diff.unified_diff.clone(), diff.complete))
+    }
+}
+
+/// `GET /v1/conversations/{id}/turns/{turn_id}/diff`: the diff as
+/// `text/x-diff`, empty when the turn changed nothing.
+pub(crate) async fn handle_turn_diff(
+    State(state): State<AppState>,
+    Path((id, turn_id)): Path<(String, String)>,
+) -> Response {
+    let thread_id = match parse_conversation_id(&id) {
+        Ok(thread_id) => thread_id,
+        Err(e) => return e.into_response(),
+    };
+    let Some((unified_diff, complete)) = state.turn_diffs.get(&thread_id.to_string(), &turn_id)
+    else {
+        return ProxyError::new(
+            StatusCode::NOT_FOUND,
+            format!("No turn {turn_id} found in conversation {id}"),
+            "invalid_request_error",
+        )
+        .with_code("turn_not_found")
+        .into_response();
+    };
+    let status = if complete { "completed" } else { "in_progress" };
+    (
+        [
+            (CONTENT_TYPE, "text/x-diff; charset=utf-8"),
+            (HeaderName::from_static(TURN_STATUS_HEADER), status),
+        ],
+        unified_diff,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codex_protocol::protocol::TurnCompleteEvent;
+    use codex_protocol::protocol::TurnDiffEvent;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn the_latest_diff_of_each_turn_is_kept() {
+        let diffs = TurnDiffs::default();
+        let diff = |unified_diff: &str| {
+            EventMsg::TurnDiff(TurnDiffEvent {
+                unified_diff: unified_diff.to_string(),
+            })
+        };
+        let complete = EventMsg::TurnComplete(TurnCompleteEvent {
+            last_agent_message: None,
+        });
+        diffs.record_event("c1", "t1", &diff("diff --git a/x b/x\n+1\n"));
+        assert_eq!(
+            diffs.get("c1", "t1"),
+            Some(("diff --git a/x b/x\n+1\n".to_string(), false))
+        );
+        diffs.record_event("c1", "t1", &diff("diff --git a/x b/x\n+1\n+2\n"));
+        diffs.record_event("c1", "t1", &complete);
+        diffs.record_event("c1", "t2", &complete);
+        assert_eq!(
+            diffs.get("c1", "t1"),
+            Some(("diff --git a/x b/x\n+1\n+2\n".to_string(), true))
+        );
+        assert_eq!(diffs.get("c1", "t2"), Some((String::new(), true)));
+
+        diffs.remove_conversation("c1");
+        assert_eq!(diffs.get("c1", "t1"), None);
+    }
+}
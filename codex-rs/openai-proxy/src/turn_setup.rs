@@ -0,0 +1,331 @@
+//! The setup every Codex turn goes through, whichever endpoint runs it:
+//! chat completions, Responses, Messages, the WebSocket and the MCP facade.
+//! Each endpoint parses its request into [`TurnOptions`]; checking them,
+//! resolving the thread, claiming a turn slot and submitting the
+//! `UserTurn` happen here, so a policy added for one endpoint holds for all
+//! of them.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use codex_core::CodexThread;
+use codex_core::protocol::AskForApproval;
+use codex_core::protocol::Op;
+use codex_core::protocol::SandboxPolicy;
+use codex_core::protocol::Submission;
+use codex_protocol::ThreadId;
+use codex_protocol::config_types::ReasoningSummary;
+use codex_protocol::openai_models::ReasoningEffort;
+use codex_protocol::user_input::UserInput;
+use tracing::info_span;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::access_log::RequestContext;
+use crate::approvals;
+use crate::attachments;
+use crate::attachments::Attachment;
+use crate::attachments::PreparedAttachment;
+use crate::dry_run;
+use crate::get_or_create_thread;
+use crate::images;
+use crate::mcp_servers::McpServerRequest;
+use crate::moderation;
+use crate::redaction;
+use crate::sandbox_mode;
+use crate::threads::TurnGuard;
+use crate::turn_slots::TurnPermit;
+
+/// What a request asks of its turn. Endpoints fill in what their request
+/// format carries and leave the rest at the defaults.
+#[derive(Debug, Default)]
+pub(crate) struct TurnOptions {
+    /// The model as the client named it.
+    pub(crate) model: String,
+    /// The turn's text, before attachment notes and redaction.
+    pub(crate) text: String,
+    pub(crate) conversation_id: Option<String>,
+    /// Config overrides a thread started for the turn gets, such as its
+    /// profile.
+    pub(crate) thread_overrides: Vec<(String, toml::Value)>,
+    pub(crate) base_instructions: Option<String>,
+    pub(crate) mcp_servers: Vec<McpServerRequest>,
+    /// The end user the request is made for, as in the OpenAI `user` field.
+    pub(crate) user: Option<String>,
+    pub(crate) approval_policy: Option<String>,
+    /// Whether anyone can answer `on-request` approvals: a stream's client
+    /// or the approval webhook.
+    pub(crate) answerable: bool,
+    pub(crate) sandbox_mode: Option<String>,
+    pub(crate) dry_run: bool,
+    pub(crate) cwd: Option<String>,
+    pub(crate) effort: Option<ReasoningEffort>,
+    /// Codex's `final_output_json_schema`.
+    pub(crate) output_schema: Option<serde_json::Value>,
+    pub(crate) image_urls: Vec<String>,
+    pub(crate) attachments: Vec<Attachment>,
+    pub(crate) priority: i32,
+    pub(crate) interrupt_previous_turn: Option<bool>,
+}
+
+impl TurnOptions {
+    /// Checks the options against the proxy's policies, moderates the text
+    /// and loads images and attachments, so a bad request fails before it
+    /// queues or starts a thread.
+    pub(crate) async fn prepare(
+        self,
+        state: &AppState,
+        context: &RequestContext,
+    ) -> Result<PreparedTurn, ProxyError> {
+        context.set_user(self.user.as_deref());
+        let approval_policy =
+            approvals::approval_policy(self.approval_policy.as_deref(), self.answerable)?;
+        let sandbox_policy = sandbox_mode::sandbox_policy(
+            self.sandbox_mode.as_deref(),
+            state.allow_danger_full_access,
+        )?;
+        let (approval_policy, sandbox_policy) =
+            dry_run::policies(self.dry_run, approval_policy, sandbox_policy);
+        let cwd = state.workspaces.resolve(self.cwd.as_deref())?;
+        let mut thread_overrides = self.thread_overrides;
+        thread_overrides.extend(state.mcp_servers.overrides(&self.mcp_servers)?);
+        let attachments = attachments::prepare(self.attachments, &state.files)?;
+        moderation::check(state, &self.text).await?;
+        let images = images::image_inputs(&state.http_client, self.image_urls).await?;
+        Ok(PreparedTurn {
+            model: self.model,
+            text: self.text,
+            conversation_id: self.conversation_id,
+            thread_overrides,
+            base_instructions: self.base_instructions,
+            approval_policy,
+            sandbox_policy,
+            dry_run: self.dry_run,
+            cwd,
+            effort: self.effort,
+            output_schema: self.output_schema,
+            images,
+            attachments,
+            priority: self.priority,
+            interrupt_previous_turn: self.interrupt_previous_turn,
+        })
+    }
+}
+
+/// [`TurnOptions`] that passed the proxy's checks, ready to run.
+#[derive(Debug)]
+pub(crate) struct PreparedTurn {
+    model: String,
+    text: String,
+    conversation_id: Option<String>,
+    /// The request's overrides plus those adding its MCP servers.
+    thread_overrides: Vec<(String, toml::Value)>,
+    base_instructions: Option<String>,
+    approval_policy: AskForApproval,
+    sandbox_policy: SandboxPolicy,
+    dry_run: bool,
+    cwd: PathBuf,
+    effort: Option<ReasoningEffort>,
+    output_schema: Option<serde_json::Value>,
+    images: Vec<UserInput>,
+    attachments: Vec<PreparedAttachment>,
+    priority: i32,
+    interrupt_previous_turn: Option<bool>,
+}
+
+impl PreparedTurn {
+    /// Continues the requested conversation, or starts a thread for the
+    /// turn.
+    pub(crate) async fn thread(
+        &self,
+        state: &AppState,
+        context: &RequestContext,
+    ) -> Result<(Arc<CodexThread>, ThreadId), ProxyError> {
+        let (thread, thread_id) = get_or_create_thread(
+            state,
+            &self.model,
+            self.conversation_id.clone(),
+            self.thread_overrides.clone(),
+            self.base_instructions.as_deref(),
+        )
+        .await?;
+        context.set_conversation_id(thread_id);
+        Ok((thread, thread_id))
+    }
+}
+
+/// Runs `turn` on `thread`: waits out any turn already running on it, then
+/// for a turn slot, writes the attachments into the conversation and
+/// submits the redacted input. Waiting for the conversation first keeps
+/// follow-ups queued behind their own conversation from holding slots other
+/// conversations could use.
+pub(crate) async fn submit(
+    state: &AppState,
+    context: &RequestContext,
+    (thread, thread_id): (Arc<CodexThread>, ThreadId),
+    turn: PreparedTurn,
+) -> Result<SubmittedTurn, ProxyError> {
+    let turn_guard = state
+        .threads
+        .begin_turn(thread_id, turn.interrupt_previous_turn)
+        .await;
+    let (turn_permit, ticket) = state.turn_slots.acquire(turn.priority).await?;
+    if let Some(ticket) = ticket {
+        context.set_queue_ticket(ticket);
+    }
+    let paths = state
+        .threads
+        .attachments()
+        .materialize(thread_id, turn.attachments)?;
+    let text = attachments::with_attachment_note(turn.text, &paths);
+
+    let mut items = vec![UserInput::Text {
+        text: redaction::scrub_prompt(state.redactor.as_deref(), &text),
+    }];
+    items.extend(turn.images);
+    context.record_input(&items);
+    context.record_sandbox(&turn.sandbox_policy);
+    let mut submitted = SubmittedTurn {
+        thread,
+        thread_id,
+        submission_id: String::new(),
+        model: state.model_map.codex_model(&turn.model),
+        approval_policy: turn.approval_policy,
+        dry_run: turn.dry_run,
+        sandbox_policy: turn.sandbox_policy,
+        cwd: turn.cwd,
+        effort: turn.effort,
+        output_schema: turn.output_schema,
+        _turn_guard: turn_guard,
+        _turn_permit: turn_permit,
+    };
+    submitted.submit(items).await?;
+    Ok(submitted)
+}
+
+/// A turn Codex is running. Holds the turn slot and the conversation until
+/// dropped.
+pub(crate) struct SubmittedTurn {
+    pub(crate) thread: Arc<CodexThread>,
+    pub(crate) thread_id: ThreadId,
+    /// The id the turn's events carry; a resubmission replaces it.
+    pub(crate) submission_id: String,
+    /// The Codex model the turn runs on.
+    pub(crate) model: String,
+    pub(crate) approval_policy: AskForApproval,
+    /// Whether approvals are to be denied and their patches handed back.
+    pub(crate) dry_run: bool,
+    sandbox_policy: SandboxPolicy,
+    cwd: PathBuf,
+    effort: Option<ReasoningEffort>,
+    output_schema: Option<serde_json::Value>,
+    _turn_guard: TurnGuard,
+    _turn_permit: TurnPermit,
+}
+
+impl SubmittedTurn {
+    /// The `codex.turn` span the turn's events are handled in.
+    pub(crate) fn span(&self) -> tracing::Span {
+        info_span!(
+            "codex.turn",
+            conversation_id = %self.thread_id,
+            submission_id = %self.submission_id,
+            model = %self.model,
+        )
+    }
+
+    /// Asks again on the same thread with the same settings, as when an
+    /// answer did not match the output schema.
+    pub(crate) async fn resubmit(&mut self, text: String) -> Result<(), ProxyError> {
+        self.submit(vec![UserInput::Text { text }]).await
+    }
+
+    async fn submit(&mut self, items: Vec<UserInput>) -> Result<(), ProxyError> {
+        self.submission_id = uuid::Uuid::new_v4().to_string();
+        let submission = Submission {
+            id: self.submission_id.clone(),
+            op: Op::UserTurn {
+                items,
+                cwd: self.cwd.clone(),
+                approval_policy: self.approval_policy,
+                sandbox_policy: self.sandbox_policy.clone(),
+                model: self.model.clone(),
+                effort: self.effort,
+                summary: ReasoningSummary::Detailed,
+                final_output_json_schema: self.output_schema.clone(),
+            },
+        };
+        self.thread
+            .submit_with_id(submission)
+            .await
+            .map_err(|e| ProxyError::internal(format!("submit error: {e}")))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codex_core::config::ConfigBuilder;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    async fn start(
+        state: &AppState,
+        conversation_id: Option<String>,
+    ) -> Result<SubmittedTurn, ProxyError> {
+        let context = RequestContext::new(uuid::Uuid::new_v4().to_string());
+        let turn = TurnOptions {
+            model: "2.5-tpg".to_string(),
+            text: "hi".to_string(),
+            conversation_id,
+            ..Default::default()
+        }
+        .prepare(state, &context)
+        .await?;
+        let thread = turn.thread(state, &context).await?;
+        submit(state, &context, thread, turn).await
+    }
+
+    #[tokio::test]
+    async fn follow_ups_wait_for_their_conversation_before_a_turn_slot() {
+        let codex_home = tempfile::tempdir().expect("temp codex home");
+        let config = ConfigBuilder::default()
+            .codex_home(codex_home.path().to_path_buf())
+            .build()
+            .await
+            .expect("load config");
+        let state = crate::AppStateBuilder::new(config)
+            .ledger(PathBuf::from(":memory:"), None)
+            .max_concurrent_turns(Some(2))
+            .mock_backend()
+            .await
+            .expect("start mock backend")
+            .build()
+            .expect("build state");
+
+        // Holds one of the two slots and its conversation until dropped.
+        let first = start(&state, None).await.expect("first turn");
+        let follow_up = tokio::spawn({
+            let state = state.clone();
+            let conversation_id = Some(first.thread_id.to_string());
+            async move { start(&state, conversation_id).await.map(|_| ()) }
+        });
+        while state.threads.turns_holding(first.thread_id) < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // Without a queue, a follow-up holding the other slot would get
+        // this turn rejected.
+        let other = tokio::time::timeout(Duration::from_secs(10), start(&state, None))
+            .await
+            .expect("other conversation's turn waited");
+        assert!(other.is_ok(), "{:?}", other.err());
+
+        drop(first);
+        follow_up
+            .await
+            .expect("follow-up task")
+            .expect("follow-up turn");
+    }
+}
@@ -0,0 +1,374 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use tokio::sync::oneshot;
+
+use crate::ProxyError;
+
+/// Header clients use to jump the turn queue; higher runs first.
+pub(crate) const PRIORITY_HEADER: &str = "x-priority";
+
+/// A request waiting for a turn slot.
+struct Waiter {
+    priority: i32,
+    /// Arrival order, so equal priorities are served first come, first served.
+    seq: u64,
+    wake: oneshot::Sender<()>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    /// The max-heap pops the highest priority, then the earliest arrival.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+#[derive(Default)]
+struct SlotState {
+    /// `None` means unlimited.
+    max: Option<usize>,
+    /// How many requests may wait for a slot; 0 sheds load immediately.
+    queue_capacity: usize,
+    running: usize,
+    waiting: BinaryHeap<Waiter>,
+    next_seq: u64,
+    total_queued: u64,
+    total_rejected: u64,
+    total_queue_wait: Duration,
+}
+
+/// Caps how many turns run at once across all conversations. Past the cap,
+/// requests either fail with a 429 or, when a queue is configured, wait in a
+/// bounded queue ordered by [`PRIORITY_HEADER`].
+pub(crate) struct TurnSlots {
+    state: Arc<Mutex<SlotState>>,
+}
+
+/// Holds a turn slot until dropped, then hands it to the next waiter.
+pub(crate) struct TurnPermit {
+    state: Arc<Mutex<SlotState>>,
+}
+
+/// Where a request waited before it got its slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct QueueTicket {
+    /// 1-based position when the request joined the queue.
+    pub(crate) position: usize,
+    pub(crate) waited: Duration,
+}
+
+impl TurnSlots {
+    /// `max` of `None` disables the limit (and the queue).
+    pub(crate) fn new(max: Option<usize>, queue_capacity: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SlotState {
+                max,
+                queue_capacity,
+                ..SlotState::default()
+            })),
+        }
+    }
+
+    /// Changes the limit and queue size, as on a config reload. Waiters get
+    /// the slots a higher limit frees; with a lower one, running turns finish
+    /// and no new ones start until fewer than `max` run. Requests already
+    /// queued keep their place.
+    pub(crate) fn resize(&self, max: Option<usize>, queue_capacity: usize) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        state.max = max;
+        state.queue_capacity = queue_capacity;
+        while has_room(&state) {
+            let Some(waiter) = state.waiting.pop() else {
+                break;
+            };
+            if waiter.wake.send(()).is_ok() {
+                state.running += 1;
+            }
+        }
+    }
+
+    /// Claims a slot, waiting in the queue when all are taken and a queue is
+    /// configured. Returns the queue ticket when the request had to wait.
+    pub(crate) async fn acquire(
+        &self,
+        priority: i32,
+    ) -> Result<(TurnPermit, Option<QueueTicket>), ProxyError> {
+        let (wake, position) = {
+            let mut state = self
+                .state
+                .lock()
+                .map_err(|_| ProxyError::internal("turn slots poisoned"))?;
+            if has_room(&state) {
+                state.running += 1;
+                return Ok((self.permit(), None));
+            }
+            // Forget waiters whose clients already went away.
+            state.waiting.retain(|waiter| !waiter.wake.is_closed());
+            if state.waiting.len() >= state.queue_capacity {
+                state.total_rejected += 1;
+                return Err(overloaded(&state));
+            }
+
+            let (tx, rx) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            let waiter = Waiter {
+                priority,
+                seq,
+                wake: tx,
+            };
+            let position = state.waiting.iter().filter(|w| **w > waiter).count() + 1;
+            state.waiting.push(waiter);
+            state.total_queued += 1;
+            (rx, position)
+        };
+
+        let queued_at = Instant::now();
+        let mut pending = PendingSlot {
+            state: Arc::clone(&self.state),
+            wake: Some(wake),
+        };
+        // The releasing permit hands its slot over, so `running` already
+        // counts this request when it wakes.
+        if let Some(wake) = pending.wake.as_mut() {
+            wake.await
+                .map_err(|_| ProxyError::internal("turn queue closed"))?;
+        }
+        pending.wake = None;
+        let waited = queued_at.elapsed();
+        if let Ok(mut state) = self.state.lock() {
+            state.total_queue_wait += waited;
+        }
+        Ok((self.permit(), Some(QueueTicket { position, waited })))
+    }
+
+    fn permit(&self) -> TurnPermit {
+        TurnPermit {
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        let Ok(state) = self.state.lock() else {
+            return serde_json::Value::Null;
+        };
+        serde_json::json!({
+            "max_concurrent_turns": state.max,
+            "queue_capacity": state.queue_capacity,
+        })
+    }
+
+    /// Live counters reported by `GET /admin/turns`.
+    pub(crate) fn metrics(&self) -> serde_json::Value {
+        let Ok(state) = self.state.lock() else {
+            return serde_json::Value::Null;
+        };
+        let queued = state
+            .waiting
+            .iter()
+            .filter(|waiter| !waiter.wake.is_closed())
+            .count();
+        let dequeued = state
+            .total_queued
+            .saturating_sub(state.waiting.len() as u64);
+        let avg_queue_ms = match dequeued {
+            0 => 0,
+            n => (state.total_queue_wait.as_millis() / u128::from(n)) as u64,
+        };
+        serde_json::json!({
+            "max_concurrent_turns": state.max,
+            "running": state.running,
+            "queued": queued,
+            "queue_capacity": state.queue_capacity,
+            "total_queued": state.total_queued,
+            "total_rejected": state.total_rejected,
+            "avg_queue_ms": avg_queue_ms,
+        })
+    }
+}
+
+impl Drop for TurnPermit {
+    fn drop(&mut self) {
+        release(&self.state);
+    }
+}
+
+/// A queued request's wake-up channel. If the request is cancelled after a
+/// slot was handed to it but before it noticed, the slot is passed on.
+struct PendingSlot {
+    state: Arc<Mutex<SlotState>>,
+    wake: Option<oneshot::Receiver<()>>,
+}
+
+impl Drop for PendingSlot {
+    fn drop(&mut self) {
+        if let Some(mut wake) = self.wake.take() {
+            wake.close();
+            if wake.try_recv().is_ok() {
+                release(&self.state);
+            }
+        }
+    }
+}
+
+/// Hands a freed slot to the highest-priority live waiter, or returns it.
+/// Past a lowered limit the slot is returned.
+fn release(state: &Mutex<SlotState>) {
+    let Ok(mut state) = state.lock() else {
+        return;
+    };
+    if state.max.is_none_or(|max| state.running <= max) {
+        while let Some(waiter) = state.waiting.pop() {
+            if waiter.wake.send(()).is_ok() {
+                return;
+            }
+        }
+    }
+    state.running = state.running.saturating_sub(1);
+}
+
+fn has_room(state: &SlotState) -> bool {
+    state.max.is_none_or(|max| state.running < max)
+}
+
+fn overloaded(state: &SlotState) -> ProxyError {
+    let message = if state.queue_capacity == 0 {
+        format!(
+            "the server is running its maximum of {} turns; retry shortly",
+            state.max.unwrap_or_default()
+        )
+    } else {
+        format!(
+            "the server is running its maximum of {} turns and {} more are queued; retry shortly",
+            state.max.unwrap_or_default(),
+            state.queue_capacity
+        )
+    };
+    ProxyError::new(StatusCode::TOO_MANY_REQUESTS, message, "server_error")
+        .with_code("server_overloaded")
+}
+
+/// Reads [`PRIORITY_HEADER`]; requests without one get priority 0.
+pub(crate) fn request_priority(headers: &HeaderMap) -> Result<i32, ProxyError> {
+    let Some(value) = headers.get(PRIORITY_HEADER) else {
+        return Ok(0);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse::<i32>().ok())
+        .ok_or_else(|| {
+            ProxyError::new(
+                StatusCode::BAD_REQUEST,
+                format!("{PRIORITY_HEADER} must be an integer"),
+                "invalid_request_error",
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn excess_turns_are_shed_without_a_queue() {
+        let slots = TurnSlots::new(Some(1), 0);
+        let (first, ticket) = slots.acquire(0).await.expect("first turn fits");
+        assert_eq!(ticket, None);
+        assert_eq!(
+            slots.acquire(0).await.err().map(|e| e.status),
+            Some(StatusCode::TOO_MANY_REQUESTS)
+        );
+        drop(first);
+        assert!(slots.acquire(0).await.is_ok());
+
+        let unlimited = TurnSlots::new(None, 0);
+        let _a = unlimited.acquire(0).await.expect("unlimited");
+        let _b = unlimited.acquire(0).await.expect("unlimited");
+    }
+
+    #[tokio::test]
+    async fn queued_turns_run_by_priority() {
+        let slots = Arc::new(TurnSlots::new(Some(1), 2));
+        let (running, _) = slots.acquire(0).await.expect("first turn fits");
+
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut waiters = Vec::new();
+        for priority in [1, 5] {
+            let queue = Arc::clone(&slots);
+            let order_tx = order_tx.clone();
+            waiters.push(tokio::spawn(async move {
+                let (permit, ticket) = queue.acquire(priority).await.expect("queued");
+                order_tx.send((priority, ticket.map(|t| t.position))).ok();
+                drop(permit);
+            }));
+            // Let the waiter join the queue before the next one.
+            while slots.metrics()["queued"] != serde_json::json!(waiters.len()) {
+                tokio::task::yield_now().await;
+            }
+        }
+        assert_eq!(
+            slots.acquire(0).await.err().map(|e| e.status),
+            Some(StatusCode::TOO_MANY_REQUESTS)
+        );
+
+        drop(running);
+        for waiter in waiters {
+            waiter.await.expect("waiter");
+        }
+        assert_eq!(order_rx.recv().await, Some((5, Some(1))));
+        assert_eq!(order_rx.recv().await, Some((1, Some(1))));
+        assert_eq!(slots.metrics()["running"], serde_json::json!(0));
+    }
+
+    #[tokio::test]
+    async fn resizing_wakes_waiters_and_drains_excess_turns() {
+        let slots = Arc::new(TurnSlots::new(Some(1), 1));
+        let (first, _) = slots.acquire(0).await.expect("first turn fits");
+        let queue = Arc::clone(&slots);
+        let waiter = tokio::spawn(async move { queue.acquire(0).await.map(|(permit, _)| permit) });
+        while slots.metrics()["queued"] != serde_json::json!(1) {
+            tokio::task::yield_now().await;
+        }
+
+        slots.resize(Some(2), 1);
+        let second = waiter.await.expect("waiter").expect("woken by the resize");
+        assert_eq!(slots.metrics()["running"], serde_json::json!(2));
+
+        slots.resize(Some(1), 0);
+        drop(first);
+        assert_eq!(slots.metrics()["running"], serde_json::json!(1));
+        assert_eq!(
+            slots.acquire(0).await.err().map(|e| e.status),
+            Some(StatusCode::TOO_MANY_REQUESTS)
+        );
+        drop(second);
+        assert!(slots.acquire(0).await.is_ok());
+    }
+}
@@ -0,0 +1,130 @@
+//! Turn notifications: a webhook POSTed once a request's turns have ended,
+//! with its response id, usage and a summary, so orchestration systems
+//! waiting on batches or long turns need not poll.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::audit_log::TurnUsage;
+
+/// Characters of the final answer sent as the notification's summary.
+const MAX_SUMMARY_CHARS: usize = 500;
+
+/// Where turn notifications go and which turns they are sent for.
+#[derive(Debug)]
+pub(crate) struct TurnWebhook {
+    client: reqwest::Client,
+    url: String,
+    timeout: Duration,
+    /// Turns of interactive requests that ended sooner are not notified;
+    /// batch turns always are.
+    min_duration: Duration,
+}
+
+/// The body of a turn notification.
+#[derive(Debug, Serialize)]
+pub(crate) struct TurnNotice {
+    /// `turn.completed`, `turn.failed`, `turn.aborted` or `turn.incomplete`.
+    pub(crate) event: &'static str,
+    pub(crate) request_id: String,
+    /// The id of the chat completion, response or message the turn answered.
+    pub(crate) response_id: Option<String>,
+    pub(crate) conversation_id: Option<String>,
+    pub(crate) model: Option<String>,
+    pub(crate) user: Option<String>,
+    pub(crate) path: String,
+    /// Whether the turn ran for a batch rather than a waiting client.
+    pub(crate) background: bool,
+    pub(crate) duration_ms: u64,
+    pub(crate) usage: TurnUsage,
+    /// The start of the final answer.
+    pub(crate) summary: Option<String>,
+    pub(crate) error: Option<String>,
+}
+
+impl TurnNotice {
+    /// The event for a turn that ended with `outcome`.
+    pub(crate) fn event(outcome: Option<&str>) -> &'static str {
+        match outcome {
+            Some("completed") => "turn.completed",
+            Some("failed") => "turn.failed",
+            Some("aborted") => "turn.aborted",
+            _ => "turn.incomplete",
+        }
+    }
+
+    /// `answer` cut down to a summary.
+    pub(crate) fn summary(answer: &str) -> String {
+        let answer = answer.trim();
+        match answer.char_indices().nth(MAX_SUMMARY_CHARS) {
+            Some((end, _)) => format!("{}…", &answer[..end]),
+            None => answer.to_string(),
+        }
+    }
+}
+
+impl TurnWebhook {
+    pub(crate) fn new(url: String, timeout: Duration, min_duration: Duration) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            timeout,
+            min_duration,
+        }
+    }
+
+    /// Whether a request whose turns took `elapsed` is notified.
+    pub(crate) fn wants(&self, elapsed: Duration, background: bool) -> bool {
+        background || elapsed >= self.min_duration
+    }
+
+    /// POSTs `notice` in the background; failures are only logged.
+    pub(crate) fn send(self: &Arc<Self>, notice: TurnNotice) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let webhook = self.clone();
+        runtime.spawn(async move {
+            let response = webhook
+                .client
+                .post(&webhook.url)
+                .timeout(webhook.timeout)
+                .json(&notice)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+            if let Err(e) = response {
+                warn!("turn webhook failed for request {}: {e}", notice.request_id);
+            }
+        });
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "url": self.url,
+            "timeout_secs": self.timeout.as_secs(),
+            "min_secs": self.min_duration.as_secs(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn summaries_are_trimmed_and_cut() {
+        assert_eq!(TurnNotice::summary("  done.\n"), "done.");
+        let long = "é".repeat(MAX_SUMMARY_CHARS + 10);
+        let summary = TurnNotice::summary(&long);
+        assert_eq!(summary.chars().count(), MAX_SUMMARY_CHARS + 1);
+        assert!(summary.ends_with('…'));
+        assert_eq!(TurnNotice::event(Some("completed")), "turn.completed");
+        assert_eq!(TurnNotice::event(None), "turn.incomplete");
+    }
+}
@@ -0,0 +1,203 @@
+use axum::http::StatusCode;
+use codex_protocol::protocol::CodexErrorInfo;
+use codex_protocol::protocol::ErrorEvent;
+
+use crate::ProxyError;
+
+/// How a failed Codex turn is reported to the client: with the status an
+/// OpenAI client expects for the failure when it can be told, a 500
+/// otherwise.
+pub(crate) fn turn_error(err: &ErrorEvent) -> ProxyError {
+    classify(err).unwrap_or_else(|| ProxyError::internal(err.message.clone()))
+}
+
+/// The client-facing error for a Codex or provider failure whose cause is
+/// known: from Codex's classification first, then from the HTTP status or
+/// OpenAI error body the message carries.
+pub(crate) fn classify(err: &ErrorEvent) -> Option<ProxyError> {
+    let message = &err.message;
+    let classified = match &err.codex_error_info {
+        Some(CodexErrorInfo::ContextWindowExceeded) => Some(context_length_exceeded(message)),
+        Some(CodexErrorInfo::UsageLimitExceeded) => Some(quota_exceeded(message)),
+        Some(CodexErrorInfo::Unauthorized) => Some(unauthorized(message)),
+        Some(CodexErrorInfo::BadRequest) => Some(invalid_request(message)),
+        Some(CodexErrorInfo::SandboxError) => Some(
+            ProxyError::new(StatusCode::FORBIDDEN, message.clone(), "permission_error")
+                .with_code("sandbox_denied"),
+        ),
+        Some(
+            CodexErrorInfo::HttpConnectionFailed { http_status_code }
+            | CodexErrorInfo::ResponseStreamConnectionFailed { http_status_code }
+            | CodexErrorInfo::ResponseStreamDisconnected { http_status_code }
+            | CodexErrorInfo::ResponseTooManyFailedAttempts { http_status_code },
+        ) => http_status_code.and_then(|status| for_status(status, message)),
+        _ => None,
+    };
+    let classified = classified
+        .or_else(|| for_error_body(message))
+        .or_else(|| status_in_message(message).and_then(|status| for_status(status, message)))?;
+    Some(match retry_after(message) {
+        Some(secs) if classified.status == StatusCode::TOO_MANY_REQUESTS => {
+            classified.with_retry_after(secs)
+        }
+        _ => classified,
+    })
+}
+
+fn for_status(status: u16, message: &str) -> Option<ProxyError> {
+    match status {
+        400 => Some(invalid_request(message)),
+        401 => Some(unauthorized(message)),
+        403 => Some(ProxyError::new(
+            StatusCode::FORBIDDEN,
+            message,
+            "permission_error",
+        )),
+        429 => Some(rate_limited(message)),
+        _ => None,
+    }
+}
+
+/// Classifies by the `type`/`code` of an OpenAI error body embedded in the
+/// message, as in `unexpected status 400 Bad Request: {"error": {...}}`.
+fn for_error_body(message: &str) -> Option<ProxyError> {
+    let body = &message[message.find('{')?..];
+    let body = serde_json::Deserializer::from_str(body)
+        .into_iter::<serde_json::Value>()
+        .next()?
+        .ok()?;
+    let error = body.get("error")?;
+    let field = |name: &str| error.get(name).and_then(serde_json::Value::as_str);
+    let (kind, code) = (field("type"), field("code"));
+    match (kind, code) {
+        (_, Some("context_length_exceeded")) => Some(context_length_exceeded(message)),
+        (_, Some("rate_limit_exceeded")) | (Some("requests" | "tokens"), _) => {
+            Some(rate_limited(message))
+        }
+        (Some("insufficient_quota"), _) | (_, Some("insufficient_quota")) => {
+            Some(quota_exceeded(message))
+        }
+        (Some("authentication_error"), _) | (_, Some("invalid_api_key")) => {
+            Some(unauthorized(message))
+        }
+        (Some("invalid_request_error"), _) => Some(invalid_request(message)),
+        _ => None,
+    }
+}
+
+/// The status in Codex's `unexpected status 401 Unauthorized: ...` messages.
+fn status_in_message(message: &str) -> Option<u16> {
+    const PREFIX: &str = "unexpected status ";
+    let rest = &message[message.find(PREFIX)? + PREFIX.len()..];
+    rest.get(..3)?.parse().ok()
+}
+
+/// Seconds to wait from a "Please try again in 1.5s" hint, rounded up.
+fn retry_after(message: &str) -> Option<u64> {
+    const HINT: &str = "try again in ";
+    let lower = message.to_ascii_lowercase();
+    let rest = &lower[lower.find(HINT)? + HINT.len()..];
+    let digits = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(rest.len());
+    let value: f64 = rest[..digits].parse().ok()?;
+    let unit = rest[digits..].trim_start();
+    let secs = if unit.starts_with("ms") {
+        value / 1000.0
+    } else if unit.starts_with('s') {
+        value
+    } else if unit.starts_with('m') {
+        value * 60.0
+    } else {
+        return None;
+    };
+    Some((secs.ceil() as u64).max(1))
+}
+
+fn context_length_exceeded(message: &str) -> ProxyError {
+    invalid_request(message).with_code("context_length_exceeded")
+}
+
+fn invalid_request(message: &str) -> ProxyError {
+    ProxyError::new(StatusCode::BAD_REQUEST, message, "invalid_request_error")
+}
+
+fn unauthorized(message: &str) -> ProxyError {
+    ProxyError::new(StatusCode::UNAUTHORIZED, message, "invalid_request_error")
+        .with_code("invalid_api_key")
+}
+
+fn rate_limited(message: &str) -> ProxyError {
+    ProxyError::new(StatusCode::TOO_MANY_REQUESTS, message, "requests")
+        .with_code("rate_limit_exceeded")
+}
+
+fn quota_exceeded(message: &str) -> ProxyError {
+    ProxyError::new(StatusCode::TOO_MANY_REQUESTS, message, "insufficient_quota")
+        .with_code("insufficient_quota")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn classified(
+        message: &str,
+        info: Option<CodexErrorInfo>,
+    ) -> (StatusCode, Option<&'static str>, Option<u64>) {
+        let error = turn_error(&ErrorEvent {
+            message: message.to_string(),
+            codex_error_info: info,
+        });
+        (error.status, error.code, error.retry_after)
+    }
+
+    #[test]
+    fn failures_map_to_openai_statuses() {
+        assert_eq!(
+            classified("too long", Some(CodexErrorInfo::ContextWindowExceeded)),
+            (
+                StatusCode::BAD_REQUEST,
+                Some("context_length_exceeded"),
+                None
+            )
+        );
+        assert_eq!(
+            classified(
+                "Rate limit reached. Please try again in 1.2s.",
+                Some(CodexErrorInfo::ResponseTooManyFailedAttempts {
+                    http_status_code: Some(429)
+                })
+            ),
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                Some("rate_limit_exceeded"),
+                Some(2)
+            )
+        );
+        assert_eq!(
+            classified(
+                r#"unexpected status 400 Bad Request: {"error": {"type": "invalid_request_error", "code": "context_length_exceeded"}}, url: http://x"#,
+                Some(CodexErrorInfo::Other)
+            ),
+            (
+                StatusCode::BAD_REQUEST,
+                Some("context_length_exceeded"),
+                None
+            )
+        );
+        assert_eq!(
+            classified("unexpected status 401 Unauthorized: expired", None),
+            (StatusCode::UNAUTHORIZED, Some("invalid_api_key"), None)
+        );
+        assert_eq!(
+            classified("denied", Some(CodexErrorInfo::SandboxError)),
+            (StatusCode::FORBIDDEN, Some("sandbox_denied"), None)
+        );
+        assert_eq!(
+            classified("boom", Some(CodexErrorInfo::InternalServerError)),
+            (StatusCode::INTERNAL_SERVER_ERROR, None, None)
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! The provider's rate limits, as reported with its responses, passed on to
+//! clients so their backoff logic works as it does against OpenAI.
+//!
+//! Providers report each window as the share used, not as counts, so the
+//! OpenAI-style headers read as a limit of 100 with the percent left as
+//! what remains: the short (primary) window stands in for requests and the
+//! long (secondary) one for tokens. The provider's own `x-codex-*` headers
+//! are passed on as well. Limits the proxy enforces itself take precedence.
+
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::HeaderMap;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_LENGTH;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::header::RETRY_AFTER;
+use axum::response::Response;
+use codex_protocol::protocol::RateLimitSnapshot;
+use codex_protocol::protocol::RateLimitWindow;
+
+use crate::now_ts;
+use crate::rate_limit::format_reset;
+
+/// Error bodies larger than this are passed on without the rate limits.
+const MAX_ERROR_BODY_BYTES: usize = 64 * 1024;
+
+/// Adds `snapshot` to a buffered response: as headers, and to the error
+/// object of a 429 along with a `Retry-After` when a window is used up.
+/// Streams have sent their headers before the provider reports its limits,
+/// so they are left alone.
+pub(crate) async fn apply(response: Response, snapshot: &RateLimitSnapshot) -> Response {
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_json {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    set_headers(&mut parts.headers, snapshot);
+    if parts.status != StatusCode::TOO_MANY_REQUESTS {
+        return Response::from_parts(parts, body);
+    }
+    if !parts.headers.contains_key(RETRY_AFTER)
+        && let Some(secs) = retry_after(snapshot)
+    {
+        parts.headers.insert(RETRY_AFTER, HeaderValue::from(secs));
+    }
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_ERROR_BODY_BYTES).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let mut json = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(json) if json["error"].is_object() => json,
+        _ => return Response::from_parts(parts, Body::from(bytes)),
+    };
+    json["error"]["rate_limits"] = serde_json::to_value(snapshot).unwrap_or_default();
+    let bytes = json.to_string();
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// Sets the headers for `snapshot`, keeping any the proxy already set.
+pub(crate) fn set_headers(headers: &mut HeaderMap, snapshot: &RateLimitSnapshot) {
+    let windows = [
+        ("primary", "requests", &snapshot.primary),
+        ("secondary", "tokens", &snapshot.secondary),
+    ];
+    for (window_name, limit_name, window) in windows {
+        let Some(window) = window else {
+            continue;
+        };
+        let remaining = (100.0 - window.used_percent).clamp(0.0, 100.0).floor() as u64;
+        let mut values = vec![
+            (
+                format!("x-codex-{window_name}-used-percent"),
+                window.used_percent.to_string(),
+            ),
+            (format!("x-ratelimit-limit-{limit_name}"), "100".to_string()),
+            (
+                format!("x-ratelimit-remaining-{limit_name}"),
+                remaining.to_string(),
+            ),
+        ];
+        if let Some(minutes) = window.window_minutes {
+            values.push((
+                format!("x-codex-{window_name}-window-minutes"),
+                minutes.to_string(),
+            ));
+        }
+        if let Some(resets_at) = window.resets_at {
+            values.push((
+                format!("x-codex-{window_name}-reset-at"),
+                resets_at.to_string(),
+            ));
+            values.push((
+                format!("x-ratelimit-reset-{limit_name}"),
+                format_reset(resets_in(window).unwrap_or_default()),
+            ));
+        }
+        for (name, value) in values {
+            if let (Ok(name), Ok(value)) =
+                (HeaderName::try_from(name), HeaderValue::from_str(&value))
+                && !headers.contains_key(&name)
+            {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+/// Seconds until the soonest used-up window resets.
+fn retry_after(snapshot: &RateLimitSnapshot) -> Option<u64> {
+    [&snapshot.primary, &snapshot.secondary]
+        .into_iter()
+        .flatten()
+        .filter(|window| window.used_percent >= 100.0)
+        .filter_map(resets_in)
+        .min()
+        .map(|wait| wait.as_secs().max(1))
+}
+
+fn resets_in(window: &RateLimitWindow) -> Option<Duration> {
+    let resets_at = u64::try_from(window.resets_at?).ok()?;
+    Some(Duration::from_secs(resets_at.saturating_sub(now_ts())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn limits_become_headers_and_join_429_bodies() {
+        let snapshot = RateLimitSnapshot {
+            primary: Some(RateLimitWindow {
+                used_percent: 100.0,
+                window_minutes: Some(300),
+                resets_at: Some((now_ts() + 90) as i64),
+            }),
+            secondary: Some(RateLimitWindow {
+                used_percent: 37.5,
+                window_minutes: None,
+                resets_at: None,
+            }),
+            credits: None,
+            plan_type: None,
+        };
+        let mut response = crate::json_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            serde_json::json!({ "error": { "message": "slow down" } }).to_string(),
+        );
+        response
+            .headers_mut()
+            .insert("x-ratelimit-limit-tokens", HeaderValue::from(5000));
+
+        let response = apply(response, &snapshot).await;
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        assert_eq!(
+            header("x-ratelimit-remaining-requests").as_deref(),
+            Some("0")
+        );
+        assert_eq!(
+            header("x-codex-primary-window-minutes").as_deref(),
+            Some("300")
+        );
+        assert_eq!(
+            header("x-ratelimit-remaining-tokens").as_deref(),
+            Some("62")
+        );
+        assert_eq!(header("x-ratelimit-limit-tokens").as_deref(), Some("5000"));
+        let retry_after: u64 = header("retry-after").expect("retry-after").parse().unwrap();
+        assert!((89..=90).contains(&retry_after), "{retry_after}");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert_eq!(body["error"]["message"], "slow down");
+        assert_eq!(
+            body["error"]["rate_limits"]["secondary"]["used_percent"],
+            37.5
+        );
+    }
+}
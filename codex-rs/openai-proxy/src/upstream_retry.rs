@@ -0,0 +1,121 @@
+//! Retries of forward-mode requests that fail for transient reasons —
+//! dropped connections and 5xx answers — before any output reached the
+//! client, with exponential backoff and jitter.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use codex_core::error::CodexErr;
+use rand::Rng;
+
+use crate::proxy_config;
+
+/// Attempts per route target unless `CODEX_OPENAI_PROXY_UPSTREAM_MAX_ATTEMPTS`
+/// says otherwise.
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry unless
+/// `CODEX_OPENAI_PROXY_UPSTREAM_RETRY_BACKOFF_MS` says otherwise; it doubles
+/// with each further one.
+const DEFAULT_BACKOFF_MS: u64 = 250;
+
+/// Longest delay between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// How often a request is tried against one target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RetryPolicy {
+    /// Attempts in all, so 1 means no retries.
+    pub(crate) max_attempts: u32,
+    pub(crate) backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            backoff: Duration::from_millis(DEFAULT_BACKOFF_MS),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        let mut policy = Self::default();
+        if let Ok(value) = proxy_config::var("CODEX_OPENAI_PROXY_UPSTREAM_MAX_ATTEMPTS") {
+            policy.max_attempts = value
+                .parse::<u32>()
+                .context("parse CODEX_OPENAI_PROXY_UPSTREAM_MAX_ATTEMPTS")?
+                .max(1);
+        }
+        if let Ok(value) = proxy_config::var("CODEX_OPENAI_PROXY_UPSTREAM_RETRY_BACKOFF_MS") {
+            policy.backoff = Duration::from_millis(
+                value
+                    .parse::<u64>()
+                    .context("parse CODEX_OPENAI_PROXY_UPSTREAM_RETRY_BACKOFF_MS")?,
+            );
+        }
+        Ok(policy)
+    }
+
+    /// Whether a request that failed with `err` on attempt `attempt`
+    /// (counting from 1) is tried again.
+    pub(crate) fn should_retry(&self, attempt: u32, err: &CodexErr) -> bool {
+        attempt < self.max_attempts && is_transient(err)
+    }
+
+    /// How long to wait after attempt `attempt` failed: the backoff doubled
+    /// per earlier retry, capped, and scaled by a random 50–100%.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let exp = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let base = self.backoff.saturating_mul(exp).min(MAX_BACKOFF);
+        base.mul_f64(rand::rng().random_range(0.5..=1.0))
+    }
+}
+
+/// Failures another attempt may not hit: the connection dropping and the
+/// provider answering with a server error.
+pub(crate) fn is_transient(err: &CodexErr) -> bool {
+    match err {
+        CodexErr::Stream(..)
+        | CodexErr::ConnectionFailed(_)
+        | CodexErr::InternalServerError
+        | CodexErr::Io(_) => true,
+        _ => err
+            .http_status_code_value()
+            .is_some_and(|code| (500..600).contains(&code)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn only_transient_failures_are_retried_with_growing_delays() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(100),
+        };
+        let reset = CodexErr::Stream("connection reset".to_string(), None);
+        assert!(policy.should_retry(1, &reset));
+        assert!(policy.should_retry(2, &CodexErr::InternalServerError));
+        assert!(!policy.should_retry(3, &reset));
+        assert!(!policy.should_retry(1, &CodexErr::QuotaExceeded));
+        assert!(!policy.should_retry(1, &CodexErr::ContextWindowExceeded));
+
+        for (attempt, max_ms) in [(1, 100), (2, 200), (3, 400), (20, 10_000)] {
+            let delay = policy.delay(attempt);
+            assert!(
+                delay <= Duration::from_millis(max_ms),
+                "{attempt}: {delay:?}"
+            );
+            assert!(
+                delay >= Duration::from_millis(max_ms / 2),
+                "{attempt}: {delay:?}"
+            );
+        }
+        assert_eq!(RetryPolicy::default().max_attempts, DEFAULT_MAX_ATTEMPTS);
+    }
+}
@@ -0,0 +1,169 @@
+//! `GET /status` in forward mode: how each provider requests are routed to
+//! has been doing — latency of recent requests, the last failure and its
+//! circuit — and when the login was last refreshed, so operators and clients
+//! can route around a provider in trouble.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::circuit_breaker::CircuitBreakers;
+use crate::circuit_breaker::CircuitStatus;
+use crate::now_ts;
+
+/// Successful requests per provider the latency percentiles are taken over.
+const LATENCY_WINDOW: usize = 100;
+
+#[derive(Debug, Default)]
+struct Health {
+    /// Time to first output of the most recent successful requests.
+    latencies: VecDeque<Duration>,
+    last_success_at: Option<u64>,
+    last_failure_at: Option<u64>,
+    last_error: Option<String>,
+    /// Whether the most recent request failed.
+    failing: bool,
+}
+
+/// Recent outcomes of the requests sent to each provider.
+#[derive(Debug, Default)]
+pub(crate) struct ProviderStats {
+    providers: Mutex<HashMap<String, Health>>,
+}
+
+/// Latency percentiles in milliseconds.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct Latency {
+    samples: usize,
+    p50_ms: Option<u64>,
+    p90_ms: Option<u64>,
+    p99_ms: Option<u64>,
+}
+
+/// One provider's entry in `GET /status`.
+#[derive(Debug, Serialize)]
+pub(crate) struct ProviderStatus {
+    /// `healthy`, `degraded` (its last request failed), `down` (its circuit
+    /// is open) or `unknown` (no requests yet).
+    pub(crate) health: &'static str,
+    latency: Latency,
+    last_success_at: Option<u64>,
+    last_failure_at: Option<u64>,
+    last_error: Option<String>,
+    circuit: CircuitStatus,
+}
+
+impl ProviderStats {
+    /// Records a request to `provider` that produced output after `latency`.
+    pub(crate) fn record_success(&self, provider: &str, latency: Duration) {
+        let Ok(mut providers) = self.providers.lock() else {
+            return;
+        };
+        let health = providers.entry(provider.to_string()).or_default();
+        if health.latencies.len() == LATENCY_WINDOW {
+            health.latencies.pop_front();
+        }
+        health.latencies.push_back(latency);
+        health.last_success_at = Some(now_ts());
+        health.failing = false;
+    }
+
+    /// Records a request to `provider` that failed with `error`.
+    pub(crate) fn record_failure(&self, provider: &str, error: &str) {
+        let Ok(mut providers) = self.providers.lock() else {
+            return;
+        };
+        let health = providers.entry(provider.to_string()).or_default();
+        health.last_failure_at = Some(now_ts());
+        health.last_error = Some(error.to_string());
+        health.failing = true;
+    }
+
+    /// `provider`'s status, with its circuit from `breakers`.
+    pub(crate) fn status(
+        &self,
+        provider: &str,
+        breakers: &CircuitBreakers,
+        now: Instant,
+    ) -> ProviderStatus {
+        let circuit = breakers.status(provider, now);
+        let providers = self.providers.lock().ok();
+        let health = providers
+            .as_ref()
+            .and_then(|providers| providers.get(provider));
+        let mut latencies: Vec<Duration> = health
+            .map(|health| health.latencies.iter().copied().collect())
+            .unwrap_or_default();
+        latencies.sort();
+        let last_success_at = health.and_then(|health| health.last_success_at);
+        let last_failure_at = health.and_then(|health| health.last_failure_at);
+        let health_label = match health {
+            _ if circuit.state != "closed" => "down",
+            None => "unknown",
+            Some(health) if health.failing => "degraded",
+            Some(_) => "healthy",
+        };
+        ProviderStatus {
+            health: health_label,
+            latency: Latency {
+                samples: latencies.len(),
+                p50_ms: percentile(&latencies, 50),
+                p90_ms: percentile(&latencies, 90),
+                p99_ms: percentile(&latencies, 99),
+            },
+            last_success_at,
+            last_failure_at,
+            last_error: health.and_then(|health| health.last_error.clone()),
+            circuit,
+        }
+    }
+}
+
+/// The nearest-rank `p`th percentile of `sorted`, in milliseconds.
+fn percentile(sorted: &[Duration], p: usize) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    Some(sorted[rank - 1].as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit_breaker::BreakerSettings;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn status_reports_latency_percentiles_and_health() {
+        let stats = ProviderStats::default();
+        let breakers = CircuitBreakers::new(BreakerSettings::default());
+        let now = Instant::now();
+        assert_eq!(stats.status("openai", &breakers, now).health, "unknown");
+
+        for ms in 1..=100 {
+            stats.record_success("openai", Duration::from_millis(ms));
+        }
+        let status = stats.status("openai", &breakers, now);
+        assert_eq!(status.health, "healthy");
+        assert_eq!(
+            status.latency,
+            Latency {
+                samples: 100,
+                p50_ms: Some(50),
+                p90_ms: Some(90),
+                p99_ms: Some(99),
+            }
+        );
+
+        stats.record_failure("azure", "stream disconnected");
+        let status = stats.status("azure", &breakers, now);
+        assert_eq!(status.health, "degraded");
+        assert_eq!(status.latency.p50_ms, None);
+        assert_eq!(status.last_error.as_deref(), Some("stream disconnected"));
+    }
+}
@@ -0,0 +1,77 @@
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use serde::Deserialize;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::json_response;
+use crate::now_ts;
+use crate::rate_limit::api_key;
+
+/// Who a request's usage is charged to: its tenant, otherwise a fingerprint of
+/// its API key (the first 12 hex digits of the key's SHA-256), otherwise
+/// `anonymous`.
+pub(crate) fn usage_owner(state: &AppState, headers: &HeaderMap) -> String {
+    if let Some(tenant) = &state.tenant {
+        return tenant.to_string();
+    }
+    match api_key(headers) {
+        Some(key) => format!("key:{}", key_fingerprint(key)),
+        None => "anonymous".to_string(),
+    }
+}
+
+/// The first 12 hex digits of the key's SHA-256, safe to show and log.
+pub(crate) fn key_fingerprint(key: &str) -> String {
+    let digest = format!("{:x}", Sha256::digest(key.as_bytes()));
+    digest[..12].to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct UsageQuery {
+    #[serde(default)]
+    from: Option<u64>,
+    #[serde(default)]
+    to: Option<u64>,
+}
+
+/// `GET /admin/usage?from=&to=`: turns and tokens per tenant (or API key)
+/// with a per-model breakdown, and a per-user one for requests that named
+/// their end user. `from` and `to` are unix seconds and default
+/// to all recorded usage; a tenant's own key only sees that tenant's usage.
+pub(crate) async fn handle_usage(
+    State(state): State<AppState>,
+    Query(query): Query<UsageQuery>,
+) -> Response {
+    let from = query.from.unwrap_or(0);
+    let to = query.to.unwrap_or_else(|| now_ts() + 1);
+    if from > to {
+        return ProxyError::new(
+            StatusCode::BAD_REQUEST,
+            "from must not be after to",
+            "invalid_request_error",
+        )
+        .into_response();
+    }
+    let owner = state.tenant.as_deref().map(str::to_string);
+    let data = match state.ledger.usage(from, to, owner).await {
+        Ok(data) => data,
+        Err(e) => return e.into_response(),
+    };
+    json_response(
+        StatusCode::OK,
+        serde_json::json!({
+            "object": "usage_report",
+            "from": from,
+            "to": to,
+            "data": data,
+        })
+        .to_string(),
+    )
+}
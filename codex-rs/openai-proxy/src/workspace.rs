@@ -0,0 +1,186 @@
+use std::env;
+use std::path::Path;
+use std::path::PathBuf;
+
+use axum::http::StatusCode;
+use tracing::info;
+use tracing::warn;
+
+use crate::ProxyError;
+
+/// What to do with a requested `cwd` outside every workspace root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutsideRootPolicy {
+    /// Fail the request with 403.
+    Reject,
+    /// Run the turn in the default workspace instead.
+    Clamp,
+}
+
+impl OutsideRootPolicy {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reject" => Some(Self::Reject),
+            "clamp" => Some(Self::Clamp),
+            _ => None,
+        }
+    }
+}
+
+/// Decides which directory a turn runs in. Requests may pick their own `cwd`,
+/// but only inside one of the configured workspace roots.
+pub(crate) struct WorkspacePolicy {
+    /// Canonicalized roots; empty means per-request `cwd` is not allowed.
+    roots: Vec<PathBuf>,
+    /// Used when a request names no `cwd`: the first root, or the proxy's own
+    /// working directory when no roots are configured.
+    default: PathBuf,
+    outside: OutsideRootPolicy,
+}
+
+impl WorkspacePolicy {
+    pub(crate) fn new(roots: Vec<PathBuf>, outside: OutsideRootPolicy) -> anyhow::Result<Self> {
+        let roots = roots
+            .into_iter()
+            .map(|root| {
+                root.canonicalize().map_err(|e| {
+                    anyhow::anyhow!("workspace root {} is not accessible: {e}", root.display())
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let default = match roots.first() {
+            Some(root) => root.clone(),
+            None => env::current_dir().map_err(|e| {
+                anyhow::anyhow!("cannot determine the proxy's working directory: {e}")
+            })?,
+        };
+        info!("turns without a cwd run in {}", default.display());
+        Ok(Self {
+            roots,
+            default,
+            outside,
+        })
+    }
+
+    /// Settings reported by `GET /admin/config`.
+    pub(crate) fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "roots": self.roots,
+            "default": self.default,
+            "outside": match self.outside {
+                OutsideRootPolicy::Reject => "reject",
+                OutsideRootPolicy::Clamp => "clamp",
+            },
+        })
+    }
+
+    /// Returns the directory to run a turn in. Relative paths are resolved
+    /// against the first root.
+    pub(crate) fn resolve(&self, requested: Option<&str>) -> Result<PathBuf, ProxyError> {
+        let Some(requested) = requested else {
+            return Ok(self.default.clone());
+        };
+        let Some(first_root) = self.roots.first() else {
+            return Err(ProxyError::new(
+                StatusCode::BAD_REQUEST,
+                "cwd is not accepted: no workspace roots are configured",
+                "invalid_request_error",
+            ));
+        };
+
+        let requested_path = Path::new(requested);
+        let path = if requested_path.is_absolute() {
+            requested_path.to_path_buf()
+        } else {
+            first_root.join(requested_path)
+        };
+        let path = path.canonicalize().map_err(|e| {
+            ProxyError::new(
+                StatusCode::BAD_REQUEST,
+                format!("cwd {requested} is not accessible: {e}"),
+                "invalid_request_error",
+            )
+        })?;
+        if !path.is_dir() {
+            return Err(ProxyError::new(
+                StatusCode::BAD_REQUEST,
+                format!("cwd {requested} is not a directory"),
+                "invalid_request_error",
+            ));
+        }
+        if !self.roots.iter().any(|root| path.starts_with(root)) {
+            if self.outside == OutsideRootPolicy::Clamp {
+                warn!(
+                    "cwd {requested} is outside the workspace roots; using {}",
+                    self.default.display()
+                );
+                return Ok(self.default.clone());
+            }
+            return Err(ProxyError::new(
+                StatusCode::FORBIDDEN,
+                format!("cwd {requested} is outside the allowed workspace roots"),
+                "permission_error",
+            )
+            .with_code("workspace_not_allowed"));
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn cwd_must_stay_inside_a_root() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let outside = tempfile::tempdir().expect("tempdir");
+        std::fs::create_dir(root.path().join("repo")).expect("mkdir");
+        let policy =
+            WorkspacePolicy::new(vec![root.path().to_path_buf()], OutsideRootPolicy::Reject)
+                .expect("policy");
+        let canonical_root = root.path().canonicalize().expect("canonicalize");
+
+        assert_eq!(
+            policy.resolve(Some("repo")).ok(),
+            Some(canonical_root.join("repo"))
+        );
+        assert_eq!(
+            policy
+                .resolve(Some(&outside.path().to_string_lossy()))
+                .err()
+                .map(|e| e.status),
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            policy.resolve(Some("repo/../..")).err().map(|e| e.status),
+            Some(StatusCode::FORBIDDEN)
+        );
+    }
+
+    #[test]
+    fn cwd_is_rejected_without_roots() {
+        let policy = WorkspacePolicy::new(Vec::new(), OutsideRootPolicy::Reject).expect("policy");
+        assert_eq!(
+            policy.resolve(Some("/tmp")).err().map(|e| e.status),
+            Some(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn clamp_runs_outside_requests_in_the_first_root() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let outside = tempfile::tempdir().expect("tempdir");
+        let policy =
+            WorkspacePolicy::new(vec![root.path().to_path_buf()], OutsideRootPolicy::Clamp)
+                .expect("policy");
+        let canonical_root = root.path().canonicalize().expect("canonicalize");
+
+        assert_eq!(
+            policy.resolve(Some(&outside.path().to_string_lossy())).ok(),
+            Some(canonical_root.clone())
+        );
+        assert_eq!(policy.resolve(None).ok(), Some(canonical_root));
+    }
+}
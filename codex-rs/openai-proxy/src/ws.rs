@@ -0,0 +1,634 @@
+use std::sync::Arc;
+
+use axum::Extension;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::extract::ws::Message;
+use axum::extract::ws::WebSocket;
+use axum::extract::ws::WebSocketUpgrade;
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use codex_core::CodexThread;
+use codex_core::protocol::Op;
+use codex_core::protocol::ReviewDecision;
+use codex_protocol::ThreadId;
+use codex_protocol::openai_models::ReasoningEffort;
+use codex_protocol::protocol::EventMsg;
+use futures::SinkExt;
+use futures::StreamExt;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+use tracing::info;
+use tracing::info_span;
+use tracing::warn;
+
+use crate::AppState;
+use crate::ProxyError;
+use crate::access_log::RequestContext;
+use crate::approvals;
+use crate::attachments;
+use crate::base_instructions_header;
+use crate::codex_events;
+use crate::codex_events::AgentText;
+use crate::dry_run::ProposedPatch;
+use crate::log_message;
+use crate::map_tool_call;
+use crate::mcp_servers::McpServerRequest;
+use crate::output_cap;
+use crate::output_cap::OutputCap;
+use crate::profile_header;
+use crate::profile_override;
+use crate::sandbox_mode;
+use crate::turn_setup;
+use crate::turn_setup::PreparedTurn;
+use crate::turn_setup::SubmittedTurn;
+use crate::turn_setup::TurnOptions;
+use crate::turn_slots;
+use crate::usage::usage_owner;
+
+/// Connection-wide defaults, given as query parameters on the upgrade request.
+#[derive(Debug, Deserialize)]
+pub(crate) struct WsParams {
+    model: Option<String>,
+    conversation_id: Option<String>,
+    cwd: Option<String>,
+    approval_policy: Option<String>,
+    sandbox_mode: Option<String>,
+    #[serde(default)]
+    dry_run: bool,
+    /// The client's end user, recorded for usage attribution.
+    user: Option<String>,
+}
+
+/// Messages clients send over the socket, tagged by `type`.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Starts a turn on the connection's conversation.
+    Turn(TurnMessage),
+    /// Interrupts the running turn.
+    Interrupt,
+    /// Answers an `exec_approval_request` or `apply_patch_approval_request`.
+    Approval {
+        approval_id: String,
+        decision: ReviewDecision,
+    },
+}
+
+/// A `turn` message.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+struct TurnMessage {
+    input: String,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    reasoning_effort: Option<ReasoningEffort>,
+    #[serde(default)]
+    include_reasoning: bool,
+    /// Caps the turn's answer; the tighter of this and
+    /// `CODEX_OPENAI_PROXY_MAX_OUTPUT_TOKENS` applies.
+    #[serde(default)]
+    max_output_tokens: Option<u64>,
+    /// File parts in the chat (`{"type":"file"}`) or Responses
+    /// (`{"type":"input_file"}`) shape, written into the workspace.
+    #[serde(default)]
+    attachments: Vec<serde_json::Value>,
+    /// Tool servers added to the thread when this turn starts it; see
+    /// [`crate::mcp_servers`].
+    #[serde(default)]
+    mcp_servers: Vec<McpServerRequest>,
+}
+
+/// `GET /v1/ws`: a full-duplex alternative to SSE. The connection is bound to
+/// one conversation; clients send turns, interrupts and approval decisions as
+/// JSON text frames and receive deltas, tool calls, Codex events and turn
+/// results the same way. One turn runs at a time.
+pub(crate) async fn handle_ws(
+    State(state): State<AppState>,
+    Extension(context): Extension<RequestContext>,
+    Query(params): Query<WsParams>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let priority = match turn_slots::request_priority(&headers) {
+        Ok(priority) => priority,
+        Err(e) => return e.into_response(),
+    };
+    // Checked here so a bad connection fails its upgrade; each turn is
+    // checked again as it starts.
+    let checked = approvals::approval_policy(params.approval_policy.as_deref(), true)
+        .and_then(|_| {
+            sandbox_mode::sandbox_policy(
+                params.sandbox_mode.as_deref(),
+                state.allow_danger_full_access,
+            )
+        })
+        .and_then(|_| state.workspaces.resolve(params.cwd.as_deref()));
+    if let Err(e) = checked {
+        return e.into_response();
+    }
+    let (base_instructions, profile) =
+        match (base_instructions_header(&headers), profile_header(&headers)) {
+            (Ok(instructions), Ok(profile)) => (instructions, profile),
+            (Err(e), _) | (_, Err(e)) => return e.into_response(),
+        };
+    if let Some(model) = &params.model {
+        context.set_model(model);
+    }
+
+    let session = Session {
+        usage_owner: usage_owner(&state, &headers),
+        state,
+        request_id: context.request_id().to_string(),
+        model: params.model,
+        conversation_id: params.conversation_id,
+        thread: None,
+        cwd: params.cwd,
+        approval_policy: params.approval_policy,
+        sandbox_mode: params.sandbox_mode,
+        dry_run: params.dry_run,
+        user: params.user,
+        priority,
+        base_instructions,
+        profile,
+        turn: None,
+    };
+    let span = info_span!("ws.session", request_id = %context.request_id());
+    upgrade.on_upgrade(move |socket| session.run(socket).instrument(span))
+}
+
+struct Session {
+    state: AppState,
+    request_id: String,
+    /// Who the connection's turns are charged to.
+    usage_owner: String,
+    model: Option<String>,
+    /// The conversation to continue; set once the first turn created it.
+    conversation_id: Option<String>,
+    thread: Option<(Arc<CodexThread>, ThreadId)>,
+    cwd: Option<String>,
+    approval_policy: Option<String>,
+    sandbox_mode: Option<String>,
+    dry_run: bool,
+    user: Option<String>,
+    priority: i32,
+    /// From the upgrade request's [`crate::BASE_INSTRUCTIONS_HEADER`].
+    base_instructions: Option<String>,
+    /// From the upgrade request's [`crate::PROFILE_HEADER`].
+    profile: Option<String>,
+    turn: Option<JoinHandle<()>>,
+}
+
+impl Session {
+    async fn run(mut self, socket: WebSocket) {
+        let (mut sink, mut incoming) = socket.split();
+        let (tx, mut rx) = mpsc::channel::<serde_json::Value>(64);
+        loop {
+            tokio::select! {
+                message = incoming.next() => match message {
+                    Some(Ok(Message::Text(text))) => self.handle_message(text.as_str(), &tx).await,
+                    Some(Ok(Message::Binary(_))) => {
+                        let error =
+                            self.error("messages must be JSON text frames", "invalid_message");
+                        send(&tx, error).await;
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) | None => break,
+                },
+                Some(event) = rx.recv() => {
+                    if sink.send(Message::Text(event.to_string().into())).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Nobody is listening any more, so stop burning tokens on the turn.
+        if self.turn_running()
+            && let Some((thread, thread_id)) = &self.thread
+        {
+            info!("websocket closed mid-turn; interrupting thread {thread_id}");
+            if let Err(e) = thread.submit(Op::Interrupt).await {
+                warn!("failed to interrupt thread {thread_id}: {e}");
+            }
+        }
+    }
+
+    fn turn_running(&self) -> bool {
+        self.turn.as_ref().is_some_and(|turn| !turn.is_finished())
+    }
+
+    async fn handle_message(&mut self, text: &str, tx: &mpsc::Sender<serde_json::Value>) {
+        let message = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(message) => message,
+            Err(e) => {
+                send(
+                    tx,
+                    self.error(format!("invalid message: {e}"), "invalid_message"),
+                )
+                .await;
+                return;
+            }
+        };
+        match message {
+            ClientMessage::Turn(message) => {
+                if let Err(e) = self.start_turn(message, tx).await {
+                    send(tx, self.proxy_error(e)).await;
+                }
+            }
+            ClientMessage::Interrupt => {
+                let thread = self.thread.as_ref().filter(|_| self.turn_running());
+                let Some((thread, _)) = thread else {
+                    send(tx, self.error("no turn is running", "turn_not_running")).await;
+                    return;
+                };
+                if let Err(e) = thread.submit(Op::Interrupt).await {
+                    send(
+                        tx,
+                        self.error(format!("failed to interrupt: {e}"), "internal_error"),
+                    )
+                    .await;
+                }
+            }
+            ClientMessage::Approval {
+                approval_id,
+                decision,
+            } => {
+                let Some((thread, thread_id)) = &self.thread else {
+                    send(tx, self.error("no conversation yet", "approval_not_found")).await;
+                    return;
+                };
+                match self
+                    .state
+                    .approvals
+                    .decide(thread, *thread_id, &approval_id, decision.clone())
+                    .await
+                {
+                    Ok(()) => {
+                        send(
+                            tx,
+                            serde_json::json!({
+                                "type": "approval.submitted",
+                                "approval_id": approval_id,
+                                "decision": decision,
+                            }),
+                        )
+                        .await;
+                    }
+                    Err(e) => send(tx, self.proxy_error(e)).await,
+                }
+            }
+        }
+    }
+
+    async fn start_turn(
+        &mut self,
+        message: TurnMessage,
+        tx: &mpsc::Sender<serde_json::Value>,
+    ) -> Result<(), ProxyError> {
+        if self.turn_running() {
+            return Err(ProxyError::new(
+                axum::http::StatusCode::CONFLICT,
+                "a turn is already running on this connection",
+                "invalid_request_error",
+            )
+            .with_code("turn_already_running"));
+        }
+        let Some(model) = message.model.or_else(|| self.model.clone()) else {
+            return Err(ProxyError::new(
+                axum::http::StatusCode::BAD_REQUEST,
+                "no model given on the turn or the connection",
+                "invalid_request_error",
+            ));
+        };
+        // Per-turn, so each turn gets its own ledger row.
+        let context = RequestContext::new(self.request_id.clone())
+            .with_ledger(
+                self.state.ledger.clone(),
+                self.usage_owner.clone(),
+                "WS",
+                "/v1/ws",
+            )
+            .with_audit_log(self.state.audit_log.clone())
+            .with_turn_webhook(self.state.turn_webhook.clone(), false)
+            .with_interceptors(&self.state.interceptors)
+            .with_turn_diffs(&self.state.turn_diffs)
+            .with_model_map(&self.state.model_map);
+        let options = TurnOptions {
+            model: model.clone(),
+            text: message.input,
+            conversation_id: self.conversation_id.clone(),
+            thread_overrides: profile_override(self.profile.as_deref())
+                .into_iter()
+                .collect(),
+            base_instructions: self.base_instructions.clone(),
+            mcp_servers: message.mcp_servers,
+            user: self.user.clone(),
+            approval_policy: self.approval_policy.clone(),
+            answerable: true,
+            sandbox_mode: self.sandbox_mode.clone(),
+            dry_run: self.dry_run,
+            cwd: self.cwd.clone(),
+            effort: message.reasoning_effort,
+            output_schema: None,
+            image_urls: Vec::new(),
+            attachments: attachments::collect_attachments(&serde_json::Value::Array(
+                message.attachments,
+            )),
+            priority: self.priority,
+            interrupt_previous_turn: None,
+        };
+        let prepared = options.prepare(&self.state, &context).await?;
+        self.model = Some(model.clone());
+
+        let (thread, thread_id) = prepared.thread(&self.state, &context).await?;
+        if self.conversation_id.is_none() {
+            send(
+                tx,
+                serde_json::json!({
+                    "type": "session.created",
+                    "conversation_id": thread_id.to_string(),
+                }),
+            )
+            .await;
+        }
+        self.conversation_id = Some(thread_id.to_string());
+        self.thread = Some((thread.clone(), thread_id));
+
+        let turn = Turn {
+            state: self.state.clone(),
+            context,
+            turn_id: uuid::Uuid::new_v4().to_string(),
+            model,
+            include_reasoning: message.include_reasoning,
+            max_output_tokens: output_cap::effective(
+                self.state.max_output_tokens.get(),
+                message.max_output_tokens,
+            ),
+            tx: tx.clone(),
+        };
+        let run = turn.run((thread, thread_id), prepared);
+        self.turn = Some(tokio::spawn(run.in_current_span()));
+        Ok(())
+    }
+
+    fn error(&self, message: impl Into<String>, code: &str) -> serde_json::Value {
+        error_event(message.into(), Some(code), &self.request_id)
+    }
+
+    fn proxy_error(&self, error: ProxyError) -> serde_json::Value {
+        error_event(error.message, error.code, &self.request_id)
+    }
+}
+
+/// One turn run on behalf of a connection, streaming its events to `tx`.
+struct Turn {
+    state: AppState,
+    /// Per-turn, so each turn gets its own ledger row.
+    context: RequestContext,
+    /// The id the client knows the turn by.
+    turn_id: String,
+    model: String,
+    include_reasoning: bool,
+    max_output_tokens: Option<u64>,
+    tx: mpsc::Sender<serde_json::Value>,
+}
+
+impl Turn {
+    async fn run(self, thread: (Arc<CodexThread>, ThreadId), prepared: PreparedTurn) {
+        self.context.set_model(&self.model);
+        self.context.set_response_id(&self.turn_id);
+        let turn = match turn_setup::submit(&self.state, &self.context, thread, prepared).await {
+            Ok(turn) => turn,
+            Err(e) => {
+                self.context.set_status(e.status.as_u16());
+                let mut event = error_event(e.message, e.code, self.context.request_id());
+                event["turn_id"] = serde_json::Value::String(self.turn_id.clone());
+                send(&self.tx, event).await;
+                return;
+            }
+        };
+        let span = turn.span();
+        self.handle_events(&turn).instrument(span).await;
+    }
+
+    async fn handle_events(&self, turn: &SubmittedTurn) {
+        self.emit("turn.started", serde_json::json!({})).await;
+        log_message(
+            serde_json::json!({
+                "type": "ws_turn_submitted",
+                "conversation_id": turn.thread_id.to_string(),
+                "turn_id": self.turn_id,
+            })
+            .to_string(),
+        );
+
+        let mut tool_seen = false;
+        let mut agent_text = AgentText::default();
+        let mut output_cap = OutputCap::new(self.max_output_tokens);
+        let mut message = String::new();
+        loop {
+            let mut ev = match turn.thread.next_event().await {
+                Ok(ev) => ev,
+                Err(e) => {
+                    self.fail(format!("event error: {e}")).await;
+                    break;
+                }
+            };
+            if ev.id != turn.submission_id {
+                continue;
+            }
+            if !self.context.intercept_event(&mut ev.msg) {
+                continue;
+            }
+            // Past the output cap wait out the interrupt, counting what the
+            // turn used, then complete it with what was delivered.
+            if output_cap.reached() {
+                if let EventMsg::TokenCount(count) = &ev.msg
+                    && let Some(info) = &count.info
+                {
+                    self.context.add_tokens(info.last_token_usage.total_tokens);
+                }
+                if output_cap::ends_turn(&ev.msg) {
+                    self.context.set_status(200);
+                    self.emit(
+                        "turn.completed",
+                        serde_json::json!({ "finish_reason": "length", "message": message }),
+                    )
+                    .await;
+                    break;
+                }
+                continue;
+            }
+            if let Some(text) = agent_text.text(&ev.msg) {
+                let text = output_cap.take(&text);
+                if !text.is_empty() {
+                    message.push_str(text);
+                    self.emit("delta", serde_json::json!({ "delta": text }))
+                        .await;
+                }
+                if output_cap.reached() {
+                    output_cap::interrupt(&turn.thread).await;
+                    continue;
+                }
+            }
+            self.context.record_event(&ev.msg);
+            match ev.msg {
+                EventMsg::AgentReasoningDelta(d) if self.include_reasoning => {
+                    self.emit("reasoning.delta", serde_json::json!({ "delta": d.delta }))
+                        .await;
+                }
+                EventMsg::RawResponseItem(raw) => {
+                    if let Some(call) = map_tool_call(&raw.item) {
+                        tool_seen = true;
+                        self.emit("tool_call", serde_json::json!({ "tool_call": call }))
+                            .await;
+                    }
+                }
+                EventMsg::TurnComplete(done) => {
+                    self.context.set_status(200);
+                    let finish_reason = if tool_seen { "tool_calls" } else { "stop" };
+                    self.emit(
+                        "turn.completed",
+                        serde_json::json!({
+                            "finish_reason": finish_reason,
+                            "message": done.last_agent_message,
+                        }),
+                    )
+                    .await;
+                    break;
+                }
+                EventMsg::TurnAborted(abort) => {
+                    // Interrupts come from the client, as with nginx's 499.
+                    self.context.set_status(499);
+                    self.emit(
+                        "turn.aborted",
+                        serde_json::json!({ "reason": format!("{:?}", abort.reason) }),
+                    )
+                    .await;
+                    break;
+                }
+                EventMsg::Error(err) => {
+                    self.fail(format!("Codex error: {}", err.message)).await;
+                    break;
+                }
+                EventMsg::TokenCount(count) => {
+                    if let Some(info) = count.info {
+                        self.context.add_tokens(info.last_token_usage.total_tokens);
+                    }
+                }
+                msg @ EventMsg::PatchApplyEnd(_) => {
+                    if let Some(event) = codex_events::patch_event(&msg) {
+                        send(&self.tx, event.into_event()).await;
+                    }
+                }
+                msg @ EventMsg::PlanUpdate(_) => {
+                    if let Some(event) = codex_events::plan_event(&msg) {
+                        send(&self.tx, event.into_event()).await;
+                    }
+                }
+                msg @ (EventMsg::ExecCommandBegin(_) | EventMsg::ExecCommandEnd(_)) => {
+                    if let Some(event) = codex_events::exec_event(&msg) {
+                        send(&self.tx, event.into_event()).await;
+                    }
+                }
+                msg @ (EventMsg::ExecApprovalRequest(_)
+                | EventMsg::ApplyPatchApprovalRequest(_))
+                    if turn.dry_run =>
+                {
+                    if let Some(patch) = ProposedPatch::from_event(&msg) {
+                        send(&self.tx, patch.event().into_event()).await;
+                    }
+                    self.state
+                        .approvals
+                        .deny_dry_run(&turn.thread, turn.thread_id, &ev.id, &msg)
+                        .await;
+                }
+                msg @ (EventMsg::ExecApprovalRequest(_)
+                | EventMsg::ApplyPatchApprovalRequest(_)) => {
+                    if let Some(request) = self
+                        .state
+                        .approvals
+                        .handle(
+                            &turn.thread,
+                            turn.thread_id,
+                            &ev.id,
+                            turn.approval_policy,
+                            &msg,
+                        )
+                        .await
+                    {
+                        send(&self.tx, request.into_event()).await;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.state.approvals.clear_turn(&turn.submission_id);
+    }
+
+    /// Sends a turn-scoped event of type `kind` carrying `fields`.
+    async fn emit(&self, kind: &str, fields: serde_json::Value) {
+        let mut event = serde_json::json!({ "type": kind, "turn_id": self.turn_id });
+        if let (Some(event), serde_json::Value::Object(fields)) = (event.as_object_mut(), fields) {
+            event.extend(fields);
+        }
+        send(&self.tx, event).await;
+    }
+
+    async fn fail(&self, message: String) {
+        self.context.set_status(500);
+        let mut event = error_event(message, None, self.context.request_id());
+        event["turn_id"] = serde_json::Value::String(self.turn_id.clone());
+        send(&self.tx, event).await;
+    }
+}
+
+fn error_event(message: String, code: Option<&str>, request_id: &str) -> serde_json::Value {
+    serde_json::json!({
+        "type": "error",
+        "message": message,
+        "code": code,
+        "request_id": request_id,
+    })
+}
+
+/// Queues an event for the socket; dropped if the client already left.
+async fn send(tx: &mpsc::Sender<serde_json::Value>, event: serde_json::Value) {
+    let _ = tx.send(event).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn client_messages_are_tagged_by_type() {
+        assert_eq!(
+            serde_json::from_str::<ClientMessage>(r#"{"type":"turn","input":"hi"}"#).ok(),
+            Some(ClientMessage::Turn(TurnMessage {
+                input: "hi".to_string(),
+                ..TurnMessage::default()
+            }))
+        );
+        assert_eq!(
+            serde_json::from_str::<ClientMessage>(r#"{"type":"interrupt"}"#).ok(),
+            Some(ClientMessage::Interrupt)
+        );
+        assert_eq!(
+            serde_json::from_str::<ClientMessage>(
+                r#"{"type":"approval","approval_id":"call_1","decision":"approved"}"#
+            )
+            .ok(),
+            Some(ClientMessage::Approval {
+                approval_id: "call_1".to_string(),
+                decision: ReviewDecision::Approved,
+            })
+        );
+        assert!(serde_json::from_str::<ClientMessage>(r#"{"type":"resume"}"#).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+// Polls the /admin JSON endpoints and renders them. Tokens per minute are
+// bucketed from the request ledger, so the graph only covers the last
+// 1000 requests of the hour.
+const STORAGE_KEY = 'codex-proxy-admin';
+const REFRESH_MS = 5000;
+const WINDOW_SECS = 3600;
+
+// State
+let refreshTimer = null;
+
+// DOM Elements
+const apiKeyInput = document.getElementById('api-key');
+const refreshBtn = document.getElementById('refresh-btn');
+const autoRefreshInput = document.getElementById('auto-refresh');
+const statusEl = document.getElementById('status');
+const tokenChart = document.getElementById('token-chart');
+
+// Initialize
+function init() {
+    apiKeyInput.value = localStorage.getItem(STORAGE_KEY) || '';
+    apiKeyInput.addEventListener('change', () => {
+        localStorage.setItem(STORAGE_KEY, apiKeyInput.value);
+        refresh();
+    });
+    refreshBtn.addEventListener('click', refresh);
+    autoRefreshInput.addEventListener('change', scheduleRefresh);
+    window.addEventListener('resize', () => drawTokenChart(lastRequests));
+    refresh();
+    scheduleRefresh();
+}
+
+function scheduleRefresh() {
+    clearInterval(refreshTimer);
+    refreshTimer = autoRefreshInput.checked ? setInterval(refresh, REFRESH_MS) : null;
+}
+
+function headers() {
+    const result = { 'Content-Type': 'application/json' };
+    if (apiKeyInput.value) {
+        result['Authorization'] = 'Bearer ' + apiKeyInput.value;
+    }
+    return result;
+}
+
+async function getJson(path) {
+    const response = await fetch(path, { headers: headers() });
+    const body = await response.json();
+    if (!response.ok) {
+        throw new Error((body.error && body.error.message) || response.statusText);
+    }
+    return body;
+}
+
+// Refresh
+let lastRequests = [];
+
+async function refresh() {
+    const now = Math.floor(Date.now() / 1000);
+    const from = now - WINDOW_SECS;
+    const results = await Promise.allSettled([
+        getJson('/admin/threads'),
+        getJson('/admin/turns'),
+        getJson('/admin/streams'),
+        getJson('/admin/rate-limits'),
+        getJson(`/admin/requests?from=${from}&limit=1000`),
+        getJson(`/admin/usage?from=${from}`),
+    ]);
+    const [threads, turns, streams, rateLimits, requests, usage] =
+        results.map((result) => (result.status === 'fulfilled' ? result.value : null));
+    const failed = results.find((result) => result.status === 'rejected');
+    statusEl.textContent = failed ? 'Refresh failed: ' + failed.reason.message : '';
+
+    if (threads) renderThreads(threads.data || []);
+    if (turns || streams) renderTurns(turns, streams);
+    if (rateLimits) renderRateLimits(rateLimits);
+    if (requests) {
+        lastRequests = requests.data || [];
+        renderErrors(lastRequests);
+        drawTokenChart(lastRequests);
+        setStat('token-count', lastRequests.reduce((sum, r) => sum + (r.tokens || 0), 0));
+    }
+    if (usage) renderUsage(usage.data || []);
+    setStat('updated-at', new Date().toLocaleTimeString());
+}
+
+// Rendering helpers
+function setStat(id, value, alert) {
+    const el = document.getElementById(id);
+    el.textContent = value;
+    el.classList.toggle('alert', !!alert);
+}
+
+function cell(text, className) {
+    const td = document.createElement('td');
+    td.textContent = text === null || text === undefined ? '-' : text;
+    if (className) td.className = className;
+    return td;
+}
+
+function fillTable(id, rows, columns, empty) {
+    const tbody = document.getElementById(id);
+    tbody.innerHTML = '';
+    if (rows.length === 0) {
+        const tr = document.createElement('tr');
+        const td = cell(empty, 'empty');
+        td.colSpan = columns;
+        tr.appendChild(td);
+        tbody.appendChild(tr);
+        return;
+    }
+    for (const row of rows) {
+        const tr = document.createElement('tr');
+        for (const item of row) {
+            tr.appendChild(item instanceof Node ? wrap(item) : cell(item));
+        }
+        tbody.appendChild(tr);
+    }
+}
+
+function wrap(node) {
+    const td = document.createElement('td');
+    td.appendChild(node);
+    return td;
+}
+
+function fillMetrics(id, entries) {
+    const dl = document.getElementById(id);
+    dl.innerHTML = '';
+    for (const [label, value] of entries) {
+        const dt = document.createElement('dt');
+        dt.textContent = label;
+        const dd = document.createElement('dd');
+        dd.textContent = value === null || value === undefined ? 'unlimited' : value;
+        dl.append(dt, dd);
+    }
+}
+
+function ago(seconds) {
+    if (seconds < 60) return `${seconds}s ago`;
+    if (seconds < 3600) return `${Math.floor(seconds / 60)}m ago`;
+    return `${Math.floor(seconds / 3600)}h ago`;
+}
+
+function badge(text, className) {
+    const span = document.createElement('span');
+    span.className = 'badge ' + (className || '');
+    span.textContent = text;
+    return span;
+}
+
+// Panels
+function renderThreads(threads) {
+    const now = Math.floor(Date.now() / 1000);
+    setStat('thread-count', threads.length);
+    fillTable('threads-body', threads.map((thread) => {
+        const abort = document.createElement('button');
+        abort.className = 'btn btn-small btn-danger';
+        abort.textContent = 'Abort';
+        abort.disabled = !thread.turn_running;
+        abort.addEventListener('click', () => abortThread(thread.id));
+        const id = document.createElement('span');
+        id.textContent = thread.id + ' ';
+        id.appendChild(thread.turn_running ? badge('busy', 'busy') : badge('idle'));
+        return [id, thread.model, ago(Math.max(0, now - thread.last_activity_at)), abort];
+    }), 4, 'No live threads');
+}
+
+async function abortThread(id) {
+    try {
+        const response = await fetch(`/admin/threads/${encodeURIComponent(id)}/abort`, {
+            method: 'POST',
+            headers: headers(),
+        });
+        if (!response.ok) {
+            const body = await response.json();
+            throw new Error((body.error && body.error.message) || response.statusText);
+        }
+    } catch (e) {
+        statusEl.textContent = 'Abort failed: ' + e.message;
+    }
+    refresh();
+}
+
+function renderTurns(turns, streams) {
+    const entries = [];
+    if (turns) {
+        setStat('running-count', turns.running);
+        setStat('queued-count', turns.queued, turns.queued > 0);
+        entries.push(
+            ['Max concurrent turns', turns.max_concurrent_turns],
+            ['Running', turns.running],
+            ['Queued', `${turns.queued} / ${turns.queue_capacity}`],
+            ['Total queued', turns.total_queued],
+            ['Total rejected', turns.total_rejected],
+            ['Avg queue wait', `${turns.avg_queue_ms} ms`],
+        );
+    }
+    if (streams) {
+        entries.push(
+            ['Open streams', streams.open_streams],
+            ['Buffered events', streams.buffered_events],
+            ['Full streams', streams.full_streams],
+            ['Blocked sends', streams.blocked_sends],
+            ['Coalesced deltas', streams.coalesced_deltas],
+            ['Dropped deltas', streams.dropped_deltas],
+        );
+    }
+    fillMetrics('turns-list', entries);
+}
+
+function renderRateLimits(status) {
+    fillMetrics('rate-limit-list', [
+        ['Requests / minute', status.requests_per_minute],
+        ['Tokens / minute', status.tokens_per_minute],
+        ['Rejected (429)', status.total_rejected],
+        ['Tracked clients', status.tracked_clients],
+    ]);
+    fillTable('rate-limit-body', (status.clients || []).map((client) => [
+        client.client,
+        client.remaining_requests,
+        client.remaining_tokens,
+        ago(client.idle_secs),
+    ]), 4, 'No rate limit clients');
+}
+
+function renderErrors(requests) {
+    const errors = requests.filter((request) => request.status >= 400);
+    setStat('error-count', errors.length, errors.length > 0);
+    fillTable('errors-body', errors.slice(0, 20).map((request) => [
+        new Date(request.at * 1000).toLocaleTimeString(),
+        badge(request.status, 'error'),
+        `${request.method} ${request.path}`,
+        request.model,
+    ]), 4, 'No errors in the last hour');
+}
+
+function renderUsage(owners) {
+    const rows = [];
+    for (const owner of owners) {
+        for (const model of owner.models || []) {
+            rows.push([owner.tenant, model.model || '-', model.turns, model.tokens]);
+        }
+    }
+    fillTable('usage-body', rows, 4, 'No usage in the last hour');
+}
+
+// Token graph: one bar per minute of the last hour.
+function drawTokenChart(requests) {
+    const minutes = WINDOW_SECS / 60;
+    const now = Math.floor(Date.now() / 1000);
+    const buckets = new Array(minutes).fill(0);
+    for (const request of requests) {
+        const index = minutes - 1 - Math.floor((now - request.at) / 60);
+        if (index >= 0 && index < minutes) {
+            buckets[index] += request.tokens || 0;
+        }
+    }
+
+    const ratio = window.devicePixelRatio || 1;
+    const width = tokenChart.clientWidth;
+    const height = tokenChart.clientHeight;
+    tokenChart.width = width * ratio;
+    tokenChart.height = height * ratio;
+    const ctx = tokenChart.getContext('2d');
+    ctx.scale(ratio, ratio);
+    ctx.clearRect(0, 0, width, height);
+
+    const max = Math.max(...buckets, 1);
+    const top = 16;
+    const bottom = height - 16;
+    const barWidth = width / minutes;
+    ctx.fillStyle = '#4ec9b0';
+    buckets.forEach((tokens, i) => {
+        const barHeight = ((bottom - top) * tokens) / max;
+        ctx.fillRect(i * barWidth + 1, bottom - barHeight, Math.max(barWidth - 2, 1), barHeight);
+    });
+
+    ctx.fillStyle = '#858585';
+    ctx.font = '11px Consolas, Monaco, monospace';
+    ctx.fillText(`max ${max} tokens/min`, 4, 11);
+    ctx.fillText('-60m', 4, height - 3);
+    ctx.textAlign = 'right';
+    ctx.fillText('now', width - 4, height - 3);
+    ctx.strokeStyle = '#3e3e42';
+    ctx.beginPath();
+    ctx.moveTo(0, bottom + 0.5);
+    ctx.lineTo(width, bottom + 0.5);
+    ctx.stroke();
+}
+
+init();
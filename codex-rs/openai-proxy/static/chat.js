@@ -0,0 +1,605 @@
+// Conversations are kept in localStorage; the proxy holds their history and
+// is addressed by conversation_id. Without /v1/responses (forward mode) the
+// page falls back to /v1/chat/completions and resends the history itself.
+const STORAGE_KEY = 'codex-proxy-chat';
+
+// State
+let conversations = [];
+let activeId = null;
+let abortController = null;
+let useChatCompletions = false;
+
+// DOM Elements
+const messagesDiv = document.getElementById('messages');
+const conversationList = document.getElementById('conversation-list');
+const modelSelect = document.getElementById('model-select');
+const apiKeyInput = document.getElementById('api-key');
+const askApprovalInput = document.getElementById('ask-approval');
+const newChatBtn = document.getElementById('new-chat-btn');
+const composer = document.getElementById('composer');
+const promptInput = document.getElementById('prompt');
+const sendBtn = document.getElementById('send-btn');
+const stopBtn = document.getElementById('stop-btn');
+const statusEl = document.getElementById('status');
+
+// Initialize
+function init() {
+    loadState();
+    setupEventListeners();
+    loadModels();
+    renderConversationList();
+    renderMessages();
+}
+
+// Setup event listeners
+function setupEventListeners() {
+    newChatBtn.addEventListener('click', () => {
+        activeId = null;
+        saveState();
+        renderConversationList();
+        renderMessages();
+        promptInput.focus();
+    });
+    composer.addEventListener('submit', (e) => {
+        e.preventDefault();
+        send();
+    });
+    promptInput.addEventListener('keydown', (e) => {
+        if (e.key === 'Enter' && !e.shiftKey && !e.isComposing) {
+            e.preventDefault();
+            send();
+        }
+    });
+    stopBtn.addEventListener('click', () => {
+        if (abortController) {
+            abortController.abort();
+        }
+    });
+    modelSelect.addEventListener('change', saveState);
+    askApprovalInput.addEventListener('change', saveState);
+    apiKeyInput.addEventListener('change', () => {
+        saveState();
+        loadModels();
+    });
+}
+
+// Persistence
+function loadState() {
+    try {
+        const saved = JSON.parse(localStorage.getItem(STORAGE_KEY) || '{}');
+        conversations = saved.conversations || [];
+        activeId = saved.activeId || null;
+        apiKeyInput.value = saved.apiKey || '';
+        askApprovalInput.checked = !!saved.askApproval;
+        modelSelect.dataset.saved = saved.model || '';
+    } catch (e) {
+        console.error('Failed to load saved chats:', e);
+    }
+}
+
+function saveState() {
+    const state = {
+        conversations,
+        activeId,
+        apiKey: apiKeyInput.value,
+        model: modelSelect.value || modelSelect.dataset.saved,
+        askApproval: askApprovalInput.checked,
+    };
+    // Rendered nodes of a streaming turn are not saved.
+    localStorage.setItem(STORAGE_KEY, JSON.stringify(state, (key, value) =>
+        key === 'node' ? undefined : value));
+}
+
+function activeConversation() {
+    return conversations.find((c) => c.id === activeId) || null;
+}
+
+function headers() {
+    const result = { 'Content-Type': 'application/json' };
+    if (apiKeyInput.value) {
+        result['Authorization'] = 'Bearer ' + apiKeyInput.value;
+    }
+    return result;
+}
+
+// Model picker
+async function loadModels() {
+    try {
+        const response = await fetch('/v1/models', { headers: headers() });
+        const body = await response.json();
+        const saved = modelSelect.value || modelSelect.dataset.saved;
+        modelSelect.innerHTML = '';
+        for (const model of body.data || []) {
+            const option = document.createElement('option');
+            option.value = model.id;
+            option.textContent = model.id;
+            modelSelect.appendChild(option);
+        }
+        if (saved && [...modelSelect.options].some((o) => o.value === saved)) {
+            modelSelect.value = saved;
+        }
+    } catch (e) {
+        setStatus('Failed to load models: ' + e.message);
+    }
+}
+
+// Conversation list
+function renderConversationList() {
+    conversationList.innerHTML = '';
+    for (const conversation of conversations) {
+        const item = document.createElement('li');
+        item.className = 'conversation-item' + (conversation.id === activeId ? ' active' : '');
+        item.title = conversation.model;
+
+        const title = document.createElement('span');
+        title.className = 'conversation-title';
+        title.textContent = conversation.title;
+        item.appendChild(title);
+
+        const remove = document.createElement('button');
+        remove.className = 'conversation-delete';
+        remove.textContent = '✕';
+        remove.title = 'Delete';
+        remove.addEventListener('click', (e) => {
+            e.stopPropagation();
+            deleteConversation(conversation);
+        });
+        item.appendChild(remove);
+
+        item.addEventListener('click', () => {
+            activeId = conversation.id;
+            saveState();
+            renderConversationList();
+            renderMessages();
+        });
+        conversationList.appendChild(item);
+    }
+}
+
+async function deleteConversation(conversation) {
+    if (conversation.conversationId) {
+        // The proxy may already have expired it; the local copy goes either way.
+        await fetch('/v1/conversations/' + conversation.conversationId, {
+            method: 'DELETE',
+            headers: headers(),
+        }).catch(() => {});
+    }
+    conversations = conversations.filter((c) => c !== conversation);
+    if (activeId === conversation.id) {
+        activeId = null;
+    }
+    saveState();
+    renderConversationList();
+    renderMessages();
+}
+
+// Messages
+function renderMessages() {
+    messagesDiv.innerHTML = '';
+    const conversation = activeConversation();
+    if (!conversation || conversation.entries.length === 0) {
+        const empty = document.createElement('div');
+        empty.className = 'empty-state';
+        empty.textContent = 'Pick a model and start typing.';
+        messagesDiv.appendChild(empty);
+        return;
+    }
+    for (const entry of conversation.entries) {
+        messagesDiv.appendChild(renderEntry(entry));
+    }
+    scrollToBottom();
+}
+
+// An entry is a chat bubble (user, assistant, error) or a collapsible
+// activity block (reasoning, tool, approval).
+function renderEntry(entry) {
+    if (entry.kind === 'user' || entry.kind === 'assistant' || entry.kind === 'error') {
+        const bubble = document.createElement('div');
+        bubble.className = 'bubble ' + entry.kind;
+        bubble.textContent = entry.text;
+        return bubble;
+    }
+
+    const block = document.createElement('details');
+    block.className = 'activity ' + entry.kind + (entry.failed ? ' failed' : '');
+    block.open = entry.kind === 'approval';
+    const summary = document.createElement('summary');
+    summary.textContent = entry.title;
+    block.appendChild(summary);
+    if (entry.text) {
+        const pre = document.createElement('pre');
+        pre.textContent = entry.text;
+        block.appendChild(pre);
+    }
+    if (entry.kind === 'approval' && !entry.decided) {
+        const actions = document.createElement('div');
+        actions.className = 'approval-actions';
+        for (const [label, decision, style] of [
+            ['Approve', 'approved', 'btn-primary'],
+            ['Deny', 'denied', 'btn-danger'],
+        ]) {
+            const button = document.createElement('button');
+            button.className = 'btn ' + style;
+            button.textContent = label;
+            button.addEventListener('click', () => decide(entry, decision));
+            actions.appendChild(button);
+        }
+        block.appendChild(actions);
+    }
+    return block;
+}
+
+// Streaming turns
+class Turn {
+    constructor(conversation) {
+        this.conversation = conversation;
+        this.assistant = null;
+        this.reasoning = null;
+        this.commands = new Map();
+    }
+
+    push(entry) {
+        this.conversation.entries.push(entry);
+        entry.node = renderEntry(entry);
+        messagesDiv.appendChild(entry.node);
+        scrollToBottom();
+        return entry;
+    }
+
+    refresh(entry) {
+        const node = renderEntry(entry);
+        entry.node.replaceWith(node);
+        entry.node = node;
+        scrollToBottom();
+    }
+
+    text(delta) {
+        if (!this.assistant) {
+            this.assistant = this.push({ kind: 'assistant', text: '' });
+            this.assistant.node.classList.add('streaming');
+        }
+        this.assistant.text += delta;
+        this.assistant.node.textContent = this.assistant.text;
+        scrollToBottom();
+    }
+
+    endMessage(text) {
+        if (!this.assistant && text) {
+            this.text(text);
+        }
+        if (this.assistant) {
+            this.assistant.node.classList.remove('streaming');
+        }
+        this.assistant = null;
+    }
+
+    thinking(delta) {
+        if (!this.reasoning) {
+            this.reasoning = this.push({ kind: 'reasoning', title: '💭 Reasoning', text: '' });
+        }
+        this.reasoning.text += delta;
+        this.refresh(this.reasoning);
+    }
+
+    tool(title, text, failed) {
+        return this.push({ kind: 'tool', title, text, failed: !!failed });
+    }
+
+    finish() {
+        this.endMessage('');
+        for (const entry of this.conversation.entries) {
+            delete entry.node;
+        }
+        saveState();
+    }
+}
+
+// Handles one named Responses (or Codex progress) event.
+function handleEvent(turn, type, data) {
+    switch (type) {
+        case 'response.created':
+            if (data.response && data.response.conversation_id) {
+                turn.conversation.conversationId = data.response.conversation_id;
+            }
+            break;
+        case 'response.output_text.delta':
+            turn.text(data.delta);
+            break;
+        case 'response.reasoning_summary_text.delta':
+            turn.thinking(data.delta);
+            break;
+        case 'response.output_item.done':
+            handleItem(turn, data.item || {});
+            break;
+        case 'response.failed':
+            turn.push({
+                kind: 'error',
+                text: (data.response && data.response.error && data.response.error.message)
+                    || 'The response failed.',
+            });
+            break;
+        case 'response.incomplete':
+            turn.endMessage('');
+            setStatus('Stopped at the output token limit.');
+            break;
+        case 'codex.exec_command.begin': {
+            const entry = turn.tool('$ ' + commandLine(data.command), 'running in ' + data.cwd);
+            turn.commands.set(data.call_id, entry);
+            break;
+        }
+        case 'codex.exec_command.end': {
+            const title = '$ ' + commandLine(data.command) + '  (exit ' + data.exit_code + ', '
+                + data.duration_ms + ' ms)';
+            const output = data.output + (data.output_truncated ? '\n… (truncated)' : '');
+            const entry = turn.commands.get(data.call_id);
+            if (entry) {
+                entry.title = title;
+                entry.text = output;
+                entry.failed = data.exit_code !== 0;
+                turn.refresh(entry);
+            } else {
+                turn.tool(title, output, data.exit_code !== 0);
+            }
+            break;
+        }
+        case 'codex.patch_apply':
+            turn.tool(
+                (data.success ? '📝 Patched ' : '📝 Patch failed: ')
+                    + (data.files || []).map((f) => f.path).join(', '),
+                data.unified_diff,
+                !data.success,
+            );
+            break;
+        case 'exec_approval_request':
+        case 'apply_patch_approval_request':
+            turn.push({
+                kind: 'approval',
+                title: type === 'exec_approval_request'
+                    ? '⚠️ Run ' + commandLine(data.command) + '?'
+                    : '⚠️ Apply patch?',
+                text: data.reason || JSON.stringify(data.changes || {}, null, 2),
+                conversationId: data.conversation_id,
+                approvalId: data.approval_id,
+            });
+            break;
+        default:
+            break;
+    }
+}
+
+// Output items: the answer itself, reasoning, and tool calls.
+function handleItem(turn, item) {
+    switch (item.type) {
+        case 'message': {
+            const text = (item.content || []).map((part) => part.text || '').join('');
+            turn.endMessage(text);
+            break;
+        }
+        case 'reasoning':
+            turn.reasoning = null;
+            break;
+        case 'function_call':
+            turn.endMessage('');
+            turn.tool('🔧 ' + item.name, prettyJson(item.arguments));
+            break;
+        case 'function_call_output':
+            turn.tool('↩ Tool output', typeof item.output === 'string'
+                ? item.output
+                : JSON.stringify(item.output, null, 2));
+            break;
+        default:
+            turn.endMessage('');
+            turn.tool('🔧 ' + item.type, JSON.stringify(item, null, 2));
+            break;
+    }
+}
+
+async function decide(entry, decision) {
+    try {
+        const response = await fetch(
+            '/v1/conversations/' + entry.conversationId + '/approvals/' + entry.approvalId,
+            { method: 'POST', headers: headers(), body: JSON.stringify({ decision }) },
+        );
+        if (!response.ok) {
+            throw new Error(await errorMessage(response));
+        }
+        entry.decided = true;
+        entry.title += decision === 'approved' ? '  ✔ approved' : '  ✖ denied';
+        // Decisions arrive mid-turn, so only this block is redrawn.
+        if (entry.node) {
+            const node = renderEntry(entry);
+            entry.node.replaceWith(node);
+            entry.node = node;
+        } else {
+            renderMessages();
+        }
+        saveState();
+    } catch (e) {
+        setStatus('Failed to send the decision: ' + e.message);
+    }
+}
+
+async function send() {
+    const text = promptInput.value.trim();
+    if (!text || abortController) {
+        return;
+    }
+    let conversation = activeConversation();
+    if (!conversation) {
+        conversation = {
+            id: 'chat-' + Date.now() + '-' + Math.random().toString(16).slice(2),
+            conversationId: null,
+            title: text.slice(0, 40),
+            model: modelSelect.value,
+            entries: [],
+            history: [],
+        };
+        conversations.unshift(conversation);
+        activeId = conversation.id;
+        renderConversationList();
+        messagesDiv.innerHTML = '';
+    }
+    promptInput.value = '';
+
+    const turn = new Turn(conversation);
+    turn.push({ kind: 'user', text });
+    conversation.history.push({ role: 'user', content: text });
+    saveState();
+
+    abortController = new AbortController();
+    sendBtn.disabled = true;
+    stopBtn.disabled = false;
+    setStatus('Codex is working…');
+    try {
+        if (!useChatCompletions) {
+            const response = await post('/v1/responses', {
+                model: conversation.model || modelSelect.value,
+                input: text,
+                stream: true,
+                conversation_id: conversation.conversationId || undefined,
+                approval_policy: askApprovalInput.checked ? 'on-request' : undefined,
+            });
+            if (response.status === 404 && !conversation.conversationId) {
+                useChatCompletions = true;
+            } else {
+                await readResponse(response, (type, data) => handleEvent(turn, type, data));
+            }
+        }
+        if (useChatCompletions) {
+            const response = await post('/v1/chat/completions', {
+                model: conversation.model || modelSelect.value,
+                messages: conversation.history,
+                stream: true,
+            });
+            await readResponse(response, (type, data) => handleChatChunk(turn, type, data));
+        }
+        setStatus('');
+    } catch (e) {
+        if (e.name === 'AbortError') {
+            setStatus('Stopped.');
+        } else {
+            turn.push({ kind: 'error', text: e.message });
+            setStatus('');
+        }
+    } finally {
+        const answer = conversation.entries
+            .filter((entry) => entry.kind === 'assistant')
+            .slice(-1)[0];
+        if (answer) {
+            conversation.history.push({ role: 'assistant', content: answer.text });
+        }
+        turn.finish();
+        abortController = null;
+        sendBtn.disabled = false;
+        stopBtn.disabled = true;
+    }
+}
+
+// Chat completion chunks, for proxies without the Responses API.
+function handleChatChunk(turn, type, data) {
+    if (type !== 'message') {
+        handleEvent(turn, type, data);
+        return;
+    }
+    if (data.error) {
+        turn.push({ kind: 'error', text: data.error.message });
+        return;
+    }
+    const delta = (data.choices && data.choices[0] && data.choices[0].delta) || {};
+    if (delta.content) {
+        turn.text(delta.content);
+    }
+    if (delta.reasoning_content) {
+        turn.thinking(delta.reasoning_content);
+    }
+    for (const call of delta.tool_calls || []) {
+        turn.endMessage('');
+        turn.tool('🔧 ' + call.function.name, prettyJson(call.function.arguments));
+    }
+}
+
+function post(path, body) {
+    return fetch(path, {
+        method: 'POST',
+        headers: headers(),
+        body: JSON.stringify(body),
+        signal: abortController.signal,
+    });
+}
+
+// Reads an SSE response, calling onEvent(type, data) per event; unnamed
+// events have the type 'message' and `[DONE]` ends the stream.
+async function readResponse(response, onEvent) {
+    if (!response.ok) {
+        throw new Error(await errorMessage(response));
+    }
+    const reader = response.body.getReader();
+    const decoder = new TextDecoder();
+    let buffer = '';
+    for (;;) {
+        const { value, done } = await reader.read();
+        if (done) {
+            break;
+        }
+        buffer += decoder.decode(value, { stream: true });
+        let boundary;
+        while ((boundary = buffer.indexOf('\n\n')) !== -1) {
+            const block = buffer.slice(0, boundary);
+            buffer = buffer.slice(boundary + 2);
+            let type = 'message';
+            const data = [];
+            for (const line of block.split('\n')) {
+                if (line.startsWith('event:')) {
+                    type = line.slice(6).trim();
+                } else if (line.startsWith('data:')) {
+                    data.push(line.slice(5).trimStart());
+                }
+            }
+            // Keep-alive comments and pings carry nothing to render.
+            if (data.length === 0 || type === 'ping') {
+                continue;
+            }
+            const payload = data.join('\n');
+            if (payload === '[DONE]') {
+                return;
+            }
+            try {
+                onEvent(type, JSON.parse(payload));
+            } catch (e) {
+                console.error('Failed to handle event:', type, payload, e);
+            }
+        }
+    }
+}
+
+async function errorMessage(response) {
+    const text = await response.text();
+    try {
+        return JSON.parse(text).error.message;
+    } catch (e) {
+        return response.status + ' ' + (text || response.statusText);
+    }
+}
+
+function commandLine(command) {
+    return Array.isArray(command) ? command.join(' ') : String(command);
+}
+
+function prettyJson(text) {
+    try {
+        return JSON.stringify(JSON.parse(text), null, 2);
+    } catch (e) {
+        return text;
+    }
+}
+
+function setStatus(text) {
+    statusEl.textContent = text;
+}
+
+function scrollToBottom() {
+    messagesDiv.scrollTop = messagesDiv.scrollHeight;
+}
+
+// Start
+init();
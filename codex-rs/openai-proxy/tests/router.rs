@@ -0,0 +1,2273 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::Router;
+use axum::body::Body;
+use axum::body::Bytes;
+use axum::http::Request;
+use axum::http::StatusCode;
+use codex_core::config::ConfigBuilder;
+use codex_openai_proxy::AppStateBuilder;
+use pretty_assertions::assert_eq;
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn proxy_mounts_under_another_app() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .build()
+        .expect("build state");
+    let app = Router::new().nest("/codex", codex_openai_proxy::router(state));
+
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).expect("request");
+    let health = app
+        .clone()
+        .oneshot(get("/codex/healthz"))
+        .await
+        .expect("healthz");
+    assert_eq!(health.status(), StatusCode::OK);
+    let models = app.oneshot(get("/codex/v1/models")).await.expect("models");
+    assert_eq!(models.status(), StatusCode::OK);
+    let models: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(models.into_body(), usize::MAX)
+            .await
+            .expect("body"),
+    )
+    .expect("json body");
+    let entries = models["data"].as_array().expect("model entries");
+    assert!(!entries.is_empty());
+    for entry in entries {
+        assert!(entry["context_window"].is_u64(), "{entry}");
+        assert!(entry["capabilities"]["reasoning"].is_boolean(), "{entry}");
+    }
+}
+
+async fn mock_app(codex_home: &Path) -> Router {
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .build()
+        .expect("build state");
+    codex_openai_proxy::router(state)
+}
+
+#[tokio::test]
+async fn each_app_resolves_models_with_its_own_map() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let map_path = codex_home.path().join("models.toml");
+    std::fs::write(
+        &map_path,
+        "unmatched = \"reject\"\nallow_unknown_models = true\n\n[aliases]\n\"2.5-tpg\" = \"gpt-5.2\"\n",
+    )
+    .expect("write model map");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .model_map(&map_path)
+        .await
+        .expect("load model map")
+        .build()
+        .expect("build state");
+    let strict = codex_openai_proxy::router(state);
+
+    let (status, body) = complete(strict.clone(), "hi").await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let (status, body) = post_json(
+        strict.clone(),
+        "/v1/chat/completions",
+        serde_json::json!({
+            "model": "gpt-5.2",
+            "messages": [{ "role": "user", "content": "hi" }],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["error"]["code"], "model_not_found");
+
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).expect("request");
+    let models = strict.oneshot(get("/v1/models")).await.expect("models");
+    let models: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(models.into_body(), usize::MAX)
+            .await
+            .expect("body"),
+    )
+    .expect("json body");
+    let ids = models["data"]
+        .as_array()
+        .expect("model entries")
+        .iter()
+        .map(|entry| entry["id"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec![serde_json::json!("2.5-tpg")]);
+
+    // Another app in the same process keeps running names as given.
+    let other_home = tempfile::tempdir().expect("temp codex home");
+    let (status, body) = post_json(
+        mock_app(other_home.path()).await,
+        "/v1/chat/completions",
+        serde_json::json!({
+            "model": "gpt-5.2",
+            "messages": [{ "role": "user", "content": "hi" }],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{body:?}");
+}
+
+/// POSTs `body` to `uri` and returns the status and the whole response body.
+async fn post_json(app: Router, uri: &str, body: serde_json::Value) -> (StatusCode, Bytes) {
+    let request = Request::post(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("request");
+    let response = app.oneshot(request).await.expect("response");
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    (status, body)
+}
+
+/// Sends `prompt` as a non-streaming chat completion.
+async fn complete(app: Router, prompt: &str) -> (StatusCode, serde_json::Value) {
+    let (status, body) = post_json(
+        app,
+        "/v1/chat/completions",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "messages": [{ "role": "user", "content": prompt }],
+        }),
+    )
+    .await;
+    (status, serde_json::from_slice(&body).expect("json body"))
+}
+
+/// Sends `prompt` as a non-streaming chat completion with one extra header.
+async fn complete_with_header(
+    app: Router,
+    header: (&str, &str),
+    prompt: &str,
+) -> (StatusCode, serde_json::Value) {
+    let request = Request::post("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .header(header.0, header.1)
+        .body(Body::from(
+            serde_json::json!({
+                "model": "2.5-tpg",
+                "messages": [{ "role": "user", "content": prompt }],
+            })
+            .to_string(),
+        ))
+        .expect("request");
+    let response = app.oneshot(request).await.expect("response");
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    (status, serde_json::from_slice(&body).expect("json body"))
+}
+
+#[tokio::test]
+async fn chat_completions_run_against_the_mock_backend() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let (status, body) = complete(mock_app(codex_home.path()).await, "hi").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body["choices"][0]["message"]["content"],
+        "This is a scripted reply from the mock backend."
+    );
+    assert_eq!(body["choices"][0]["finish_reason"], "stop");
+    assert_eq!(body["model"], "2.5-tpg");
+    assert!(
+        body["system_fingerprint"]
+            .as_str()
+            .is_some_and(|fingerprint| fingerprint.starts_with("fp_"))
+    );
+}
+
+#[tokio::test]
+async fn upstream_failures_keep_their_status() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+
+    let (status, body) = complete(app.clone(), "[mock:context_length]").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "context_length_exceeded");
+
+    let (status, body) = complete(app, "[mock:rate_limit]").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["error"]["code"], "rate_limit_exceeded");
+}
+
+/// Sends `prompt` as a streaming chat completion and returns the `data:`
+/// payloads.
+async fn stream(app: Router, prompt: &str) -> Vec<String> {
+    stream_request(
+        app,
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "stream": true,
+            "messages": [{ "role": "user", "content": prompt }],
+        }),
+    )
+    .await
+}
+
+/// Sends a streaming chat completion request and returns the `data:` payloads.
+async fn stream_request(app: Router, body: serde_json::Value) -> Vec<String> {
+    let (status, body) = post_json(app, "/v1/chat/completions", body).await;
+    assert_eq!(status, StatusCode::OK);
+    String::from_utf8(body.to_vec())
+        .expect("utf-8 body")
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(str::to_string)
+        .collect()
+}
+
+#[tokio::test]
+async fn streamed_text_arrives_once() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let data = stream(mock_app(codex_home.path()).await, "hi").await;
+    let first: serde_json::Value = serde_json::from_str(&data[0]).expect("first chunk");
+    assert_eq!(first["choices"][0]["delta"]["role"], "assistant");
+    let chunks = data
+        .iter()
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .collect::<Vec<_>>();
+    assert!(chunks.iter().all(|chunk| {
+        chunk["id"] == first["id"]
+            && chunk["model"] == "2.5-tpg"
+            && chunk["system_fingerprint"] == first["system_fingerprint"]
+    }));
+    assert!(first["system_fingerprint"].is_string());
+    let text = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect::<String>();
+    assert_eq!(text, "This is a scripted reply from the mock backend.");
+    assert_eq!(data.last().map(String::as_str), Some("[DONE]"));
+}
+
+#[tokio::test]
+async fn rapid_deltas_are_merged_under_a_minimum_interval() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let data = stream_request(
+        mock_app(codex_home.path()).await,
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "stream": true,
+            "stream_min_interval_ms": 60_000,
+            "messages": [{ "role": "user", "content": "hi" }],
+        }),
+    )
+    .await;
+    let deltas = data
+        .iter()
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .filter_map(|chunk| {
+            chunk["choices"][0]["delta"]["content"]
+                .as_str()
+                .map(str::to_string)
+        })
+        .filter(|delta| !delta.is_empty())
+        .collect::<Vec<_>>();
+    // The first delta goes out at once; the rest wait for the interval, or
+    // for the finish chunk, whichever comes first.
+    assert_eq!(
+        deltas,
+        vec![
+            "This ".to_string(),
+            "is a scripted reply from the mock backend.".to_string()
+        ]
+    );
+    assert_eq!(data.last().map(String::as_str), Some("[DONE]"));
+}
+
+#[tokio::test]
+async fn word_granularity_rechunks_deltas_on_word_boundaries() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let words = vec![
+        "This ",
+        "is ",
+        "a ",
+        "scripted ",
+        "reply ",
+        "from ",
+        "the ",
+        "mock ",
+        "backend.",
+    ];
+    let data = stream_request(
+        app.clone(),
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "stream": true,
+            "stream_granularity": "word",
+            "messages": [{ "role": "user", "content": "hi" }],
+        }),
+    )
+    .await;
+    let deltas = data
+        .iter()
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .filter_map(|chunk| {
+            chunk["choices"][0]["delta"]["content"]
+                .as_str()
+                .map(str::to_string)
+        })
+        .filter(|delta| !delta.is_empty())
+        .collect::<Vec<_>>();
+    assert_eq!(deltas, words);
+
+    // Responses events split in two are renumbered to keep the sequence.
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "input": "hi",
+            "stream": true,
+            "stream_granularity": "word",
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let events = String::from_utf8(body.to_vec())
+        .expect("utf-8 body")
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .collect::<Vec<_>>();
+    let deltas = events
+        .iter()
+        .filter(|event| event["type"] == "response.output_text.delta")
+        .filter_map(|event| event["delta"].as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(deltas, words);
+    let numbers = events
+        .iter()
+        .filter_map(|event| event["sequence_number"].as_u64())
+        .collect::<Vec<_>>();
+    assert_eq!(numbers, (0..numbers.len() as u64).collect::<Vec<_>>());
+
+    let (status, _) = post_json(
+        app,
+        "/v1/chat/completions",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "stream": true,
+            "stream_granularity": "syllable",
+            "messages": [{ "role": "user", "content": "hi" }],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn stream_failures_end_with_an_error_chunk() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let data = stream(mock_app(codex_home.path()).await, "[mock:context_length]").await;
+    let [.., error, done] = data.as_slice() else {
+        panic!("stream too short: {data:?}");
+    };
+    assert_eq!(done, "[DONE]");
+    let error: serde_json::Value = serde_json::from_str(error).expect("error chunk");
+    assert_eq!(error["error"]["code"], "context_length_exceeded");
+    assert_eq!(error["choices"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn agent_mode_rejects_seeds() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let chat = serde_json::json!({
+        "model": "2.5-tpg",
+        "messages": [{ "role": "user", "content": "hi" }],
+        "seed": 7,
+    });
+    let mut chat_stream = chat.clone();
+    chat_stream["stream"] = true.into();
+
+    for (path, request) in [
+        ("/v1/chat/completions", chat),
+        ("/v1/chat/completions", chat_stream),
+        (
+            "/v1/responses",
+            serde_json::json!({ "model": "2.5-tpg", "input": "hi", "seed": 7 }),
+        ),
+        (
+            "/v1/responses",
+            serde_json::json!({ "model": "2.5-tpg", "input": "hi", "seed": 7, "stream": true }),
+        ),
+    ] {
+        let (status, body) = post_json(app.clone(), path, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{path}");
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+        assert_eq!(body["error"]["code"], "unsupported_parameter", "{path}");
+    }
+}
+
+#[tokio::test]
+async fn multiple_candidates_need_forward_mode() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let (status, body) = post_json(
+        mock_app(codex_home.path()).await,
+        "/v1/chat/completions",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "n": 2,
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(error["error"]["code"], "unsupported_parameter");
+}
+
+#[tokio::test]
+async fn streams_report_token_usage() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+
+    let data = stream_request(
+        app.clone(),
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "stream": true,
+            "stream_options": { "include_usage": true },
+            "messages": [{ "role": "user", "content": "hi" }],
+        }),
+    )
+    .await;
+    let [.., usage, done] = data.as_slice() else {
+        panic!("stream too short: {data:?}");
+    };
+    assert_eq!(done, "[DONE]");
+    let usage: serde_json::Value = serde_json::from_str(usage).expect("usage chunk");
+    assert_eq!(usage["choices"], serde_json::json!([]));
+    assert_eq!(
+        usage["usage"],
+        serde_json::json!({ "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 })
+    );
+
+    let (status, body) = post_json(
+        app,
+        "/v1/responses",
+        serde_json::json!({ "model": "2.5-tpg", "input": "hi", "stream": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let events = String::from_utf8(body.to_vec())
+        .expect("utf-8 body")
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .collect::<Vec<_>>();
+    let usage = serde_json::json!({ "input_tokens": 10, "output_tokens": 5, "total_tokens": 15 });
+    let updates = events
+        .iter()
+        .filter(|event| event["type"] == "response.usage")
+        .map(|event| &event["usage"])
+        .collect::<Vec<_>>();
+    assert_eq!(updates, vec![&usage]);
+    let completed = events
+        .iter()
+        .find(|event| event["type"] == "response.completed")
+        .expect("response.completed");
+    assert_eq!(completed["response"]["usage"], usage);
+}
+
+#[tokio::test]
+async fn answers_stop_at_the_output_token_cap() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+
+    let data = stream_request(
+        app.clone(),
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "stream": true,
+            "max_tokens": 3,
+            "messages": [{ "role": "user", "content": "hi" }],
+        }),
+    )
+    .await;
+    let chunks = data
+        .iter()
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .collect::<Vec<_>>();
+    let text = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect::<String>();
+    assert_eq!(text, "This is a sc");
+    let finish = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["finish_reason"].as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(finish, vec!["length"]);
+    assert_eq!(data.last().map(String::as_str), Some("[DONE]"));
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({ "model": "2.5-tpg", "input": "hi", "max_output_tokens": 3 }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["status"], "incomplete");
+    assert_eq!(body["incomplete_details"]["reason"], "max_output_tokens");
+    assert_eq!(body["output"][0]["content"][0]["text"], "This is a sc");
+
+    let (status, body) = post_json(
+        app,
+        "/v1/messages",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "max_tokens": 3,
+            "messages": [{ "role": "user", "content": "hi" }],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["stop_reason"], "max_tokens");
+    assert_eq!(body["content"][0]["text"], "This is a sc");
+}
+
+#[tokio::test]
+async fn capped_streams_still_report_usage() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let data = stream_request(
+        mock_app(codex_home.path()).await,
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "stream": true,
+            "max_tokens": 3,
+            "stream_options": { "include_usage": true },
+            "messages": [{ "role": "user", "content": "hi" }],
+        }),
+    )
+    .await;
+    let [.., finish, usage, done] = data.as_slice() else {
+        panic!("too few chunks: {data:?}");
+    };
+    let finish: serde_json::Value = serde_json::from_str(finish).expect("finish chunk");
+    assert_eq!(finish["choices"][0]["finish_reason"], "length");
+    let usage: serde_json::Value = serde_json::from_str(usage).expect("usage chunk");
+    assert_eq!(usage["choices"], serde_json::json!([]));
+    assert!(usage["usage"]["total_tokens"].is_u64(), "{usage}");
+    assert_eq!(done, "[DONE]");
+}
+
+#[tokio::test]
+async fn responses_keep_their_metadata_for_retrieval() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "input": "hi",
+            "metadata": { "run_id": "run-7" },
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let created: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(
+        created["metadata"],
+        serde_json::json!({ "run_id": "run-7" })
+    );
+
+    let id = created["id"].as_str().expect("response id");
+    let get = |uri: String| Request::get(uri).body(Body::empty()).expect("request");
+    let fetched = app
+        .clone()
+        .oneshot(get(format!("/v1/responses/{id}")))
+        .await
+        .expect("retrieve");
+    assert_eq!(fetched.status(), StatusCode::OK);
+    let fetched: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(fetched.into_body(), usize::MAX)
+            .await
+            .expect("body"),
+    )
+    .expect("json body");
+    assert_eq!(fetched, created);
+
+    let missing = app
+        .oneshot(get("/v1/responses/resp-unknown".to_string()))
+        .await
+        .expect("retrieve");
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn unstored_responses_are_not_kept() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({ "model": "2.5-tpg", "input": "hi", "store": false }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let created: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(created["store"], false);
+    assert_eq!(created["conversation_id"], serde_json::Value::Null);
+
+    let id = created["id"].as_str().expect("response id");
+    let fetched = app
+        .clone()
+        .oneshot(
+            Request::get(format!("/v1/responses/{id}"))
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("retrieve");
+    assert_eq!(fetched.status(), StatusCode::NOT_FOUND);
+    let rollouts = walkdir(&codex_home.path().join("sessions"));
+    assert_eq!(rollouts, Vec::<PathBuf>::new());
+
+    let (status, _) = post_json(
+        app,
+        "/v1/responses",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "input": "hi",
+            "store": false,
+            "conversation_id": "00000000-0000-0000-0000-000000000000",
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+/// Every file below `dir`, if it exists.
+fn walkdir(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .flat_map(|entry| {
+            let path = entry.path();
+            if path.is_dir() {
+                walkdir(&path)
+            } else {
+                vec![path]
+            }
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn oversized_bodies_get_an_openai_error() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .max_request_body_bytes(Some(1024))
+        .build()
+        .expect("build state");
+    let app = codex_openai_proxy::router(state);
+    let body = serde_json::json!({
+        "model": "2.5-tpg",
+        "messages": [{ "role": "user", "content": "x".repeat(4096) }],
+    })
+    .to_string();
+
+    let (status, streamed) = post_json(
+        app.clone(),
+        "/v1/chat/completions",
+        serde_json::from_str(&body).expect("json"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    let streamed: serde_json::Value = serde_json::from_slice(&streamed).expect("json error");
+    assert_eq!(streamed["error"]["code"], "request_too_large");
+
+    let request = Request::post("/v1/responses")
+        .header("content-type", "application/json")
+        .header("content-length", body.len())
+        .body(Body::from(body))
+        .expect("request");
+    let response = app.oneshot(request).await.expect("response");
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let declared: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body"),
+    )
+    .expect("json error");
+    assert_eq!(declared["error"]["code"], "request_too_large");
+}
+
+#[tokio::test]
+async fn root_serves_the_chat_ui() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).expect("request");
+
+    let root = app.clone().oneshot(get("/")).await.expect("root");
+    assert_eq!(root.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(root.headers()["location"], "/static/chat.html");
+
+    for asset in ["/static/chat.html", "/static/chat.js", "/static/chat.css"] {
+        let response = app.clone().oneshot(get(asset)).await.expect("asset");
+        assert_eq!(response.status(), StatusCode::OK, "{asset}");
+    }
+}
+
+#[tokio::test]
+async fn admin_serves_the_dashboard() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).expect("request");
+
+    let admin = app.clone().oneshot(get("/admin")).await.expect("admin");
+    assert_eq!(admin.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(admin.headers()["location"], "/static/admin.html");
+
+    for asset in [
+        "/static/admin.html",
+        "/static/admin.js",
+        "/static/admin.css",
+    ] {
+        let response = app.clone().oneshot(get(asset)).await.expect("asset");
+        assert_eq!(response.status(), StatusCode::OK, "{asset}");
+    }
+
+    let response = app
+        .oneshot(get("/admin/rate-limits"))
+        .await
+        .expect("rate limits");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let status: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(status["requests_per_minute"], serde_json::Value::Null);
+    assert_eq!(status["clients"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn admin_endpoints_need_the_admin_token() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = |admin_token: Option<&str>| {
+        AppStateBuilder::new(config.clone())
+            .ledger(PathBuf::from(":memory:"), None)
+            .admin_token(admin_token.map(str::to_string))
+            .listen_addr("0.0.0.0:11435".parse().expect("address"))
+            .build()
+            .expect("build state")
+    };
+    let get = |uri: &str, token: Option<&str>| {
+        let mut request = Request::get(uri);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {token}"));
+        }
+        request.body(Body::empty()).expect("request")
+    };
+
+    let guarded = codex_openai_proxy::router(state(Some("s3cret")));
+    for uri in ["/admin/threads", "/admin/config", "/v1/sessions"] {
+        for (token, expected) in [
+            (None, StatusCode::UNAUTHORIZED),
+            (Some("wrong"), StatusCode::UNAUTHORIZED),
+            (Some("s3cret"), StatusCode::OK),
+        ] {
+            let response = guarded
+                .clone()
+                .oneshot(get(uri, token))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), expected, "{uri} with {token:?}");
+        }
+    }
+    let health = guarded
+        .oneshot(get("/healthz", None))
+        .await
+        .expect("healthz");
+    assert_eq!(health.status(), StatusCode::OK);
+
+    // Listening beyond loopback without a token serves them to no one.
+    let closed = codex_openai_proxy::router(state(None));
+    let response = closed
+        .oneshot(get("/admin/threads", Some("anything")))
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let error: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(error["error"]["code"], "admin_token_required");
+}
+
+#[tokio::test]
+async fn finished_completions_cannot_be_cancelled() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let data = stream(app.clone(), "hi").await;
+    let first: serde_json::Value = serde_json::from_str(&data[0]).expect("first chunk");
+    let id = first["id"].as_str().expect("completion id");
+    assert!(id.starts_with("chatcmpl-"));
+
+    let (status, body) = post_json(
+        app,
+        &format!("/v1/chat/completions/{id}/cancel"),
+        serde_json::json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["error"]["code"], "completion_not_found");
+}
+
+#[tokio::test]
+async fn follow_ups_may_ask_to_interrupt_the_previous_turn() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({ "model": "2.5-tpg", "input": "hi" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let first: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    let conversation_id = first["conversation_id"].as_str().expect("conversation id");
+
+    let (status, body) = post_json(
+        app,
+        "/v1/responses",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "input": "and again",
+            "conversation_id": conversation_id,
+            "interrupt_previous_turn": true,
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["conversation_id"], conversation_id);
+    assert_eq!(body["status"], "completed");
+}
+
+#[tokio::test]
+async fn parallel_tool_calls_are_accepted() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    for parallel in [true, false] {
+        let (status, body) = post_json(
+            app.clone(),
+            "/v1/chat/completions",
+            serde_json::json!({
+                "model": "2.5-tpg",
+                "parallel_tool_calls": parallel,
+                "messages": [{ "role": "user", "content": "hi" }],
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "parallel_tool_calls: {parallel}");
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+        assert_eq!(body["choices"][0]["finish_reason"], "stop");
+    }
+}
+
+#[tokio::test]
+async fn parallel_tool_calls_cannot_change_mid_conversation() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({ "model": "2.5-tpg", "input": "hi" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let first: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    let conversation_id = first["conversation_id"].as_str().expect("conversation id");
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/chat/completions",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "conversation_id": conversation_id,
+            "parallel_tool_calls": true,
+            "messages": [{ "role": "user", "content": "again" }],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["error"]["code"], "unsupported_parameter");
+
+    let (status, _) = post_json(
+        app,
+        "/v1/chat/completions",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "conversation_id": conversation_id,
+            "messages": [{ "role": "user", "content": "again" }],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn tool_calls_past_max_tool_calls_end_the_response() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({ "model": "2.5-tpg", "input": "[mock:tool]", "max_tool_calls": 0 }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["status"], "incomplete");
+    assert_eq!(body["incomplete_details"]["reason"], "max_tool_calls");
+    let output = body["output"].as_array().expect("output");
+    assert!(output.iter().all(|item| item["type"] != "function_call"));
+
+    let (status, body) = post_json(
+        app,
+        "/v1/responses",
+        serde_json::json!({ "model": "2.5-tpg", "input": "[mock:tool]", "max_tool_calls": 1 }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["status"], "completed");
+}
+
+#[tokio::test]
+async fn responses_keep_codex_tool_calls_to_themselves() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "input": "hi",
+            "tools": [{ "type": "function", "name": "lookup", "parameters": {} }],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(error["error"]["code"], "unsupported_parameter");
+
+    let (status, body) = post_json(
+        app,
+        "/v1/responses",
+        serde_json::json!({ "model": "2.5-tpg", "input": "[mock:tool]" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    let output = body["output"].as_array().expect("output");
+    assert!(
+        output
+            .iter()
+            .all(|item| item["type"] != "function_call" && item["type"] != "function_call_output"),
+        "{output:?}"
+    );
+    assert_eq!(output.last().expect("a message")["type"], "message");
+}
+
+#[tokio::test]
+async fn local_shell_calls_show_up_as_shell_tool_calls() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let data = stream(mock_app(codex_home.path()).await, "[mock:local_shell]").await;
+    let tool_call = data
+        .iter()
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .find_map(|chunk| {
+            chunk["choices"][0]["delta"]["tool_calls"][0]
+                .as_object()
+                .cloned()
+        })
+        .expect("a tool call chunk");
+    assert_eq!(tool_call["id"], "call_mock_shell");
+    assert_eq!(tool_call["function"]["name"], "shell");
+    let arguments: serde_json::Value = serde_json::from_str(
+        tool_call["function"]["arguments"]
+            .as_str()
+            .expect("arguments"),
+    )
+    .expect("json arguments");
+    assert_eq!(arguments["command"], serde_json::json!(["echo", "mock"]));
+}
+
+#[tokio::test]
+async fn streamed_tool_calls_each_get_their_own_index() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let data = stream(mock_app(codex_home.path()).await, "[mock:parallel_shell]").await;
+    let tool_calls = data
+        .iter()
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .filter_map(|chunk| {
+            chunk["choices"][0]["delta"]["tool_calls"][0]
+                .as_object()
+                .cloned()
+        })
+        .map(|call| (call["index"].clone(), call["id"].clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tool_calls,
+        vec![
+            (serde_json::json!(0), serde_json::json!("call_mock_shell_1")),
+            (serde_json::json!(1), serde_json::json!("call_mock_shell_2")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn links_in_answers_after_a_web_search_are_cited() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({ "model": "gpt-5", "input": "[mock:web_search]" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let response: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    let message = response["output"]
+        .as_array()
+        .expect("output")
+        .iter()
+        .find(|item| item["type"] == "message")
+        .expect("a message");
+    assert_eq!(
+        message["content"][0]["annotations"],
+        serde_json::json!([{
+            "type": "url_citation",
+            "url": "https://www.rust-lang.org/",
+            "title": "the Rust site",
+            "start_index": 13,
+            "end_index": 56,
+        }])
+    );
+
+    let data = stream(app, "[mock:web_search]").await;
+    let annotations = data
+        .iter()
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .find_map(|chunk| {
+            chunk["choices"][0]["delta"]["annotations"]
+                .as_array()
+                .cloned()
+        })
+        .expect("an annotations chunk");
+    assert_eq!(
+        annotations[0]["url_citation"]["url"],
+        "https://www.rust-lang.org/"
+    );
+}
+
+#[tokio::test]
+async fn answers_that_do_not_match_the_json_schema_are_rejected() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let schema = |answer_type: &str| {
+        serde_json::json!({
+            "type": "object",
+            "properties": { "answer": { "type": answer_type } },
+            "required": ["answer"],
+        })
+    };
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({
+            "model": "gpt-5",
+            "input": "[mock:json]",
+            "text": { "format": { "type": "json_schema", "name": "a", "schema": schema("integer") } },
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let response: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(response["status"], "completed");
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({
+            "model": "gpt-5",
+            "input": "[mock:json]",
+            "text": { "format": { "type": "json_schema", "name": "a", "schema": schema("string") } },
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let response: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(response["status"], "incomplete");
+    assert_eq!(
+        response["incomplete_details"]["reason"],
+        "json_schema_validation"
+    );
+
+    let (status, body) = post_json(
+        app,
+        "/v1/chat/completions",
+        serde_json::json!({
+            "model": "gpt-5",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": { "name": "a", "schema": schema("integer") },
+            },
+        }),
+    )
+    .await;
+    // Retried once like a stream, then failed with the same error.
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let error: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(error["error"]["code"], "json_schema_validation_failed");
+    assert_eq!(error["error"]["type"], "invalid_request_error");
+}
+
+#[tokio::test]
+async fn streamed_answers_that_do_not_match_the_json_schema_fail_after_one_retry() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let data = stream_request(
+        mock_app(codex_home.path()).await,
+        serde_json::json!({
+            "model": "gpt-5",
+            "stream": true,
+            "stream_options": { "include_usage": true },
+            "messages": [{ "role": "user", "content": "hi" }],
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "a",
+                    "schema": { "type": "object", "required": ["answer"] },
+                },
+            },
+        }),
+    )
+    .await;
+    let chunks = data
+        .iter()
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .collect::<Vec<_>>();
+    let errors = chunks
+        .iter()
+        .filter(|chunk| chunk.get("error").is_some())
+        .collect::<Vec<_>>();
+    assert_eq!(errors.len(), 1, "{data:?}");
+    assert_eq!(errors[0]["error"]["code"], "json_schema_validation_failed");
+    // Neither answer reaches the client, and nothing reads as truncated.
+    let text = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect::<String>();
+    assert_eq!(text, "");
+    assert!(
+        chunks
+            .iter()
+            .all(|chunk| chunk["choices"][0]["finish_reason"].is_null()),
+        "{data:?}"
+    );
+    // Both attempts went to the model and are billed.
+    let usage = chunks
+        .iter()
+        .find(|chunk| chunk["usage"].is_object())
+        .expect("usage chunk");
+    assert!(
+        usage["usage"]["total_tokens"].as_i64() >= Some(30),
+        "{usage}"
+    );
+    assert_eq!(data.last().map(String::as_str), Some("[DONE]"));
+}
+
+#[tokio::test]
+async fn base_instructions_replace_the_system_prompt() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let (status, completion) = complete_with_header(
+        app.clone(),
+        ("x-codex-base-instructions", "You are a pirate."),
+        "[mock:instructions]",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        completion["choices"][0]["message"]["content"],
+        "You are a pirate."
+    );
+
+    let (status, completion) = complete(app, "[mock:instructions]").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(
+        completion["choices"][0]["message"]["content"],
+        "You are a pirate."
+    );
+}
+
+#[tokio::test]
+async fn requests_pick_a_codex_profile() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    std::fs::write(
+        codex_home.path().join("config.toml"),
+        "[profiles.fast]\nmodel_reasoning_effort = \"low\"\n",
+    )
+    .expect("write config");
+    let app = mock_app(codex_home.path()).await;
+
+    let (status, _) = complete_with_header(app.clone(), ("x-codex-profile", "fast"), "hi").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, error) = complete_with_header(app, ("x-codex-profile", "slow"), "hi").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"]["code"], "profile_not_found");
+}
+
+#[tokio::test]
+async fn every_request_is_written_to_the_audit_log() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let audit_path = codex_home.path().join("audit.jsonl");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .audit_log(audit_path.clone(), false)
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .build()
+        .expect("build state");
+    let app = codex_openai_proxy::router(state);
+
+    let (status, _) = complete(app, "a secret prompt").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut log = String::new();
+    for _ in 0..50 {
+        log = std::fs::read_to_string(&audit_path).unwrap_or_default();
+        if !log.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let entry: serde_json::Value = serde_json::from_str(log.trim()).expect("one json line");
+    assert_eq!(entry["path"], "/v1/chat/completions");
+    assert_eq!(entry["status"], 200);
+    assert_eq!(entry["outcome"], "completed");
+    assert_eq!(entry["prompt_sha256"].as_str().map(str::len), Some(64));
+    assert!(!log.contains("a secret prompt"));
+}
+
+#[tokio::test]
+async fn danger_full_access_is_opt_in_and_audited() {
+    let request = serde_json::json!({
+        "model": "2.5-tpg",
+        "messages": [{ "role": "user", "content": "run the tests" }],
+        "sandbox_mode": "danger-full-access",
+    });
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let (status, body) = post_json(
+        mock_app(codex_home.path()).await,
+        "/v1/chat/completions",
+        request.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let error: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(error["error"]["code"], "danger_full_access_disabled");
+
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let audit_path = codex_home.path().join("audit.jsonl");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .audit_log(audit_path.clone(), false)
+        .allow_danger_full_access(true)
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .build()
+        .expect("build state");
+    let (status, _) = post_json(
+        codex_openai_proxy::router(state),
+        "/v1/chat/completions",
+        request,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut log = String::new();
+    for _ in 0..50 {
+        log = std::fs::read_to_string(&audit_path).unwrap_or_default();
+        if !log.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let entry: serde_json::Value = serde_json::from_str(log.trim()).expect("one json line");
+    assert_eq!(entry["sandbox"], "danger-full-access");
+}
+
+#[tokio::test]
+async fn request_mcp_servers_must_be_allowlisted() {
+    let request = serde_json::json!({
+        "model": "2.5-tpg",
+        "input": "look up the docs",
+        "mcp_servers": [{ "name": "docs", "url": "http://127.0.0.1:9/mcp" }],
+    });
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let (status, body) = post_json(
+        mock_app(codex_home.path()).await,
+        "/v1/responses",
+        request.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let error: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(error["error"]["code"], "mcp_server_not_allowed");
+
+    // Messages requests go through the same checks.
+    let (status, _) = post_json(
+        mock_app(codex_home.path()).await,
+        "/v1/messages",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "messages": [{ "role": "user", "content": "look up the docs" }],
+            "mcp_servers": request["mcp_servers"],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .mcp_server_allowlist(Vec::new(), vec!["http://127.0.0.1:9/".to_string()])
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .build()
+        .expect("build state");
+    let (status, body) =
+        post_json(codex_openai_proxy::router(state), "/v1/responses", request).await;
+    assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+}
+
+#[tokio::test]
+async fn prompts_are_redacted_before_they_reach_the_model() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .redaction(vec!["email".to_string()], Vec::new(), true)
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .build()
+        .expect("build state");
+    let app = codex_openai_proxy::router(state);
+
+    let (status, body) = complete(app, "[mock:echo] write to ana@example.com").await;
+    assert_eq!(status, StatusCode::OK);
+    let answer = body["choices"][0]["message"]["content"]
+        .as_str()
+        .expect("answer");
+    assert!(answer.contains("write to [REDACTED:email]"), "{answer}");
+    assert!(!answer.contains("ana@example.com"));
+}
+
+#[tokio::test]
+async fn moderation_rejects_input_before_a_turn_starts() {
+    // An OpenAI-style moderation endpoint that flags anything mentioning "sabotage".
+    let moderation = Router::new().route(
+        "/v1/moderations",
+        axum::routing::post(
+            |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                let flagged = body["input"]
+                    .as_str()
+                    .is_some_and(|input| input.contains("sabotage"));
+                axum::Json(serde_json::json!({
+                    "results": [{ "flagged": flagged, "categories": { "violence": flagged } }]
+                }))
+            },
+        ),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind moderation endpoint");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(async move { axum::serve(listener, moderation).await });
+
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let rules = codex_home.path().join("moderation.txt");
+    std::fs::write(&rules, "# local rules\n(?i)\\bforbidden\\b\n").expect("write rules");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .moderation_rules(rules)
+        .moderation_endpoint(
+            format!("http://{addr}/v1/moderations"),
+            None,
+            Duration::from_secs(5),
+        )
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .build()
+        .expect("build state");
+    let app = codex_openai_proxy::router(state);
+
+    let (status, body) = complete(app.clone(), "tell me something Forbidden").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "content_policy");
+
+    let (status, body) = complete(app.clone(), "plan some sabotage").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "content_policy");
+    assert_eq!(
+        body["error"]["message"],
+        "The input was flagged by the content policy: violence."
+    );
+
+    let (status, _) = complete(app, "say hello").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+/// Rewrites "cat" to "dog" in chat prompts.
+struct RewritePrompt;
+
+impl codex_openai_proxy::RequestInterceptor for RewritePrompt {
+    fn on_request(
+        &self,
+        _parts: &mut axum::http::request::Parts,
+        body: Option<&mut serde_json::Value>,
+    ) -> Result<(), axum::response::Response> {
+        if let Some(content) = body.and_then(|body| body.pointer_mut("/messages/0/content"))
+            && let Some(text) = content.as_str()
+        {
+            *content = serde_json::Value::String(text.replace("cat", "dog"));
+        }
+        Ok(())
+    }
+}
+
+/// Tags responses with the path they answered.
+struct TagResponse;
+
+impl codex_openai_proxy::ResponseInterceptor for TagResponse {
+    fn on_response(
+        &self,
+        _method: &axum::http::Method,
+        uri: &axum::http::Uri,
+        parts: &mut axum::http::response::Parts,
+    ) {
+        if let Ok(value) = axum::http::HeaderValue::from_str(uri.path()) {
+            parts.headers.insert("x-intercepted", value);
+        }
+    }
+}
+
+/// Upper-cases streamed answer text and drops every other event.
+struct ShoutDeltas;
+
+impl codex_openai_proxy::EventInterceptor for ShoutDeltas {
+    fn on_event(&self, event: &mut codex_protocol::protocol::EventMsg) -> bool {
+        match event {
+            codex_protocol::protocol::EventMsg::AgentMessageContentDelta(delta) => {
+                delta.delta = delta.delta.to_uppercase();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[tokio::test]
+async fn interceptors_rewrite_requests_responses_and_events() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .request_interceptor(RewritePrompt)
+        .response_interceptor(TagResponse)
+        .event_interceptor(ShoutDeltas)
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .build()
+        .expect("build state");
+    let app = codex_openai_proxy::router(state);
+
+    let request = Request::post("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            serde_json::json!({
+                "model": "2.5-tpg",
+                "messages": [{ "role": "user", "content": "[mock:echo] feed the cat" }],
+            })
+            .to_string(),
+        ))
+        .expect("request");
+    let response = app.oneshot(request).await.expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response
+            .headers()
+            .get("x-intercepted")
+            .map(|value| value.as_bytes()),
+        Some(b"/v1/chat/completions".as_slice())
+    );
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    let answer = body["choices"][0]["message"]["content"]
+        .as_str()
+        .expect("answer");
+    // Dropping everything but deltas still lets the turn complete.
+    assert!(answer.contains("FEED THE DOG"), "{answer}");
+}
+
+/// Turns every finished turn into an interrupted one.
+struct AbortTurns;
+
+impl codex_openai_proxy::EventInterceptor for AbortTurns {
+    fn on_event(&self, event: &mut codex_protocol::protocol::EventMsg) -> bool {
+        if let codex_protocol::protocol::EventMsg::TurnComplete(_) = event {
+            *event = codex_protocol::protocol::EventMsg::TurnAborted(
+                codex_protocol::protocol::TurnAbortedEvent {
+                    reason: codex_protocol::protocol::TurnAbortReason::Interrupted,
+                },
+            );
+        }
+        true
+    }
+}
+
+#[tokio::test]
+async fn aborted_turns_end_as_incomplete() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .event_interceptor(AbortTurns)
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .build()
+        .expect("build state");
+    let app = codex_openai_proxy::router(state);
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({ "model": "2.5-tpg", "input": "hi" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["status"], "incomplete");
+    assert_eq!(body["incomplete_details"]["reason"], "interrupted");
+
+    let (status, body) = complete(app.clone(), "hi").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["choices"][0]["finish_reason"], "stop");
+    assert_eq!(body["choices"][0]["abort_reason"], "interrupted");
+
+    let data = stream(app.clone(), "hi").await;
+    let finish = data
+        .iter()
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .filter(|chunk| chunk["choices"][0]["finish_reason"].is_string())
+        .map(|chunk| chunk["choices"][0].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(finish.len(), 1);
+    assert_eq!(finish[0]["finish_reason"], "stop");
+    assert_eq!(finish[0]["abort_reason"], "interrupted");
+    assert_eq!(data.last().map(String::as_str), Some("[DONE]"));
+
+    let (status, body) = post_json(
+        app,
+        "/v1/messages",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "max_tokens": 64,
+            "messages": [{ "role": "user", "content": "hi" }],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["stop_reason"], "end_turn");
+    assert_eq!(body["abort_reason"], "interrupted");
+}
+
+#[tokio::test]
+async fn messages_reject_client_tools() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let (status, body) = post_json(
+        mock_app(codex_home.path()).await,
+        "/v1/messages",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "max_tokens": 64,
+            "messages": [{ "role": "user", "content": "what's the weather?" }],
+            "tools": [{ "name": "get_weather", "input_schema": { "type": "object" } }],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+    assert!(
+        body["error"]["message"]
+            .as_str()
+            .is_some_and(|message| message.contains("get_weather")),
+        "{body}"
+    );
+}
+
+/// Raises an exec approval at the start of every turn, as a misbehaving
+/// model could even under `never`.
+struct RequestApproval;
+
+impl codex_openai_proxy::EventInterceptor for RequestApproval {
+    fn on_event(&self, event: &mut codex_protocol::protocol::EventMsg) -> bool {
+        if let codex_protocol::protocol::EventMsg::TurnStarted(_) = event {
+            *event = codex_protocol::protocol::EventMsg::ExecApprovalRequest(
+                serde_json::from_value(serde_json::json!({
+                    "call_id": "call-approval",
+                    "command": ["touch", "file"],
+                    "cwd": "/tmp",
+                    "parsed_cmd": [],
+                }))
+                .expect("exec approval request"),
+            );
+        }
+        true
+    }
+}
+
+#[tokio::test]
+async fn approvals_under_never_are_denied_with_a_warning() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .event_interceptor(RequestApproval)
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .build()
+        .expect("build state");
+    let app = codex_openai_proxy::router(state);
+
+    let (status, body) = complete(app.clone(), "hi").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["choices"][0]["finish_reason"], "stop");
+
+    let data = stream(app, "hi").await;
+    let warnings = data
+        .iter()
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .filter(|event| event["type"] == "codex.warning")
+        .collect::<Vec<_>>();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0]["code"], "approval_auto_denied");
+    assert_eq!(warnings[0]["approval_id"], "call-approval");
+    assert_eq!(data.last().map(String::as_str), Some("[DONE]"));
+}
+
+/// Reports a diff at the start of every turn, as if it had edited a file.
+struct ReportDiff;
+
+impl codex_openai_proxy::EventInterceptor for ReportDiff {
+    fn on_event(&self, event: &mut codex_protocol::protocol::EventMsg) -> bool {
+        if let codex_protocol::protocol::EventMsg::TurnStarted(_) = event {
+            *event = codex_protocol::protocol::EventMsg::TurnDiff(
+                codex_protocol::protocol::TurnDiffEvent {
+                    unified_diff: "diff --git a/x b/x\n+1\n".to_string(),
+                },
+            );
+        }
+        true
+    }
+}
+
+#[tokio::test]
+async fn turn_diffs_can_be_fetched_by_response_id() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .event_interceptor(ReportDiff)
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .build()
+        .expect("build state");
+    let app = codex_openai_proxy::router(state);
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({ "model": "2.5-tpg", "input": "hi" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    let conversation_id = body["conversation_id"].as_str().expect("conversation id");
+    let response_id = body["id"].as_str().expect("response id");
+
+    let get = |uri: String| Request::get(uri).body(Body::empty()).expect("request");
+    let response = app
+        .clone()
+        .oneshot(get(format!(
+            "/v1/conversations/{conversation_id}/turns/{response_id}/diff"
+        )))
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response
+            .headers()
+            .get("x-codex-turn-status")
+            .map(|value| value.as_bytes()),
+        Some(b"completed".as_slice())
+    );
+    let diff = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    assert_eq!(diff, Bytes::from_static(b"diff --git a/x b/x\n+1\n"));
+
+    let response = app
+        .oneshot(get(format!(
+            "/v1/conversations/{conversation_id}/turns/resp_unknown/diff"
+        )))
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn dry_runs_return_patches_without_applying_them() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let workspace = tempfile::tempdir().expect("temp workspace");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .workspace_roots(vec![workspace.path().to_path_buf()], false)
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .build()
+        .expect("build state");
+    let app = codex_openai_proxy::router(state);
+    let cwd = workspace.path().to_string_lossy().to_string();
+    let expected_patch = serde_json::json!({
+        "call_id": "call_mock_patch",
+        "files": [{ "path": workspace.path().join("mock.txt"), "kind": "add" }],
+    });
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/chat/completions",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "messages": [{ "role": "user", "content": "[mock:patch] add a file" }],
+            "cwd": cwd,
+            "dry_run": true,
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    let patches = body["proposed_patches"]
+        .as_array()
+        .expect("proposed patches");
+    assert_eq!(patches.len(), 1);
+    assert_eq!(patches[0]["call_id"], expected_patch["call_id"]);
+    assert_eq!(patches[0]["files"], expected_patch["files"]);
+    let diff = patches[0]["unified_diff"].as_str().expect("unified diff");
+    assert!(diff.ends_with("@@ -0,0 +1,1 @@\n+mock\n"), "{diff}");
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "input": "[mock:patch] add a file",
+            "cwd": cwd,
+            "dry_run": true,
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    let patches = body["output"]
+        .as_array()
+        .expect("output")
+        .iter()
+        .filter(|item| item["type"] == "proposed_patch")
+        .collect::<Vec<_>>();
+    assert_eq!(patches.len(), 1);
+    assert_eq!(patches[0]["files"], expected_patch["files"]);
+
+    let (status, body) = post_json(
+        app,
+        "/v1/messages",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "messages": [{ "role": "user", "content": "[mock:patch] add a file" }],
+            "cwd": cwd,
+            "dry_run": true,
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    let patches = body["proposed_patches"]
+        .as_array()
+        .expect("proposed patches");
+    assert_eq!(patches.len(), 1);
+    assert_eq!(patches[0]["files"], expected_patch["files"]);
+
+    assert!(!workspace.path().join("mock.txt").exists());
+}
+
+#[tokio::test]
+async fn plan_updates_are_streamed_and_returned() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let expected_plan = serde_json::json!({
+        "explanation": "Mock plan",
+        "plan": [
+            { "step": "Read the code", "status": "completed" },
+            { "step": "Change it", "status": "in_progress" },
+        ],
+    });
+
+    let data = stream(app.clone(), "[mock:plan] make a plan").await;
+    let plans = data
+        .iter()
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .filter(|event| event["type"] == "codex.plan.delta")
+        .collect::<Vec<_>>();
+    assert_eq!(plans.len(), 1);
+    assert_eq!(plans[0]["plan"], expected_plan["plan"]);
+
+    let (status, body) = post_json(
+        app,
+        "/v1/responses",
+        serde_json::json!({ "model": "2.5-tpg", "input": "[mock:plan] make a plan" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["plan"], expected_plan);
+}
+
+#[tokio::test]
+async fn finished_turns_are_posted_to_the_turn_webhook() {
+    let (notices_tx, mut notices) = tokio::sync::mpsc::unbounded_channel::<serde_json::Value>();
+    let receiver = Router::new().route(
+        "/turns",
+        axum::routing::post(
+            move |axum::Json(notice): axum::Json<serde_json::Value>| async move {
+                let _ = notices_tx.send(notice);
+            },
+        ),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind webhook receiver");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let config = ConfigBuilder::default()
+        .codex_home(codex_home.path().to_path_buf())
+        .build()
+        .await
+        .expect("load config");
+    let state = AppStateBuilder::new(config)
+        .ledger(PathBuf::from(":memory:"), None)
+        .turn_webhook(
+            format!("http://{addr}/turns"),
+            Duration::from_secs(5),
+            Duration::ZERO,
+        )
+        .mock_backend()
+        .await
+        .expect("start mock backend")
+        .build()
+        .expect("build state");
+    let app = codex_openai_proxy::router(state);
+
+    let (status, body) = complete(app.clone(), "[mock:echo] ship it").await;
+    assert_eq!(status, StatusCode::OK);
+    let notice = tokio::time::timeout(Duration::from_secs(10), notices.recv())
+        .await
+        .expect("a notification")
+        .expect("open channel");
+    assert_eq!(notice["event"], "turn.completed");
+    assert_eq!(notice["response_id"], body["id"]);
+    assert_eq!(notice["path"], "/v1/chat/completions");
+    assert_eq!(notice["background"], false);
+    assert!(
+        notice["summary"]
+            .as_str()
+            .is_some_and(|summary| summary.contains("ship it")),
+        "{notice}"
+    );
+    assert!(notice["usage"]["total_tokens"].is_i64());
+
+    // Requests that start no turn are not notified.
+    let models = app
+        .oneshot(
+            Request::get("/v1/models")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("models");
+    assert_eq!(models.status(), StatusCode::OK);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(300), notices.recv())
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn uploaded_files_can_be_fetched_and_attached() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let body = "--b0undary\r\n\
+Content-Disposition: form-data; name=\"purpose\"\r\n\r\n\
+user_data\r\n\
+--b0undary\r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"../notes.txt\"\r\n\
+Content-Type: text/plain\r\n\r\n\
+remember the milk\r\n\
+--b0undary--\r\n";
+    let request = Request::post("/v1/files")
+        .header("content-type", "multipart/form-data; boundary=b0undary")
+        .body(Body::from(body))
+        .expect("request");
+    let response = app.clone().oneshot(request).await.expect("upload");
+    assert_eq!(response.status(), StatusCode::OK);
+    let file: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body"),
+    )
+    .expect("json body");
+    assert_eq!(file["object"], "file");
+    assert_eq!(file["filename"], "notes.txt");
+    assert_eq!(file["bytes"], 17);
+    assert_eq!(file.get("path"), None);
+    let id = file["id"].as_str().expect("file id");
+    let files_dir = codex_home.path().join("openai-proxy").join("files");
+    assert!(
+        files_dir
+            .join("shared")
+            .join(id)
+            .join("notes.txt")
+            .is_file()
+    );
+
+    let get = |uri: String| Request::get(uri).body(Body::empty()).expect("request");
+    let content = app
+        .clone()
+        .oneshot(get(format!("/v1/files/{id}/content")))
+        .await
+        .expect("content");
+    assert_eq!(content.status(), StatusCode::OK);
+    let content = axum::body::to_bytes(content.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    assert_eq!(&content[..], b"remember the milk");
+    let missing = app
+        .clone()
+        .oneshot(get("/v1/files/file-0123abcd/content".to_string()))
+        .await
+        .expect("content");
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+    // A turn can be handed the upload by its id.
+    let (status, _) = post_json(
+        app,
+        "/v1/chat/completions",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "messages": [{
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": "What should I remember?" },
+                    { "type": "file", "file": { "file_id": id } },
+                ],
+            }],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rollouts_can_be_imported_as_conversations() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/responses",
+        serde_json::json!({ "model": "2.5-tpg", "input": "remember the number 42" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let first: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    let original = first["conversation_id"].as_str().expect("conversation id");
+
+    // Rollouts are written in the background; wait for the turn to land.
+    let sessions = codex_home.path().join("sessions");
+    let mut rollout = None;
+    for _ in 0..100 {
+        rollout = find_rollout(&sessions, original)
+            .filter(|path| std::fs::read_to_string(path).is_ok_and(|text| text.contains("42")));
+        if rollout.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let rollout = rollout.expect("rollout of the first conversation");
+
+    let relative = rollout.strip_prefix(&sessions).expect("under sessions");
+    let (status, body) = post_json(
+        app.clone(),
+        "/v1/conversations/import",
+        serde_json::json!({ "rollout_path": relative }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+    let imported: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(imported["object"], "conversation");
+    assert_eq!(imported["imported_from"], original);
+    let id = imported["id"].as_str().expect("imported id");
+    assert_ne!(id, original);
+
+    let (status, _) = post_json(
+        app.clone(),
+        "/v1/chat/completions",
+        serde_json::json!({
+            "model": "2.5-tpg",
+            "conversation_id": id,
+            "messages": [{ "role": "user", "content": "what was the number?" }],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The rollout itself can be posted too.
+    let request = Request::post("/v1/conversations/import")
+        .header("content-type", "application/x-ndjson")
+        .body(Body::from(std::fs::read(&rollout).expect("read rollout")))
+        .expect("request");
+    let response = app.clone().oneshot(request).await.expect("import");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Only rollouts in the Codex home's sessions are read.
+    let outside = codex_home.path().join("outside.jsonl");
+    std::fs::copy(&rollout, &outside).expect("copy rollout");
+    let (status, body) = post_json(
+        app,
+        "/v1/conversations/import",
+        serde_json::json!({ "rollout_path": outside }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let error: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(error["error"]["code"], "rollout_path_not_allowed");
+}
+
+#[tokio::test]
+async fn past_sessions_are_listed_newest_first() {
+    let codex_home = tempfile::tempdir().expect("temp codex home");
+    let app = mock_app(codex_home.path()).await;
+    let mut ids = Vec::new();
+    for prompt in ["first session", "second session"] {
+        let (status, body) = post_json(
+            app.clone(),
+            "/v1/responses",
+            serde_json::json!({ "model": "2.5-tpg", "input": prompt }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let response: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+        let id = response["conversation_id"]
+            .as_str()
+            .expect("conversation id")
+            .to_string();
+        // Rollout file names order sessions to the second.
+        let sessions = codex_home.path().join("sessions");
+        for _ in 0..100 {
+            if find_rollout(&sessions, &id)
+                .is_some_and(|path| std::fs::read_to_string(path).is_ok_and(|t| t.contains(prompt)))
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        ids.push(id);
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+    }
+
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).expect("request");
+    let response = app
+        .clone()
+        .oneshot(get("/v1/sessions?limit=1"))
+        .await
+        .expect("sessions");
+    assert_eq!(response.status(), StatusCode::OK);
+    let page: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body"),
+    )
+    .expect("json body");
+    assert_eq!(page["object"], "list");
+    assert_eq!(page["has_more"], true);
+    let newest = &page["data"][0];
+    assert_eq!(newest["id"], ids[1].as_str());
+    assert!(
+        newest["first_message"]
+            .as_str()
+            .is_some_and(|message| message.contains("second session")),
+        "{newest}"
+    );
+    assert!(newest["model"].is_string(), "{newest}");
+
+    let cursor = page["next_cursor"].as_str().expect("next cursor");
+    let uri = format!("/v1/sessions?after={}", cursor.replace('|', "%7C"));
+    let response = app.oneshot(get(&uri)).await.expect("sessions");
+    let page: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body"),
+    )
+    .expect("json body");
+    assert_eq!(page["data"][0]["id"], ids[0].as_str());
+    assert_eq!(page["has_more"], false);
+}
+
+/// The rollout file of conversation `id` somewhere under `dir`.
+fn find_rollout(dir: &Path, id: &str) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find_map(|path| {
+            if path.is_dir() {
+                find_rollout(&path, id)
+            } else {
+                path.file_name()
+                    .is_some_and(|name| name.to_string_lossy().contains(id))
+                    .then_some(path)
+            }
+        })
+}